@@ -1,5 +1,5 @@
 use compile_state::state::{CompileEnvironment, SloshVm, SloshVmTrait};
-use slvm::CallFuncSig;
+use slvm::{CallClosure, CallFuncSig};
 
 pub mod lisp_adapters;
 
@@ -14,3 +14,17 @@ pub fn add_builtin(
     let s = env.alloc_string(doc_string.to_string());
     env.set_global_property(si, key, s);
 }
+
+/// Like add_builtin but for a closure that can carry Rust state, see GVm::add_builtin_closure.
+pub fn add_builtin_closure(
+    env: &mut SloshVm,
+    name: &str,
+    func: Box<CallClosure<CompileEnvironment>>,
+    doc_string: &str,
+) {
+    let f = env.add_builtin_closure(func);
+    let si = env.set_named_global(name, f);
+    let key = env.intern("doc-string");
+    let s = env.alloc_string(doc_string.to_string());
+    env.set_global_property(si, key, s);
+}
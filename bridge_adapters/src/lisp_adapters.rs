@@ -4,6 +4,9 @@
 //!     this allows rust native functions annotated with the bridge macro to receive normal
 //!     rust types.
 //! #. To convert a slosh &Value to a mutable reference type implement `impl SlAsMut<&Value> for MutRefType`.
+//! #. To pass your own rust type implement [`native::SlNative`] for it and put it on the heap with
+//!     `vm.alloc_object(NativeObject::new(..))`, annotated functions can then take `&MyType` or
+//!     `&mut MyType`.
 //! #. To convert some rust type back to a value that the rust native function
 //!     annotated by the bridge macro returns implement `impl SlFrom<&Value> for RustType`.
 //! TODO PC ISSUE #7 - returning values via annotated or lifetime?
@@ -95,7 +98,9 @@
 //!  [`Value`]::Lambda(Handle),
 //!  [`Value`]::Closure(Handle),
 //!  [`Value`]::Continuation(Handle),
+//!  [`Value`]::Coroutine(Handle),
 //!  [`Value`]::CallFrame(Handle),
+//!  [`Value`]::Object(Handle),
 //!  [`Value`]::Error(Handle),
 
 use bridge_types::BridgedType;
@@ -105,6 +110,7 @@ use bridge_types::{LooseString, SloshChar};
 use compile_state::state::SloshVm;
 
 use slvm::{VMResult, Value};
+pub mod native;
 pub mod numbers;
 pub mod primitives;
 pub mod text;
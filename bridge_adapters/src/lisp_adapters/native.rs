@@ -0,0 +1,49 @@
+use crate::lisp_adapters::{SlAsMut, SlFromRef, SlFromRefMut};
+use bridge_types::ErrorStrings;
+use compile_state::state::SloshVm;
+use slvm::{VMError, VMResult, Value};
+use std::any::Any;
+
+/// A Rust type that annotated functions can take by reference from a [`Value`]`::Object`.
+/// Allocate it with `vm.alloc_object(NativeObject::new(MyType::TYPE_NAME, my_value))`.
+pub trait SlNative: Any {
+    /// Name of the type, use the same name for the [`slvm::NativeObject`].
+    const TYPE_NAME: &'static str;
+}
+
+fn mismatched_object<T: SlNative>(value: &Value, vm: &SloshVm) -> VMError {
+    VMError::new_conversion(ErrorStrings::fix_me_mismatched_type(
+        T::TYPE_NAME,
+        value.display_type(vm),
+    ))
+}
+
+impl<'a, T: SlNative> SlFromRef<'a, Value> for &'a T {
+    fn sl_from_ref(value: Value, vm: &'a SloshVm) -> VMResult<Self> {
+        match value {
+            Value::Object(h) => vm
+                .get_object(h)
+                .downcast_ref::<T>()
+                .ok_or_else(|| mismatched_object::<T>(&value, vm)),
+            _ => Err(mismatched_object::<T>(&value, vm)),
+        }
+    }
+}
+
+impl<'a, T: SlNative> SlFromRefMut<'a, Value> for &'a mut T {
+    fn sl_from_ref_mut(value: Value, vm: &'a mut SloshVm) -> VMResult<Self> {
+        (&value).sl_as_mut(vm)
+    }
+}
+
+impl<'a, T: SlNative> SlAsMut<'a, T> for &Value {
+    fn sl_as_mut(&mut self, vm: &'a mut SloshVm) -> VMResult<&'a mut T> {
+        match self {
+            Value::Object(h) if vm.get_object(*h).is::<T>() => Ok(vm
+                .get_object_mut(*h)?
+                .downcast_mut::<T>()
+                .expect("object type was checked")),
+            _ => Err(mismatched_object::<T>(self, vm)),
+        }
+    }
+}
@@ -22,15 +22,15 @@
 use bridge_types::Param;
 use bridge_types::PassingStyle;
 use bridge_types::TypeHandle;
+use quote::__private::TokenStream;
 use quote::quote;
 use quote::ToTokens;
-use quote::__private::TokenStream;
 use std::fmt::{Display, Formatter};
 use syn::__private::{Span, TokenStream2};
 use syn::spanned::Spanned;
 use syn::{
     parse, parse_macro_input, AttributeArgs, Error, FnArg, GenericArgument, Generics, Ident, Item,
-    ItemFn, Lit, Meta, NestedMeta, PathArguments, ReturnType, Type, TypeBareFn, TypePath,
+    ItemFn, Lit, Meta, NestedMeta, PathArguments, Receiver, ReturnType, Type, TypeBareFn, TypePath,
     TypeReference, TypeTuple,
 };
 extern crate static_assertions;
@@ -410,7 +410,8 @@ fn make_orig_fn_call(
     // this means all returned rust native types must implement TryIntoExpression
     // this is nested inside the builtin expression which must always
     // return a VMResult.
-    let skip = usize::from(takes_env);
+    let skip = num_skipped_inputs(original_item_fn, takes_env);
+    let is_method = get_receiver(original_item_fn).is_some();
     let takes_env = if takes_env {
         quote! {environment, } // environment is the name that is passed in to this function
     } else {
@@ -462,6 +463,10 @@ fn make_orig_fn_call(
                 }}
             }
         }
+    } else if is_method {
+        quote! {
+            this.#original_fn_name(#takes_env #(#arg_names),*)
+        }
     } else {
         quote! {
             #original_fn_name(#takes_env #(#arg_names),*)
@@ -529,11 +534,7 @@ fn generate_inner_fn_signature_to_orig_fn_call(
     original_item_fn: &ItemFn,
     takes_env: bool,
 ) -> MacroResult<Vec<Ident>> {
-    let len = if takes_env {
-        original_item_fn.sig.inputs.len() - 1
-    } else {
-        original_item_fn.sig.inputs.len()
-    };
+    let len = original_item_fn.sig.inputs.len() - num_skipped_inputs(original_item_fn, takes_env);
     let mut arg_names = vec![];
     for i in 0..len {
         let parse_name = "arg_".to_string() + &i.to_string();
@@ -976,7 +977,27 @@ fn embed_params_vec(params: &[Param]) -> TokenStream {
 //    );
 //}
 // ```
+//
+// For a method (takes &self or &mut self) intern_ is an associated function that registers a builtin
+// closure that calls the method on a shared instance:
+// ```
+// pub fn intern_inc(this: std::rc::Rc<std::cell::RefCell<Self>>, env: &mut compile_state::state::SloshVm) {
+//     let fn_name = "counter-inc";
+//     bridge_adapters::add_builtin_closure(
+//         env,
+//         fn_name,
+//         Box::new(move |environment, args| {
+//             let mut this = this.try_borrow_mut().map_err(|_| {
+//                 slvm::VMError::new_vm(format!("{fn_name}: instance is already borrowed"))
+//             })?;
+//             Self::parse_inc(&mut this, environment, args)
+//         }),
+//         " my docs\n",
+//     );
+// }
+// ```
 fn generate_intern_fn(
+    original_item_fn: &ItemFn,
     original_fn_name_str: &str,
     fn_name_ident: &Ident,
     fn_name: &str,
@@ -984,10 +1005,49 @@ fn generate_intern_fn(
 ) -> TokenStream {
     let parse_name = get_parse_fn_name(original_fn_name_str);
     let intern_name = get_intern_fn_name(original_fn_name_str);
-    quote! {
-        fn #intern_name(env: &mut compile_state::state::SloshVm) {
-            let #fn_name_ident = #fn_name;
-            bridge_adapters::add_builtin(env, #fn_name_ident, #parse_name, #doc_comments);
+    if let Some(receiver) = get_receiver(original_item_fn) {
+        let vis = &original_item_fn.vis;
+        // Like call_builtin with a closure, a borrow conflict (a method called while another method
+        // on the same instance is running) is an error not a panic.
+        let (borrow, this) = if receiver.mutability.is_some() {
+            (
+                quote! { let mut this = this.try_borrow_mut() },
+                quote! { &mut this },
+            )
+        } else {
+            (quote! { let this = this.try_borrow() }, quote! { &this })
+        };
+        quote! {
+            #vis fn #intern_name(
+                this: std::rc::Rc<std::cell::RefCell<Self>>,
+                env: &mut compile_state::state::SloshVm,
+            ) {
+                let #fn_name_ident = #fn_name;
+                bridge_adapters::add_builtin_closure(
+                    env,
+                    #fn_name_ident,
+                    Box::new(
+                        move |environment: &mut compile_state::state::SloshVm,
+                              args: &[slvm::Value]| {
+                            #borrow.map_err(|_| {
+                                slvm::VMError::new_vm(format!(
+                                    "{}: instance is already borrowed",
+                                    #fn_name_ident
+                                ))
+                            })?;
+                            Self::#parse_name(#this, environment, args)
+                        },
+                    ),
+                    #doc_comments,
+                );
+            }
+        }
+    } else {
+        quote! {
+            fn #intern_name(env: &mut compile_state::state::SloshVm) {
+                let #fn_name_ident = #fn_name;
+                bridge_adapters::add_builtin(env, #fn_name_ident, #parse_name, #doc_comments);
+            }
         }
     }
 }
@@ -1000,7 +1060,9 @@ fn generate_intern_fn(
 /// passed to the builtin function. To map a vector of ArgType structs to an actual function
 /// call the ExpandVecToArgs trait is used. A sample parse_ function for a function that takes
 /// one argument is shown below.
+#[allow(clippy::too_many_arguments)]
 fn generate_parse_fn(
+    receiver: Option<&Receiver>,
     generics: Option<Generics>,
     original_fn_name_str: &str,
     fn_name_ident: &Ident,
@@ -1013,10 +1075,17 @@ fn generate_parse_fn(
     let arg_vec_literal = embed_params_vec(params);
 
     let const_params_len = get_const_params_len_ident();
+    // A method's parse_ function also takes the instance to call it on.
+    let this = match receiver {
+        Some(receiver) if receiver.mutability.is_some() => quote! { this: &mut Self, },
+        Some(_) => quote! { this: &Self, },
+        None => quote! {},
+    };
     if let Some(generics) = generics {
         let params = generics.params.clone();
         quote! {
             fn #parse_name #generics(
+                #this
                 environment: &#params mut compile_state::state::SloshVm,
                 args: &#params [slvm::Value],
             ) -> slvm::VMResult<slvm::Value> {
@@ -1030,6 +1099,7 @@ fn generate_parse_fn(
     } else {
         quote! {
             fn #parse_name(
+                #this
                 environment: &mut compile_state::state::SloshVm,
                 args: &[slvm::Value],
             ) -> slvm::VMResult<slvm::Value> {
@@ -1090,11 +1160,11 @@ fn generate_builtin_fn(
     )?;
     // initialize the innermost token stream to the code of the original_fn_call
     let mut prev_token_stream = orig_fn_call;
-    let skip = usize::from(takes_env);
+    let skip = num_skipped_inputs(original_item_fn, takes_env);
     let inputs_less_env_len = original_item_fn.sig.inputs.len() - skip;
     if inputs_less_env_len != params.len() {
         let err_str = format!(
-            "sl_sh_fn macro is broken, signature of target function has an arity of {}, but this macro computed its arity as: {} (arity is - 1 if takes_env is true).",
+            "sl_sh_fn macro is broken, signature of target function has an arity of {}, but this macro computed its arity as: {} (arity is - 1 if takes_env is true and - 1 for self).",
             inputs_less_env_len,
             params.len(),
         );
@@ -1431,11 +1501,8 @@ fn parse_src_function_arguments(
     takes_env: bool,
 ) -> MacroResult<Vec<Param>> {
     let mut parsed_args = vec![];
-    let len = if takes_env {
-        original_item_fn.sig.inputs.len() - 1
-    } else {
-        original_item_fn.sig.inputs.len()
-    };
+    let skip = num_skipped_inputs(original_item_fn, takes_env);
+    let len = original_item_fn.sig.inputs.len() - skip;
     let mut arg_names = vec![];
     for i in 0..len {
         let parse_name = "arg_".to_string() + &i.to_string();
@@ -1443,14 +1510,12 @@ fn parse_src_function_arguments(
         arg_names.push(parse_name);
     }
 
-    let skip = usize::from(takes_env);
-
     for (i, fn_arg) in original_item_fn.sig.inputs.iter().enumerate().skip(skip) {
         match fn_arg {
             FnArg::Receiver(_) => {
                 return Err(Error::new(
                     original_item_fn.span(),
-                    "Associated functions that take the self argument are not supported.",
+                    "The self argument must be the first argument.",
                 ))
             }
             FnArg::Typed(ty) => {
@@ -1465,6 +1530,19 @@ fn parse_src_function_arguments(
     Ok(parsed_args)
 }
 
+/// return the self receiver if the function is a method, e.g. `fn inc(&mut self, by: i64)`.
+fn get_receiver(original_item_fn: &ItemFn) -> Option<&Receiver> {
+    match original_item_fn.sig.inputs.first() {
+        Some(FnArg::Receiver(receiver)) => Some(receiver),
+        _ => None,
+    }
+}
+
+/// number of leading inputs of the function that are not lisp arguments (self and the environment).
+fn num_skipped_inputs(original_item_fn: &ItemFn, takes_env: bool) -> usize {
+    usize::from(get_receiver(original_item_fn).is_some()) + usize::from(takes_env)
+}
+
 /// return the function names the macro will create. Given a base name, <base>
 /// return intern_<base> Ident to be used as function name
 fn get_intern_fn_name(original_fn_name: &str) -> Ident {
@@ -1562,6 +1640,17 @@ fn generate_sl_sh_fn(
 ) -> MacroResult<TokenStream> {
     let (fn_name, fn_name_ident, takes_env, inline, generics) =
         parse_attributes(original_item_fn, attr_args)?;
+    let receiver = get_receiver(original_item_fn);
+    if let Some(receiver) = receiver {
+        if receiver.reference.is_none() {
+            return Err(Error::new(
+                receiver.span(),
+                "sl_sh_fn methods must take &self or &mut self.",
+            ));
+        }
+    }
+    // A method body refers to self so it can not be inlined.
+    let inline = inline && receiver.is_none();
     let original_fn_name_str = original_item_fn.sig.ident.to_string();
     let original_fn_name_str = original_fn_name_str.as_str();
 
@@ -1577,19 +1666,18 @@ fn generate_sl_sh_fn(
         inline,
     )?;
 
-    let args_len = if takes_env {
-        original_item_fn.sig.inputs.len() - 1
-    } else {
-        original_item_fn.sig.inputs.len()
-    };
+    let args_len =
+        original_item_fn.sig.inputs.len() - num_skipped_inputs(original_item_fn, takes_env);
     let doc_comments = get_documentation_for_fn(original_item_fn)?;
     let intern_fn = generate_intern_fn(
+        original_item_fn,
         original_fn_name_str,
         &fn_name_ident,
         fn_name.as_str(),
         doc_comments,
     );
     let parse_fn = generate_parse_fn(
+        receiver,
         generics,
         original_fn_name_str,
         &fn_name_ident,
@@ -1610,6 +1698,9 @@ fn generate_sl_sh_fn(
 /// function name to be interned with the "fn_name" attribute. This macro outputs all of the
 /// generated bridge code as well as the original function's code so it can be used
 /// by the generated bridge code.
+///
+/// It can also be applied to a method that takes &self or &mut self inside an impl block, then
+/// `Type::intern_<method>(Rc<RefCell<Type>>, env)` registers a builtin bound to that instance.
 #[proc_macro_attribute]
 pub fn sl_sh_fn(
     attr: proc_macro::TokenStream,
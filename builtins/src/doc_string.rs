@@ -0,0 +1,152 @@
+//! Doc strings (the "doc-string" property of globals) split into their sections and usage lines
+//! built from a function's parameters, shared by the usage builtin, the doc tests and slosh-lsp.
+
+use crate::SloshVm;
+use lazy_static::lazy_static;
+use regex::{Regex, RegexBuilder};
+use slvm::{Chunk, Value};
+use std::sync::Arc;
+
+lazy_static! {
+    /// The doc string format: an optional "Usage: ..." line, the description, "Section: name" and
+    /// optionally "Example:" followed by code (the doc tests run it).
+    pub static ref DOC_REGEX: Regex =
+    //TODO PC optional Usage section OR must be auto generated?
+    // legacy/builtins.rs L#937
+        RegexBuilder::new(r#"(\s*?Usage:(.+?)$\n\n|\s*?)(\S{1}.*)\n\n\s*Section:(.+?)$(\n\n\s*Example:\n(.*)|\s*)"#)
+            .multi_line(true)
+            .dot_matches_new_line(true)
+            .crlf(true)
+            .build()
+            .unwrap();
+}
+
+/// The sections of a doc string.
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub struct DocString {
+    /// Text after "Usage:", trimmed.
+    pub usage: Option<String>,
+    pub description: String,
+    pub section: String,
+    pub example: Option<String>,
+}
+
+impl DocString {
+    /// Split raw into its sections, None if it does not follow the format (see [`DOC_REGEX`]).
+    pub fn parse(raw: &str) -> Option<Self> {
+        let cap = DOC_REGEX.captures(raw)?;
+        Some(Self {
+            usage: cap.get(2).map(|x| x.as_str().trim().to_string()),
+            description: cap.get(3)?.as_str().to_string(),
+            section: cap.get(4)?.as_str().trim().to_string(),
+            example: cap.get(6).map(|x| x.as_str().trim().to_string()),
+        })
+    }
+}
+
+/// The raw doc string of the global in slot, if it has one.
+pub fn global_doc_string(vm: &mut SloshVm, slot: u32) -> Option<String> {
+    let docstring_key = vm.intern_static("doc-string");
+    vm.get_global_property(slot, docstring_key)
+        .and_then(|x| match x {
+            Value::String(h) => Some(vm.get_string(h).to_string()),
+            Value::StringConst(i) => Some(vm.get_interned(i).to_string()),
+            _ => None,
+        })
+}
+
+/// The parameter names of a function, in order.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Params {
+    pub required: Vec<&'static str>,
+    /// Parameters after %.
+    pub optional: Vec<&'static str>,
+    /// The parameter after & (or &key for key arguments).
+    pub rest: Option<&'static str>,
+}
+
+impl Params {
+    /// The parameters of chunk, None if it was compiled without their names.
+    pub fn from_chunk(vm: &SloshVm, chunk: &Chunk) -> Option<Self> {
+        let dbg_args = chunk.dbg_args.as_ref()?;
+        // The rest parameter is counted as optional if it follows % otherwise as required, names
+        // for the locals follow the parameters.
+        let total = (chunk.args + chunk.opt_args) as usize;
+        let names: Vec<&'static str> = dbg_args
+            .iter()
+            .take(total)
+            .map(|i| vm.get_interned(*i))
+            .collect();
+        if names.len() < total {
+            return None;
+        }
+        let mut required_len = chunk.args as usize;
+        if chunk.rest && chunk.opt_args == 0 {
+            required_len = required_len.saturating_sub(1);
+        }
+        let rest_len = usize::from(chunk.rest);
+        Some(Self {
+            required: names[..required_len].to_vec(),
+            optional: names[required_len..total - rest_len].to_vec(),
+            rest: if chunk.rest {
+                names.last().copied()
+            } else {
+                None
+            },
+        })
+    }
+
+    /// Labels for each parameter as they appear in a usage line, "% " marks the first optional one
+    /// and "& " the rest.
+    pub fn labels(&self) -> Vec<String> {
+        let mut labels: Vec<String> = self.required.iter().map(|p| p.to_string()).collect();
+        for (i, p) in self.optional.iter().enumerate() {
+            labels.push(if i == 0 {
+                format!("% {p}")
+            } else {
+                p.to_string()
+            });
+        }
+        if let Some(rest) = self.rest {
+            labels.push(if rest == "&key" {
+                rest.to_string()
+            } else {
+                format!("& {rest}")
+            });
+        }
+        labels
+    }
+
+    /// Usage line for a function called name, "(name a b % c & d)".
+    pub fn usage(&self, name: &str) -> String {
+        let mut usage = format!("({name}");
+        for label in self.labels() {
+            usage.push(' ');
+            usage.push_str(&label);
+        }
+        usage.push(')');
+        usage
+    }
+}
+
+/// The chunk of each arity of the function in slot (one unless it is multi-arity), empty if the
+/// global is not a lambda.
+pub fn global_arities(vm: &SloshVm, slot: u32) -> Vec<Arc<Chunk>> {
+    let lambda = match vm.get_global(slot) {
+        Value::Lambda(h) => vm.get_lambda(h),
+        Value::Closure(h) => vm.get_closure(h).0,
+        _ => return Vec::new(),
+    };
+    lambda.arities.clone().unwrap_or_else(|| vec![lambda])
+}
+
+/// Usage built from the parameters of the function in slot, a line for each arity, empty if the
+/// global is not a lambda.
+pub fn usage(vm: &SloshVm, slot: u32, name: &str) -> String {
+    global_arities(vm, slot)
+        .iter()
+        .filter_map(|l| Params::from_chunk(vm, l))
+        .map(|params| params.usage(name))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
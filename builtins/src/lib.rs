@@ -6,10 +6,62 @@ use slvm::{VMError, VMResult, Value};
 pub mod bridge_macro_tests;
 pub mod collections;
 pub mod conversions;
+pub mod doc_string;
 pub mod io;
+pub mod macros;
 pub mod print;
+pub mod profile;
 pub mod string;
 
+/// What a group of builtins is able to do, used to build restricted (sandboxed) environments.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Capability {
+    /// Pure computation: collections, strings, conversions, macros, properties, coroutines, etc.
+    Core,
+    /// Write to stdout/stderr (pr, prn, epr, eprn, dasm, profile).
+    Print,
+    /// Access the file system (fs-*).
+    FileSystem,
+    /// Load and evaluate code at runtime (load, eval, read-all), these are in the compiler.
+    Eval,
+    /// Run external processes (sh, $sh, etc), these are part of the slosh shell.
+    Process,
+    /// Start threads that run code in their own VM (spawn, pmap, chan-*), these are in the compiler.
+    Threads,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 6] = [
+        Capability::Core,
+        Capability::Print,
+        Capability::FileSystem,
+        Capability::Eval,
+        Capability::Process,
+        Capability::Threads,
+    ];
+}
+
+/// Add the builtins from this crate that need capability.
+/// Eval, Process and Threads builtins are not in this crate so they are added by the compiler and
+/// shell.
+pub fn add_capability_builtins(env: &mut SloshVm, capability: Capability) {
+    match capability {
+        Capability::Core => {
+            collections::setup_collection_builtins(env);
+            string::add_str_builtins(env);
+            add_misc_builtins(env);
+            macros::add_macro_builtins(env);
+            conversions::add_conv_builtins(env);
+        }
+        Capability::Print => {
+            print::add_print_builtins(env);
+            profile::add_profile_builtins(env);
+        }
+        Capability::FileSystem => io::add_io_builtins(env),
+        Capability::Eval | Capability::Process | Capability::Threads => {}
+    }
+}
+
 fn get_globals(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
     if !registers.is_empty() {
         return Err(VMError::new_vm(
@@ -163,6 +215,44 @@ fn remainder(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
     }
 }
 
+fn coroutine(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    if let [lambda] = registers {
+        vm.new_coroutine(*lambda)
+    } else {
+        Err(VMError::new_vm("coroutine: takes one argument (a lambda)"))
+    }
+}
+
+fn resume(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    match registers {
+        [Value::Coroutine(h)] => vm.resume(*h, Value::Nil),
+        [Value::Coroutine(h), arg] => vm.resume(*h, *arg),
+        _ => Err(VMError::new_vm(
+            "resume: takes a coroutine and an optional value",
+        )),
+    }
+}
+
+fn coroutine_copy(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    if let [Value::Coroutine(h)] = registers {
+        vm.copy_coroutine(*h)
+    } else {
+        Err(VMError::new_vm("coroutine-copy: takes one coroutine"))
+    }
+}
+
+fn coroutine_done(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    if let [Value::Coroutine(h)] = registers {
+        if vm.coroutine_done(*h) {
+            Ok(Value::True)
+        } else {
+            Ok(Value::False)
+        }
+    } else {
+        Err(VMError::new_vm("coroutine-done?: takes one coroutine"))
+    }
+}
+
 pub fn add_global_value(env: &mut SloshVm, name: &str, val: Value, doc_string: &str) {
     let si = env.set_named_global(name, val);
     let key = env.intern("doc-string");
@@ -196,6 +286,90 @@ Example:
 (test::assert-error (rem 1))
 (test::assert-error (rem 1 2 3))
 (test::assert-error (rem 1 2.0))
+",
+    );
+    bridge_adapters::add_builtin(
+        env,
+        "coroutine",
+        coroutine,
+        "Usage: (coroutine lambda) -> coroutine
+
+Create a coroutine that runs lambda (called with no arguments) on its own stack.  It does not start
+until resumed, it runs until it yields or lambda returns.
+
+Section: core
+
+Example:
+(def co-test (coroutine (fn () (yield 1) (yield 2) 3)))
+(test::assert-false (coroutine-done? co-test))
+(test::assert-equal 1 (resume co-test))
+(test::assert-equal 2 (resume co-test))
+(test::assert-equal 3 (resume co-test))
+(test::assert-true (coroutine-done? co-test))
+(test::assert-equal :rt (car (get-error (coroutine 1))))
+",
+    );
+    bridge_adapters::add_builtin(
+        env,
+        "resume",
+        resume,
+        "Usage: (resume coroutine value?) -> yielded-value
+
+Run coroutine until it yields or returns, returns the yielded (or returned) value.  The yield the
+coroutine was suspended on returns value (nil if not provided).  Resuming a coroutine that is done
+is an error, an error raised in the coroutine is raised by resume and finishes the coroutine.
+
+Section: core
+
+Example:
+(def resume-test (coroutine (fn () (loop (total) (0) (recur (+ total (yield total)))))))
+(test::assert-equal 0 (resume resume-test))
+(test::assert-equal 5 (resume resume-test 5))
+(test::assert-equal 15 (resume resume-test 10))
+(def resume-test (coroutine (fn () (yield 1) (err :test \"failed\"))))
+(test::assert-equal 1 (resume resume-test))
+(test::assert-equal :test (car (get-error (resume resume-test))))
+(test::assert-true (coroutine-done? resume-test))
+(test::assert-equal '(:rt . \"resume: coroutine is done\") (get-error (resume resume-test)))
+",
+    );
+    bridge_adapters::add_builtin(
+        env,
+        "coroutine-done?",
+        coroutine_done,
+        "Usage: (coroutine-done? coroutine) -> t/nil
+
+True if coroutine has finished (returned or raised an error) and can not be resumed.
+
+Section: core
+
+Example:
+(def done-test (coroutine (fn () (yield 1))))
+(test::assert-false (coroutine-done? done-test))
+(resume done-test)
+(test::assert-false (coroutine-done? done-test))
+(resume done-test)
+(test::assert-true (coroutine-done? done-test))
+",
+    );
+    bridge_adapters::add_builtin(
+        env,
+        "coroutine-copy",
+        coroutine_copy,
+        "Usage: (coroutine-copy coroutine) -> coroutine
+
+Return a copy of a coroutine that is not running.  The copy continues from the same point (a copy
+of its stack) and resuming one does not affect the other.
+
+Section: core
+
+Example:
+(def copy-test (coroutine (fn () (+ 1 (yield 1)))))
+(resume copy-test)
+(def copy-test2 (coroutine-copy copy-test))
+(test::assert-equal 11 (resume copy-test 10))
+(test::assert-equal 21 (resume copy-test2 20))
+(test::assert-true (coroutine-done? copy-test2))
 ",
     );
     bridge_adapters::add_builtin(
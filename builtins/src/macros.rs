@@ -0,0 +1,794 @@
+use crate::print::pretty_form;
+use bridge_adapters::add_builtin;
+use compile_state::state::{SloshVm, SloshVmTrait};
+use slvm::{Interned, VMError, VMResult, Value};
+use std::collections::HashMap;
+
+/// What a pattern variable matched, Many for a variable under an ellipsis.
+#[derive(Clone, Debug)]
+enum Binding {
+    One(Value),
+    Many(Vec<Binding>),
+}
+
+type Bindings = HashMap<Interned, Binding>;
+
+/// The symbols that have a meaning to syntax-rules itself.
+struct Syntax {
+    literals: Vec<Interned>,
+    ellipsis: Interned,
+    underscore: Interned,
+}
+
+impl Syntax {
+    fn is_ellipsis(&self, val: Value) -> bool {
+        matches!(val, Value::Symbol(i) if i == self.ellipsis)
+    }
+
+    fn is_var(&self, i: Interned) -> bool {
+        i != self.ellipsis && i != self.underscore && !self.literals.contains(&i)
+    }
+}
+
+fn list_items(vm: &SloshVm, val: Value) -> Option<Vec<Value>> {
+    match val {
+        Value::Pair(_) | Value::List(_, _) => Some(val.iter(vm).collect()),
+        Value::Nil => Some(Vec::new()),
+        _ => None,
+    }
+}
+
+/// Pattern variables in pat.
+fn pattern_vars(vm: &SloshVm, syntax: &Syntax, pat: Value, vars: &mut Vec<Interned>) {
+    match pat {
+        Value::Symbol(i) if syntax.is_var(i) => vars.push(i),
+        Value::Pair(_) | Value::List(_, _) => {
+            for p in pat.iter(vm) {
+                pattern_vars(vm, syntax, p, vars);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn match_pattern(
+    vm: &SloshVm,
+    syntax: &Syntax,
+    pat: Value,
+    form: Value,
+    binds: &mut Bindings,
+) -> VMResult<bool> {
+    match pat {
+        Value::Symbol(i) if i == syntax.underscore => Ok(true),
+        Value::Symbol(i) if syntax.literals.contains(&i) => {
+            Ok(matches!(form, Value::Symbol(f) if f == i))
+        }
+        Value::Symbol(i) => {
+            binds.insert(i, Binding::One(form));
+            Ok(true)
+        }
+        Value::Pair(_) | Value::List(_, _) | Value::Nil => {
+            if let Some(forms) = list_items(vm, form) {
+                let pats: Vec<Value> = pat.iter(vm).collect();
+                match_seq(vm, syntax, &pats, &forms, binds)
+            } else {
+                Ok(false)
+            }
+        }
+        _ => Ok(vm.is_equal_pair(pat, form)? == Value::True),
+    }
+}
+
+fn match_seq(
+    vm: &SloshVm,
+    syntax: &Syntax,
+    pats: &[Value],
+    forms: &[Value],
+    binds: &mut Bindings,
+) -> VMResult<bool> {
+    let mut fi = 0;
+    let mut pi = 0;
+    while pi < pats.len() {
+        if pi + 1 < pats.len() && syntax.is_ellipsis(pats[pi + 1]) {
+            // Match as many forms as possible while leaving enough for the patterns after.
+            let after = pats.len() - (pi + 2);
+            if forms.len() < fi + after {
+                return Ok(false);
+            }
+            let n = forms.len() - fi - after;
+            let mut matches = Vec::with_capacity(n);
+            for form in &forms[fi..fi + n] {
+                let mut b = Bindings::new();
+                if !match_pattern(vm, syntax, pats[pi], *form, &mut b)? {
+                    return Ok(false);
+                }
+                matches.push(b);
+            }
+            let mut vars = Vec::new();
+            pattern_vars(vm, syntax, pats[pi], &mut vars);
+            for var in vars {
+                let many = matches.iter_mut().filter_map(|b| b.remove(&var)).collect();
+                binds.insert(var, Binding::Many(many));
+            }
+            fi += n;
+            pi += 2;
+        } else {
+            if fi >= forms.len() || !match_pattern(vm, syntax, pats[pi], forms[fi], binds)? {
+                return Ok(false);
+            }
+            fi += 1;
+            pi += 1;
+        }
+    }
+    Ok(fi == forms.len())
+}
+
+/// Add the symbols bound by a binding pattern (a symbol or destructure) to binders.
+fn pattern_binders(vm: &SloshVm, pat: Value, binders: &mut Vec<Interned>) {
+    match pat {
+        Value::Symbol(i) => {
+            let name = vm.get_interned(i);
+            if !matches!(name, "&" | "%" | "&key" | "_" | "...") {
+                binders.push(i);
+            }
+        }
+        Value::Pair(_) | Value::List(_, _) => {
+            let items: Vec<Value> = pat.iter(vm).collect();
+            // Skip the head of a [] or {} destructure (read as (vec ..) or (make-hash ..)).
+            let skip = match items.first() {
+                Some(Value::Symbol(i)) => matches!(vm.get_interned(*i), "vec" | "make-hash"),
+                _ => false,
+            };
+            for item in items.iter().skip(usize::from(skip)) {
+                pattern_binders(vm, *item, binders);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The forms that bind symbols (or quote code) found in a template or form.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FormKind {
+    Quote,
+    BackQuote,
+    Let,
+    LetWhile,
+    Fn,
+    Macro,
+    Loop,
+    Match,
+    Try,
+    Other,
+}
+
+/// Resolves the head of a form to its kind the way the compiler does (a local first, then the
+/// global), so a local, pattern variable or different global with the same name is not mistaken
+/// for a binding form.
+struct Heads {
+    loop_: Interned,
+    catch: Interned,
+}
+
+impl Heads {
+    fn new(vm: &mut SloshVm) -> Self {
+        Self {
+            loop_: vm.intern("loop"),
+            catch: vm.intern("catch"),
+        }
+    }
+
+    fn kind(&self, vm: &SloshVm, locals: &[Interned], head: Value) -> FormKind {
+        let Value::Symbol(i) = head else {
+            return FormKind::Other;
+        };
+        if locals.contains(&i) {
+            return FormKind::Other;
+        }
+        let global = |i| vm.global_intern_slot(i).map(|slot| vm.get_global(slot));
+        let Some(val) = global(i) else {
+            return FormKind::Other;
+        };
+        let specials = vm.specials();
+        match val {
+            Value::Special(s) if s == specials.quote => FormKind::Quote,
+            Value::Special(s) if s == specials.backquote => FormKind::BackQuote,
+            Value::Special(s) if s == specials.let_ => FormKind::Let,
+            Value::Special(s) if s == specials.let_while => FormKind::LetWhile,
+            Value::Special(s) if s == specials.fn_ => FormKind::Fn,
+            Value::Special(s) if s == specials.mac_ => FormKind::Macro,
+            Value::Special(s) if s == specials.match_ => FormKind::Match,
+            Value::Special(s) if s == specials.try_ => FormKind::Try,
+            Value::Lambda(_) | Value::Closure(_) if global(self.loop_) == Some(val) => {
+                FormKind::Loop
+            }
+            _ => FormKind::Other,
+        }
+    }
+}
+
+/// Find the symbols a template binds with let, let-while, loop, fn and try's catch clauses.
+/// locals are the pattern variables and symbols bound by enclosing forms, they hide globals.
+fn template_binders(
+    vm: &SloshVm,
+    heads: &Heads,
+    tmpl: Value,
+    locals: &mut Vec<Interned>,
+    binders: &mut Vec<Interned>,
+) {
+    let Some(items) = list_items(vm, tmpl) else {
+        return;
+    };
+    let Some(head) = items.first() else {
+        return;
+    };
+    let mut bound = Vec::new();
+    match heads.kind(vm, locals, *head) {
+        FormKind::Quote => return,
+        kind @ (FormKind::Let | FormKind::LetWhile) => {
+            let lists = if kind == FormKind::Let { 1 } else { 2 };
+            for binds in items.iter().skip(1).take(lists) {
+                if let Some(binds) = list_items(vm, *binds) {
+                    for pat in binds.iter().step_by(2) {
+                        pattern_binders(vm, *pat, &mut bound);
+                    }
+                }
+            }
+        }
+        FormKind::Fn | FormKind::Loop => {
+            if let Some(params) = items.get(1) {
+                pattern_binders(vm, *params, &mut bound);
+            }
+        }
+        FormKind::Try => {
+            for clause in &items[1..] {
+                if let Some(clause) = list_items(vm, *clause) {
+                    if matches!(clause.first(), Some(Value::Symbol(i)) if *i == heads.catch) {
+                        if let Some(sym) = clause.get(2) {
+                            pattern_binders(vm, *sym, &mut bound);
+                        }
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+    let len = locals.len();
+    binders.extend_from_slice(&bound);
+    locals.extend_from_slice(&bound);
+    for item in items {
+        template_binders(vm, heads, item, locals, binders);
+    }
+    locals.truncate(len);
+}
+
+/// Record the ellipsis depth of each pattern variable in pat (or its first use in a template).
+fn var_depths(
+    vm: &SloshVm,
+    syntax: &Syntax,
+    pat: Value,
+    depth: usize,
+    vars: &HashMap<Interned, usize>,
+    depths: &mut HashMap<Interned, usize>,
+) {
+    match pat {
+        Value::Symbol(i) if syntax.is_var(i) && (vars.is_empty() || vars.contains_key(&i)) => {
+            depths.entry(i).or_insert(depth);
+        }
+        Value::Pair(_) | Value::List(_, _) => {
+            let items: Vec<Value> = pat.iter(vm).collect();
+            for (i, item) in items.iter().enumerate() {
+                let d = match items.get(i + 1) {
+                    Some(next) if syntax.is_ellipsis(*next) => depth + 1,
+                    _ => depth,
+                };
+                var_depths(vm, syntax, *item, d, vars, depths);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Expand tmpl, depths has the remaining ellipsis depth of each pattern variable in binds.
+fn expand_template(
+    vm: &mut SloshVm,
+    syntax: &Syntax,
+    tmpl: Value,
+    binds: &Bindings,
+    depths: &HashMap<Interned, usize>,
+    renames: &HashMap<Interned, Value>,
+) -> VMResult<Value> {
+    match tmpl {
+        Value::Symbol(i) => match binds.get(&i) {
+            Some(Binding::One(val)) => Ok(*val),
+            Some(Binding::Many(_)) => Err(VMError::new_compile(format!(
+                "syntax-rules: pattern variable {} must be followed by ...",
+                vm.get_interned(i)
+            ))),
+            None => Ok(renames.get(&i).copied().unwrap_or(tmpl)),
+        },
+        Value::Pair(_) | Value::List(_, _) => {
+            let items: Vec<Value> = tmpl.iter(vm).collect();
+            let mut res = Vec::with_capacity(items.len());
+            let mut i = 0;
+            while i < items.len() {
+                if i + 1 < items.len() && syntax.is_ellipsis(items[i + 1]) {
+                    // Repeat for the variables that have more depth than their use in items[i].
+                    let mut uses = HashMap::new();
+                    var_depths(vm, syntax, items[i], 0, depths, &mut uses);
+                    let vars: Vec<Interned> = uses
+                        .iter()
+                        .filter(|(var, d)| depths.get(var).is_some_and(|depth| depth > d))
+                        .map(|(var, _)| *var)
+                        .collect();
+                    let mut len = None;
+                    for var in &vars {
+                        if let Some(Binding::Many(many)) = binds.get(var) {
+                            if len.is_some_and(|l| l != many.len()) {
+                                return Err(VMError::new_compile(
+                                    "syntax-rules: pattern variables under ... have different lengths",
+                                ));
+                            }
+                            len = Some(many.len());
+                        }
+                    }
+                    let Some(len) = len else {
+                        return Err(VMError::new_compile(
+                            "syntax-rules: ... must follow a pattern variable matched with ...",
+                        ));
+                    };
+                    let mut inner_depths = depths.clone();
+                    for var in &vars {
+                        inner_depths.insert(*var, depths[var] - 1);
+                    }
+                    for n in 0..len {
+                        let mut inner = binds.clone();
+                        for var in &vars {
+                            if let Some(Binding::Many(many)) = binds.get(var) {
+                                inner.insert(*var, many[n].clone());
+                            }
+                        }
+                        res.push(expand_template(
+                            vm,
+                            syntax,
+                            items[i],
+                            &inner,
+                            &inner_depths,
+                            renames,
+                        )?);
+                    }
+                    i += 2;
+                } else {
+                    res.push(expand_template(
+                        vm, syntax, items[i], binds, depths, renames,
+                    )?);
+                    i += 1;
+                }
+            }
+            if res.is_empty() {
+                Ok(Value::Nil)
+            } else {
+                Ok(vm.alloc_list_ro(res))
+            }
+        }
+        _ => Ok(tmpl),
+    }
+}
+
+fn expand_rules(vm: &mut SloshVm, literals: Value, rules: Value, form: Value) -> VMResult<Value> {
+    let literals = list_items(vm, literals)
+        .ok_or_else(|| VMError::new_compile("syntax-rules: literals must be a list"))?
+        .iter()
+        .map(|l| match l {
+            Value::Symbol(i) => Ok(*i),
+            _ => Err(VMError::new_compile(
+                "syntax-rules: literals must be symbols",
+            )),
+        })
+        .collect::<VMResult<Vec<Interned>>>()?;
+    let syntax = Syntax {
+        literals,
+        ellipsis: vm.intern("..."),
+        underscore: vm.intern("_"),
+    };
+    let forms = list_items(vm, form).unwrap_or_default();
+    let rules: Vec<Value> = rules.iter(vm).collect();
+    for rule in rules {
+        let (pattern, template) = match list_items(vm, rule).as_deref() {
+            Some([pattern, template]) => (*pattern, *template),
+            _ => {
+                return Err(VMError::new_compile(
+                    "syntax-rules: each rule must be (pattern template)",
+                ))
+            }
+        };
+        // The first item of the pattern is the macro's name, skip it.
+        let pats: Vec<Value> = pattern.iter(vm).skip(1).collect();
+        let mut binds = Bindings::new();
+        if match_seq(vm, &syntax, &pats, &forms, &mut binds)? {
+            let heads = Heads::new(vm);
+            let mut locals: Vec<Interned> = binds.keys().copied().collect();
+            let mut binders = Vec::new();
+            template_binders(vm, &heads, template, &mut locals, &mut binders);
+            let mut renames = HashMap::new();
+            for binder in binders {
+                if !binds.contains_key(&binder) && !renames.contains_key(&binder) {
+                    let idx = vm.env_mut().next_gensym();
+                    let name = format!("{}#{idx}", vm.get_interned(binder));
+                    renames.insert(binder, Value::Symbol(vm.intern(&name)));
+                }
+            }
+            let mut depths = HashMap::new();
+            var_depths(vm, &syntax, pattern, 0, &HashMap::new(), &mut depths);
+            return expand_template(vm, &syntax, template, &binds, &depths, &renames);
+        }
+    }
+    Err(VMError::new_compile(format!(
+        "syntax-rules: no rule matches {}",
+        form.display_value(vm)
+    )))
+}
+
+fn syntax_rules_expand(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    if let [literals, rules, form] = registers {
+        // The expansion is built from unrooted values, no collections until it is returned.
+        vm.pause_gc();
+        let res = expand_rules(vm, *literals, *rules, *form);
+        vm.unpause_gc();
+        res
+    } else {
+        Err(VMError::new_compile(
+            "syntax-rules-expand: takes three arguments (literals rules form)",
+        ))
+    }
+}
+
+/// If form is a call of a global macro return the macro.
+fn macro_of(vm: &SloshVm, form: Value) -> Option<Value> {
+    if let Value::Pair(_) | Value::List(_, _) = form {
+        if let Some(Value::Symbol(i)) = form.iter(vm).next() {
+            let slot = vm.global_intern_slot(i)?;
+            let global = vm.get_global(slot);
+            if let Value::Lambda(_) | Value::Closure(_) = global {
+                if matches!(vm.get_heap_property(global, ":macro"), Some(Value::True)) {
+                    return Some(global);
+                }
+            }
+        }
+    }
+    None
+}
+
+fn call_macro(vm: &mut SloshVm, mac: Value, form: Value) -> VMResult<Value> {
+    let args: Vec<Value> = form.iter(vm).skip(1).collect();
+    match mac {
+        Value::Lambda(h) => {
+            let l = vm.get_lambda(h);
+            vm.do_call(l, &args[..], None)
+        }
+        Value::Closure(h) => {
+            let (l, caps) = vm.get_closure(h);
+            let caps = caps.to_vec();
+            vm.do_call(l, &args[..], Some(&caps[..]))
+        }
+        _ => Err(VMError::new_vm("expand-macro: not a macro")),
+    }
+}
+
+/// Expands all the macros in a form, it knows which parts of the special forms are code.
+struct Expander {
+    /// Symbols bound by enclosing forms, these hide any global macro with the same name.
+    locals: Vec<Interned>,
+    heads: Heads,
+}
+
+impl Expander {
+    fn new(vm: &mut SloshVm) -> Self {
+        Self {
+            locals: Vec::new(),
+            heads: Heads::new(vm),
+        }
+    }
+
+    fn expand(&mut self, vm: &mut SloshVm, form: Value) -> VMResult<Value> {
+        let mut form = form;
+        loop {
+            match form.iter(vm).next() {
+                Some(Value::Symbol(i)) if self.locals.contains(&i) => break,
+                _ => {}
+            }
+            if let Some(mac) = macro_of(vm, form) {
+                form = call_macro(vm, mac, form)?;
+            } else {
+                break;
+            }
+        }
+        let items = match list_items(vm, form) {
+            Some(items) if !items.is_empty() => items,
+            _ => return Ok(form),
+        };
+        let kind = self.heads.kind(vm, &self.locals, items[0]);
+        let locals = self.locals.len();
+        let res = match kind {
+            FormKind::Quote => return Ok(form),
+            FormKind::BackQuote => {
+                let mut res = vec![items[0]];
+                for item in &items[1..] {
+                    res.push(self.expand_back_quote(vm, *item)?);
+                }
+                res
+            }
+            FormKind::Let | FormKind::LetWhile => {
+                let lists = if kind == FormKind::Let { 1 } else { 2 };
+                let mut res = vec![items[0]];
+                for binds in items.iter().skip(1).take(lists) {
+                    res.push(self.expand_bindings(vm, *binds)?);
+                }
+                self.expand_rest(vm, &items[1 + lists.min(items.len() - 1)..], &mut res)?;
+                res
+            }
+            FormKind::Fn | FormKind::Macro if is_multi_arity(vm, &items[1..]) => {
+                let mut res = vec![items[0]];
+                for clause in &items[1..] {
+                    let clause: Vec<Value> = clause.iter(vm).collect();
+                    let mut new_clause = Vec::new();
+                    self.bind_pattern(vm, clause[0]);
+                    new_clause.push(clause[0]);
+                    self.expand_rest(vm, &clause[1..], &mut new_clause)?;
+                    self.locals.truncate(locals);
+                    res.push(vm.alloc_list_ro(new_clause));
+                }
+                res
+            }
+            FormKind::Fn | FormKind::Macro | FormKind::Loop if items.len() > 1 => {
+                self.bind_pattern(vm, items[1]);
+                let mut res = vec![items[0], items[1]];
+                self.expand_rest(vm, &items[2..], &mut res)?;
+                res
+            }
+            FormKind::Match if items.len() > 1 => {
+                let mut res = vec![items[0], self.expand(vm, items[1])?];
+                for arm in &items[2..] {
+                    match list_items(vm, *arm) {
+                        Some(arm) if !arm.is_empty() => {
+                            self.bind_pattern(vm, arm[0]);
+                            let mut new_arm = vec![arm[0]];
+                            self.expand_rest(vm, &arm[1..], &mut new_arm)?;
+                            self.locals.truncate(locals);
+                            res.push(vm.alloc_list_ro(new_arm));
+                        }
+                        _ => res.push(*arm),
+                    }
+                }
+                res
+            }
+            _ => {
+                let mut res = Vec::with_capacity(items.len());
+                self.expand_rest(vm, &items, &mut res)?;
+                res
+            }
+        };
+        self.locals.truncate(locals);
+        Ok(vm.alloc_list_ro(res))
+    }
+
+    fn expand_rest(
+        &mut self,
+        vm: &mut SloshVm,
+        items: &[Value],
+        res: &mut Vec<Value>,
+    ) -> VMResult<()> {
+        for item in items {
+            res.push(self.expand(vm, *item)?);
+        }
+        Ok(())
+    }
+
+    fn bind_pattern(&mut self, vm: &SloshVm, pat: Value) {
+        pattern_binders(vm, pat, &mut self.locals);
+    }
+
+    /// Expand the values of a binding list (pattern value ...), the patterns are left alone.
+    fn expand_bindings(&mut self, vm: &mut SloshVm, binds: Value) -> VMResult<Value> {
+        let Some(items) = list_items(vm, binds) else {
+            return Ok(binds);
+        };
+        if items.is_empty() {
+            return Ok(binds);
+        }
+        let mut res = Vec::with_capacity(items.len());
+        for pair in items.chunks(2) {
+            res.push(pair[0]);
+            if let Some(val) = pair.get(1) {
+                res.push(self.expand(vm, *val)?);
+            }
+            self.bind_pattern(vm, pair[0]);
+        }
+        Ok(vm.alloc_list_ro(res))
+    }
+
+    /// Only the unquoted parts of a back-quote template are code.
+    fn expand_back_quote(&mut self, vm: &mut SloshVm, tmpl: Value) -> VMResult<Value> {
+        let items = match list_items(vm, tmpl) {
+            Some(items) if !items.is_empty() => items,
+            _ => return Ok(tmpl),
+        };
+        match items[0] {
+            Value::Symbol(i)
+                if matches!(
+                    vm.get_interned(i),
+                    "unquote" | "unquote-splice" | "unquote-splice!"
+                ) =>
+            {
+                let mut res = vec![items[0]];
+                self.expand_rest(vm, &items[1..], &mut res)?;
+                Ok(vm.alloc_list_ro(res))
+            }
+            _ => {
+                let mut res = Vec::with_capacity(items.len());
+                for item in items {
+                    res.push(self.expand_back_quote(vm, item)?);
+                }
+                Ok(vm.alloc_list_ro(res))
+            }
+        }
+    }
+}
+
+/// True if the forms after fn are arity clauses, ((param*) expr*)+ (see the compiler).
+fn is_multi_arity(vm: &SloshVm, cdr: &[Value]) -> bool {
+    !cdr.is_empty()
+        && cdr.iter().all(|clause| match clause {
+            Value::Pair(_) | Value::List(_, _) => matches!(
+                clause.iter(vm).next(),
+                Some(Value::Nil | Value::Pair(_) | Value::List(_, _))
+            ),
+            _ => false,
+        })
+}
+
+fn expand_macro_all(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    if let [form] = registers {
+        vm.pause_gc();
+        let res = Expander::new(vm).expand(vm, *form);
+        vm.unpause_gc();
+        res
+    } else {
+        Err(VMError::new_vm("expand-macro-all: takes one argument"))
+    }
+}
+
+fn expand_macro_1(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    if let [form] = registers {
+        if let Some(mac) = macro_of(vm, *form) {
+            call_macro(vm, mac, *form)
+        } else {
+            Ok(*form)
+        }
+    } else {
+        Err(VMError::new_vm("expand-macro-1: takes one argument"))
+    }
+}
+
+/// Lines of a diff from old to new, unchanged lines start with two spaces, removed with "- " and
+/// added with "+ ".
+fn diff_lines(old: &[&str], new: &[&str]) -> Vec<String> {
+    // Longest common subsequence table, lcs[i][j] is for old[i..] and new[j..].
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut res = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            res.push(format!("  {}", old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            res.push(format!("- {}", old[i]));
+            i += 1;
+        } else {
+            res.push(format!("+ {}", new[j]));
+            j += 1;
+        }
+    }
+    res
+}
+
+fn expand_macro_diff(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    if let [form] = registers {
+        vm.pause_gc();
+        let expansion = Expander::new(vm).expand(vm, *form);
+        vm.unpause_gc();
+        let expansion = expansion?;
+        let old = pretty_form(vm, *form, DIFF_WIDTH);
+        let new = pretty_form(vm, expansion, DIFF_WIDTH);
+        let old: Vec<&str> = old.lines().collect();
+        let new: Vec<&str> = new.lines().collect();
+        let diff = diff_lines(&old, &new).join("\n");
+        Ok(vm.alloc_string(diff))
+    } else {
+        Err(VMError::new_vm("expand-macro-diff: takes one argument"))
+    }
+}
+
+/// Width the forms are pretty printed to for expand-macro-diff.
+const DIFF_WIDTH: usize = 80;
+
+pub fn add_macro_builtins(env: &mut SloshVm) {
+    add_builtin(
+        env,
+        "syntax-rules-expand",
+        syntax_rules_expand,
+        r#"Usage: (syntax-rules-expand literals rules arguments) -> expansion
+
+Expand a syntax-rules macro called with arguments (a list), see syntax-rules.
+
+Section: core
+
+Example:
+(test::assert-equal '(+ 1 2) (syntax-rules-expand '() '(((_ a b) (+ a b))) '(1 2)))
+"#,
+    );
+    add_builtin(
+        env,
+        "expand-macro-all",
+        expand_macro_all,
+        r#"Usage: (expand-macro-all form) -> expansion
+
+Expand every macro call in form (the form itself and any sub forms) until no macros are left.
+Quoted and back-quoted forms are left alone.  Useful to see what a macro really produces.
+
+Section: core
+
+Example:
+(defmacro expand-all-test (x) `(when ~x :yes))
+(test::assert-equal '(if #t :yes) (expand-macro-all '(expand-all-test #t)))
+(test::assert-equal '(list '(when a b)) (expand-macro-all '(list '(when a b))))
+(test::assert-equal '(fn (when) (when 1)) (expand-macro-all '(fn (when) (when 1))))
+(test::assert-equal '(let (x (if a b)) `(when ~(if c d))) (expand-macro-all '(let (x (when a b)) `(when ~(when c d)))))
+"#,
+    );
+    add_builtin(
+        env,
+        "expand-macro-1",
+        expand_macro_1,
+        r#"Usage: (expand-macro-1 form) -> expansion
+
+Expand form once if it is a macro call, otherwise return it unchanged.  Sub forms and the result
+are not expanded, use it to step through an expansion.
+
+Section: core
+
+Example:
+(defmacro expand-1-test (x) `(when ~x (expand-1-test ~x)))
+(test::assert-equal '(when #t (expand-1-test #t)) (expand-macro-1 '(expand-1-test #t)))
+(test::assert-equal '(if #t (expand-1-test #t)) (expand-macro-1 (expand-macro-1 '(expand-1-test #t))))
+(test::assert-equal '(+ 1 2) (expand-macro-1 '(+ 1 2)))
+"#,
+    );
+    add_builtin(
+        env,
+        "expand-macro-diff",
+        expand_macro_diff,
+        r#"Usage: (expand-macro-diff form) -> string
+
+Pretty print form and its full expansion (see expand-macro-all) and return a line diff of them.
+Unchanged lines start with two spaces, lines only in form with "- " and lines only in the
+expansion with "+ ".  In the REPL use (prn (expand-macro-diff '(...))) to view it.
+
+Section: core
+
+Example:
+(test::assert-equal "- (when a b)\n+ (if a b)" (expand-macro-diff '(when a b)))
+(test::assert-equal "  (+ 1 2)" (expand-macro-diff '(+ 1 2)))
+"#,
+    );
+}
@@ -1,7 +1,7 @@
 use crate::SloshVm;
 use compile_state::state::SloshVmTrait;
 use slvm::{Interned, VMError, VMResult, Value};
-use std::io::{stdout, Write};
+use std::io::{stderr, stdout, Write};
 
 fn is_sym(vm: &SloshVm, name: &str, intern: Interned) -> bool {
     if let Some(i) = vm.get_if_interned(name) {
@@ -99,6 +99,69 @@ pub fn pretty_value(vm: &SloshVm, val: Value) -> String {
     }
 }
 
+fn pretty_form_out(vm: &SloshVm, val: Value, col: usize, width: usize, res: &mut String) {
+    let flat = display_value(vm, val);
+    let items: Option<Vec<Value>> = match val {
+        Value::Pair(_) | Value::List(_, _) if col + flat.len() > width => {
+            // Improper lists are always printed flat.
+            let mut items = Vec::new();
+            let mut cdr = val;
+            while let Some((car, ncdr)) = cdr.get_pair(vm) {
+                items.push(car);
+                cdr = ncdr;
+            }
+            if let Value::Nil = cdr {
+                Some(items)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    };
+    let Some(items) = items else {
+        res.push_str(&flat);
+        return;
+    };
+    let mut prefix = String::new();
+    if items.len() == 2 && quotey(vm, items[0], &mut prefix) {
+        res.push_str(&prefix);
+        pretty_form_out(vm, items[1], col + prefix.len(), width, res);
+        return;
+    }
+    res.push('(');
+    let rest_col = match items[0] {
+        Value::Pair(_) | Value::List(_, _) => {
+            pretty_form_out(vm, items[0], col + 1, width, res);
+            col + 1
+        }
+        head => {
+            // (head first-arg on one line with the rest of the args indented under.
+            let head = display_value(vm, head);
+            res.push_str(&head);
+            if let Some(arg) = items.get(1) {
+                res.push(' ');
+                pretty_form_out(vm, *arg, col + head.len() + 2, width, res);
+            }
+            col + 2
+        }
+    };
+    let skip = if rest_col == col + 1 { 1 } else { 2 };
+    for item in items.iter().skip(skip) {
+        res.push('\n');
+        res.push_str(&" ".repeat(rest_col));
+        pretty_form_out(vm, *item, rest_col, width, res);
+    }
+    res.push(')');
+}
+
+/// Return val printed as code, lists that do not fit in width columns are broken over lines with
+/// their arguments indented.
+pub fn pretty_form(vm: &SloshVm, val: Value, width: usize) -> String {
+    let mut res = String::new();
+    pretty_form_out(vm, val, 0, width, &mut res);
+    res
+}
+
 pub fn pr(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
     for v in registers {
         print!("{}", pretty_value(vm, *v));
@@ -115,6 +178,22 @@ pub fn prn(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
     Ok(Value::Nil)
 }
 
+pub fn epr(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    for v in registers {
+        eprint!("{}", pretty_value(vm, *v));
+    }
+    stderr().flush()?;
+    Ok(Value::Nil)
+}
+
+pub fn eprn(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    for v in registers {
+        eprint!("{}", pretty_value(vm, *v));
+    }
+    eprintln!();
+    Ok(Value::Nil)
+}
+
 pub fn dasm(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
     if registers.len() != 1 {
         return Err(VMError::new_compile(
@@ -139,5 +218,7 @@ pub fn dasm(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
 pub fn add_print_builtins(env: &mut SloshVm) {
     env.set_global_builtin("pr", pr);
     env.set_global_builtin("prn", prn);
+    env.set_global_builtin("epr", epr);
+    env.set_global_builtin("eprn", eprn);
     env.set_global_builtin("dasm", dasm);
 }
@@ -0,0 +1,280 @@
+//! Reports for the VM's sampling profiler (see slvm::Profile) and the profile-thunk builtin.
+
+use crate::SloshVm;
+use compile_state::state::SloshVmTrait;
+use slvm::{Chunk, Profile, ProfileCount, VMError, VMResult, Value};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::sync::Arc;
+
+/// Names for the chunks of global functions, keyed by chunk address.
+fn global_fn_names(vm: &SloshVm) -> HashMap<usize, &'static str> {
+    fn add(names: &mut HashMap<usize, &'static str>, chunk: &Arc<Chunk>, name: &'static str) {
+        names.insert(Arc::as_ptr(chunk) as usize, name);
+        for arity in chunk.arities.iter().flatten() {
+            add(names, arity, name);
+        }
+    }
+    let mut names = HashMap::new();
+    for (interned, slot) in vm.globals() {
+        let name = vm.get_interned(*interned);
+        match vm.get_global(*slot as u32) {
+            Value::Lambda(h) => add(&mut names, &vm.get_lambda(h), name),
+            Value::Closure(h) => add(&mut names, &vm.get_closure(h).0, name),
+            _ => {}
+        }
+    }
+    names
+}
+
+/// A profile with frames resolved to function names and source lines.
+struct NamedProfile {
+    total: ProfileCount,
+    // Function name and file:line of each frame, innermost first.
+    stacks: Vec<(Vec<(String, String)>, ProfileCount)>,
+}
+
+impl NamedProfile {
+    fn new(vm: &SloshVm, profile: &Profile) -> Self {
+        let names = global_fn_names(vm);
+        let stacks = profile
+            .stacks()
+            .map(|(frames, count)| {
+                let frames = frames
+                    .iter()
+                    .map(|frame| {
+                        let chunk = frame.chunk;
+                        let name = match names.get(&(Arc::as_ptr(chunk) as usize)) {
+                            Some(name) => name.to_string(),
+                            None => format!("fn@{}:{}", chunk.file_name, chunk.start_line()),
+                        };
+                        let line = match frame.line {
+                            Some(line) => format!("{}:{line}", chunk.file_name),
+                            None => format!("{}:?", chunk.file_name),
+                        };
+                        (name, line)
+                    })
+                    .collect();
+                (frames, count)
+            })
+            .collect();
+        Self {
+            total: profile.total(),
+            stacks,
+        }
+    }
+
+    fn percent(&self, count: ProfileCount) -> f64 {
+        if self.total.nanos == 0 {
+            0.0
+        } else {
+            count.nanos as f64 * 100.0 / self.total.nanos as f64
+        }
+    }
+}
+
+fn millis(count: ProfileCount) -> f64 {
+    count.nanos as f64 / 1_000_000.0
+}
+
+/// Sort by time, most first (ties by name so the report is stable).
+fn sorted<K: Ord + Clone>(counts: HashMap<K, ProfileCount>) -> Vec<(K, ProfileCount)> {
+    let mut counts: Vec<(K, ProfileCount)> = counts.into_iter().collect();
+    counts.sort_by(|(k1, c1), (k2, c2)| c2.nanos.cmp(&c1.nanos).then_with(|| k1.cmp(k2)));
+    counts
+}
+
+/// Flat profile (self and total time per function and self time per line) and the call graph
+/// (total time of each function split by the functions it calls).
+pub fn profile_report(vm: &SloshVm, profile: &Profile) -> String {
+    let profile = NamedProfile::new(vm, profile);
+    let mut fn_self: HashMap<&str, ProfileCount> = HashMap::new();
+    let mut fn_total: HashMap<&str, ProfileCount> = HashMap::new();
+    let mut line_self: HashMap<(&str, &str), ProfileCount> = HashMap::new();
+    let mut calls: HashMap<&str, HashMap<&str, ProfileCount>> = HashMap::new();
+    for (frames, count) in &profile.stacks {
+        if let Some((name, line)) = frames.first() {
+            fn_self.entry(name).or_default().add(*count);
+            line_self.entry((line, name)).or_default().add(*count);
+        }
+        // Count recursive functions and calls once per sample.
+        let mut seen = HashSet::new();
+        let mut seen_calls = HashSet::new();
+        for (i, (name, _)) in frames.iter().enumerate() {
+            if seen.insert(name) {
+                fn_total.entry(name).or_default().add(*count);
+            }
+            if let Some((caller, _)) = frames.get(i + 1) {
+                if seen_calls.insert((caller, name)) {
+                    calls
+                        .entry(caller)
+                        .or_default()
+                        .entry(name)
+                        .or_default()
+                        .add(*count);
+                }
+            }
+        }
+    }
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "Profile: {} samples, {:.3}ms",
+        profile.total.samples,
+        millis(profile.total)
+    );
+    let _ = writeln!(out, "\nFlat profile by function:");
+    let _ = writeln!(
+        out,
+        "{:>7} {:>11} {:>7} {:>11} {:>8}  function",
+        "self%", "self ms", "total%", "total ms", "samples"
+    );
+    for (name, count) in sorted(fn_self.clone()) {
+        let total = fn_total.get(name).copied().unwrap_or_default();
+        let _ = writeln!(
+            out,
+            "{:>7.2} {:>11.3} {:>7.2} {:>11.3} {:>8}  {name}",
+            profile.percent(count),
+            millis(count),
+            profile.percent(total),
+            millis(total),
+            count.samples
+        );
+    }
+    let _ = writeln!(out, "\nFlat profile by line:");
+    let _ = writeln!(
+        out,
+        "{:>7} {:>11} {:>8}  line",
+        "self%", "self ms", "samples"
+    );
+    for ((line, name), count) in sorted(line_self) {
+        let _ = writeln!(
+            out,
+            "{:>7.2} {:>11.3} {:>8}  {line} ({name})",
+            profile.percent(count),
+            millis(count),
+            count.samples
+        );
+    }
+    let _ = writeln!(
+        out,
+        "\nCall graph (total time of each function and its callees):"
+    );
+    for (name, total) in sorted(fn_total) {
+        let _ = writeln!(
+            out,
+            "{:>7.2}% {:>11.3}ms  {name}",
+            profile.percent(total),
+            millis(total)
+        );
+        if let Some(callees) = calls.remove(name) {
+            for (callee, count) in sorted(callees) {
+                let _ = writeln!(
+                    out,
+                    "{:>7.2}% {:>11.3}ms      -> {callee}",
+                    profile.percent(count),
+                    millis(count)
+                );
+            }
+        }
+    }
+    out
+}
+
+/// Folded stacks, one "outer;...;inner microseconds" line per call stack, the input format of
+/// flamegraph tools (flamegraph.pl, inferno, speedscope).
+pub fn profile_folded(vm: &SloshVm, profile: &Profile) -> String {
+    let profile = NamedProfile::new(vm, profile);
+    let mut folded: HashMap<String, ProfileCount> = HashMap::new();
+    for (frames, count) in &profile.stacks {
+        let stack: Vec<String> = frames
+            .iter()
+            .rev()
+            .map(|(name, _)| name.replace([' ', ';'], "_"))
+            .collect();
+        folded.entry(stack.join(";")).or_default().add(*count);
+    }
+    let mut folded: Vec<(String, ProfileCount)> = folded.into_iter().collect();
+    folded.sort_by(|(s1, _), (s2, _)| s1.cmp(s2));
+    let mut out = String::new();
+    for (stack, count) in folded {
+        let micros = count.nanos / 1000;
+        if micros > 0 {
+            let _ = writeln!(out, "{stack} {micros}");
+        }
+    }
+    out
+}
+
+fn call_thunk(vm: &mut SloshVm, thunk: Value) -> VMResult<Value> {
+    match thunk {
+        Value::Lambda(h) => {
+            let l = vm.get_lambda(h);
+            vm.do_call(l, &[], None)
+        }
+        Value::Closure(h) => {
+            let (l, caps) = vm.get_closure(h);
+            let caps = caps.to_vec();
+            vm.do_call(l, &[], Some(&caps[..]))
+        }
+        Value::Builtin(idx) => vm.call_builtin(idx, &[]),
+        _ => Err(VMError::new_vm(format!(
+            "profile-thunk: expected a function, got a {}",
+            thunk.display_type(vm)
+        ))),
+    }
+}
+
+fn profile_thunk(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    let (thunk, folded) = match registers {
+        [thunk] => (*thunk, false),
+        [thunk, Value::Keyword(i)] if vm.get_interned(*i) == "report" => (*thunk, false),
+        [thunk, Value::Keyword(i)] if vm.get_interned(*i) == "folded" => (*thunk, true),
+        _ => {
+            return Err(VMError::new_vm(
+                "profile-thunk: takes a function and optionally :report or :folded",
+            ))
+        }
+    };
+    // Profiles can nest, the outer one gets the samples of the inner.
+    let outer = vm.take_profile();
+    vm.set_profile(Some(Profile::default()));
+    let res = call_thunk(vm, thunk);
+    let profile = vm.take_profile().unwrap_or_default();
+    if let Some(mut outer) = outer {
+        outer.merge(&profile);
+        vm.set_profile(Some(outer));
+    }
+    if folded {
+        let folded = profile_folded(vm, &profile);
+        res.map(|_| vm.alloc_string(folded))
+    } else {
+        eprint!("{}", profile_report(vm, &profile));
+        res
+    }
+}
+
+pub fn add_profile_builtins(env: &mut SloshVm) {
+    bridge_adapters::add_builtin(
+        env,
+        "profile-thunk",
+        profile_thunk,
+        "Usage: (profile-thunk thunk :report?) -> value, (profile-thunk thunk :folded) -> string
+
+Call thunk (a function with no arguments) while sampling where it spends time.  With :report (the
+default) print a flat profile (by function and by line) and call graph totals to stderr and return
+the value of thunk.  With :folded return the samples as folded stacks (microseconds per call stack)
+for flamegraph tools instead.  See the profile macro.
+
+Section: core
+
+Example:
+(def profile-test-fib (fn (n) (if (< n 2) n (+ (profile-test-fib (- n 1)) (profile-test-fib (- n 2))))))
+(def folded (profile-thunk (fn () (profile-test-fib 18)) :folded))
+(test::assert-true (string? folded))
+(test::assert-true (str-contains folded \"profile-test-fib;profile-test-fib\"))
+(test::assert-error (profile-thunk 1))
+",
+    );
+}
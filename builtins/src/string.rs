@@ -171,7 +171,7 @@ fn str_map_inner(vm: &mut SloshVm, func: Value, string: Value) -> VMResult<Strin
                 let caps: Vec<Handle> = caps.to_vec();
                 vm.do_call(func, &[param], Some(&caps[..]))
             }
-            Value::Builtin(idx) => vm.get_builtin(idx)(vm, &[param]),
+            Value::Builtin(idx) => vm.call_builtin(idx, &[param]),
             _ => Err(VMError::new_vm(
                 "str-map: second arg must be callable".to_string(),
             )),
@@ -0,0 +1,65 @@
+use bridge_macros::sl_sh_fn;
+use compile_state::state::{new_slosh_vm, SloshVmTrait};
+use slvm::{VMResult, Value};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+struct Counter {
+    count: i32,
+}
+
+impl Counter {
+    /// obligatory doc
+    #[sl_sh_fn(fn_name = "counter-inc")]
+    pub fn inc(&mut self, by: i32) -> VMResult<i32> {
+        self.count += by;
+        Ok(self.count)
+    }
+
+    /// obligatory doc
+    #[sl_sh_fn(fn_name = "counter-get")]
+    fn get(&self) -> i32 {
+        self.count
+    }
+}
+
+pub fn main() {
+    let mut vm = new_slosh_vm();
+    let counter = Rc::new(RefCell::new(Counter { count: 0 }));
+    Counter::intern_inc(counter.clone(), &mut vm);
+    Counter::intern_get(counter.clone(), &mut vm);
+
+    let inc = vm.intern("counter-inc");
+    let inc = vm.get_global(vm.global_intern_slot(inc).unwrap());
+    let get = vm.intern("counter-get");
+    let get = vm.get_global(vm.global_intern_slot(get).unwrap());
+    let (Value::Builtin(inc), Value::Builtin(get)) = (inc, get) else {
+        panic!("expected builtins");
+    };
+    assert_eq!(
+        Value::from(2_i32),
+        vm.call_builtin(inc, &[Value::from(2_i32)]).unwrap()
+    );
+    assert_eq!(
+        Value::from(5_i32),
+        vm.call_builtin(inc, &[Value::from(3_i32)]).unwrap()
+    );
+    assert_eq!(Value::from(5_i32), vm.call_builtin(get, &[]).unwrap());
+    assert_eq!(5, counter.borrow().count);
+    assert!(vm.call_builtin(get, &[Value::Nil]).is_err());
+
+    // A method can not run while the instance is borrowed, it is an error not a panic.
+    {
+        let _held = counter.borrow();
+        assert_eq!(Value::from(5_i32), vm.call_builtin(get, &[]).unwrap());
+        assert!(vm.call_builtin(inc, &[Value::from(1_i32)]).is_err());
+    }
+    {
+        let _held = counter.borrow_mut();
+        assert!(vm.call_builtin(get, &[]).is_err());
+    }
+    assert_eq!(
+        Value::from(6_i32),
+        vm.call_builtin(inc, &[Value::from(1_i32)]).unwrap()
+    );
+}
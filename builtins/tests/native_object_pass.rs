@@ -0,0 +1,54 @@
+use bridge_adapters::lisp_adapters::native::SlNative;
+use bridge_macros::sl_sh_fn;
+use compile_state::state::new_slosh_vm;
+use slvm::{NativeObject, VMResult, Value};
+use std::fmt;
+
+struct Account {
+    balance: i32,
+}
+
+impl SlNative for Account {
+    const TYPE_NAME: &'static str = "Account";
+}
+
+impl fmt::Display for Account {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#<Account {}>", self.balance)
+    }
+}
+
+/// obligatory doc
+#[sl_sh_fn(fn_name = "account-balance")]
+fn balance(account: &Account) -> VMResult<i32> {
+    Ok(account.balance)
+}
+
+/// obligatory doc
+#[sl_sh_fn(fn_name = "account-deposit!")]
+fn deposit(account: &mut Account, amount: i32) -> VMResult<i32> {
+    account.balance += amount;
+    Ok(account.balance)
+}
+
+pub fn main() {
+    let mut vm = new_slosh_vm();
+    let account = vm.alloc_object(NativeObject::new_display(
+        Account::TYPE_NAME,
+        Account { balance: 10 },
+    ));
+    assert_eq!(
+        Value::from(15_i32),
+        parse_deposit(&mut vm, &[account, Value::from(5_i32)]).unwrap()
+    );
+    assert_eq!(
+        Value::from(15_i32),
+        parse_balance(&mut vm, &[account]).unwrap()
+    );
+    assert_eq!("#<Account 15>", account.display_value(&vm));
+    assert_eq!("Account", account.display_type(&vm));
+    assert!(parse_balance(&mut vm, &[Value::from(1_i32)]).is_err());
+    let other = vm.alloc_object(NativeObject::new("Other", 1_u8));
+    assert!(parse_balance(&mut vm, &[other]).is_err());
+    assert!(parse_deposit(&mut vm, &[other, Value::from(1_i32)]).is_err());
+}
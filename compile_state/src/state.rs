@@ -191,9 +191,12 @@ pub struct Specials {
     pub str_: Interned,
     pub let_: Interned,
     pub let_while: Interned,
+    pub match_: Interned,
     pub call_cc: Interned,
     pub defer: Interned,
     pub on_error: Interned,
+    pub try_: Interned,
+    pub yield_: Interned,
     pub while_: Interned,
     pub doc_string: Interned,
     pub get: Interned,
@@ -204,6 +207,7 @@ pub struct Specials {
 
     pub rest: Interned,
     pub optional: Interned,
+    pub key_args: Interned,
     pub scratch: Interned,
 }
 
@@ -281,9 +285,13 @@ Example:
 (test::assert-equal "Two" test-do-two)
 (test::assert-equal "Three" test-do-three)
 "#),
-            fn_: add_special(vm, "fn", "Usage: (fn (param*) expr*) -> exprN
+            fn_: add_special(vm, "fn", "Usage: (fn (param*) expr*) -> exprN | (fn ((param*) expr*)+) -> exprN
 
-Create a function (lambda).
+Create a function (lambda).  With a list of ((param*) expr*) clauses creates a multi-arity
+function, a call runs the first clause that accepts the number of arguments.
+Params after &key are key args, passed as :name value pairs after the other args in any order.
+A key arg can have a default with := (nil otherwise) and passing an unknown key is an error.
+&key can not be used with & or % (optional) params.
 
 Section: core
 
@@ -306,7 +314,17 @@ Example:
 (test::assert-equal 12 test-fn1)
 (test::assert-equal 21 test-fn2)
 (test::assert-equal 30 test-fn3)
-(test::assert-equal 63 ((fn (x y z) (set! test-fn1 x)(set! test-fn2 y)(set! test-fn3 z)(+ x y z)) 12 21 30))"),
+(test::assert-equal 63 ((fn (x y z) (set! test-fn1 x)(set! test-fn2 y)(set! test-fn3 z)(+ x y z)) 12 21 30))
+(def test-fn-arity (fn (() 0) ((x) x) ((x y % z) (+ x y (if (nil? z) 0 z)))))
+(test::assert-equal 0 (test-fn-arity))
+(test::assert-equal 5 (test-fn-arity 5))
+(test::assert-equal 11 (test-fn-arity 5 6))
+(test::assert-equal 18 (test-fn-arity 5 6 7))
+(test::assert-error (test-fn-arity 5 6 7 8))
+(def test-fn-keys (fn (cmd &key verbose level := 3) (list cmd verbose level)))
+(test::assert-equal '(\"ls\" nil 3) (test-fn-keys \"ls\"))
+(test::assert-equal '(\"ls\" #t 1) (test-fn-keys \"ls\" :level 1 :verbose #t))
+(test::assert-error (test-fn-keys \"ls\" :quiet #t))"),
             mac_: add_special(vm, "macro", "Usage: (macro (args) `(apply + ,@args))
 
 Define an anonymous macro.
@@ -972,6 +990,68 @@ Example:
     (test::assert-equal (+ idx 2) v2)
     (test::assert-equal (+ idx 3) v3)
     (if (< idx 5) (this-fn (+ idx 1)))))0)"#),
+            match_: add_special(vm, "match", r#"Usage: (match value (pattern :when guard? form*)*) -> result
+
+Match value against each clause's pattern in order and evaluate the form(s) of the first that
+matches (and whose guard, if any, is true) with the pattern's variables bound, returns the last
+form's result (nil if none).  Raises a :match error if no clause matches, give a default clause
+(_ or nil) to avoid that.
+Patterns:
+- _ or nil matches anything, use either for a default clause
+- a symbol matches anything and binds it to the value
+- a literal (number, string, char, keyword, #t, #f or 'quoted, 'nil for nil) matches an equal
+  value
+- [p0 p1 ...] matches a vector or list with the same number of elements matching p0 p1 ...,
+  [p0 ... & rest] matches at least that many with rest matching the remaining elements as a list
+- (list p0 ...) is like [p0 ...] but only matches lists
+- {p0 :key0 p1 :key1 ...} matches a map that has each key with the values matching p0 p1 ...
+- (err key-pattern data-pattern) matches an error's keyword and data
+- (? pred pattern?) matches if (pred value) is true and the value matches pattern (if given)
+Patterns nest, for instance [(? int? x) {name :name} & _].
+
+Unlike the match macro this replaces a symbol pattern binds rather than comparing against the
+symbol's value (use :when (= x var) for that) and no match is an error instead of #f.
+
+Section: conditional
+
+Example:
+(defn match-test (v)
+    (match v
+      (0 "zero")
+      ("str" "the string")
+      ((? string? s) (str "string " s))
+      ((? symbol? s) :when (eq? s 'secret) "hidden")
+      ((? symbol?) v)
+      ([] "empty")
+      ([x] (str "one " x))
+      ([x y] (str "two " x " " y))
+      ([:cmd & args] (str "cmd with " (len args)))
+      ({name :name, age :age} :when (> age 17) (str name " adult"))
+      ({name :name} name)
+      ((err :test msg) (str "error " msg))))
+(test::assert-equal "zero" (match-test 0))
+(test::assert-equal "the string" (match-test "str"))
+(test::assert-equal "string other" (match-test "other"))
+(test::assert-equal "hidden" (match-test 'secret))
+(test::assert-equal 'sym (match-test 'sym))
+(test::assert-equal "empty" (match-test []))
+(test::assert-equal "empty" (match-test '()))
+(test::assert-equal "one 1" (match-test [1]))
+(test::assert-equal "two 1 2" (match-test '(1 2)))
+(test::assert-equal "cmd with 2" (match-test [:cmd 1 2]))
+(test::assert-equal "Ann adult" (match-test {:name "Ann" :age 30}))
+(test::assert-equal "Bob" (match-test {:name "Bob" :age 3}))
+(test::assert-equal "error oops" (match-test (mk-err :test "oops")))
+(test::assert-error (match-test 1.5))
+(test::assert-equal :other (match 1.5 (0 :zero) (_ :other)))
+(test::assert-equal :same (let (y 3) (match 3 (x :when (= x y) :same) (_ :other))))
+(test::assert-equal 4 (let (x 3) (match 4 (x x))))
+(test::assert-equal :default (match 4 (1 :one) (nil :default)))
+(test::assert-equal :nil (match nil (0 :zero) ('nil :nil) (nil :default)))
+(test::assert-equal :yes (match '(1 (2 3)) ((list 1 [_ 3]) :yes) (_ :no)))
+(test::assert-equal :no (match [1 [2 3]] ((list 1 [_ 3]) :yes) (_ :no)))
+(test::assert-equal 'sym (match 'sym ('other 1) ('sym 'sym)))
+"#),
             let_while: add_special(vm, "let-while", r#"Usage: (let-while (initial-bindings) (loop bindings) condition & let-body)
 
 Takes list of initial bindings (done once before loop) of form (binding0 sexp0, binding1 sexp1, ...),
@@ -1005,6 +1085,72 @@ Example:
             call_cc: add_special(vm, "call/cc", ""),
             defer: add_special(vm, "defer", ""),
             on_error: add_special(vm, "on-error", ""),
+            try_: add_special(
+                vm,
+                "try",
+                r#"Usage: (try form* (catch :error-key symbol form*)* (catch _ symbol form*)? (finally form*)?) -> result
+
+Evaluate the forms (like do), if one raises an error the first catch clause with the error's key
+(or _ for any error) is evaluated with symbol bound to the error and its result returned.  An
+error that is not caught goes to the outer handlers (handler-bind, try, get-error) first, before
+anything is unwound so they can still invoke restarts from inside the try, and is raised again
+from the try if there are none.  The finally forms are evaluated on the way out (after the body or
+catch, when a catch raises an error, before an uncaught error is raised again and when a
+continuation or restart jumps out) and their result is ignored.
+
+Section: core
+
+Example:
+(def try-test-cleaned 0)
+(defn try-test (thunk)
+    (try (thunk)
+      (catch :io e (str "io " e))
+      (catch :test e :test-error)
+      (catch _ e :other)
+      (finally (set! try-test-cleaned (+ try-test-cleaned 1)))))
+(test::assert-equal 3 (try-test (fn () (+ 1 2))))
+(test::assert-equal 1 try-test-cleaned)
+(test::assert-equal :test-error (try-test (fn () (err :test "oops"))))
+(test::assert-equal :test-error (try-test (fn () (err (mk-err :test "oops")))))
+(test::assert-equal :other (try-test (fn () (err :rt "oops"))))
+(test::assert-equal 4 try-test-cleaned)
+(test::assert-equal "oops" (try (err :test "oops") (catch :test e (match e ((err _ msg) msg)))))
+(test::assert-equal '(:io . "not caught") (get-error (try (err :io "not caught") (catch :test e :caught))))
+(def try-test-finally nil)
+(test::assert-equal :io (car (get-error (try (err :io "x") (finally (set! try-test-finally :ran))))))
+(test::assert-equal :ran try-test-finally)
+(test::assert-equal :escaped (call/cc (fn (k) (try (k :escaped) (finally (set! try-test-finally :k))))))
+(test::assert-equal :k try-test-finally)
+(test::assert-equal '(:b . "b") (get-error (try (err :a "a") (catch :a e (err :b "b")) (finally (set! try-test-finally :catch)))))
+(test::assert-equal :catch try-test-finally)
+(test::assert-equal 5 (handler-bind ((:oops (fn (e) (invoke-restart 'use-value 5))))
+    (try (restart-case (signal :oops "x") (use-value (v) v))
+      (catch :other e :other)
+      (finally (set! try-test-finally :restart)))))
+(test::assert-equal :restart try-test-finally)
+"#,
+            ),
+            yield_: add_special(
+                vm,
+                "yield",
+                r#"Usage: (yield value?) -> resume-value
+
+Suspend the running coroutine, its resume returns value (nil if not provided).  When the coroutine
+is resumed again the yield returns the value passed to that resume.  It is an error to yield
+outside of a coroutine or from inside a native call (a macro expansion or builtin calling back
+into lisp) made by the coroutine.
+
+Section: core
+
+Example:
+(def yield-test (coroutine (fn () (let (x (yield 1)) (yield (+ x 1)) :done))))
+(test::assert-equal 1 (resume yield-test))
+(test::assert-equal 11 (resume yield-test 10))
+(test::assert-equal :done (resume yield-test))
+(test::assert-true (coroutine-done? yield-test))
+(test::assert-equal '(:rt . "yield: not in a coroutine") (get-error (yield 1)))
+"#,
+            ),
             while_: add_special(vm, "while", ""),
             doc_string: add_special(vm, "doc-string", ""),
             get: add_special(vm, "get", ""),
@@ -1015,6 +1161,7 @@ Example:
 
             rest: vm.intern_static("&"),
             optional: vm.intern_static("%"),
+            key_args: vm.intern_static("&key"),
             scratch: vm.intern_static("[SCRATCH]"),
         }
     }
@@ -7,20 +7,24 @@ use crate::compile::compile_call::{
     compile_call, compile_call_myself, compile_call_reg, compile_callg,
 };
 use crate::compile::compile_cond::{compile_and, compile_if, compile_or, compile_while};
-use crate::compile::compile_fn::compile_fn;
+use crate::compile::compile_fn::{compile_fn, compile_multi_arity_fn, is_multi_arity};
 use crate::compile::compile_let::{compile_let, compile_let_while};
+use crate::compile::compile_match::compile_match;
 use crate::compile::compile_math::compile_math;
 use crate::compile::compile_seq::{compile_cons, compile_vec};
 use crate::compile::compile_store::{compile_def, compile_set};
+use crate::compile::compile_try::compile_try;
 use crate::pass1::pass1;
 
 mod compile_call;
 mod compile_cond;
 pub mod compile_fn;
 mod compile_let;
+mod compile_match;
 mod compile_math;
 mod compile_seq;
 mod compile_store;
+mod compile_try;
 mod destructure;
 mod util;
 
@@ -39,10 +43,14 @@ fn compile_special(
     cdr: &[Value],
     result: usize,
 ) -> VMResult<()> {
+    // Math, cons and vector forms never make a tail call, their arguments are not in tail position.
+    let tail = state.tail;
+    state.tail = false;
     if !(compile_math(env, state, car, cdr, result)?
         || compile_cons(env, state, car, cdr, result)?
         || compile_vec(env, state, car, cdr, result)?)
     {
+        state.tail = tail;
         match car {
             Value::Special(i) if i == env.specials().doc_string => {
                 if cdr.len() == 1 {
@@ -53,14 +61,18 @@ fn compile_special(
                 }
             }
             Value::Special(i) if i == env.specials().fn_ => {
-                if cdr.len() > 1 {
+                if is_multi_arity(env, cdr) {
+                    compile_multi_arity_fn(env, state, cdr, result, false)?
+                } else if cdr.len() > 1 {
                     compile_fn(env, state, cdr[0], &cdr[1..], result, false)?
                 } else {
                     return Err(VMError::new_compile("Malformed fn form."));
                 }
             }
             Value::Special(i) if i == env.specials().mac_ => {
-                if cdr.len() > 1 {
+                if is_multi_arity(env, cdr) {
+                    compile_multi_arity_fn(env, state, cdr, result, true)?
+                } else if cdr.len() > 1 {
                     compile_fn(env, state, cdr[0], &cdr[1..], result, true)?
                 } else {
                     return Err(VMError::new_compile("Malformed macro form."));
@@ -230,6 +242,9 @@ fn compile_special(
             Value::Special(i) if i == env.specials().let_while => {
                 compile_let_while(env, state, cdr, result)?;
             }
+            Value::Special(i) if i == env.specials().match_ => {
+                compile_match(env, state, cdr, result)?;
+            }
             Value::Special(i) if i == env.specials().call_cc => {
                 if cdr.len() != 1 {
                     return Err(VMError::new_compile("Requires one argument."));
@@ -250,6 +265,26 @@ fn compile_special(
                     ));
                 }
             }
+            Value::Special(i) if i == env.specials().try_ => {
+                compile_try(env, state, cdr, result)?;
+            }
+            Value::Special(i) if i == env.specials().yield_ => {
+                if cdr.len() > 1 {
+                    return Err(VMError::new_compile("yield: takes zero or one argument."));
+                }
+                if let Some(val) = cdr.first() {
+                    let tail = state.tail;
+                    state.tail = false;
+                    let res = compile(env, state, *val, result);
+                    state.tail = tail;
+                    res?;
+                } else {
+                    state.chunk.encode1(REGN, result as u16, env.own_line())?;
+                }
+                state
+                    .chunk
+                    .encode2(YIELD, result as u16, result as u16, env.own_line())?;
+            }
             Value::Special(i) if i == env.specials().on_error => {
                 if cdr.len() != 1 {
                     return Err(VMError::new_compile("Requires one argument."));
@@ -391,10 +426,15 @@ fn compile_list(
                 return Err(VMError::new_compile(format!("Symbol {sym} not defined (maybe you need to use 'def {sym}' to pre-declare it).")));
             }
         }
+        // Generated forms can use a special directly so a local can not shadow it.
+        Value::Special(_) => compile_special(env, state, car, cdr, result)?,
         Value::Builtin(builtin) => compile_call(env, state, Value::Builtin(builtin), cdr, result)?,
         Value::Lambda(h) => compile_call(env, state, Value::Lambda(h), cdr, result)?,
         Value::Pair(_) | Value::List(_, _) => {
             let (ncar, ncdr) = car.get_pair(env).expect("Pair/List not a Pair or List?");
+            // The callee is not in tail position, only the call of its result can be.
+            let tail = state.tail;
+            state.tail = false;
             if let Value::List(h, idx) = ncdr {
                 // This unsafe should be fine (it breaks the lifetime away from env) since the
                 // vector that backs a list is read only.
@@ -409,6 +449,7 @@ fn compile_list(
                 let ncdr: Vec<Value> = ncdr.iter(env).collect();
                 compile_list(env, state, ncar, &ncdr[..], result)?;
             }
+            state.tail = tail;
             compile_call_reg(env, state, result as u16, cdr, result)?
         }
         _ => {
@@ -82,21 +82,19 @@ pub(crate) fn compile_call_reg(
 ) -> VMResult<()> {
     let tail = state.tail && state.defers == 0;
     state.tail = false;
-    let b_reg = if tail {
-        let b_reg = result + cdr.len() + 2;
+    compile_params(env, state, cdr, result + 1, false)?;
+    let line = env.own_line();
+    if tail {
+        // Copy the callable above the params once they are compiled (compiling them can use the
+        // registers after result), the BMOV may overwrite reg.
+        let b_reg = result + cdr.len() + 1;
         if b_reg > state.max_regs {
             state.max_regs = b_reg;
         }
+        state.chunk.encode2(MOV, b_reg as u16, reg, line)?;
         state
             .chunk
-            .encode2(MOV, b_reg as u16, reg, env.own_line())?;
-        b_reg
-    } else {
-        0
-    };
-    compile_params(env, state, cdr, result + 1, tail)?;
-    let line = env.own_line();
-    if tail {
+            .encode3(BMOV, 1, (result + 1) as u16, cdr.len() as u16, line)?;
         state
             .chunk
             .encode2(TCALL, b_reg as u16, cdr.len() as u16, line)?;
@@ -3,7 +3,7 @@ use crate::compile::util::get_args_iter;
 use crate::pass1::pass1;
 use crate::{compile, CompileState, SloshVm};
 use compile_state::state::SloshVmTrait;
-use slvm::{VMError, VMResult, Value, CLOSE, CONST, JMPNU, MOV, SRET};
+use slvm::{Chunk, Interned, VMError, VMResult, Value, CLOSE, CONST, JMPNU, MOV, SRET};
 use std::sync::Arc;
 
 pub fn mk_state(
@@ -17,6 +17,8 @@ pub fn mk_state(
     let args_iter: Vec<Value> = get_args_iter(env, args, "fn")?.collect();
     let mut opt = false;
     let mut rest = false;
+    let mut keys = false;
+    let mut key_params: Vec<(Interned, Value)> = Vec::new();
     let mut opt_comps = Vec::new();
     let mut destructures = Vec::new();
     let mut next_is_opt = false;
@@ -24,13 +26,49 @@ pub fn mk_state(
     let mut total_args = 0_usize;
     for a in args_iter {
         if next_is_opt {
-            opt_comps.pop();
-            opt_comps.push(a);
+            if keys {
+                if let Some((_, default)) = key_params.last_mut() {
+                    *default = a;
+                }
+            } else {
+                opt_comps.pop();
+                opt_comps.push(a);
+            }
             next_is_opt = false;
             continue;
         }
         let a = resolve_destruct_containers(env, a);
         match a {
+            Value::Symbol(i) if i == env.specials().key_args => {
+                if rest {
+                    return Err(VMError::new_compile(
+                        "invalid args, &key can not be used with & or more than once",
+                    ));
+                }
+                // A keyword could fill an optional before the keys are seen, so do not mix them.
+                if opt {
+                    return Err(VMError::new_compile(
+                        "invalid args, &key can not be used with % (optional)",
+                    ));
+                }
+                // The key/value pairs are collected like a rest arg then destructured.
+                rest = true;
+                keys = true;
+                new_state.symbols.borrow_mut().reserve_reg();
+                if let Some(dbg_args) = new_state.chunk.dbg_args.as_mut() {
+                    dbg_args.push(i);
+                }
+                new_state.chunk.args += 1;
+                total_args += 1;
+            }
+            Value::Symbol(i) if keys => {
+                if i == env.specials().rest || i == env.specials().optional {
+                    return Err(VMError::new_compile(
+                        "invalid args, & and % can not follow &key",
+                    ));
+                }
+                key_params.push((i, Value::Nil));
+            }
             Value::Symbol(i) => {
                 if i == env.specials().rest {
                     rest = true;
@@ -51,7 +89,12 @@ pub fn mk_state(
                 }
             }
             Value::Keyword(i) if i == env.specials().numeq => {
-                if !opt {
+                if keys && key_params.is_empty() {
+                    return Err(VMError::new_compile(
+                        "invalid args, := must follow a key arg after &key",
+                    ));
+                }
+                if !opt && !keys {
                     return Err(VMError::new_compile(
                         "invalid args, := must come after % (optional)",
                     ));
@@ -89,21 +132,44 @@ pub fn mk_state(
                 total_args += 1;
                 destructures.push(DestructType::Map(handle, total_args));
             }
+            _ if keys => {
+                return Err(VMError::new_compile(
+                    "invalid args, key args after &key must be symbols",
+                ))
+            }
             _ => return Err(VMError::new_compile("invalid args, must be symbols")),
         }
     }
+    if keys {
+        destructures.push(DestructType::Keys(key_params, total_args));
+    }
     new_state.chunk.rest = rest;
     Ok((new_state, opt_comps, destructures))
 }
 
-pub(crate) fn compile_fn(
+/// True if the fn forms (after fn) are arity clauses, ((param*) expr*)+, vs (param*) expr*.
+/// A param list can not contain a list so a clause is a list whose first element is a list.
+pub(crate) fn is_multi_arity(env: &SloshVm, cdr: &[Value]) -> bool {
+    !cdr.is_empty()
+        && cdr.iter().all(|clause| match clause {
+            Value::Pair(_) | Value::List(_, _) => matches!(
+                clause.iter(env).next(),
+                Some(Value::Nil | Value::Pair(_) | Value::List(_, _))
+            ),
+            _ => false,
+        })
+}
+
+/// Compile the chunk for a function with args and body cdr.
+fn compile_fn_chunk(
     env: &mut SloshVm,
     state: &mut CompileState,
     args: Value,
     cdr: &[Value],
-    result: usize,
-    is_macro: bool,
-) -> VMResult<()> {
+) -> VMResult<Chunk> {
+    if cdr.is_empty() {
+        return Err(VMError::new_compile("Malformed fn form, missing body."));
+    }
     let (mut new_state, opt_comps, destructure_patterns) = mk_state(env, state, args)?;
     for r in cdr.iter() {
         pass1(env, &mut new_state, *r)?;
@@ -140,19 +206,69 @@ pub(crate) fn compile_fn(
     new_state
         .chunk
         .encode1(SRET, reserved as u16, env.own_line())?;
-    let mut closure = false;
     if !new_state.symbols.borrow().captures.borrow().is_empty() {
         let mut caps = Vec::new();
         for (_, _, c) in new_state.symbols.borrow().captures.borrow().iter() {
             caps.push(*c as u32);
         }
         new_state.chunk.captures = Some(caps);
-        closure = true;
     }
     new_state.chunk.input_regs = reserved;
     new_state.chunk.extra_regs = new_state.max_regs - reserved;
+    Ok(new_state.chunk)
+}
+
+pub(crate) fn compile_fn(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    args: Value,
+    cdr: &[Value],
+    result: usize,
+    is_macro: bool,
+) -> VMResult<()> {
+    let chunk = compile_fn_chunk(env, state, args, cdr)?;
+    emit_lambda(env, state, chunk, result, is_macro)
+}
+
+/// Compile a multi-arity function, each clause is ((param*) expr*).  Calls run the first clause
+/// that accepts the number of args.
+pub(crate) fn compile_multi_arity_fn(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    clauses: &[Value],
+    result: usize,
+    is_macro: bool,
+) -> VMResult<()> {
+    let line = env.own_line().unwrap_or(1);
+    let mut chunk = Chunk::new(state.chunk.file_name, line);
+    let mut arities = Vec::new();
+    let mut captures = Vec::new();
+    for clause in clauses {
+        let clause: Vec<Value> = clause.iter(env).collect();
+        let arity = compile_fn_chunk(env, state, clause[0], &clause[1..])?;
+        if let Some(caps) = &arity.captures {
+            captures.extend_from_slice(caps);
+        }
+        arities.push(Arc::new(arity));
+    }
+    if !captures.is_empty() {
+        chunk.captures = Some(captures);
+    }
+    chunk.arities = Some(arities);
+    emit_lambda(env, state, chunk, result, is_macro)
+}
+
+/// Put the lambda for chunk in the result register, as a closure if it captures.
+fn emit_lambda(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    chunk: Chunk,
+    result: usize,
+    is_macro: bool,
+) -> VMResult<()> {
+    let closure = chunk.captures.is_some();
     env.pause_gc();
-    let lambda = env.alloc_lambda(Arc::new(new_state.chunk));
+    let lambda = env.alloc_lambda(Arc::new(chunk));
     env.unpause_gc();
     if is_macro {
         // Unwrap safe since we just allocated lambda on the heap.
@@ -0,0 +1,473 @@
+use compile_state::state::{CompileState, Symbols};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use slvm::opcodes::*;
+use slvm::{VMError, VMResult, Value};
+
+use crate::compile::destructure::setup_dbg;
+use crate::{compile, mkconst, SloshVm, SloshVmTrait};
+
+/// A pattern destructured into its parts.
+enum Pattern {
+    Wildcard,
+    Bind(slvm::Interned),
+    Literal(Value),
+    /// Elements and optional rest pattern, list_only does not match vectors.
+    Seq(Vec<Value>, Option<Value>, bool),
+    /// Pattern and key pairs.
+    Map(Vec<(Value, Value)>),
+    /// Key and data patterns.
+    Error(Value, Value),
+    /// Predicate and the pattern for the value if it passes.
+    Pred(Value, Value),
+}
+
+fn list_items(env: &SloshVm, val: Value) -> Vec<Value> {
+    val.iter(env).collect()
+}
+
+fn seq_pattern(env: &SloshVm, items: &[Value], list_only: bool) -> VMResult<Pattern> {
+    let mut elements = Vec::new();
+    let mut items = items.iter();
+    while let Some(item) = items.next() {
+        if matches!(item, Value::Symbol(i) if *i == env.specials().rest) {
+            let rest = items
+                .next()
+                .ok_or_else(|| VMError::new_compile("match: & requires a pattern"))?;
+            if items.next().is_some() {
+                return Err(VMError::new_compile("match: only one pattern can follow &"));
+            }
+            return Ok(Pattern::Seq(elements, Some(*rest), list_only));
+        }
+        elements.push(*item);
+    }
+    Ok(Pattern::Seq(elements, None, list_only))
+}
+
+fn map_pattern(env: &SloshVm, pairs: Vec<(Value, Value)>) -> VMResult<Pattern> {
+    let or_i = env.get_if_interned("or");
+    if pairs
+        .iter()
+        .any(|(k, _)| matches!(k, Value::Keyword(i) if Some(*i) == or_i))
+    {
+        return Err(VMError::new_compile(
+            "match: :or is not supported in map patterns",
+        ));
+    }
+    Ok(Pattern::Map(pairs))
+}
+
+fn parse_pattern(env: &mut SloshVm, pat: Value) -> VMResult<Pattern> {
+    Ok(match pat {
+        Value::Symbol(i) if env.get_interned(i) == "_" => Pattern::Wildcard,
+        // nil is the default clause (as in the original match macro), use 'nil to match nil.
+        Value::Nil => Pattern::Wildcard,
+        Value::Symbol(i) => Pattern::Bind(i),
+        Value::Vector(h) => seq_pattern(env, env.get_vector(h), false)?,
+        Value::Map(h) => {
+            let pairs = env.get_map(h).iter().map(|(k, v)| (*k, *v)).collect();
+            map_pattern(env, pairs)?
+        }
+        Value::Pair(_) | Value::List(_, _) => {
+            let items = list_items(env, pat);
+            let list_i = env.intern("list");
+            let pred_i = env.intern("?");
+            match items[0] {
+                Value::Symbol(i) if i == env.specials().vec => {
+                    seq_pattern(env, &items[1..], false)?
+                }
+                Value::Symbol(i) if i == list_i => seq_pattern(env, &items[1..], true)?,
+                Value::Symbol(i) if i == env.specials().make_hash => {
+                    let chunks = items[1..].chunks_exact(2);
+                    if !chunks.remainder().is_empty() {
+                        return Err(VMError::new_compile(
+                            "match: map pattern requires pattern key pairs",
+                        ));
+                    }
+                    let pairs = chunks.map(|p| (p[0], p[1])).collect();
+                    map_pattern(env, pairs)?
+                }
+                Value::Symbol(i) if i == env.specials().quote && items.len() == 2 => {
+                    Pattern::Literal(items[1])
+                }
+                Value::Symbol(i) if i == env.specials().err && items.len() == 3 => {
+                    Pattern::Error(items[1], items[2])
+                }
+                Value::Symbol(i) if i == pred_i && (items.len() == 2 || items.len() == 3) => {
+                    let sym_ = Value::Symbol(env.intern("_"));
+                    Pattern::Pred(items[1], items.get(2).copied().unwrap_or(sym_))
+                }
+                _ => {
+                    return Err(VMError::new_compile(format!(
+                        "match: invalid pattern {}",
+                        pat.display_value(env)
+                    )))
+                }
+            }
+        }
+        Value::True
+        | Value::False
+        | Value::Byte(_)
+        | Value::Int(_)
+        | Value::Float(_)
+        | Value::CodePoint(_)
+        | Value::CharCluster(_, _)
+        | Value::CharClusterLong(_)
+        | Value::Keyword(_)
+        | Value::StringConst(_)
+        | Value::String(_) => Pattern::Literal(pat),
+        _ => {
+            return Err(VMError::new_compile(format!(
+                "match: invalid pattern {}",
+                pat.display_value(env)
+            )))
+        }
+    })
+}
+
+/// A scratch register above any bindings, temps are only used within one test.
+fn temp_reg(state: &mut CompileState, num: usize) -> usize {
+    let reg = state.reserved_regs();
+    if state.max_regs < reg + num {
+        state.max_regs = reg + num;
+    }
+    reg
+}
+
+/// Reserve num consecutive registers for destructured values.
+fn reserve_regs(env: &SloshVm, state: &mut CompileState, num: usize) -> usize {
+    let start = state.reserved_regs();
+    for _ in 0..num {
+        let reg = state.symbols.borrow_mut().reserve_reg();
+        setup_dbg(env, state, reg, env.specials().scratch);
+    }
+    start
+}
+
+fn fail_if_false(
+    env: &SloshVm,
+    state: &mut CompileState,
+    reg: usize,
+    fails: &mut Vec<usize>,
+) -> VMResult<()> {
+    let jmp = state.chunk.add_jump(0);
+    state
+        .chunk
+        .encode2(JMPF, reg as u16, jmp as u16, env.own_line())?;
+    fails.push(jmp);
+    Ok(())
+}
+
+/// Fail unless the type of the value in reg is one of types.
+fn check_type(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    reg: usize,
+    types: &[&'static str],
+    fails: &mut Vec<usize>,
+) -> VMResult<()> {
+    let t = temp_reg(state, 3);
+    state
+        .chunk
+        .encode2(TYPE, t as u16, reg as u16, env.own_line())?;
+    let ok = state.chunk.add_jump(0);
+    for type_name in types {
+        let kw = Value::Keyword(env.intern_static(type_name));
+        mkconst(env, state, kw, t + 1)?;
+        state
+            .chunk
+            .encode3(EQ, (t + 2) as u16, t as u16, (t + 1) as u16, env.own_line())?;
+        state
+            .chunk
+            .encode2(JMPT, (t + 2) as u16, ok as u16, env.own_line())?;
+    }
+    let fail = state.chunk.add_jump(0);
+    state.chunk.encode1(JMP, fail as u16, env.own_line())?;
+    fails.push(fail);
+    state.chunk.update_jump(ok, state.chunk.code.len() as u32);
+    Ok(())
+}
+
+/// Fail unless the length of the value in reg is len (or at least len).
+fn check_len(
+    env: &SloshVm,
+    state: &mut CompileState,
+    reg: usize,
+    len: usize,
+    at_least: bool,
+    fails: &mut Vec<usize>,
+) -> VMResult<()> {
+    let t = temp_reg(state, 3);
+    state
+        .chunk
+        .encode2(LEN, t as u16, reg as u16, env.own_line())?;
+    state
+        .chunk
+        .encode2(REGI, (t + 1) as u16, len as u16, env.own_line())?;
+    let op = if at_least { NUMGTE } else { NUMEQ };
+    state
+        .chunk
+        .encode3(op, (t + 2) as u16, t as u16, (t + 1) as u16, env.own_line())?;
+    fail_if_false(env, state, t + 2, fails)
+}
+
+/// Emit the tests and bindings for pat against the value in reg, jumps that fail are added to
+/// fails (to be pointed at the next clause).
+fn compile_pattern(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    pat: Value,
+    reg: usize,
+    fails: &mut Vec<usize>,
+) -> VMResult<()> {
+    match parse_pattern(env, pat)? {
+        Pattern::Wildcard => {}
+        Pattern::Bind(i) => {
+            let bind_reg = state.symbols.borrow_mut().insert(i);
+            if let Some(lets) = &mut state.lets {
+                lets.insert(i, bind_reg);
+            }
+            setup_dbg(env, state, bind_reg, i);
+            state
+                .chunk
+                .encode2(MOV, bind_reg as u16, reg as u16, env.own_line())?;
+        }
+        Pattern::Literal(val) => {
+            let t = temp_reg(state, 3);
+            state
+                .chunk
+                .encode2(MOV, t as u16, reg as u16, env.own_line())?;
+            mkconst(env, state, val, t + 1)?;
+            state.chunk.encode3(
+                EQUAL,
+                (t + 2) as u16,
+                t as u16,
+                (t + 1) as u16,
+                env.own_line(),
+            )?;
+            fail_if_false(env, state, t + 2, fails)?;
+        }
+        Pattern::Seq(elements, rest, list_only) => {
+            if list_only {
+                check_type(env, state, reg, &["Pair", "Nil"], fails)?;
+            } else {
+                check_type(env, state, reg, &["Vector", "Pair", "Nil"], fails)?;
+            }
+            check_len(env, state, reg, elements.len(), rest.is_some(), fails)?;
+            let len = elements.len() + usize::from(rest.is_some());
+            if len > 0 {
+                let start = reserve_regs(env, state, len);
+                let op = if rest.is_some() { LDSCR } else { LDSC };
+                state
+                    .chunk
+                    .encode3(op, start as u16, len as u16, reg as u16, env.own_line())?;
+                for (i, element) in elements.into_iter().chain(rest).enumerate() {
+                    compile_pattern(env, state, element, start + i, fails)?;
+                }
+            }
+        }
+        Pattern::Map(pairs) => {
+            check_type(env, state, reg, &["Map"], fails)?;
+            if !pairs.is_empty() {
+                let start = reserve_regs(env, state, pairs.len());
+                for (i, (_, key)) in pairs.iter().enumerate() {
+                    compile(env, state, *key, start + i)?;
+                }
+                state.chunk.encode3(
+                    MDSC,
+                    start as u16,
+                    pairs.len() as u16,
+                    reg as u16,
+                    env.own_line(),
+                )?;
+                for i in 0..pairs.len() {
+                    // A missing key does not match.
+                    let jmp = state.chunk.add_jump(0);
+                    state
+                        .chunk
+                        .encode2(JMPU, (start + i) as u16, jmp as u16, env.own_line())?;
+                    fails.push(jmp);
+                }
+                for (i, (sub_pat, _)) in pairs.into_iter().enumerate() {
+                    compile_pattern(env, state, sub_pat, start + i, fails)?;
+                }
+            }
+        }
+        Pattern::Error(key_pat, data_pat) => {
+            check_type(env, state, reg, &["Error"], fails)?;
+            let start = reserve_regs(env, state, 2);
+            let key = Value::Keyword(env.intern("key"));
+            let data = Value::Keyword(env.intern("data"));
+            mkconst(env, state, key, start)?;
+            mkconst(env, state, data, start + 1)?;
+            state
+                .chunk
+                .encode3(MDSC, start as u16, 2, reg as u16, env.own_line())?;
+            compile_pattern(env, state, key_pat, start, fails)?;
+            compile_pattern(env, state, data_pat, start + 1, fails)?;
+        }
+        Pattern::Pred(pred, sub_pat) => {
+            let t = temp_reg(state, 3);
+            compile(env, state, pred, t)?;
+            state
+                .chunk
+                .encode2(MOV, (t + 2) as u16, reg as u16, env.own_line())?;
+            state
+                .chunk
+                .encode3(CALL, t as u16, 1, (t + 1) as u16, env.own_line())?;
+            fail_if_false(env, state, t + 1, fails)?;
+            compile_pattern(env, state, sub_pat, reg, fails)?;
+        }
+    }
+    Ok(())
+}
+
+/// Compile one (pattern :when guard? body*) clause, jumps to end when it matched.
+fn compile_clause(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    clause: Value,
+    val_reg: usize,
+    result: usize,
+    end: usize,
+    tail: bool,
+) -> VMResult<()> {
+    let items = match clause {
+        Value::Pair(_) | Value::List(_, _) => list_items(env, clause),
+        _ => {
+            return Err(VMError::new_compile(format!(
+                "match: clause must be a list (pattern body*), got {}",
+                clause.display_value(env)
+            )))
+        }
+    };
+    let when_i = env.intern("when");
+    let (guard, body) = match items.get(1) {
+        Some(Value::Keyword(i)) if *i == when_i => {
+            let guard = items
+                .get(2)
+                .copied()
+                .ok_or_else(|| VMError::new_compile("match: :when requires a guard expression"))?;
+            (Some(guard), &items[3..])
+        }
+        _ => (None, &items[1..]),
+    };
+    let mut fails = Vec::new();
+    compile_pattern(env, state, items[0], val_reg, &mut fails)?;
+    if let Some(guard) = guard {
+        let t = temp_reg(state, 1);
+        compile(env, state, guard, t)?;
+        fail_if_false(env, state, t, &mut fails)?;
+    }
+    let free_reg = temp_reg(state, 1);
+    if body.is_empty() {
+        state.chunk.encode1(REGN, result as u16, env.own_line())?;
+    } else {
+        let last = body.len() - 1;
+        for (i, r) in body.iter().enumerate() {
+            if i == last {
+                state.tail = tail;
+            }
+            compile(env, state, *r, free_reg)?;
+        }
+        state.tail = false;
+        state
+            .chunk
+            .encode2(MOV, result as u16, free_reg as u16, env.own_line())?;
+    }
+    state.chunk.encode1(JMP, end as u16, env.own_line())?;
+    for fail in fails {
+        state.chunk.update_jump(fail, state.chunk.code.len() as u32);
+    }
+    Ok(())
+}
+
+/// True if clause always matches (an unguarded _, nil or symbol pattern).
+fn is_default_clause(env: &mut SloshVm, clause: Value) -> bool {
+    if !matches!(clause, Value::Pair(_) | Value::List(_, _)) {
+        return false;
+    }
+    let items = list_items(env, clause);
+    let when_i = env.intern("when");
+    let guarded = matches!(items.get(1), Some(Value::Keyword(i)) if *i == when_i);
+    !guarded && matches!(items.first(), Some(Value::Nil | Value::Symbol(_)))
+}
+
+fn match_inner(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    cdr: &[Value],
+    result: usize,
+    tail: bool,
+) -> VMResult<()> {
+    let symbols = Rc::new(RefCell::new(Symbols::with_let(state.symbols.clone())));
+    state.symbols = symbols.clone();
+    let mut val_reg = symbols.borrow_mut().reserve_reg();
+    while val_reg <= result {
+        // Make sure we do not step on the result or any other regs in temp use below it.
+        val_reg = symbols.borrow_mut().reserve_reg();
+    }
+    setup_dbg(env, state, val_reg, env.specials().scratch);
+    compile(env, state, cdr[0], val_reg)?;
+    let end = state.chunk.add_jump(0);
+    let mut has_default = false;
+    for clause in &cdr[1..] {
+        has_default = has_default || is_default_clause(env, *clause);
+        // Each clause gets its own scope so bindings from a failed clause do not leak.
+        state.symbols = Rc::new(RefCell::new(Symbols::with_let(symbols.clone())));
+        compile_clause(env, state, *clause, val_reg, result, end, tail)?;
+    }
+    state.symbols = symbols.clone();
+    // Nothing matched, only possible without a default clause.
+    if !has_default {
+        let t = temp_reg(state, 4);
+        let match_kw = Value::Keyword(env.intern("match"));
+        let msg = Value::StringConst(env.intern("no clause matched value: "));
+        mkconst(env, state, match_kw, t)?;
+        mkconst(env, state, msg, t + 1)?;
+        state
+            .chunk
+            .encode2(MOV, (t + 2) as u16, val_reg as u16, env.own_line())?;
+        state.chunk.encode3(
+            STR,
+            (t + 1) as u16,
+            (t + 1) as u16,
+            (t + 2) as u16,
+            env.own_line(),
+        )?;
+        state
+            .chunk
+            .encode2(ERR, t as u16, (t + 1) as u16, env.own_line())?;
+    }
+    state.chunk.update_jump(end, state.chunk.code.len() as u32);
+    state
+        .chunk
+        .encode1(CLRREG, val_reg as u16, env.own_line())?;
+    Ok(())
+}
+
+/// Compile (match value (pattern :when guard? body*)*), runs the body of the first clause whose
+/// pattern matches value (and guard is true) with the pattern's bindings.  Raises a :match error if
+/// no clause matches (there is no default clause).
+pub(crate) fn compile_match(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    cdr: &[Value],
+    result: usize,
+) -> VMResult<()> {
+    if cdr.is_empty() {
+        return Err(VMError::new_compile("match: requires a value and clauses"));
+    }
+    let old_symbols = state.symbols.clone();
+    let old_tail = state.tail;
+    let old_lets = state.lets.take();
+    state.lets = Some(HashMap::new());
+    state.tail = false;
+    let res = match_inner(env, state, cdr, result, old_tail);
+    state.tail = old_tail;
+    state.symbols = old_symbols;
+    state.lets = old_lets;
+    res
+}
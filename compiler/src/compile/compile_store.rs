@@ -2,6 +2,18 @@ use crate::{compile, CompileState, SloshVm};
 use compile_state::state::SloshVmTrait;
 use slvm::*;
 
+/// Record where the global in slot is defined (the def-file and def-line properties) for tools
+/// like slosh-lsp's go to definition.
+fn set_def_location(env: &mut SloshVm, state: &CompileState, slot: u32) {
+    if let (false, Some(line)) = (state.chunk.file_name.is_empty(), env.own_line()) {
+        let file = env.intern_static(state.chunk.file_name);
+        let file_key = env.intern("def-file");
+        env.set_global_property(slot, file_key, Value::StringConst(file));
+        let line_key = env.intern("def-line");
+        env.set_global_property(slot, line_key, line.into());
+    }
+}
+
 pub(crate) fn compile_def(
     env: &mut SloshVm,
     state: &mut CompileState,
@@ -13,6 +25,7 @@ pub(crate) fn compile_def(
         (1, Some(Value::Symbol(si))) => {
             // 'def symbol' predeclares a symbol to be used later, no bytecode.
             let si_const = env.get_reserve_global(*si);
+            set_def_location(env, state, si_const);
             if let Some(doc_string) = state.doc_string {
                 let key = env.intern("doc-string");
                 env.set_global_property(si_const, key, doc_string);
@@ -21,6 +34,7 @@ pub(crate) fn compile_def(
         }
         (2, Some(Value::Symbol(si))) => {
             let si_const = env.get_reserve_global(*si);
+            set_def_location(env, state, si_const);
             if let Some(doc_string) = state.doc_string {
                 let key = env.intern("doc-string");
                 env.set_global_property(si_const, key, doc_string);
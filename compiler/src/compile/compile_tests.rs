@@ -3,7 +3,9 @@ mod tests {
     use builtins::print::{dasm, prn};
     use compile_state::state::new_slosh_vm;
     use compile_state::state::SloshVmTrait;
-    use compiler_test_utils::{assert_vals, exec, read_test};
+    use compiler_test_utils::{
+        assert_vals, exec, exec_compile_error, exec_runtime_error, read_test,
+    };
 
     #[test]
     fn test_def_set() {
@@ -158,6 +160,173 @@ mod tests {
         assert_vals(&env, expected, result);
     }
 
+    #[test]
+    fn test_key_args() {
+        let mut env = new_slosh_vm();
+
+        let result = exec(
+            &mut env,
+            "(do (def fnx (fn (a &key b c := 3) `(~a ~b ~c))) `(~(fnx 1) ~(fnx 1 :c 5 :b 4)))",
+        );
+        let expected = read_test(&mut env, "((1 nil 3) (1 4 5))");
+        assert_vals(&env, expected, result);
+
+        // (fnx 1 :c 6) would fill b with :c, so optionals and keys can not be mixed.
+        exec_compile_error(&mut env, "(fn (a % b := 2 &key c) `(~a ~b ~c))");
+    }
+
+    #[test]
+    fn test_multi_arity_fn() {
+        let mut env = new_slosh_vm();
+
+        let result = exec(
+            &mut env,
+            "(do (def fnx (fn (() 0) ((x) `(1 ~x)) ((x y) `(2 ~x ~y)))) `(~(fnx) ~(fnx 5) ~(fnx 5 6)))",
+        );
+        let expected = read_test(&mut env, "(0 (1 5) (2 5 6))");
+        assert_vals(&env, expected, result);
+
+        let result = exec(
+            &mut env,
+            "(do (def fnx (fn ((x) (fnx x 10)) ((x % y) `(~x ~y)))) `(~(fnx 1) ~(fnx 1 2)))",
+        );
+        let expected = read_test(&mut env, "((1 10) (1 2))");
+        assert_vals(&env, expected, result);
+
+        let result = exec(
+            &mut env,
+            "(do (def fnx nil) ((fn (a b) (def fnx (fn ((x) `(~a ~x)) ((x y) `(~b ~x ~y))))) 1 2) `(~(fnx 3) ~(fnx 3 4)))",
+        );
+        let expected = read_test(&mut env, "((1 3) (2 3 4))");
+        assert_vals(&env, expected, result);
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        let mut env = new_slosh_vm();
+
+        let result = exec(
+            &mut env,
+            "(do (def fnx (fn (g) (+ 1 (g 5)))) (fnx (fn (x) x)))",
+        );
+        let expected = read_test(&mut env, "6");
+        assert_vals(&env, expected, result);
+
+        let result = exec(
+            &mut env,
+            "(do (def fnx (fn (g) (* 2 (g 5)))) (fnx (fn (x) x)))",
+        );
+        let expected = read_test(&mut env, "10");
+        assert_vals(&env, expected, result);
+
+        let result = exec(
+            &mut env,
+            "(do (def mk (fn (a) (fn (b) (list a b)))) (def fnx (fn (a) ((mk a) 2))) (fnx 1))",
+        );
+        let expected = read_test(&mut env, "(1 2)");
+        assert_vals(&env, expected, result);
+
+        let result = exec(
+            &mut env,
+            "(do (def fnx (fn (f) (fn (a) (f (list 'x a))))) ((fnx (fn (l) l)) 3))",
+        );
+        let expected = read_test(&mut env, "(x 3)");
+        assert_vals(&env, expected, result);
+    }
+
+    #[test]
+    fn test_quoted_fn() {
+        let mut env = new_slosh_vm();
+
+        let result = exec(&mut env, "(do (def fnx (fn () '(fn (1) x))) (fnx))");
+        let expected = read_test(&mut env, "(fn (1) x)");
+        assert_vals(&env, expected, result);
+
+        let result = exec(&mut env, "(do (def fnx (fn (a) `(fn (~a 1) x))) (fnx 'y))");
+        let expected = read_test(&mut env, "(fn (y 1) x)");
+        assert_vals(&env, expected, result);
+    }
+
+    #[test]
+    fn test_match() {
+        let mut env = new_slosh_vm();
+
+        let result = exec(
+            &mut env,
+            "(do (def fnx (fn (v) (match v (0 :zero) ('x :x) ([] :empty) ([a] `(:one ~a)) ([a b] :when (= a b) :same) ([a & r] `(:many ~a ~r)) ({n :name} n) (_ :other)))) `(~(fnx 0) ~(fnx 'x) ~(fnx []) ~(fnx '(1)) ~(fnx [2 2]) ~(fnx [1 2 3]) ~(fnx {:name :n}) ~(fnx 1.5)))",
+        );
+        let expected = read_test(
+            &mut env,
+            "(:zero :x :empty (:one 1) :same (:many 1 (2 3)) :n :other)",
+        );
+        assert_vals(&env, expected, result);
+
+        let result = exec(
+            &mut env,
+            "(do (def fnx (fn (v) (match v ((list a [b c]) `(~a ~b ~c)) ([a [b c]] :vec)))) `(~(fnx '(1 (2 3))) ~(fnx [1 [2 3]])))",
+        );
+        let expected = read_test(&mut env, "((1 2 3) :vec)");
+        assert_vals(&env, expected, result);
+
+        exec_runtime_error(&mut env, "(match 1 (0 :zero))");
+        exec_runtime_error(&mut env, "(match 1 (x :when (= x 0) :zero))");
+        let result = exec(
+            &mut env,
+            "`(~(match 1 (0 :zero) (nil :default)) ~(match nil ('nil :nil)))",
+        );
+        let expected = read_test(&mut env, "(:default :nil)");
+        assert_vals(&env, expected, result);
+        exec_compile_error(&mut env, "(match 1 ((1 2) :bad))");
+    }
+
+    #[test]
+    fn test_try() {
+        let mut env = new_slosh_vm();
+
+        let result = exec(
+            &mut env,
+            "(do (def x 0) (def fnx (fn (v) (try (if (= v 0) (err :zero \"zero\") v) (catch :zero e :caught) (finally (set! x (+ x 1)))))) `(~(fnx 1) ~(fnx 0) ~x))",
+        );
+        let expected = read_test(&mut env, "(1 :caught 2)");
+        assert_vals(&env, expected, result);
+
+        let result = exec(
+            &mut env,
+            "(try (try (err :a \"a\") (catch :b e :inner)) (catch _ e :outer))",
+        );
+        let expected = read_test(&mut env, ":outer");
+        assert_vals(&env, expected, result);
+
+        let result = exec(&mut env, "(try)");
+        let expected = read_test(&mut env, "nil");
+        assert_vals(&env, expected, result);
+
+        // The finally runs when a catch raises and when an outer handler takes an uncaught error.
+        let result = exec(
+            &mut env,
+            "(do (def y nil) `(~(call/cc (fn (out) (on-error (fn (k d) (out (cons k d)))) (try (err :a \"a\") (catch :a e (err :b \"b\")) (finally (set! y :catch))))) ~y))",
+        );
+        let expected = read_test(&mut env, "((:b . \"b\") :catch)");
+        assert_vals(&env, expected, result);
+        let result = exec(
+            &mut env,
+            "(do (def y nil) `(~(call/cc (fn (out) (on-error (fn (k d) (out k))) (try (err :a \"a\") (catch :b e :caught) (finally (set! y :outer))))) ~y))",
+        );
+        let expected = read_test(&mut env, "(:a :outer)");
+        assert_vals(&env, expected, result);
+
+        // Locals named like the forms try generates do not change what it does.
+        let result = exec(
+            &mut env,
+            "(let (cons 1 not 2 or 3 eq? 4 match 5 on-error 6) `(~(try (err :a \"a\") (catch :a e (+ cons not))) ~(try (try (err :a \"a\") (catch :b e 0)) (catch :a e (+ or eq?))) ~(try match)))",
+        );
+        let expected = read_test(&mut env, "(3 7 5)");
+        assert_vals(&env, expected, result);
+
+        exec_runtime_error(&mut env, "(try (err :a \"a\") (catch :b e :caught))");
+        exec_compile_error(&mut env, "(try (catch :a e 1) 2)");
+    }
+
     #[test]
     fn test_captures() {
         let mut env = new_slosh_vm();
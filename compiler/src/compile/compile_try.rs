@@ -0,0 +1,349 @@
+use compile_state::state::{CompileState, Symbols};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use slvm::opcodes::*;
+use slvm::{Interned, VMError, VMResult, Value};
+
+use crate::compile::compile_fn::compile_fn;
+use crate::compile::destructure::setup_dbg;
+use crate::{compile, mkconst, SloshVm, SloshVmTrait};
+
+/// A (catch key symbol form*) clause, key None is the catch all _.
+struct Catch {
+    key: Option<Interned>,
+    sym: Interned,
+    body: Vec<Value>,
+}
+
+/// Body forms, catch clauses and finally forms of a try.
+type TryParts = (Vec<Value>, Vec<Catch>, Option<Vec<Value>>);
+
+/// Split the cdr of a try into the body, catch clauses and finally forms.
+fn parse_try(env: &mut SloshVm, cdr: &[Value]) -> VMResult<TryParts> {
+    let catch_i = env.intern("catch");
+    let finally_i = env.intern("finally");
+    let mut body = Vec::new();
+    let mut catches = Vec::new();
+    let mut finally = None;
+    for form in cdr {
+        let items: Vec<Value> = match form {
+            Value::Pair(_) | Value::List(_, _) => form.iter(env).collect(),
+            _ => Vec::new(),
+        };
+        match items.first() {
+            Some(Value::Symbol(i)) if *i == catch_i => {
+                if finally.is_some() {
+                    return Err(VMError::new_compile("try: catch must come before finally"));
+                }
+                let key = match items.get(1) {
+                    Some(Value::Keyword(k)) => Some(*k),
+                    Some(Value::Symbol(s)) if env.get_interned(*s) == "_" => None,
+                    _ => {
+                        return Err(VMError::new_compile(
+                            "try: catch requires an error keyword or _",
+                        ))
+                    }
+                };
+                let sym = match items.get(2) {
+                    Some(Value::Symbol(s)) => *s,
+                    _ => {
+                        return Err(VMError::new_compile(
+                            "try: catch requires a symbol to bind the error to",
+                        ))
+                    }
+                };
+                catches.push(Catch {
+                    key,
+                    sym,
+                    body: items[3..].to_vec(),
+                });
+            }
+            Some(Value::Symbol(i)) if *i == finally_i => {
+                if finally.is_some() {
+                    return Err(VMError::new_compile("try: only one finally allowed"));
+                }
+                finally = Some(items[1..].to_vec());
+            }
+            _ => {
+                if !catches.is_empty() || finally.is_some() {
+                    return Err(VMError::new_compile(
+                        "try: body forms must come before catch and finally",
+                    ));
+                }
+                body.push(*form);
+            }
+        }
+    }
+    Ok((body, catches, finally))
+}
+
+/// Compile the body into a lambda and call it with call/cc, the result is (nil . body-result) or
+/// (key . data) for an error the try handles.  The body runs with an error handler that continues
+/// with (key . data) if catches is None (handle every error) or the error's key is in catches.
+/// Other errors are passed to the handler that was active before, in place (nothing is unwound so
+/// its handlers can still use restarts from the body), or handled if there is none.
+fn compile_protected(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    body: &[Value],
+    catches: Option<&[Interned]>,
+    result: usize,
+) -> VMResult<()> {
+    let sym = |env: &mut SloshVm, name: &'static str| Value::Symbol(env.intern_static(name));
+    let k = sym(env, "[TRY-K]");
+    let old = sym(env, "[TRY-OLD]");
+    let key = sym(env, "[TRY-KEY]");
+    let data = sym(env, "[TRY-DATA]");
+    let err_key = sym(env, "[TRY-ERR-KEY]");
+    // Use the special forms themselves (not their symbols) so the forms mean the same thing
+    // whatever locals the user's code (which these are compiled with) has.
+    let specials = env.specials();
+    let fn_ = Value::Special(specials.fn_);
+    let on_error = Value::Special(specials.on_error);
+    let cons = Value::Special(specials.cons);
+    let do_ = Value::Special(specials.do_);
+    let let_ = Value::Special(specials.let_);
+    let if_ = Value::Special(specials.if_);
+    let or = Value::Special(specials.or);
+    let not = Value::Special(specials.not);
+    let eq = Value::Special(specials.eq);
+    let match_ = Value::Special(specials.match_);
+    // A match pattern, not a call.
+    let err = sym(env, "err");
+    let wildcard = sym(env, "_");
+    // The forms are not rooted anywhere, keep the GC off until they are compiled.
+    env.pause_gc();
+    let handler_args = env.alloc_list_ro(vec![key, data]);
+    let pair = env.alloc_list_ro(vec![cons, key, data]);
+    let continue_k = env.alloc_list_ro(vec![k, pair]);
+    let handler_body = if let Some(catches) = catches {
+        // Catch on the error's own key if data is an error value (raised with the :error key).
+        let err_pat = env.alloc_list_ro(vec![err, err_key, wildcard]);
+        let err_clause = env.alloc_list_ro(vec![err_pat, err_key]);
+        let other_clause = env.alloc_list_ro(vec![wildcard, key]);
+        let get_key = env.alloc_list_ro(vec![match_, data, err_clause, other_clause]);
+        let bind_key = env.alloc_list_ro(vec![err_key, get_key]);
+        let not_old = env.alloc_list_ro(vec![not, old]);
+        let mut tests = vec![or, not_old];
+        for catch in catches {
+            tests.push(env.alloc_list_ro(vec![eq, err_key, Value::Keyword(*catch)]));
+        }
+        let tests = env.alloc_list_ro(tests);
+        let caught = env.alloc_list_ro(vec![let_, bind_key, tests]);
+        let call_old = env.alloc_list_ro(vec![old, key, data]);
+        env.alloc_list_ro(vec![if_, caught, continue_k, call_old])
+    } else {
+        continue_k
+    };
+    let handler = env.alloc_list_ro(vec![fn_, handler_args, handler_body]);
+    let set_handler = env.alloc_list_ro(vec![on_error, handler]);
+    let mut do_body = vec![do_];
+    if body.is_empty() {
+        do_body.push(Value::Nil);
+    }
+    do_body.extend_from_slice(body);
+    let do_body = env.alloc_list_ro(do_body);
+    let ok = env.alloc_list_ro(vec![cons, Value::Nil, do_body]);
+    let clear_handler = env.alloc_list_ro(vec![on_error, Value::Nil]);
+    let bind_old = env.alloc_list_ro(vec![old, clear_handler]);
+    let protected = env.alloc_list_ro(vec![let_, bind_old, set_handler, ok]);
+    let args = env.alloc_list_ro(vec![k]);
+    let res = compile_fn(env, state, args, &[protected], result, false);
+    env.unpause_gc();
+    res?;
+    state
+        .chunk
+        .encode2(CCC, result as u16, result as u16, env.own_line())
+}
+
+fn try_inner(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    cdr: &[Value],
+    result: usize,
+) -> VMResult<()> {
+    let (body, catches, finally) = parse_try(env, cdr)?;
+    if let Some(finally) = &finally {
+        compile_fn(env, state, Value::Nil, finally, result, false)?;
+        state.chunk.encode1(DFR, result as u16, env.own_line())?;
+        state.defers += 1;
+    }
+    let catch_all = catches.iter().any(|catch| catch.key.is_none());
+    let keys: Vec<Interned> = catches.iter().filter_map(|catch| catch.key).collect();
+    let handled = if catch_all { None } else { Some(&keys[..]) };
+    compile_protected(env, state, &body, handled, result)?;
+
+    let symbols = Rc::new(RefCell::new(Symbols::with_let(state.symbols.clone())));
+    state.symbols = symbols.clone();
+    let mut key_reg = symbols.borrow_mut().reserve_reg();
+    while key_reg <= result {
+        key_reg = symbols.borrow_mut().reserve_reg();
+    }
+    setup_dbg(env, state, key_reg, env.specials().scratch);
+    let data_reg = symbols.borrow_mut().reserve_reg();
+    setup_dbg(env, state, data_reg, env.specials().scratch);
+    let err_reg = symbols.borrow_mut().reserve_reg();
+    setup_dbg(env, state, err_reg, env.specials().scratch);
+    let kw_reg = symbols.borrow_mut().reserve_reg();
+    setup_dbg(env, state, kw_reg, env.specials().scratch);
+    let t = symbols.borrow().regs_count();
+    if state.max_regs < t + 2 {
+        state.max_regs = t + 2;
+    }
+
+    state
+        .chunk
+        .encode2(CAR, key_reg as u16, result as u16, env.own_line())?;
+    state
+        .chunk
+        .encode2(CDR, result as u16, result as u16, env.own_line())?;
+    let end = state.chunk.add_jump(0);
+    // A nil key means the body finished without an error, result has its value.
+    state
+        .chunk
+        .encode2(JMPF, key_reg as u16, end as u16, env.own_line())?;
+    state
+        .chunk
+        .encode2(MOV, data_reg as u16, result as u16, env.own_line())?;
+    // Raising an error value reports it with the :error key, catch on the error's own key.
+    let is_err_end = state.chunk.add_jump(0);
+    state
+        .chunk
+        .encode2(ISERR, t as u16, data_reg as u16, env.own_line())?;
+    let mkerr = state.chunk.add_jump(0);
+    state
+        .chunk
+        .encode2(JMPF, t as u16, mkerr as u16, env.own_line())?;
+    state
+        .chunk
+        .encode2(MOV, err_reg as u16, data_reg as u16, env.own_line())?;
+    state
+        .chunk
+        .encode1(JMP, is_err_end as u16, env.own_line())?;
+    state
+        .chunk
+        .update_jump(mkerr, state.chunk.code.len() as u32);
+    state.chunk.encode3(
+        MKERR,
+        err_reg as u16,
+        key_reg as u16,
+        data_reg as u16,
+        env.own_line(),
+    )?;
+    state
+        .chunk
+        .update_jump(is_err_end, state.chunk.code.len() as u32);
+    let key = Value::Keyword(env.intern("key"));
+    mkconst(env, state, key, kw_reg)?;
+    state
+        .chunk
+        .encode3(MDSC, kw_reg as u16, 1, err_reg as u16, env.own_line())?;
+
+    let mut caught_all = false;
+    for catch in catches {
+        let next = if let Some(key) = catch.key {
+            mkconst(env, state, Value::Keyword(key), t)?;
+            state
+                .chunk
+                .encode3(EQ, (t + 1) as u16, kw_reg as u16, t as u16, env.own_line())?;
+            let next = state.chunk.add_jump(0);
+            state
+                .chunk
+                .encode2(JMPF, (t + 1) as u16, next as u16, env.own_line())?;
+            Some(next)
+        } else {
+            None
+        };
+        state.symbols = Rc::new(RefCell::new(Symbols::with_let(symbols.clone())));
+        let sym_reg = state.symbols.borrow_mut().insert(catch.sym);
+        if let Some(lets) = &mut state.lets {
+            lets.insert(catch.sym, sym_reg);
+        }
+        setup_dbg(env, state, sym_reg, catch.sym);
+        state
+            .chunk
+            .encode2(MOV, sym_reg as u16, err_reg as u16, env.own_line())?;
+        let free_reg = state.reserved_regs();
+        if catch.body.is_empty() {
+            state.chunk.encode1(REGN, result as u16, env.own_line())?;
+        } else if finally.is_some() {
+            // Protect the catch body too so the finally runs before an error from it goes on.
+            compile_protected(env, state, &catch.body, None, result)?;
+            state
+                .chunk
+                .encode2(CAR, key_reg as u16, result as u16, env.own_line())?;
+            state
+                .chunk
+                .encode2(CDR, result as u16, result as u16, env.own_line())?;
+            let caught_ok = state.chunk.add_jump(0);
+            state
+                .chunk
+                .encode2(JMPF, key_reg as u16, caught_ok as u16, env.own_line())?;
+            state.chunk.encode0(DFRPOP, env.own_line())?;
+            state
+                .chunk
+                .encode2(ERR, key_reg as u16, result as u16, env.own_line())?;
+            state
+                .chunk
+                .update_jump(caught_ok, state.chunk.code.len() as u32);
+        } else {
+            for r in &catch.body {
+                compile(env, state, *r, free_reg)?;
+            }
+            state
+                .chunk
+                .encode2(MOV, result as u16, free_reg as u16, env.own_line())?;
+        }
+        state.chunk.encode1(JMP, end as u16, env.own_line())?;
+        state.symbols = symbols.clone();
+        if let Some(next) = next {
+            state.chunk.update_jump(next, state.chunk.code.len() as u32);
+        } else {
+            // Nothing after a catch all can match.
+            caught_all = true;
+            break;
+        }
+    }
+    if !caught_all {
+        // Not caught, run the finally now then pass the error on unchanged.
+        if finally.is_some() {
+            state.chunk.encode0(DFRPOP, env.own_line())?;
+        }
+        state
+            .chunk
+            .encode2(ERR, key_reg as u16, data_reg as u16, env.own_line())?;
+    }
+    state.chunk.update_jump(end, state.chunk.code.len() as u32);
+    for reg in [key_reg, data_reg, err_reg] {
+        state.chunk.encode1(CLRREG, reg as u16, env.own_line())?;
+    }
+    if finally.is_some() {
+        state.chunk.encode0(DFRPOP, env.own_line())?;
+        state.defers -= 1;
+    }
+    Ok(())
+}
+
+/// Compile (try body* (catch :key sym form*)* (finally form*)?).  The body runs with an error
+/// handler installed, an error is handled by the first catch with its key (or _).  Other errors go
+/// to the outer handler before anything is unwound, or are raised again from the try if there is
+/// none.  The finally forms run on the way out (via a defer), including when a catch raises.
+pub(crate) fn compile_try(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    cdr: &[Value],
+    result: usize,
+) -> VMResult<()> {
+    let old_symbols = state.symbols.clone();
+    let old_tail = state.tail;
+    let old_lets = state.lets.take();
+    state.lets = Some(HashMap::new());
+    state.tail = false;
+    let res = try_inner(env, state, cdr, result);
+    state.tail = old_tail;
+    state.symbols = old_symbols;
+    state.lets = old_lets;
+    res
+}
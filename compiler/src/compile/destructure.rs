@@ -17,12 +17,15 @@ pub struct Destructure {
     map_keys: Option<Vec<Value>>,
     rest: bool,
     allow_extra: bool,
+    check_keys: bool,
     register_labels: Vec<Register>,
 }
 
 pub enum DestructType {
     Vector(Handle, usize),
     Map(Handle, usize),
+    /// Key args (&key), names with their defaults and the register with the key/value list.
+    Keys(Vec<(Interned, Value)>, usize),
 }
 
 pub struct DestructState {
@@ -99,8 +102,9 @@ impl DestructState {
                 for (i, key) in keys.iter().enumerate() {
                     compile(env, state, *key, destructure.start_reg as usize + i)?;
                 }
+                let op = if destructure.check_keys { KDSC } else { MDSC };
                 state.chunk.encode3(
-                    MDSC,
+                    op,
                     destructure.start_reg,
                     destructure.len,
                     destructure.reg,
@@ -281,6 +285,7 @@ impl DestructState {
             map_keys: None,
             rest,
             allow_extra,
+            check_keys: false,
             register_labels,
         });
         if !opt_comps.is_empty() {
@@ -352,6 +357,7 @@ impl DestructState {
             map_keys: Some(keys),
             rest: false,
             allow_extra: false,
+            check_keys: false,
             register_labels,
         });
         if !opt_comps.is_empty() {
@@ -360,6 +366,38 @@ impl DestructState {
         Ok(())
     }
 
+    fn do_key_destructure(
+        &mut self,
+        keys: Vec<(Interned, Value)>,
+        reg: usize,
+        next_reg: &mut usize,
+    ) {
+        let start_reg = *next_reg;
+        let mut map_keys = Vec::new();
+        let mut opt_comps = Vec::new();
+        let mut register_labels = Vec::new();
+        for (name, default) in keys {
+            register_labels.push(Register::Named(name, *next_reg as u16));
+            map_keys.push(Value::Keyword(name));
+            // Key args are all optional, nil if no default.
+            opt_comps.push((*next_reg, default));
+            *next_reg += 1;
+        }
+        self.destructures.push(Destructure {
+            start_reg: start_reg as u16,
+            len: map_keys.len() as u16,
+            reg: reg as u16,
+            map_keys: Some(map_keys),
+            rest: false,
+            allow_extra: false,
+            check_keys: true,
+            register_labels,
+        });
+        if !opt_comps.is_empty() {
+            self.all_optionals.push(opt_comps);
+        }
+    }
+
     pub fn do_destructure(
         &mut self,
         env: &mut SloshVm,
@@ -381,6 +419,9 @@ impl DestructState {
                     self.do_map_destructure(env, map, reg, &mut stack, &mut next_reg)?;
                     env.heap_unsticky(Value::Map(map));
                 }
+                DestructType::Keys(keys, reg) => {
+                    self.do_key_destructure(keys, reg, &mut next_reg);
+                }
             }
         }
         Ok(())
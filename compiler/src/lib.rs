@@ -1,13 +1,10 @@
 pub mod reader;
 
 use crate::load_eval::add_load_builtins;
+use crate::parallel::add_parallel_builtins;
 pub use crate::reader::*;
-use builtins::add_misc_builtins;
-use builtins::collections::setup_collection_builtins;
-use builtins::conversions::add_conv_builtins;
-use builtins::io::add_io_builtins;
-use builtins::print::add_print_builtins;
-use builtins::string::add_str_builtins;
+use builtins::add_capability_builtins;
+pub use builtins::Capability;
 
 pub use compile_state::state::*;
 use slvm::{INT_BITS, INT_MAX, INT_MIN};
@@ -22,20 +19,32 @@ pub mod compile;
 pub mod pass1;
 
 pub mod load_eval;
+pub mod parallel;
 #[cfg(test)]
 pub mod test_utils;
 
 pub use crate::compile::*;
 
 pub fn set_builtins(env: &mut SloshVm) {
-    setup_collection_builtins(env);
-    add_print_builtins(env);
-    add_load_builtins(env);
-    add_str_builtins(env);
-    add_misc_builtins(env);
-    add_io_builtins(env);
-    add_conv_builtins(env);
+    set_builtins_with(env, &Capability::ALL);
+}
+
+/// Add only the builtins for capabilities, use this to build a restricted VM.
+/// The Process builtins are part of the shell so are not added here.
+pub fn set_builtins_with(env: &mut SloshVm, capabilities: &[Capability]) {
+    for capability in capabilities {
+        add_capability_builtins(env, *capability);
+        match capability {
+            Capability::Eval => add_load_builtins(env),
+            Capability::Threads => add_parallel_builtins(env, capabilities),
+            _ => {}
+        }
+    }
+    add_int_globals(env);
+}
 
+/// Set the globals describing the VM's integers (*int-bits*, *int-max* and *int-min*).
+pub fn add_int_globals(env: &mut SloshVm) {
     env.set_named_global("*int-bits*", (INT_BITS as i64).into());
     env.set_named_global("*int-max*", INT_MAX.into());
     env.set_named_global("*int-min*", INT_MIN.into());
@@ -18,7 +18,7 @@ const fn from_utf8(bytes: &[u8]) -> &str {
 }
 
 //const CORE_LISP: &[u8] = include_bytes!("../lisp/core.slosh");
-const CORE_LISP: &str = from_utf8(include_bytes!("../../lisp/core.slosh"));
+pub const CORE_LISP: &str = from_utf8(include_bytes!("../../lisp/core.slosh"));
 const COLORS_LISP: &str = from_utf8(include_bytes!("../../lisp/sh-color.slosh"));
 pub const SLSHRC: &str = from_utf8(include_bytes!("../../init.slosh"));
 
@@ -105,7 +105,21 @@ pub fn load_internal(vm: &mut SloshVm, name: &'static str) -> VMResult<Value> {
             }
         },
     };
+    load_reader(&mut reader, name)
+}
+
+/// Compile and execute each form from reader in order, returns the value of the last one.
+pub fn load_reader(reader: &mut Reader, name: &'static str) -> VMResult<Value> {
+    // Line numbers only move forward within a file, so start this one at 1 and put the loading
+    // file's line back after.
+    let line_num = reader.vm().line_num();
+    reader.vm().set_line_num(1);
+    let res = load_reader_forms(reader, name);
+    reader.vm().set_line_num(line_num);
+    res
+}
 
+fn load_reader_forms(reader: &mut Reader, name: &'static str) -> VMResult<Value> {
     let mut last = Value::Nil;
     let mut doc_string = None;
     while let Some(exp) = reader.next() {
@@ -274,13 +288,16 @@ fn read_all(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
                 ));
             }
         };
+        // Forms already read are not rooted anywhere until they are in the vector so do not let
+        // the GC run until it is allocated.
+        vm.pause_gc();
         let reader = Reader::from_string(string_as_code, vm, "", 1, 0);
-        let mut vals = vec![];
-        for exp in reader {
-            let exp = exp.map_err(|e| VMError::new("read", e.to_string()))?;
-            vals.push(exp);
-        }
-        Ok(vm.alloc_vector(vals))
+        let vals: Result<Vec<Value>, _> = reader.collect();
+        let res = vals
+            .map(|vals| vm.alloc_vector(vals))
+            .map_err(|e| VMError::new("read", e.to_string()));
+        vm.unpause_gc();
+        res
     } else {
         Err(VMError::new_compile(
             "eval: wrong number of args, expected one",
@@ -293,3 +310,23 @@ pub fn add_load_builtins(env: &mut SloshVm) {
     env.set_global_builtin("eval", eval);
     env.set_global_builtin("read-all", read_all);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use compile_state::state::new_slosh_vm;
+
+    #[test]
+    fn test_read_all_survives_gc() {
+        let mut vm = new_slosh_vm();
+        // Read repeatedly, a varying number of forms, so the heap fills (and collects) at every
+        // point in read-all including allocating the result vector.
+        for i in 0..10_000 {
+            let expected = vec!["(1 2)"; i % 4 + 1];
+            let code = vm.alloc_string(expected.join(" "));
+            let res = read_all(&mut vm, &[code]).unwrap();
+            let forms: Vec<String> = res.iter(&vm).map(|v| v.display_value(&vm)).collect();
+            assert_eq!(forms, expected);
+        }
+    }
+}
@@ -0,0 +1,937 @@
+//! Parallel evaluation, run code on other threads each with its own VM.
+//!
+//! A SloshVm is single threaded so a task runs on a worker thread with its own VM (with the
+//! capabilities of the VM that started it and core.slosh loaded).  Workers are kept for later tasks
+//! and restore their globals after each one (or are dropped if a task changed one in place).  The
+//! function a task runs is passed as a form and compiled in the worker's VM so it can not capture
+//! locals or see globals from the parent, anything it needs is passed as arguments.  A task gets the
+//! parent's remaining fuel, time limit and heap object cap and interrupting the parent while it
+//! waits for a task interrupts the task.  Arguments and results are deep copied between the
+//! heaps.  Channels are the exception, every copy of a channel is the same queue so tasks can use
+//! them to pass values while running.
+
+use crate::load_eval::{load_one_expression, load_reader, CORE_LISP};
+use crate::{set_builtins_with, Capability, Reader};
+use compile_state::state::{new_slosh_vm, SloshVm, SloshVmTrait};
+use slvm::{NativeObject, VMError, VMErrorObj, VMResult, Value};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Same as the default main thread stack, the compiler and VM recurse on deep forms.
+const WORKER_STACK_SIZE: usize = 8 * 1024 * 1024;
+/// How often a blocked join or chan-recv checks the VM's interrupt flag.
+const INTERRUPT_POLL: Duration = Duration::from_millis(50);
+
+/// A value copied out of a VM's heap so it can move to another thread and be rebuilt in another VM.
+#[derive(Clone, Debug)]
+enum Portable {
+    /// A value that does not reference the heap or interner.
+    Plain(Value),
+    Symbol(String),
+    Keyword(String),
+    StringConst(String),
+    String(String),
+    Char(String),
+    /// A chain of pairs, the cars and the final cdr.
+    Pairs(Vec<Portable>, Box<Portable>),
+    List(Vec<Portable>),
+    Vector(Vec<Portable>),
+    Map(Vec<(Portable, Portable)>),
+    Bytes(Vec<u8>),
+    Channel(Channel),
+}
+
+impl Portable {
+    /// Deep copy val, functions and other objects (except channels) can not be copied.
+    fn from_value(vm: &SloshVm, val: Value) -> VMResult<Self> {
+        Self::copy(vm, val, &mut HashSet::new())
+    }
+
+    fn copy(vm: &SloshVm, val: Value, path: &mut HashSet<Value>) -> VMResult<Self> {
+        let val = val.unref(vm);
+        Ok(match val {
+            Value::Byte(_)
+            | Value::Int(_)
+            | Value::Float(_)
+            | Value::CodePoint(_)
+            | Value::CharCluster(_, _)
+            | Value::True
+            | Value::False
+            | Value::Nil
+            | Value::Undefined => Portable::Plain(val),
+            Value::Symbol(i) => Portable::Symbol(vm.get_interned(i).to_string()),
+            Value::Keyword(i) => Portable::Keyword(vm.get_interned(i).to_string()),
+            Value::StringConst(i) => Portable::StringConst(vm.get_interned(i).to_string()),
+            Value::String(h) => Portable::String(vm.get_string(h).to_string()),
+            Value::CharClusterLong(h) => Portable::Char(vm.get_string(h).to_string()),
+            Value::Bytes(h) => Portable::Bytes(vm.get_bytes(h).to_vec()),
+            Value::Vector(h) => Portable::Vector(Self::copy_all(vm, val, vm.get_vector(h), path)?),
+            Value::List(h, start) => {
+                let items = &vm.get_vector(h)[start as usize..];
+                Portable::List(Self::copy_all(vm, val, items, path)?)
+            }
+            Value::Map(h) => {
+                enter(vm, val, path)?;
+                let mut map = Vec::with_capacity(vm.get_map(h).len());
+                for (key, val) in vm.get_map(h) {
+                    map.push((Self::copy(vm, *key, path)?, Self::copy(vm, *val, path)?));
+                }
+                path.remove(&val);
+                Portable::Map(map)
+            }
+            Value::Pair(_) => {
+                // Walk the cdrs in a loop so long lists do not recurse.
+                let mut cars = Vec::new();
+                let mut entered = Vec::new();
+                let mut tail = val;
+                while let Value::Pair(h) = tail {
+                    enter(vm, tail, path)?;
+                    entered.push(tail);
+                    let (car, cdr) = vm.get_pair(h);
+                    cars.push(Self::copy(vm, car, path)?);
+                    tail = cdr.unref(vm);
+                }
+                let tail = Self::copy(vm, tail, path)?;
+                for pair in entered {
+                    path.remove(&pair);
+                }
+                Portable::Pairs(cars, Box::new(tail))
+            }
+            Value::Object(h) => match vm.get_object(h).downcast_ref::<Channel>() {
+                Some(chan) => Portable::Channel(chan.clone()),
+                None => return Err(not_portable(vm, val)),
+            },
+            _ => return Err(not_portable(vm, val)),
+        })
+    }
+
+    fn copy_all(
+        vm: &SloshVm,
+        container: Value,
+        items: &[Value],
+        path: &mut HashSet<Value>,
+    ) -> VMResult<Vec<Self>> {
+        enter(vm, container, path)?;
+        let items = items
+            .iter()
+            .map(|item| Self::copy(vm, *item, path))
+            .collect::<VMResult<Vec<Self>>>()?;
+        path.remove(&container);
+        Ok(items)
+    }
+
+    /// Rebuild the value in vm, GC must be paused until the result is rooted.
+    fn to_value(&self, vm: &mut SloshVm) -> Value {
+        match self {
+            Portable::Plain(val) => *val,
+            Portable::Symbol(s) => Value::Symbol(vm.intern(s)),
+            Portable::Keyword(s) => Value::Keyword(vm.intern(s)),
+            Portable::StringConst(s) => Value::StringConst(vm.intern(s)),
+            Portable::String(s) => vm.alloc_string(s.clone()),
+            Portable::Char(s) => vm.alloc_char(s),
+            Portable::Pairs(cars, tail) => {
+                let mut res = tail.to_value(vm);
+                for car in cars.iter().rev() {
+                    let car = car.to_value(vm);
+                    res = vm.alloc_pair(car, res);
+                }
+                res
+            }
+            Portable::List(items) => {
+                let items = items.iter().map(|item| item.to_value(vm)).collect();
+                vm.alloc_list_ro(items)
+            }
+            Portable::Vector(items) => {
+                let items = items.iter().map(|item| item.to_value(vm)).collect();
+                vm.alloc_vector(items)
+            }
+            Portable::Map(items) => {
+                let mut map = HashMap::with_capacity(items.len());
+                for (key, val) in items {
+                    let key = key.to_value(vm);
+                    let val = val.to_value(vm);
+                    map.insert(key, val);
+                }
+                vm.alloc_map(map)
+            }
+            Portable::Bytes(bytes) => vm.alloc_bytes(bytes.clone()),
+            Portable::Channel(chan) => vm.alloc_object(NativeObject::new("Channel", chan.clone())),
+        }
+    }
+
+    /// Rebuild the value in vm with GC paused, use the result before allocating anything else.
+    fn rebuild(&self, vm: &mut SloshVm) -> Value {
+        vm.pause_gc();
+        let res = self.to_value(vm);
+        vm.unpause_gc();
+        res
+    }
+}
+
+/// Mark container as being copied, finding it again before it is done means it contains itself.
+fn enter(vm: &SloshVm, container: Value, path: &mut HashSet<Value>) -> VMResult<()> {
+    if path.insert(container) {
+        Ok(())
+    } else {
+        Err(VMError::new_vm(format!(
+            "can not copy a {} that contains itself to another thread",
+            container.display_type(vm)
+        )))
+    }
+}
+
+fn not_portable(vm: &SloshVm, val: Value) -> VMError {
+    VMError::new_vm(format!(
+        "can not copy a {} to another thread",
+        val.display_type(vm)
+    ))
+}
+
+/// A queue shared between VMs, values are copied in on send and out on receive.
+#[derive(Clone, Debug, Default)]
+struct Channel(Arc<(Mutex<VecDeque<Portable>>, Condvar)>);
+
+impl Channel {
+    fn send(&self, msg: Portable) {
+        let (queue, ready) = &*self.0;
+        queue
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push_back(msg);
+        ready.notify_one();
+    }
+
+    /// Wait for a message, None if timeout passes first.  Raises an error if vm is interrupted.
+    fn recv(&self, vm: &SloshVm, timeout: Option<Duration>) -> VMResult<Option<Portable>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let (queue, ready) = &*self.0;
+        let mut queue = queue.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            if let Some(msg) = queue.pop_front() {
+                return Ok(Some(msg));
+            }
+            let mut wait = INTERRUPT_POLL;
+            if let Some(deadline) = deadline {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Ok(None);
+                }
+                wait = wait.min(deadline - now);
+            }
+            queue = ready
+                .wait_timeout(queue, wait)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+            check_interrupt(vm, "chan-recv")?;
+        }
+    }
+}
+
+fn check_interrupt(vm: &SloshVm, name: &str) -> VMResult<()> {
+    if vm.interrupt_flag().swap(false, Ordering::Relaxed) {
+        Err(VMError::new("interrupted", format!("{name}: interrupted")))
+    } else {
+        Ok(())
+    }
+}
+
+/// Result of a task, the error is (key, message) since VMError can not leave its VM.
+type TaskResult = Result<Portable, (String, String)>;
+/// Result of a job, one value for each call it made.
+type WorkerResult = Result<Vec<Portable>, (String, String)>;
+
+/// Work for a worker thread, func is compiled and called once for each set of args in calls.
+struct Job {
+    func: Portable,
+    calls: Vec<Vec<Portable>>,
+    budget: Budget,
+    interrupt: Arc<AtomicBool>,
+    result: Sender<WorkerResult>,
+}
+
+/// What a worker VM runs a job with, taken from the VM that starts it.
+#[derive(Clone)]
+struct Budget {
+    load_path: Option<Portable>,
+    fuel: Option<u64>,
+    time_left: Option<Duration>,
+    object_cap: Option<usize>,
+}
+
+impl Budget {
+    fn new(vm: &mut SloshVm) -> Self {
+        let i_load_path = vm.intern("*load-path*");
+        let load_path = vm
+            .global_intern_slot(i_load_path)
+            .and_then(|slot| Portable::from_value(vm, vm.get_global(slot)).ok());
+        Self {
+            load_path,
+            fuel: vm.fuel(),
+            time_left: vm.time_left(),
+            object_cap: vm.heap_object_cap(),
+        }
+    }
+
+    fn apply(&self, vm: &mut SloshVm) {
+        if let Some(load_path) = &self.load_path {
+            let load_path = load_path.rebuild(vm);
+            vm.set_named_global("*load-path*", load_path);
+        }
+        vm.set_fuel(self.fuel);
+        vm.set_time_limit(self.time_left);
+        vm.set_heap_object_cap(self.object_cap);
+    }
+}
+
+/// Idle worker threads, each waiting for a job on its sender.  A worker's VM (with core.slosh
+/// loaded) is kept for later jobs instead of building one per task.
+static IDLE_WORKERS: Mutex<Vec<(Vec<Capability>, Sender<Job>)>> = Mutex::new(Vec::new());
+
+/// A started job, result gets its result and setting interrupt stops it.
+struct Worker {
+    result: Receiver<WorkerResult>,
+    interrupt: Arc<AtomicBool>,
+}
+
+impl Worker {
+    /// Run func once for each set of args in calls on an idle worker (or a new one).
+    fn start(
+        capabilities: &[Capability],
+        budget: &Budget,
+        func: Portable,
+        calls: Vec<Vec<Portable>>,
+    ) -> VMResult<Self> {
+        let (sender, result) = mpsc::channel();
+        let interrupt = Arc::new(AtomicBool::new(false));
+        let mut job = Job {
+            func,
+            calls,
+            budget: budget.clone(),
+            interrupt: interrupt.clone(),
+            result: sender,
+        };
+        loop {
+            let idle = {
+                let mut idle = IDLE_WORKERS.lock().unwrap_or_else(PoisonError::into_inner);
+                idle.iter()
+                    .position(|(caps, _)| caps == capabilities)
+                    .map(|i| idle.swap_remove(i).1)
+            };
+            match idle {
+                // A worker that has exited gives the job back, try the next one.
+                Some(jobs) => match jobs.send(job) {
+                    Ok(()) => break,
+                    Err(mpsc::SendError(returned)) => job = returned,
+                },
+                None => {
+                    start_worker(capabilities.to_vec(), job)?;
+                    break;
+                }
+            }
+        }
+        Ok(Self { result, interrupt })
+    }
+
+    /// Wait for the result, an interrupt of vm interrupts the job and raises an :interrupted error.
+    fn wait(&self, vm: &SloshVm, name: &str) -> VMResult<WorkerResult> {
+        loop {
+            match self.result.recv_timeout(INTERRUPT_POLL) {
+                Ok(res) => return Ok(res),
+                Err(RecvTimeoutError::Timeout) => {
+                    if let Err(err) = check_interrupt(vm, name) {
+                        self.interrupt.store(true, Ordering::Relaxed);
+                        return Err(err);
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Ok(Err((
+                        "thread".to_string(),
+                        format!("{name}: task panicked"),
+                    )))
+                }
+            }
+        }
+    }
+}
+
+/// Start a worker thread for capabilities running job, after the job it waits (idle) for more.
+fn start_worker(capabilities: Vec<Capability>, job: Job) -> VMResult<()> {
+    thread::Builder::new()
+        .name("slosh-worker".to_string())
+        .stack_size(WORKER_STACK_SIZE)
+        .spawn(move || {
+            let (jobs, receiver) = mpsc::channel();
+            let mut vm = match new_worker_vm(&capabilities) {
+                Ok(vm) => vm,
+                Err(err) => {
+                    let _ = job.result.send(Err(error_parts(None, err)));
+                    return;
+                }
+            };
+            let globals = GlobalsSnapshot::new(&vm);
+            let mut job = job;
+            loop {
+                let res = run_job(&mut vm, &job);
+                // Go back to idle before sending the result so the next task can reuse this VM,
+                // unless the job changed a global in place or there are already enough idle
+                // workers.
+                let unchanged = globals.unchanged(&vm);
+                let max_idle = thread::available_parallelism().map_or(1, |n| n.get());
+                let mut idle = IDLE_WORKERS.lock().unwrap_or_else(PoisonError::into_inner);
+                let keep = unchanged && idle.len() < max_idle;
+                if keep {
+                    idle.push((capabilities.clone(), jobs.clone()));
+                }
+                drop(idle);
+                let _ = job.result.send(res);
+                if !keep {
+                    return;
+                }
+                match receiver.recv() {
+                    Ok(next) => job = next,
+                    Err(_) => return,
+                }
+            }
+        })
+        .map_err(|e| VMError::new("thread", format!("unable to start thread: {e}")))?;
+    Ok(())
+}
+
+fn new_worker_vm(capabilities: &[Capability]) -> VMResult<SloshVm> {
+    let mut vm = new_slosh_vm();
+    set_builtins_with(&mut vm, capabilities);
+    let mut reader = Reader::from_static_string(CORE_LISP, &mut vm, "core.slosh", 1, 0);
+    load_reader(&mut reader, "core.slosh")?;
+    // Each job starts with the globals as they are now.
+    vm.save_globals();
+    Ok(vm)
+}
+
+/// The contents of a heap object reachable from a worker's globals.
+#[derive(PartialEq)]
+enum Contents {
+    String(String),
+    Bytes(Vec<u8>),
+    Vector(Vec<Value>),
+    Map(HashMap<Value, Value>),
+    Pair(Value, Value),
+    Value(Value),
+}
+
+/// The heap objects reachable from a worker's saved globals and their contents.  Restoring the
+/// globals only puts back the slots, a job that changes one of these objects in place (pushes to
+/// a core vector for instance) would change it for later jobs so the worker is dropped instead.
+struct GlobalsSnapshot(Vec<(Value, Contents)>);
+
+impl GlobalsSnapshot {
+    fn new(vm: &SloshVm) -> Self {
+        let mut objects = Vec::new();
+        let mut seen = HashSet::new();
+        let mut todo: Vec<Value> = vm
+            .globals()
+            .values()
+            .map(|slot| vm.get_global(*slot as u32))
+            .collect();
+        while let Some(val) = todo.pop() {
+            if !seen.insert(val) {
+                continue;
+            }
+            if let Some(contents) = Self::contents(vm, val) {
+                match &contents {
+                    Contents::Vector(items) => todo.extend_from_slice(items),
+                    Contents::Map(map) => todo.extend(map.iter().flat_map(|(k, v)| [*k, *v])),
+                    Contents::Pair(car, cdr) => todo.extend([*car, *cdr]),
+                    Contents::Value(v) => todo.push(*v),
+                    Contents::String(_) | Contents::Bytes(_) => {}
+                }
+                objects.push((val, contents));
+            }
+            match val {
+                Value::Lambda(h) => todo.extend_from_slice(&vm.get_lambda(h).constants),
+                Value::Closure(h) => {
+                    let (chunk, captures) = vm.get_closure(h);
+                    todo.extend_from_slice(&chunk.constants);
+                    todo.extend(captures.iter().map(|h| Value::Value(*h)));
+                }
+                _ => {}
+            }
+        }
+        Self(objects)
+    }
+
+    fn contents(vm: &SloshVm, val: Value) -> Option<Contents> {
+        Some(match val {
+            Value::String(h) => Contents::String(vm.get_string(h).to_string()),
+            Value::Bytes(h) => Contents::Bytes(vm.get_bytes(h).to_vec()),
+            Value::Vector(h) | Value::List(h, _) => Contents::Vector(vm.get_vector(h).to_vec()),
+            Value::Map(h) => Contents::Map(vm.get_map(h).clone()),
+            Value::Pair(h) => {
+                let (car, cdr) = vm.get_pair(h);
+                Contents::Pair(car, cdr)
+            }
+            Value::Value(h) => Contents::Value(vm.get_value(h)),
+            _ => return None,
+        })
+    }
+
+    /// True if every object still has the contents it had when the snapshot was taken.  Objects
+    /// are checked in the order they were found so an object is only read while the (unchanged)
+    /// one it was found in still keeps it alive.
+    fn unchanged(&self, vm: &SloshVm) -> bool {
+        self.0
+            .iter()
+            .all(|(val, contents)| Self::contents(vm, *val).as_ref() == Some(contents))
+    }
+}
+
+/// Run a job on a worker's VM then put the VM back as it was for the next one.  The global slots
+/// are restored, a GlobalsSnapshot catches a job that changed a global in place.
+fn run_job(vm: &mut SloshVm, job: &Job) -> WorkerResult {
+    vm.set_interrupt_flag(job.interrupt.clone());
+    job.budget.apply(vm);
+    let res = run_calls(vm, &job.func, &job.calls).map_err(|e| error_parts(Some(vm), e));
+    // An error leaves the VM where it failed, nothing will resume it.
+    vm.reset();
+    vm.set_fuel(None);
+    vm.set_time_limit(None);
+    vm.set_heap_object_cap(None);
+    vm.restore_globals();
+    vm.save_globals();
+    res
+}
+
+fn error_parts(vm: Option<&SloshVm>, err: VMError) -> (String, String) {
+    let msg = match (err.obj, vm) {
+        (VMErrorObj::Message(msg), _) => msg,
+        (VMErrorObj::Object(val), Some(vm)) => val.pretty_value(vm),
+        (VMErrorObj::Object(val), None) => format!("{val:?}"),
+    };
+    (err.key.to_string(), msg)
+}
+
+/// Raise a worker's error in vm.
+fn raise(vm: &mut SloshVm, (key, msg): &(String, String)) -> VMError {
+    let key = vm.intern(key);
+    VMError::new(vm.get_interned(key), msg.clone())
+}
+
+fn run_calls(
+    vm: &mut SloshVm,
+    func: &Portable,
+    calls: &[Vec<Portable>],
+) -> VMResult<Vec<Portable>> {
+    let form = func.rebuild(vm);
+    vm.heap_sticky(form);
+    vm.set_line_num(1);
+    let res = load_one_expression(vm, form, "spawn", None);
+    vm.heap_unsticky(form);
+    let func = vm.execute(res?.0)?;
+    vm.heap_sticky(func);
+    let mut results = Vec::with_capacity(calls.len());
+    for args in calls {
+        vm.pause_gc();
+        let args: Vec<Value> = args.iter().map(|arg| arg.to_value(vm)).collect();
+        vm.unpause_gc();
+        let res = match func {
+            Value::Lambda(h) => {
+                let l = vm.get_lambda(h);
+                vm.do_call(l, &args, None)
+            }
+            Value::Closure(h) => {
+                let (l, caps) = vm.get_closure(h);
+                let caps = caps.to_vec();
+                vm.do_call(l, &args, Some(&caps[..]))
+            }
+            Value::Builtin(idx) => vm.call_builtin(idx, &args),
+            _ => Err(VMError::new_vm(format!(
+                "spawn: expected a function, got a {}",
+                func.display_type(vm)
+            ))),
+        };
+        match res.and_then(|res| Portable::from_value(vm, res)) {
+            Ok(res) => results.push(res),
+            Err(err) => {
+                vm.heap_unsticky(func);
+                return Err(err);
+            }
+        }
+    }
+    vm.heap_unsticky(func);
+    Ok(results)
+}
+
+/// A spawned task, done holds its result once it has been joined.
+struct Task {
+    worker: Worker,
+    done: Option<TaskResult>,
+}
+
+fn spawn_form(
+    vm: &mut SloshVm,
+    capabilities: &[Capability],
+    registers: &[Value],
+) -> VMResult<Value> {
+    let (form, args) = registers
+        .split_first()
+        .ok_or_else(|| VMError::new_vm("spawn-form: takes a function form and arguments"))?;
+    let func = Portable::from_value(vm, *form)?;
+    let args = args
+        .iter()
+        .map(|arg| Portable::from_value(vm, *arg))
+        .collect::<VMResult<Vec<Portable>>>()?;
+    let budget = Budget::new(vm);
+    let worker = Worker::start(capabilities, &budget, func, vec![args])?;
+    Ok(vm.alloc_object(NativeObject::new("Task", Task { worker, done: None })))
+}
+
+fn join(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    let [Value::Object(h)] = registers else {
+        return Err(VMError::new_vm("join: takes a task"));
+    };
+    let h = *h;
+    let task = vm
+        .get_object(h)
+        .downcast_ref::<Task>()
+        .ok_or_else(|| VMError::new_vm("join: takes a task"))?;
+    let done = match &task.done {
+        Some(done) => done.clone(),
+        None => {
+            let res = task
+                .worker
+                .wait(vm, "join")?
+                .map(|mut res| res.pop().unwrap_or(Portable::Plain(Value::Nil)));
+            if let Some(task) = vm.get_object_mut(h)?.downcast_mut::<Task>() {
+                task.done = Some(res.clone());
+            }
+            res
+        }
+    };
+    match done {
+        Ok(res) => Ok(res.rebuild(vm)),
+        Err(err) => Err(raise(vm, &err)),
+    }
+}
+
+fn pmap_form(
+    vm: &mut SloshVm,
+    capabilities: &[Capability],
+    registers: &[Value],
+) -> VMResult<Value> {
+    let [form, items] = registers else {
+        return Err(VMError::new_vm(
+            "pmap-form: takes a function form and a sequence",
+        ));
+    };
+    let func = Portable::from_value(vm, *form)?;
+    let items = items
+        .iter(vm)
+        .map(|item| Portable::from_value(vm, item).map(|item| vec![item]))
+        .collect::<VMResult<Vec<Vec<Portable>>>>()?;
+    if items.is_empty() {
+        return Ok(vm.alloc_vector(Vec::new()));
+    }
+    let threads = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(items.len());
+    let budget = Budget::new(vm);
+    let workers = items
+        .chunks(items.len().div_ceil(threads))
+        .map(|calls| Worker::start(capabilities, &budget, func.clone(), calls.to_vec()))
+        .collect::<VMResult<Vec<_>>>()?;
+    let mut results = Vec::with_capacity(items.len());
+    for (i, worker) in workers.iter().enumerate() {
+        let res = match worker.wait(vm, "pmap") {
+            Ok(Ok(res)) => Ok(res),
+            Ok(Err(err)) => Err(raise(vm, &err)),
+            Err(err) => Err(err),
+        };
+        match res {
+            Ok(res) => results.extend(res),
+            Err(err) => {
+                // No one will use the rest of the results.
+                for worker in &workers[i + 1..] {
+                    worker.interrupt.store(true, Ordering::Relaxed);
+                }
+                return Err(err);
+            }
+        }
+    }
+    Ok(Portable::Vector(results).rebuild(vm))
+}
+
+fn chan(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    if !registers.is_empty() {
+        return Err(VMError::new_vm("chan: takes no arguments"));
+    }
+    Ok(vm.alloc_object(NativeObject::new("Channel", Channel::default())))
+}
+
+fn get_channel(vm: &SloshVm, val: Value, name: &str) -> VMResult<Channel> {
+    if let Value::Object(h) = val {
+        if let Some(chan) = vm.get_object(h).downcast_ref::<Channel>() {
+            return Ok(chan.clone());
+        }
+    }
+    Err(VMError::new_vm(format!(
+        "{name}: expected a channel, got a {}",
+        val.display_type(vm)
+    )))
+}
+
+fn chan_send(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    let [ch, val] = registers else {
+        return Err(VMError::new_vm("chan-send: takes a channel and a value"));
+    };
+    let chan = get_channel(vm, *ch, "chan-send")?;
+    chan.send(Portable::from_value(vm, *val)?);
+    Ok(*val)
+}
+
+fn chan_recv(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    let (ch, timeout) = match registers {
+        [ch] => (*ch, None),
+        [ch, timeout] => {
+            let millis = timeout.get_int(vm)?;
+            (*ch, Some(Duration::from_millis(millis.max(0) as u64)))
+        }
+        _ => {
+            return Err(VMError::new_vm(
+                "chan-recv: takes a channel and optional timeout in milliseconds",
+            ))
+        }
+    };
+    let chan = get_channel(vm, ch, "chan-recv")?;
+    match chan.recv(vm, timeout)? {
+        Some(msg) => Ok(msg.rebuild(vm)),
+        None => Err(VMError::new("timeout", "chan-recv: timed out")),
+    }
+}
+
+/// Add spawn-form, join, pmap-form and the channel builtins.  Tasks get a VM with capabilities.
+pub fn add_parallel_builtins(env: &mut SloshVm, capabilities: &[Capability]) {
+    let caps = capabilities.to_vec();
+    bridge_adapters::add_builtin_closure(
+        env,
+        "spawn-form",
+        Box::new(move |vm, registers| spawn_form(vm, &caps, registers)),
+        "Usage: (spawn-form fn-form arg*) -> task
+
+Start a task that evaluates fn-form in a new VM on another thread and calls the result with args.
+Use the spawn macro instead of calling this directly.
+
+Section: threads
+
+Example:
+(test::assert-equal 6 (join (spawn-form '(fn (x y) (* x y)) 2 3)))
+",
+    );
+    bridge_adapters::add_builtin(
+        env,
+        "join",
+        join,
+        "Usage: (join task) -> value
+
+Wait for task to finish and return (a copy of) its result.  If the task raised an error join raises
+it.  A task can be joined more than once.
+
+Section: threads
+
+Example:
+(def join-test (spawn (fn () (+ 1 2))))
+(test::assert-equal 3 (join join-test))
+(test::assert-equal 3 (join join-test))
+(test::assert-equal :test (car (get-error (join (spawn (fn () (err :test \"failed\")))))))
+(test::assert-error (join 1))
+",
+    );
+    let caps = capabilities.to_vec();
+    bridge_adapters::add_builtin_closure(
+        env,
+        "pmap-form",
+        Box::new(move |vm, registers| pmap_form(vm, &caps, registers)),
+        "Usage: (pmap-form fn-form items) -> vector
+
+Map the function fn-form evaluates to over items using a thread (with its own VM) per core.  Use
+the pmap macro instead of calling this directly.
+
+Section: threads
+
+Example:
+(test::assert-equal [2 4 6] (pmap-form '(fn (x) (* x 2)) [1 2 3]))
+",
+    );
+    bridge_adapters::add_builtin(
+        env,
+        "chan",
+        chan,
+        "Usage: (chan) -> channel
+
+Create a channel to pass values between tasks.  A channel is shared (not copied) when passed to
+a task, values sent through it are copied.
+
+Section: threads
+
+Example:
+(def chan-test (chan))
+(chan-send chan-test [1 2])
+(test::assert-equal [1 2] (chan-recv chan-test))
+",
+    );
+    bridge_adapters::add_builtin(
+        env,
+        "chan-send",
+        chan_send,
+        "Usage: (chan-send channel value) -> value
+
+Send a copy of value to channel, this does not wait for it to be received.  Values that can not be
+copied to another VM (functions, objects other than channels, containers that contain themselves)
+are an error.
+
+Section: threads
+
+Example:
+(def chan-send-test (chan))
+(test::assert-equal \"msg\" (chan-send chan-send-test \"msg\"))
+(test::assert-equal \"msg\" (chan-recv chan-send-test))
+(test::assert-error (chan-send chan-send-test (fn () 1)))
+(test::assert-error (chan-send 1 2))
+",
+    );
+    bridge_adapters::add_builtin(
+        env,
+        "chan-recv",
+        chan_recv,
+        "Usage: (chan-recv channel timeout-ms?) -> value
+
+Receive the next value sent to channel, waits for one if the channel is empty.  With timeout-ms
+waiting longer than that many milliseconds raises a :timeout error.
+
+Section: threads
+
+Example:
+(def chan-recv-test (chan))
+(def chan-recv-task (spawn (fn (ch) (chan-send ch (+ 1 (chan-recv ch))) :done) chan-recv-test))
+(chan-send chan-recv-test 41)
+(test::assert-equal :done (join chan-recv-task))
+(test::assert-equal 42 (chan-recv chan-recv-test))
+(test::assert-equal :timeout (car (get-error (chan-recv chan-recv-test 10))))
+",
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(vm: &mut SloshVm, val: Value) -> VMResult<Value> {
+        let portable = Portable::from_value(vm, val)?;
+        let mut other = new_slosh_vm();
+        let copy = portable.rebuild(&mut other);
+        let back = Portable::from_value(&other, copy)?.rebuild(vm);
+        Ok(back)
+    }
+
+    #[test]
+    fn test_portable() {
+        let mut vm = new_slosh_vm();
+        let s = vm.alloc_string("string".to_string());
+        let sym = Value::Symbol(vm.intern("sym"));
+        let tail = vm.alloc_pair(Value::Nil, Value::True);
+        let pair = vm.alloc_pair(s, tail);
+        let v = vm.alloc_vector(vec![1.into(), sym, pair]);
+        let back = round_trip(&mut vm, v).unwrap();
+        assert_ne!(v, back);
+        assert_eq!(v.display_value(&vm), back.display_value(&vm));
+
+        let mut map = HashMap::new();
+        map.insert(Value::Keyword(vm.intern("key")), 2.into());
+        let map = vm.alloc_map(map);
+        let back = round_trip(&mut vm, map).unwrap();
+        assert_eq!(map.display_value(&vm), back.display_value(&vm));
+
+        // Cycles and functions can not be copied.
+        let cycle = vm.alloc_vector(vec![]);
+        vm.get_vector_mut(cycle.get_handle().unwrap())
+            .unwrap()
+            .push(cycle);
+        assert!(round_trip(&mut vm, cycle).is_err());
+        // Shared (but not cyclic) values are fine.
+        let shared = vm.alloc_vector(vec![s, s]);
+        assert!(round_trip(&mut vm, shared).is_ok());
+        let f = vm.add_builtin(chan);
+        assert!(round_trip(&mut vm, f).is_err());
+    }
+
+    fn read(vm: &mut SloshVm, text: &str) -> Value {
+        let mut reader = Reader::from_string(text.to_string(), vm, "", 1, 0);
+        reader.next().unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_worker_budget_and_interrupt() {
+        let mut vm = new_slosh_vm();
+        let caps = [Capability::Core];
+        let forever = read(&mut vm, "(fn () ((fn (f) (f f)) (fn (f) (f f))))");
+        vm.heap_sticky(forever);
+        vm.set_fuel(Some(10_000));
+        let task = spawn_form(&mut vm, &caps, &[forever]).unwrap();
+        assert_eq!(join(&mut vm, &[task]).unwrap_err().key, "fuel");
+        vm.set_fuel(None);
+
+        // Interrupting the parent while it waits interrupts the task.
+        let task = spawn_form(&mut vm, &caps, &[forever]).unwrap();
+        vm.heap_sticky(task);
+        let flag = vm.interrupt_flag();
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            flag.store(true, Ordering::Relaxed);
+        });
+        assert_eq!(join(&mut vm, &[task]).unwrap_err().key, "interrupted");
+        t.join().unwrap();
+        assert_eq!(join(&mut vm, &[task]).unwrap_err().key, "interrupted");
+        vm.heap_unsticky(task);
+
+        // Globals a task defines are gone for the next one on a reused worker.
+        let def = read(&mut vm, "(fn () (def parallel-test-global 1))");
+        let task = spawn_form(&mut vm, &caps, &[def]).unwrap();
+        assert_eq!(join(&mut vm, &[task]).unwrap(), 1.into());
+        let get = read(&mut vm, "(fn () parallel-test-global)");
+        let task = spawn_form(&mut vm, &caps, &[get]).unwrap();
+        assert!(join(&mut vm, &[task]).unwrap().is_undef());
+
+        // So is a push to a core vector, the worker that made it is not reused.
+        let len = read(&mut vm, "(fn () (len test::*tests*))");
+        vm.heap_sticky(len);
+        let task = spawn_form(&mut vm, &caps, &[len]).unwrap();
+        let before = join(&mut vm, &[task]).unwrap();
+        let push = read(
+            &mut vm,
+            "(fn () (vec-push! test::*tests* :leak) (len test::*tests*))",
+        );
+        let task = spawn_form(&mut vm, &caps, &[push]).unwrap();
+        assert_ne!(join(&mut vm, &[task]).unwrap(), before);
+        let task = spawn_form(&mut vm, &caps, &[len]).unwrap();
+        assert_eq!(join(&mut vm, &[task]).unwrap(), before);
+        vm.heap_unsticky(len);
+        vm.heap_unsticky(forever);
+    }
+
+    #[test]
+    fn test_channel() {
+        let mut vm = new_slosh_vm();
+        let ch = chan(&mut vm, &[]).unwrap();
+        let other = thread::spawn({
+            let chan = get_channel(&vm, ch, "test").unwrap();
+            move || chan.send(Portable::Plain(5.into()))
+        });
+        assert_eq!(chan_recv(&mut vm, &[ch]).unwrap(), 5.into());
+        other.join().unwrap();
+        let err = chan_recv(&mut vm, &[ch, 1.into()]).unwrap_err();
+        assert_eq!(err.key, "timeout");
+    }
+}
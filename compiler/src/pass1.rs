@@ -1,19 +1,60 @@
-use crate::compile_fn::mk_state;
+use crate::compile_fn::{is_multi_arity, mk_state};
 use crate::{CompileState, SloshVm};
 use slvm::{from_i56, VMResult, Value};
 
+/// Only the unquoted parts of a back-quote are code, the rest is data (a template (fn (~x)) is not
+/// a lambda for instance).
+fn pass1_backquote(env: &mut SloshVm, state: &mut CompileState, exp: Value) -> VMResult<()> {
+    let unquote_ = env.intern("unquote");
+    let splice_ = env.intern("unquote-splice");
+    let splice_bang_ = env.intern("unquote-splice!");
+    if let Value::Pair(_) | Value::List(_, _) = exp {
+        let (car, cdr) = exp.get_pair(env).expect("Pair/List not a Pair or List?");
+        match car {
+            Value::Symbol(i) if i == unquote_ || i == splice_ || i == splice_bang_ => {
+                for r in cdr.iter(env).collect::<Vec<Value>>() {
+                    pass1(env, state, r)?;
+                }
+            }
+            _ => {
+                for r in exp.iter(env).collect::<Vec<Value>>() {
+                    pass1_backquote(env, state, r)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
 pub fn pass1(env: &mut SloshVm, state: &mut CompileState, exp: Value) -> VMResult<()> {
     let fn_ = env.intern("fn");
     let mac_ = env.intern("macro");
+    let quote_ = env.intern("quote");
+    let backquote_ = env.intern("back-quote");
     match exp {
         Value::Pair(_) | Value::List(_, _) => {
             let (car, cdr) = exp.get_pair(env).expect("Pair/List not a Pair or List?");
             // Do an extra pass1 on lambda's so we can get all captures upfront.
             if let Value::Symbol(i) = car {
+                // Quoted forms are data, a quoted (fn ...) is not a lambda.
+                if i == quote_ {
+                    return Ok(());
+                }
+                if i == backquote_ {
+                    return pass1_backquote(env, state, cdr);
+                }
                 if i == fn_ || i == mac_ {
                     // XXX boo on this collect.
                     let cdr = cdr.iter(env).collect::<Vec<Value>>();
-                    if !cdr.is_empty() {
+                    if is_multi_arity(env, &cdr) {
+                        for clause in cdr {
+                            let clause = clause.iter(env).collect::<Vec<Value>>();
+                            let (mut new_state, _, _) = mk_state(env, state, clause[0])?;
+                            for r in clause[1..].iter() {
+                                pass1(env, &mut new_state, *r)?;
+                            }
+                        }
+                    } else if !cdr.is_empty() {
                         let (mut new_state, _, _) = mk_state(env, state, cdr[0])?;
                         for r in cdr[1..].iter() {
                             pass1(env, &mut new_state, *r)?;
@@ -52,7 +93,9 @@ pub fn pass1(env: &mut SloshVm, state: &mut CompileState, exp: Value) -> VMResul
         Value::Lambda(_) => {}
         Value::Closure(_) => {}
         Value::Continuation(_) => {}
+        Value::Coroutine(_) => {}
         Value::CallFrame(_) => {}
+        Value::Object(_) => {}
         Value::Value(_) => {}
 
         _ => {
@@ -6,8 +6,11 @@ use std::fs::File;
 use std::io::{BufReader, Cursor};
 use std::num::{ParseFloatError, ParseIntError};
 
-use compile_state::state::{SloshVm, SloshVmTrait};
-use slvm::{Chunk, Value};
+use crate::compile::compile;
+use crate::pass1::pass1;
+use compile_state::state::{CompileState, SloshVm, SloshVmTrait};
+use slvm::{Chunk, Value, RET};
+use std::sync::Arc;
 use unicode_reader::Graphemes;
 
 pub trait PeekableIterator: std::iter::Iterator {
@@ -68,6 +71,7 @@ pub struct Reader<'vm> {
     vm: &'vm mut SloshVm,
     char_iter: Option<Box<ReaderCharIter>>,
     file_name: &'static str,
+    read_eval: bool,
 }
 
 impl<'vm> Iterator for Reader<'vm> {
@@ -167,6 +171,7 @@ impl<'vm> Reader<'vm> {
             vm,
             char_iter: Some(char_iter),
             file_name,
+            read_eval: false,
         }
     }
 
@@ -197,6 +202,7 @@ impl<'vm> Reader<'vm> {
             vm,
             char_iter: Some(char_iter),
             file_name,
+            read_eval: false,
         }
     }
 
@@ -227,14 +233,24 @@ impl<'vm> Reader<'vm> {
             vm,
             char_iter: Some(char_iter),
             file_name,
+            read_eval: false,
         }
     }
 
-    fn line(&self) -> usize {
+    /// Allow reader evaluation (#.), the form after it is compiled and run while reading and the
+    /// result used in its place.  Off by default (#. is an error), only enable it for trusted input.
+    pub fn with_read_eval(mut self) -> Self {
+        self.read_eval = true;
+        self
+    }
+
+    /// Line the reader is on, after an error this is where it was found.
+    pub fn line(&self) -> usize {
         self.char_iter.as_ref().expect("Invalid Reader!").line
     }
 
-    fn column(&self) -> usize {
+    /// Column (in graphemes) the reader is on.
+    pub fn column(&self) -> usize {
         self.char_iter.as_ref().expect("Invalid Reader!").column
     }
 
@@ -1096,9 +1112,22 @@ impl<'vm> Reader<'vm> {
                             Ok(s) => return Ok(Some(Value::StringConst(self.vm.intern(s)))),
                             Err(e) => return Err(e),
                         },
-                        //"." => {
-                        //    return prep_reader_macro(environment, chars, "reader-macro-dot", ".");
-                        //}
+                        "." => {
+                            if !self.read_eval {
+                                let reason = format!(
+                                    "Reader evaluation (#.) is not enabled: line {}, col: {}",
+                                    self.line(),
+                                    self.column()
+                                );
+                                return Err(ReadError { reason });
+                            }
+                            return match self.read_inner(buffer, in_back_quote, ReadReturn::None)? {
+                                Some(exp) => self.read_eval(exp).map(Some),
+                                None => Err(ReadError {
+                                    reason: "Invalid reader evaluation".to_string(),
+                                }),
+                            };
+                        }
                         // Read an octal int
                         "o" => {
                             let exp = self.read_num_radix(buffer, 8, &read_table_term)?;
@@ -1192,6 +1221,28 @@ impl<'vm> Reader<'vm> {
         Ok(None)
     }
 
+    /// Compile and run exp for #., the result is used in place of the form.
+    /// Uses do_call (like eval) so the VM is left as it was even if exp fails, the reader may be
+    /// running inside a builtin.  The GC is paused while reading so the chunk does not need rooting.
+    fn read_eval(&mut self, exp: Value) -> Result<Value, ReadError> {
+        let line = self.line() as u32;
+        let vm = &mut *self.vm;
+        let mut state = CompileState::new_state(self.file_name, line, None);
+        pass1(vm, &mut state, exp)
+            .and_then(|_| compile(vm, &mut state, exp, 0))
+            .and_then(|_| state.chunk.encode0(RET, vm.own_line()))
+            .and_then(|_| {
+                state.chunk.extra_regs = state.max_regs;
+                vm.do_call(Arc::new(state.chunk), &[], None)
+            })
+            .map_err(|e| ReadError {
+                reason: format!(
+                    "Reader evaluation (#.) failed, line {line}: {}",
+                    e.display(vm)
+                ),
+            })
+    }
+
     fn read_form(&mut self) -> Result<Option<Value>, ReadError> {
         self.vm.pause_gc();
         let mut buffer = String::new();
@@ -1804,4 +1855,41 @@ two""#
         assert!(tokens[13] == ")");
         assert!(tokens[14] == "]");
     }
+
+    #[test]
+    fn test_reader_eval() {
+        let mut vm = build_def_vm();
+        let read_eval = |vm: &mut SloshVm, text: &str| -> Result<Vec<Value>, ReadError> {
+            Reader::from_string(text.to_string(), vm, "", 1, 0)
+                .with_read_eval()
+                .collect()
+        };
+        let exps = read_eval(&mut vm, "(1 #.(+ 1 2) '#.(* 2 2))").unwrap();
+        let mut tokens = Vec::new();
+        to_strs(&mut vm, &mut tokens, exps[0]);
+        assert_eq!(
+            tokens,
+            [
+                "(",
+                "Int:1",
+                "Int:3",
+                "(",
+                "Symbol:quote",
+                "Int:4",
+                ")",
+                ")"
+            ]
+        );
+        // A failed evaluation leaves the VM as it was.
+        let stack_max = vm.stack_max();
+        let err = read_eval(&mut vm, "#.(car)").unwrap_err();
+        assert!(err.reason.starts_with("Reader evaluation (#.) failed"));
+        assert_eq!(vm.stack_max(), stack_max);
+        // Off by default.
+        let err = tokenize_err(&mut vm, "(1 #.(+ 1 2))");
+        assert!(err
+            .reason
+            .starts_with("Reader evaluation (#.) is not enabled"));
+        assert_eq!(tokenize(&mut vm, "(1 (+ 1 2))").len(), 8);
+    }
 }
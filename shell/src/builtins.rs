@@ -326,6 +326,14 @@ where
     0
 }
 
+/// True if command is one of the shell builtins handled by run_builtin.
+pub fn is_builtin(command: &str) -> bool {
+    matches!(
+        command,
+        "cd" | "fg" | "bg" | "jobs" | "export" | "umask" | "unset" | "alias" | "unalias" | "ulimit"
+    )
+}
+
 pub fn run_builtin<'arg, I>(command: &OsStr, args: &mut I, jobs: &mut Jobs) -> Option<i32>
 where
     I: Iterator<Item = &'arg Arg>,
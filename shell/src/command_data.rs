@@ -1,5 +1,7 @@
 use crate::jobs::Jobs;
-use crate::platform::{FileDesc, FromFileDesc, Platform, Sys, STDIN_FILENO, STDOUT_FILENO};
+use crate::platform::{
+    FileDesc, FromFileDesc, Platform, Sys, STDERR_FILENO, STDIN_FILENO, STDOUT_FILENO,
+};
 use std::collections::HashSet;
 use std::ffi::OsString;
 use std::fmt::{Display, Formatter};
@@ -78,7 +80,7 @@ impl Display for Arg {
 
 /// An argument for a redirect (the source).
 #[derive(Clone, Debug)]
-enum RedirArg {
+pub enum RedirArg {
     /// Arg should resolve to a file path.
     Path(Arg),
     /// Arg should resolve to file descriptor (positive integer or '-' to close).
@@ -99,7 +101,7 @@ impl Display for RedirArg {
 
 /// An individual redirect, first element is always the target file descriptor.
 #[derive(Clone, Debug)]
-enum RedirType {
+pub enum RedirType {
     /// An input file to open and dup to fd.
     In(FileDesc, RedirArg),
     /// Inject Arg as data into the fd.
@@ -335,6 +337,11 @@ impl Redirects {
         self.redir_stack.push(redir);
     }
 
+    /// The redirects in the order they will be processed.
+    pub fn redirs(&self) -> &[RedirType] {
+        &self.redir_stack[..]
+    }
+
     /// Clear the redirect stack.
     pub fn clear(&mut self) {
         self.redir_stack.clear();
@@ -345,6 +352,21 @@ impl Redirects {
         self.redir_stack.extend(other.redir_stack.iter().cloned());
     }
 
+    /// Copy of this redir stack with output files opened to append instead of truncate.
+    pub fn appending(&self) -> Redirects {
+        let redir_stack = self
+            .redir_stack
+            .iter()
+            .map(|r| match r {
+                RedirType::OutTrunc(fd, arg @ RedirArg::Path(_)) => {
+                    RedirType::Out(*fd, arg.clone())
+                }
+                r => r.clone(),
+            })
+            .collect();
+        Self { redir_stack }
+    }
+
     fn collect_internal_fds(&self, fd_set: &mut HashSet<FileDesc>) {
         for r in &self.redir_stack {
             match r {
@@ -382,10 +404,17 @@ pub struct CommandWithArgs {
     /// args[0] is the command.
     args: Vec<Arg>,
     stdios: Option<Redirects>,
+    /// Extra environment variables to set for the child process only.
+    env_vars: Vec<(OsString, OsString)>,
+    /// Working directory for the child process, None to inherit the shell's.
+    cwd: Option<OsString>,
 }
 
 impl Display for CommandWithArgs {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (key, val) in &self.env_vars {
+            write!(f, "{}={} ", key.to_string_lossy(), val.to_string_lossy())?;
+        }
         let mut first = true;
         for arg in &self.args {
             if first {
@@ -408,6 +437,8 @@ impl CommandWithArgs {
         Self {
             args: vec![],
             stdios: None,
+            env_vars: vec![],
+            cwd: None,
         }
     }
 
@@ -431,6 +462,26 @@ impl CommandWithArgs {
         self.args.is_empty()
     }
 
+    /// Add an environment variable that will be set in the child process only.
+    pub fn push_env_var(&mut self, key: OsString, val: OsString) {
+        self.env_vars.push((key, val));
+    }
+
+    /// Environment variables to set in the child process.
+    pub fn env_vars(&self) -> &[(OsString, OsString)] {
+        &self.env_vars[..]
+    }
+
+    /// Set the working directory the child process will run in.
+    pub fn set_cwd(&mut self, cwd: OsString) {
+        self.cwd = Some(cwd);
+    }
+
+    /// Working directory for the child process if set.
+    pub fn cwd(&self) -> Option<&OsString> {
+        self.cwd.as_ref()
+    }
+
     /// Command name, None if no command name set (args are empty).
     pub fn command(&self, jobs: &mut Jobs) -> Option<io::Result<OsString>> {
         self.args.first().map(|v| v.resolve_arg(jobs))
@@ -445,6 +496,11 @@ impl CommandWithArgs {
         }
     }
 
+    /// The command followed by its args.
+    pub fn command_and_args(&self) -> &[Arg] {
+        &self.args[..]
+    }
+
     /// Iterator over the arguments for the command.
     pub fn args_iter(&self) -> CommandArgs {
         CommandArgs {
@@ -496,6 +552,19 @@ impl CommandWithArgs {
         }
     }
 
+    /// If fd is Some value then put it at the front of the redir queue for this command.
+    pub fn push_stderr_front(&mut self, fd: Option<FileDesc>) {
+        if let Some(fd) = fd {
+            if let Some(stdios) = self.stdios.as_mut() {
+                stdios.set_out_internal_fd(STDERR_FILENO, fd, false);
+            } else {
+                let mut stdios = Redirects::default();
+                stdios.set_out_internal_fd(STDERR_FILENO, fd, true);
+                self.stdios = Some(stdios);
+            }
+        }
+    }
+
     /// Process redirects.
     pub fn process_redirects(&self, jobs: &mut Jobs) -> Result<HashSet<FileDesc>, io::Error> {
         if let Some(redirects) = &self.stdios {
@@ -554,6 +623,8 @@ pub enum Run {
     And(Vec<Run>),
     Or(Vec<Run>),
     Subshell(Box<Run>),
+    /// A lisp function used as a pipeline stage, args[0] is the function's source.
+    Lisp(CommandWithArgs),
     Empty,
 }
 
@@ -580,6 +651,7 @@ impl Display for Run {
             Self::And(seq) => write_seq(f, &seq[..], "&&")?,
             Self::Or(seq) => write_seq(f, &seq[..], "||")?,
             Self::Subshell(sub_run) => write!(f, "({sub_run})")?,
+            Self::Lisp(command) => write!(f, "{command}")?,
             Self::Empty => {}
         }
         Ok(())
@@ -602,6 +674,7 @@ impl Run {
             Run::And(seq) => Run::Sequence(vec![Run::And(seq), new_run]),
             Run::Or(seq) => Run::Sequence(vec![Run::Or(seq), new_run]),
             Run::Subshell(current) => Run::Sequence(vec![Run::Subshell(current), new_run]),
+            Run::Lisp(current) => Run::Sequence(vec![Run::Lisp(current), new_run]),
             Run::Empty => new_run,
         }
     }
@@ -621,6 +694,7 @@ impl Run {
             Run::And(seq) => Run::Pipe(vec![Run::And(seq), new_run]),
             Run::Or(seq) => Run::Pipe(vec![Run::Or(seq), new_run]),
             Run::Subshell(current) => Run::Pipe(vec![Run::Subshell(current), new_run]),
+            Run::Lisp(current) => Run::Pipe(vec![Run::Lisp(current), new_run]),
             Run::Empty => new_run,
         }
     }
@@ -640,6 +714,7 @@ impl Run {
             Run::And(seq) => Run::Sequence(vec![Run::And(seq), new_run]),
             Run::Or(seq) => Run::Sequence(vec![Run::Or(seq), new_run]),
             Run::Subshell(current) => Run::Sequence(vec![Run::Subshell(current), new_run]),
+            Run::Lisp(current) => Run::Sequence(vec![Run::Lisp(current), new_run]),
             Run::Empty => new_run,
         }
     }
@@ -659,6 +734,7 @@ impl Run {
             }
             Run::Or(seq) => Run::And(vec![Run::Or(seq), new_run]),
             Run::Subshell(current) => Run::And(vec![Run::Subshell(current), new_run]),
+            Run::Lisp(current) => Run::And(vec![Run::Lisp(current), new_run]),
             Run::Empty => new_run,
         }
     }
@@ -678,6 +754,7 @@ impl Run {
                 Run::Or(seq)
             }
             Run::Subshell(current) => Run::Or(vec![Run::Subshell(current), new_run]),
+            Run::Lisp(current) => Run::Or(vec![Run::Lisp(current), new_run]),
             Run::Empty => new_run,
         }
     }
@@ -697,6 +774,7 @@ impl Run {
                     }
                 }
                 Run::Subshell(ref mut current) => current.push_stdin_front(Some(fd)),
+                Run::Lisp(current) => current.push_stdin_front(Some(fd)),
                 Run::Empty => {}
             }
         }
@@ -717,6 +795,7 @@ impl Run {
                     }
                 }
                 Run::Subshell(ref mut current) => current.push_stdout_front(Some(fd)),
+                Run::Lisp(current) => current.push_stdout_front(Some(fd)),
                 Run::Empty => {}
             }
         }
@@ -732,6 +811,7 @@ impl Run {
                 }
             }
             Run::Subshell(ref current) => current.collect_internal_fds(fd_set),
+            Run::Lisp(current) => current.collect_internal_fds(fd_set),
             Run::Empty => {}
         }
     }
@@ -757,6 +837,27 @@ impl Run {
                 }
             }
             Run::Subshell(ref mut current) => current.push_arg_end(arg),
+            // A lisp stage has no command line to extend.
+            Run::Lisp(_) => {}
+            Run::Empty => {}
+        }
+    }
+
+    /// Extend the redirects of the first command in the Run with redirs.
+    pub fn extend_redirs_start(&mut self, redirs: &Redirects) {
+        match self {
+            Run::Command(current) => current.extend_stdios(redirs),
+            Run::BackgroundCommand(current) => current.extend_stdios(redirs),
+            Run::Pipe(ref mut seq)
+            | Run::Sequence(ref mut seq)
+            | Run::And(ref mut seq)
+            | Run::Or(ref mut seq) => {
+                if let Some(run) = seq.first_mut() {
+                    run.extend_redirs_start(redirs);
+                }
+            }
+            Run::Subshell(ref mut current) => current.extend_redirs_start(redirs),
+            Run::Lisp(current) => current.extend_stdios(redirs),
             Run::Empty => {}
         }
     }
@@ -775,7 +876,33 @@ impl Run {
                 }
             }
             Run::Subshell(ref mut current) => current.extend_redirs_end(redirs),
+            Run::Lisp(current) => current.extend_stdios(redirs),
+            Run::Empty => {}
+        }
+    }
+
+    /// Extend every command of a Sequence, And or Or (and any nested in it) with start and end,
+    /// extend_redirs_start/end only reach the first or last one.  A Pipe gets start on its first
+    /// command and end on its last.  Output files are truncated by the first command only, the
+    /// rest append so the output of every command is kept.
+    pub fn extend_redirs_each(&mut self, start: &Redirects, end: &Redirects) {
+        self.extend_redirs_each_inner(start, &mut end.clone());
+    }
+
+    fn extend_redirs_each_inner(&mut self, start: &Redirects, end: &mut Redirects) {
+        match self {
+            Run::Sequence(ref mut seq) | Run::And(ref mut seq) | Run::Or(ref mut seq) => {
+                for run in seq {
+                    run.extend_redirs_each_inner(start, end);
+                }
+            }
+            Run::Subshell(ref mut current) => current.extend_redirs_each_inner(start, end),
             Run::Empty => {}
+            _ => {
+                self.extend_redirs_start(start);
+                self.extend_redirs_end(end);
+                *end = end.appending();
+            }
         }
     }
 }
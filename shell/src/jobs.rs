@@ -63,7 +63,8 @@ pub struct Job {
     names: Vec<String>,
     status: JobStatus,
     interactive: bool,
-    stealth: bool, // If true don't report when background job ends.
+    stealth: bool,    // If true don't report when background job ends.
+    background: bool, // If true the processes never take the terminal.
 }
 
 impl Job {
@@ -78,6 +79,7 @@ impl Job {
             status: JobStatus::New,
             interactive,
             stealth: false,
+            background: false,
         }
     }
 
@@ -178,6 +180,17 @@ impl Job {
         self.stealth
     }
 
+    /// Start the job's processes in the background, they stay in the shell's process group and do
+    /// not take the terminal.
+    pub fn set_background(&mut self, background: bool) {
+        self.background = background;
+    }
+
+    /// Does this job start its processes in the background?
+    pub fn background(&self) -> bool {
+        self.background
+    }
+
     /// Is this job running in an interactive shell?
     pub fn interactive(&self) -> bool {
         self.interactive
@@ -199,6 +212,10 @@ impl Display for Job {
     }
 }
 
+/// Runs the source of a lisp pipeline stage with stdin and stdout already set up.
+/// Returns the exit status for the stage.
+pub type LispRunner = fn(&str) -> i32;
+
 pub struct Jobs {
     next_job: u32,
     jobs: Vec<Job>,
@@ -207,6 +224,7 @@ pub struct Jobs {
     term_settings: Option<TermSettings>,
     alias: HashMap<String, Run>,
     local_vars: HashMap<OsString, OsString>,
+    lisp_runner: Option<LispRunner>,
 }
 
 impl Jobs {
@@ -229,6 +247,7 @@ impl Jobs {
             term_settings,
             alias: HashMap::new(),
             local_vars: HashMap::new(),
+            lisp_runner: None,
         }
     }
 
@@ -337,6 +356,16 @@ impl Jobs {
         }
     }
 
+    /// Set the function used to run lisp stages in a pipeline.
+    pub fn set_lisp_runner(&mut self, runner: LispRunner) {
+        self.lisp_runner = Some(runner);
+    }
+
+    /// Function to run lisp pipeline stages if the host provided one.
+    pub fn lisp_runner(&self) -> Option<LispRunner> {
+        self.lisp_runner
+    }
+
     /// Gets an alias.
     pub fn get_alias<S: AsRef<str>>(&self, name: S) -> Option<Run> {
         self.alias.get(name.as_ref()).cloned()
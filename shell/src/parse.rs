@@ -881,6 +881,56 @@ fn read_special_arg(
     })
 }
 
+/// True if chars (just after an open paren) start a lisp stage, i.e. (fn ...), (lambda ...) or
+/// (lisp expression).  Anything else in parens is a subshell.
+fn is_lisp_stage(chars: &Peekable<Chars>) -> bool {
+    let mut chars = chars.clone();
+    consume_whitespace(&mut chars);
+    let mut word = String::new();
+    while let Some(ch) = chars.peek() {
+        if ch.is_whitespace() || *ch == '(' || *ch == ')' {
+            break;
+        }
+        word.push(*ch);
+        chars.next();
+    }
+    word == "fn" || word == "lambda" || word == "lisp"
+}
+
+/// Read the source of a lisp function up to and including the matching close paren.
+/// Assumes chars is just after the open paren.
+fn read_lisp_stage(chars: &mut Peekable<Chars>) -> Result<String, io::Error> {
+    let mut res = String::from("(");
+    let mut depth = 1;
+    let mut in_string = false;
+    let mut escape = false;
+    for ch in chars.by_ref() {
+        res.push(ch);
+        if escape {
+            escape = false;
+        } else if ch == '\\' {
+            escape = true;
+        } else if in_string {
+            if ch == '"' {
+                in_string = false;
+            }
+        } else {
+            match ch {
+                '"' => in_string = true,
+                '(' => depth += 1,
+                ')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(res);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    Err(io::Error::new(ErrorKind::Other, "unclosed lisp function"))
+}
+
 fn parse_line_inner(
     jobs: &mut Jobs,
     chars: &mut Peekable<Chars>,
@@ -962,13 +1012,19 @@ fn parse_line_inner(
                 '(' if state.last_ch != '$' && state.last_ch != '\\' => {
                     state.proc_token(jobs)?;
                     state.end_command(false);
-                    let mut sub = parse_line_inner(jobs, chars, Some(')'))?;
-                    if let Some(sub) = sub.commands.take() {
-                        push_next_seq_run(
-                            &mut state.ret,
-                            Run::Subshell(Box::new(sub)),
-                            state.current_seq,
-                        );
+                    if is_lisp_stage(chars) {
+                        let mut command = CommandWithArgs::new();
+                        command.push_arg(Arg::Str(read_lisp_stage(chars)?.into()));
+                        push_next_seq_run(&mut state.ret, Run::Lisp(command), state.current_seq);
+                    } else {
+                        let mut sub = parse_line_inner(jobs, chars, Some(')'))?;
+                        if let Some(sub) = sub.commands.take() {
+                            push_next_seq_run(
+                                &mut state.ret,
+                                Run::Subshell(Box::new(sub)),
+                                state.current_seq,
+                            );
+                        }
                     }
                 }
                 '\n' if state.last_ch == '\\' => {
@@ -1069,6 +1125,47 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_lisp_stage_parse() {
+        test_parse(
+            "ls -l|(fn (line) (str-upper line))|sort",
+            "ls -l | (fn (line) (str-upper line)) | sort",
+        );
+        test_parse(
+            "cat file | ( lambda (l) (str \"(\" l \")\"))|wc",
+            "cat file | ( lambda (l) (str \"(\" l \")\")) | wc",
+        );
+        test_parse("(fnord -l)|grep x", "(fnord -l) | grep x");
+        let mut jobs = Jobs::new(false);
+        assert!(parse_line(&mut jobs, "ls | (fn (l) (str l)").is_err());
+        let run = parse_line(&mut jobs, "ls|(lisp upper)|(sort -r)|(lispish x)")
+            .unwrap()
+            .into_run();
+        match run {
+            Run::Pipe(stages) => {
+                assert!(matches!(stages[1], Run::Lisp(_)));
+                assert!(matches!(stages[2], Run::Subshell(_)));
+                assert!(matches!(stages[3], Run::Subshell(_)));
+            }
+            _ => panic!("expected a pipe, got {run}"),
+        }
+    }
+
+    #[test]
+    fn test_extend_redirs_each() {
+        let mut jobs = Jobs::new(false);
+        let mut run = parse_line(&mut jobs, "a; b && (c | d)").unwrap().into_run();
+        let mut start = Redirects::new();
+        start.set_in_path(STDIN_FILENO, Arg::Str("in.txt".into()));
+        let mut end = Redirects::new();
+        end.set_out_path(STDOUT_FILENO, Arg::Str("out.txt".into()), true);
+        run.extend_redirs_each(&start, &end);
+        assert_eq!(
+            run.to_string(),
+            "a 0<in.txt 1>out.txt ; b 0<in.txt 1>>out.txt && (c 0<in.txt | d 1>>out.txt)"
+        );
+    }
+
     #[test]
     fn test_strings() {
         test_parse_once("\"one\\ntwo\"", "one\x0Atwo");
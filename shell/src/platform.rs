@@ -38,6 +38,8 @@ pub trait Platform {
         jobs: &mut Jobs,
     ) -> Result<(), io::Error>;
     fn try_wait_pid(pid: Pid, job: &mut Job) -> (bool, Option<i32>);
+    /// Send signal to the process pid.
+    fn kill_pid(pid: Pid, signal: i32) -> Result<(), io::Error>;
     fn wait_job(job: &mut Job) -> Option<i32>;
     /// Move the job for job_num to te foreground.
     fn foreground_job(job: &mut Job, term_settings: &Option<TermSettings>)
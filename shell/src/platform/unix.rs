@@ -13,7 +13,7 @@ use crate::command_data::{Arg, CommandWithArgs, Run};
 use crate::jobs::{Job, JobStatus, Jobs};
 pub use crate::platform::unix::umask::mode_t;
 use crate::platform::{FromFileDesc, Platform, RLimit, RLimitVals};
-use crate::run::run_job;
+use crate::run::run_forked;
 use crate::signals::test_clear_sigint;
 use nix::libc;
 use nix::sys::signal::{self, kill, SigHandler, Signal};
@@ -135,7 +135,7 @@ impl Platform for Sys {
                     close_extra_fds(&redir_fds);
                     jobs.set_interactive(false);
                     jobs.set_no_tty();
-                    match run_job(run, jobs, false) {
+                    match run_forked(run, jobs) {
                         Ok(status) => libc::_exit(status),
                         Err(e) => {
                             eprintln!("Error running subshell: {e}");
@@ -169,6 +169,17 @@ impl Platform for Sys {
             return Err(io::Error::new(ErrorKind::Other, "no program to execute"));
         };
         let args = command.args_iter();
+        // Setting the environment or cwd in the child is not async-signal-safe so get them ready
+        // before forking, the child only has to point environ at envp and fchdir.
+        let envp = if command.env_vars().is_empty() {
+            None
+        } else {
+            Some(child_environ(command.env_vars())?)
+        };
+        let cwd_fd = match command.cwd() {
+            Some(cwd) => Some(open_dir(cwd)?),
+            None => None,
+        };
         let (UnixFileDesc(input), UnixFileDesc(output)) = Sys::anon_pipe()?;
         let result = unsafe { cvt(libc::fork())? };
 
@@ -181,11 +192,26 @@ impl Platform for Sys {
                     match command.process_redirects(jobs) {
                         Ok(mut fds) => {
                             fds.insert(UnixFileDesc(output));
+                            if let Some(fd) = cwd_fd {
+                                fds.insert(UnixFileDesc(fd));
+                            }
                             close_extra_fds(&fds);
                         }
                         Err(err) => send_error_to_parent(output, err), // This call won't return.
                     }
-                    setup_group_term(UnixPid(unistd::getpid().into()), job);
+                    if let Some((_, envp)) = &envp {
+                        set_environ(envp);
+                    }
+                    if let Some(fd) = cwd_fd {
+                        if libc::fchdir(fd) != 0 {
+                            // This call won't return.
+                            send_error_to_parent(output, io::Error::last_os_error());
+                        }
+                    }
+                    // A background process stays in the shell's group and leaves the terminal.
+                    if !job.background() {
+                        setup_group_term(UnixPid(unistd::getpid().into()), job);
+                    }
 
                     let err = exec(&program, args, jobs);
                     // This call won't return.
@@ -196,8 +222,13 @@ impl Platform for Sys {
                 n => n,
             }
         };
-        setup_group_term(UnixPid(pid), job);
+        if !job.background() {
+            setup_group_term(UnixPid(pid), job);
+        }
         unsafe {
+            if let Some(fd) = cwd_fd {
+                libc::close(fd);
+            }
             libc::close(output);
             // Close any FD for child stdio we don't care about.
             // This means any FDs we opened for pipes etc.
@@ -277,6 +308,12 @@ impl Platform for Sys {
         }
     }
 
+    fn kill_pid(pid: UnixPid, signal: i32) -> Result<(), io::Error> {
+        let signal = Signal::try_from(signal)?;
+        kill(unistd::Pid::from_raw(pid.0), signal)?;
+        Ok(())
+    }
+
     fn wait_job(job: &mut Job) -> Option<i32> {
         let mut result: Option<i32> = None;
         let mut int_cnt = 0;
@@ -435,6 +472,12 @@ impl Display for UnixPid {
     }
 }
 
+impl From<UnixPid> for i32 {
+    fn from(pid: UnixPid) -> Self {
+        pid.0
+    }
+}
+
 /// Raw file descriptor for the target platform.
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Hash, Debug)]
 pub struct UnixFileDesc(RawFd);
@@ -480,6 +523,10 @@ pub const STDIN_FILENO: UnixFileDesc = UnixFileDesc(0);
 pub const STDOUT_FILENO: UnixFileDesc = UnixFileDesc(1);
 pub const STDERR_FILENO: UnixFileDesc = UnixFileDesc(2);
 
+pub const SIGINT: i32 = libc::SIGINT;
+pub const SIGTERM: i32 = libc::SIGTERM;
+pub const SIGKILL: i32 = libc::SIGKILL;
+
 trait IsMinusOne {
     fn is_minus_one(&self) -> bool;
 }
@@ -557,6 +604,71 @@ where
     Err(io::Error::last_os_error())
 }
 
+/// The parent's environment with vars added (replacing any with the same name) as "key=val"
+/// strings and the null terminated pointers to them for environ.  Errors if an entry contains a
+/// nul byte.
+fn child_environ(
+    vars: &[(OsString, OsString)],
+) -> Result<(Vec<CString>, Vec<*const c_char>), io::Error> {
+    let mut env: Vec<(OsString, OsString)> = std::env::vars_os().collect();
+    for (key, val) in vars {
+        env.retain(|(k, _)| k != key);
+        env.push((key.clone(), val.clone()));
+    }
+    let mut saw_nul = false;
+    let strings: Vec<CString> = env
+        .iter()
+        .map(|(key, val)| {
+            let mut entry = key.clone();
+            entry.push("=");
+            entry.push(val);
+            os2c(&entry, &mut saw_nul)
+        })
+        .collect();
+    if saw_nul {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "environment variable contains a nul byte",
+        ));
+    }
+    let mut ptrs: Vec<*const c_char> = strings.iter().map(|s| s.as_ptr()).collect();
+    ptrs.push(ptr::null());
+    Ok((strings, ptrs))
+}
+
+/// Point the process environment at envp (from child_environ).
+/// SAFETY: only call in a forked child before exec, envp has to outlive the exec.
+unsafe fn set_environ(envp: &[*const c_char]) {
+    #[cfg(target_os = "macos")]
+    {
+        *libc::_NSGetEnviron() = envp.as_ptr() as *mut *mut c_char;
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        extern "C" {
+            static mut environ: *const *const c_char;
+        }
+        environ = envp.as_ptr();
+    }
+}
+
+/// Open dir for a child to fchdir to, close on exec.
+fn open_dir(dir: &OsStr) -> Result<RawFd, io::Error> {
+    let path = CString::new(dir.as_bytes())
+        .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "cwd contains a nul byte"))?;
+    let fd = unsafe {
+        libc::open(
+            path.as_ptr(),
+            libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(fd)
+    }
+}
+
 const CLOEXEC_MSG_FOOTER: [u8; 4] = *b"NOEX";
 
 /// Send an error code back tot he parent from a child process indicating it failed to fork.
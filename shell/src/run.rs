@@ -4,6 +4,7 @@ use crate::jobs::{Job, Jobs};
 use crate::parse::parse_line;
 use crate::platform::{FileDesc, Platform, Sys};
 use crate::signals::{install_sigint_handler, mask_signals};
+use std::io::Write;
 use std::{env, io};
 
 pub fn setup_shell_tty(shell_terminal: FileDesc) {
@@ -145,11 +146,44 @@ pub fn run_job(run: &Run, jobs: &mut Jobs, force_background: bool) -> Result<i32
                 }
             }
         }
+        Run::Lisp(_) => {
+            let mut job = jobs.new_job();
+            job.set_stealth(force_background);
+            match Sys::fork_run(run, &mut job, jobs) {
+                Ok(()) => finish_run(false, job, jobs),
+                Err(err) => {
+                    // Make sure we restore the terminal...
+                    jobs.restore_terminal();
+                    return Err(err);
+                }
+            }
+        }
         Run::Empty => 0,
     };
     Ok(status)
 }
 
+/// Run in a forked child process.  Lisp stages run in this process (the fork) instead of
+/// forking again, everything else is a normal job.
+pub(crate) fn run_forked(run: &Run, jobs: &mut Jobs) -> Result<i32, io::Error> {
+    if let Run::Lisp(command) = run {
+        let runner = jobs.lisp_runner().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Other,
+                "lisp pipeline stages are not supported by this shell",
+            )
+        })?;
+        let source = command.command(jobs).unwrap_or_else(|| Ok("".into()))?;
+        // Leaves the redirected fds open, they are released when the fork exits.
+        command.process_redirects(jobs)?;
+        let status = runner(&source.to_string_lossy());
+        let _ = io::stdout().flush();
+        Ok(status)
+    } else {
+        run_job(run, jobs, false)
+    }
+}
+
 pub fn run_one_command(command: &str, jobs: &mut Jobs) -> Result<i32, io::Error> {
     // Try to make sense out of whatever crap we get (looking at you fzf-tmux)
     // and make it work.
@@ -229,6 +263,21 @@ fn run_pipe(new_job: &[Run], job: &mut Job, jobs: &mut Jobs) -> Result<bool, io:
                     }
                 }
             }
+            Run::Lisp(_) => {
+                let mut program = program.clone();
+                program.push_stdin_front(next_in);
+                program.push_stdout_front(next_out);
+                match Sys::fork_run(&program, job, jobs) {
+                    Ok(()) => {
+                        jobs.restore_terminal();
+                    }
+                    Err(err) => {
+                        // Make sure we restore the terminal...
+                        jobs.restore_terminal();
+                        return Err(err);
+                    }
+                }
+            }
             Run::Pipe(_) | Run::Sequence(_) | Run::And(_) | Run::Or(_) => {
                 // Don't think this is expressible with the parser and maybe should be an error?
                 let mut program = program.clone();
@@ -1,4 +1,5 @@
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
 
 use nix::{
     libc,
@@ -6,9 +7,19 @@ use nix::{
 };
 
 static SIG_INT: AtomicBool = AtomicBool::new(false);
+static SIG_INT_FORWARD: OnceLock<Arc<AtomicBool>> = OnceLock::new();
 
 extern "C" fn sig_int_handle(_: libc::c_int, _: *mut libc::siginfo_t, _: *mut libc::c_void) {
     SIG_INT.store(true, Ordering::Relaxed);
+    if let Some(flag) = SIG_INT_FORWARD.get() {
+        flag.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Also set flag when a SIGINT is received (used to interrupt running lisp code).
+/// Can only be set once, returns false if it was already set.
+pub fn forward_sigint_to(flag: Arc<AtomicBool>) -> bool {
+    SIG_INT_FORWARD.set(flag).is_ok()
 }
 
 pub fn install_sigint_handler() -> bool {
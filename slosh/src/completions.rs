@@ -9,6 +9,9 @@ use shell::builtins::compress_tilde;
 use shell::builtins::expand_tilde;
 use slvm::{VMResult, Value};
 
+mod specs;
+pub use specs::add_complete_builtins;
+
 /// Unescape filenames for the completer so that special characters will be properly shown.
 fn unescape(input: &str) -> String {
     let mut output = Vec::with_capacity(input.len());
@@ -199,7 +202,9 @@ impl Completer for ShellCompleter {
                 HookResult::Path => get_path_matches(start),
                 HookResult::UseList(list) => list,
             },
-            CompType::Other => match self.run_hook() {
+            CompType::Other => match specs::spec_completions(&self.args, start)
+                .unwrap_or_else(|| self.run_hook())
+            {
                 HookResult::Default => {
                     if self
                         .args
@@ -0,0 +1,493 @@
+use super::{get_dir_matches, get_path_matches, HookResult};
+use crate::ENV;
+use bridge_adapters::add_builtin;
+use compile_state::state::SloshVm;
+use slvm::{VMError, VMResult, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Seconds to cache the output of a completion command if the spec does not set :cache.
+const DEFAULT_CACHE_SECS: u64 = 30;
+
+/// Words that end a command, completion starts over with a new command after one.
+const COMMAND_SEPARATORS: [&str; 5] = ["|", "||", "&&", ";", "&"];
+
+/// How long a completion command can run before it is killed, completion runs at the prompt.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(2);
+
+thread_local! {
+    /// Completion specs registered with complete, keyed by command name.
+    /// Each spec is kept in its own reserved global slot (globals are GC roots) so the GC will
+    /// not collect it, the slot is reused if the command gets a new spec.
+    static SPECS: RefCell<HashMap<String, u32>> = RefCell::new(HashMap::new());
+    /// Output lines of completion commands keyed by the command with the time they were run.
+    static COMMAND_CACHE: RefCell<HashMap<String, (Instant, Vec<String>)>> =
+        RefCell::new(HashMap::new());
+}
+
+fn set_spec(env: &mut SloshVm, command: &str, spec: Value) {
+    let slot = SPECS.with(|specs| specs.borrow().get(command).copied());
+    match slot {
+        Some(slot) => env.set_global(slot, spec),
+        None if spec.is_nil() => {}
+        None => {
+            let slot = env.reserve_global();
+            env.set_global(slot, spec);
+            SPECS.with(|specs| specs.borrow_mut().insert(command.to_string(), slot));
+        }
+    }
+}
+
+fn get_spec(env: &SloshVm, command: &str) -> Option<Value> {
+    SPECS
+        .with(|specs| specs.borrow().get(command).copied())
+        .map(|slot| env.get_global(slot))
+        .filter(|spec| !matches!(spec, Value::Nil | Value::Undefined))
+}
+
+fn string_value(env: &SloshVm, val: Value) -> Option<String> {
+    match val {
+        Value::String(h) => Some(env.get_string(h).to_string()),
+        Value::StringConst(i) => Some(env.get_interned(i).to_string()),
+        _ => None,
+    }
+}
+
+/// Get key from map, keys are compared as strings so "commit" matches regardless of how
+/// the map was built.
+fn map_get(env: &mut SloshVm, map: Value, key: &str) -> Option<Value> {
+    if let Value::Map(h) = map {
+        env.get_map(h)
+            .iter()
+            .find(|(k, _)| match k {
+                Value::Keyword(i) => env.get_interned(*i) == key,
+                _ => string_value(env, **k).as_deref() == Some(key),
+            })
+            .map(|(_, v)| *v)
+    } else {
+        None
+    }
+}
+
+/// Run a shell command and return its stdout, None if it fails to start or is killed for running
+/// longer than COMMAND_TIMEOUT.
+fn command_output(command: &str) -> Option<String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    let mut stdout = child.stdout.take()?;
+    // Read from a thread so a command that hangs can not block completion.
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = stdout.read_to_end(&mut buf);
+        let _ = tx.send(buf);
+    });
+    match rx.recv_timeout(COMMAND_TIMEOUT) {
+        Ok(buf) => {
+            let _ = child.wait();
+            Some(String::from_utf8_lossy(&buf).to_string())
+        }
+        Err(_) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            None
+        }
+    }
+}
+
+/// Run a shell command and return its output lines, cached for cache_secs.
+fn command_lines(command: &str, cache_secs: u64) -> Vec<String> {
+    let cached = COMMAND_CACHE.with(|cache| {
+        cache
+            .borrow()
+            .get(command)
+            .filter(|(time, _)| time.elapsed() < Duration::from_secs(cache_secs))
+            .map(|(_, lines)| lines.clone())
+    });
+    if let Some(lines) = cached {
+        return lines;
+    }
+    let lines: Vec<String> = command_output(command)
+        .map(|output| {
+            output
+                .lines()
+                .map(|l| l.trim().to_string())
+                .filter(|l| !l.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+    COMMAND_CACHE.with(|cache| {
+        cache
+            .borrow_mut()
+            .insert(command.to_string(), (Instant::now(), lines.clone()))
+    });
+    lines
+}
+
+fn call_spec_fn(env: &mut SloshVm, fun: Value, args: &[String]) -> VMResult<Value> {
+    env.pause_gc();
+    let args: Vec<Value> = args.iter().map(|a| env.alloc_string(a.clone())).collect();
+    let args = env.alloc_vector(args);
+    env.unpause_gc();
+    env.heap_sticky(args);
+    let res = match fun {
+        Value::Lambda(h) => {
+            let l = env.get_lambda(h);
+            env.do_call(l, &[args], None)
+        }
+        Value::Closure(h) => {
+            let (l, tcaps) = env.get_closure(h);
+            let caps = Vec::from(tcaps);
+            env.do_call(l, &[args], Some(&caps[..]))
+        }
+        _ => Err(VMError::new_vm("complete: spec is not a function")),
+    };
+    env.heap_unsticky(args);
+    res
+}
+
+/// Completions for a values spec: a list of words, a shell command (string or map with :cmd
+/// and :cache), a function called with the command's args, :files, :dirs or :default.
+fn values(env: &mut SloshVm, spec: Value, args: &[String], start: &str) -> VMResult<HookResult> {
+    Ok(match spec {
+        Value::Nil => HookResult::Default,
+        Value::Vector(_) | Value::List(_, _) | Value::Pair(_) => {
+            let words = spec.iter(env).map(|w| w.pretty_value(env)).collect();
+            HookResult::UseList(words)
+        }
+        Value::String(_) | Value::StringConst(_) => {
+            let command = string_value(env, spec).unwrap_or_default();
+            HookResult::UseList(command_lines(&command, DEFAULT_CACHE_SECS))
+        }
+        Value::Keyword(i) => match env.get_interned(i) {
+            "files" => HookResult::UseList(get_dir_matches(start)),
+            "dirs" => HookResult::Path,
+            "default" => HookResult::Default,
+            k => {
+                return Err(VMError::new_vm(format!(
+                    "complete: unknown completion :{k}, expected :files, :dirs or :default"
+                )))
+            }
+        },
+        Value::Lambda(_) | Value::Closure(_) => {
+            let res = call_spec_fn(env, spec, args)?;
+            // res may be shared (a literal in the function for instance) so do not use sticky.
+            env.pause_gc();
+            let res_values = values(env, res, args, start);
+            env.unpause_gc();
+            res_values?
+        }
+        Value::Map(_) if map_get(env, spec, "cmd").is_some() => {
+            let command = map_get(env, spec, "cmd")
+                .and_then(|c| string_value(env, c))
+                .ok_or_else(|| VMError::new_vm("complete: :cmd requires a string"))?;
+            let cache_secs = match map_get(env, spec, "cache") {
+                Some(secs) if secs.is_int() => secs.get_int(env)?.max(0) as u64,
+                _ => DEFAULT_CACHE_SECS,
+            };
+            HookResult::UseList(command_lines(&command, cache_secs))
+        }
+        Value::Map(_) => command_spec(env, spec, args, start)?,
+        _ => {
+            return Err(VMError::new_vm(format!(
+                "complete: invalid completion spec {}",
+                spec.display_value(env)
+            )))
+        }
+    })
+}
+
+/// Names of the flags in a :flags vector or map (flag to the values for its argument).
+fn flag_names(env: &mut SloshVm, flags: Value) -> Vec<String> {
+    match flags {
+        Value::Map(h) => env.get_map(h).keys().map(|k| k.pretty_value(env)).collect(),
+        _ => flags.iter(env).map(|f| f.pretty_value(env)).collect(),
+    }
+}
+
+/// Completions for a map spec with :subcommands (map of name to spec), :flags (vector of flags
+/// or map of flag to the values for its argument) and :args (values spec for other arguments).
+fn command_spec(
+    env: &mut SloshVm,
+    spec: Value,
+    args: &[String],
+    start: &str,
+) -> VMResult<HookResult> {
+    let mut node = spec;
+    let mut sub_args = args;
+    // Walk down the subcommand tree for the words before the one being completed.
+    for (i, word) in args.iter().enumerate().take(args.len().saturating_sub(1)) {
+        if let Some(sub) = map_get(env, node, "subcommands").and_then(|s| map_get(env, s, word)) {
+            if !matches!(sub, Value::Map(_)) || map_get(env, sub, "cmd").is_some() {
+                return values(env, sub, &args[i..], start);
+            }
+            node = sub;
+            sub_args = &args[i..];
+        }
+    }
+    let flags = map_get(env, node, "flags");
+    // Argument to a flag that takes values.
+    if let (Some(flags), Some(prev)) = (flags, args.len().checked_sub(2).map(|i| &args[i])) {
+        if let Some(flag_values) = map_get(env, flags, prev).filter(|v| !v.is_nil()) {
+            return values(env, flag_values, sub_args, start);
+        }
+    }
+    if start.starts_with('-') {
+        let mut names = flags.map(|f| flag_names(env, f)).unwrap_or_default();
+        names.sort();
+        return Ok(HookResult::UseList(names));
+    }
+    let mut names: Vec<String> = match map_get(env, node, "subcommands") {
+        Some(Value::Map(h)) => env.get_map(h).keys().map(|k| k.pretty_value(env)).collect(),
+        _ => Vec::new(),
+    };
+    names.sort();
+    match map_get(env, node, "args") {
+        Some(arg_spec) => match values(env, arg_spec, sub_args, start)? {
+            HookResult::UseList(list) => {
+                names.extend(list);
+                Ok(HookResult::UseList(names))
+            }
+            res if names.is_empty() => Ok(res),
+            _ => Ok(HookResult::UseList(names)),
+        },
+        None if names.is_empty() => Ok(HookResult::Default),
+        None => Ok(HookResult::UseList(names)),
+    }
+}
+
+/// Completions for the words of a command line (the last word is the one being completed)
+/// from the spec registered for its command. None if there is no spec.
+fn line_completions(env: &mut SloshVm, words: &[String]) -> VMResult<Option<HookResult>> {
+    let first = words
+        .iter()
+        .rposition(|w| COMMAND_SEPARATORS.contains(&w.as_str()))
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    let args = &words[first..];
+    // Need a command and the word being completed.
+    if args.len() < 2 {
+        return Ok(None);
+    }
+    let spec = match get_spec(env, &args[0]) {
+        Some(spec) => spec,
+        None => return Ok(None),
+    };
+    let start = args.last().map(|s| s.as_str()).unwrap_or_default();
+    Ok(Some(match values(env, spec, args, start)? {
+        HookResult::UseList(list) => {
+            HookResult::UseList(list.into_iter().filter(|c| c.starts_with(start)).collect())
+        }
+        res => res,
+    }))
+}
+
+/// Completions from a registered spec for the completer, None to use the default completions.
+pub(super) fn spec_completions(args: &[String], start: &str) -> Option<HookResult> {
+    let mut words = args.to_vec();
+    if let Some(last) = words.last_mut() {
+        *last = start.to_string();
+    }
+    let res = ENV.with(|renv| line_completions(&mut renv.borrow_mut(), &words));
+    match res {
+        Ok(Some(HookResult::Default)) | Ok(None) => None,
+        Ok(res) => res,
+        Err(e) => {
+            eprintln!("Error in completion spec: {e}");
+            None
+        }
+    }
+}
+
+/// Parse bash style complete args (-W wordlist, -C command, -f, -d, -r) and apply them.
+fn bash_complete(env: &mut SloshVm, args: &[String]) -> VMResult<()> {
+    let mut spec = None;
+    let mut remove = false;
+    let mut args = args.iter();
+    let mut commands = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-W" | "-C" => {
+                let val = args
+                    .next()
+                    .ok_or_else(|| VMError::new_vm(format!("complete: {arg} requires a value")))?;
+                spec = Some(if arg == "-W" {
+                    let words = val
+                        .split_whitespace()
+                        .map(|w| env.alloc_string(w.to_string()))
+                        .collect();
+                    env.alloc_vector(words)
+                } else {
+                    env.alloc_string(val.clone())
+                });
+            }
+            "-f" => spec = Some(Value::Keyword(env.intern_static("files"))),
+            "-d" => spec = Some(Value::Keyword(env.intern_static("dirs"))),
+            "-r" => remove = true,
+            opt if opt.starts_with('-') => {
+                return Err(VMError::new_vm(format!(
+                    "complete: unsupported option {opt}, expected -W, -C, -f, -d or -r"
+                )))
+            }
+            command => commands.push(command.to_string()),
+        }
+    }
+    let spec = match (spec, remove) {
+        (_, true) => Value::Nil,
+        (Some(spec), false) => spec,
+        (None, false) => {
+            return Err(VMError::new_vm(
+                "complete: requires one of -W, -C, -f, -d or -r",
+            ))
+        }
+    };
+    for command in commands {
+        set_spec(env, &command, spec);
+    }
+    Ok(())
+}
+
+fn complete(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    let strings: Option<Vec<String>> = registers.iter().map(|r| string_value(vm, *r)).collect();
+    match (registers, strings) {
+        ([], _) => {
+            let mut names: Vec<String> = SPECS.with(|specs| {
+                specs
+                    .borrow()
+                    .keys()
+                    .filter(|command| get_spec(vm, command).is_some())
+                    .cloned()
+                    .collect()
+            });
+            names.sort();
+            vm.pause_gc();
+            let names = names.into_iter().map(|n| vm.alloc_string(n)).collect();
+            let res = vm.alloc_vector(names);
+            vm.unpause_gc();
+            Ok(res)
+        }
+        (_, Some(args)) if args[0].starts_with('-') => {
+            bash_complete(vm, &args)?;
+            Ok(Value::Nil)
+        }
+        ([command], Some(_)) => {
+            let command = string_value(vm, *command).unwrap_or_default();
+            Ok(get_spec(vm, &command).unwrap_or(Value::Nil))
+        }
+        ([command, spec], _) => {
+            let command = string_value(vm, *command)
+                .ok_or_else(|| VMError::new_vm("complete: command must be a string"))?;
+            set_spec(vm, &command, *spec);
+            Ok(Value::Nil)
+        }
+        _ => Err(VMError::new_vm(
+            "complete: takes a command and spec, a command or bash style string options",
+        )),
+    }
+}
+
+fn complete_line(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    let line = match registers {
+        [line] => string_value(vm, *line)
+            .ok_or_else(|| VMError::new_vm("complete-line: requires a string"))?,
+        _ => return Err(VMError::new_vm("complete-line: requires one string")),
+    };
+    let mut words: Vec<String> = line.split_whitespace().map(|w| w.to_string()).collect();
+    if line.is_empty() || line.ends_with(char::is_whitespace) {
+        words.push(String::new());
+    }
+    let start = words.last().cloned().unwrap_or_default();
+    let list = match line_completions(vm, &words)? {
+        Some(HookResult::UseList(list)) => list,
+        Some(HookResult::Path) => get_path_matches(&start),
+        Some(HookResult::Default) | None => return Ok(Value::Nil),
+    };
+    vm.pause_gc();
+    let list = list.into_iter().map(|c| vm.alloc_string(c)).collect();
+    let res = vm.alloc_vector(list);
+    vm.unpause_gc();
+    Ok(res)
+}
+
+pub fn add_complete_builtins(env: &mut SloshVm) {
+    add_builtin(
+        env,
+        "complete",
+        complete,
+        r#"Usage: (complete command spec) | (complete command) | (complete) | (complete "-W" "words" command+)
+
+Register how the arguments of command are completed at the shell prompt, spec nil removes it.
+With just command returns its spec and with no args returns the commands that have specs.
+A spec is one of:
+- a vector or list of words
+- a string, a shell command that outputs the words one per line (cached for 30 seconds, it is
+  killed if it runs for more than 2 seconds)
+- a map with :cmd (shell command) and :cache (seconds to cache its output)
+- a function called with a vector of the command's words (the last is the one being
+  completed) that returns any other spec
+- :files, :dirs or :default (the normal completions)
+- a map with :subcommands (a map of subcommand name to spec), :flags (a vector of flags or a
+  map of flag to the spec for its argument, nil for no argument) and :args (the spec for
+  other arguments), this can be nested to any depth for subcommand trees.
+Bash style args are also accepted: -W "word list", -C "command", -f (files), -d (dirs) and
+-r (remove) followed by one or more commands.
+
+Section: shell
+
+Example:
+(complete "mytool" {:flags {"--help" nil "--out" :dirs}
+                    :subcommands {"start" {:args ["fast" "slow"] :flags ["--now"]}
+                                  "stop" ["all"]}})
+(test::assert-equal ["start" "stop"] (complete-line "mytool "))
+(test::assert-equal ["--help" "--out"] (complete-line "mytool --"))
+(test::assert-equal ["fast" "slow"] (complete-line "mytool start "))
+(test::assert-equal ["slow"] (complete-line "mytool start s"))
+(test::assert-equal ["--now"] (complete-line "mytool start -"))
+(test::assert-equal ["all"] (complete-line "mytool stop "))
+(complete "mytool2" (fn (args) (if (= (len args) 2) ["one" "two"] :default)))
+(test::assert-equal ["two"] (complete-line "mytool2 t"))
+(test::assert-equal nil (complete-line "mytool2 one "))
+(complete "mytool3" "printf 'a1\nb2\na3\n'")
+(test::assert-equal ["a1" "a3"] (complete-line "ls | mytool3 a"))
+(complete "mytool3" "sleep 10")
+(test::assert-equal [] (complete-line "mytool3 "))
+(complete "-W" "up down" "svc" "svc2")
+(test::assert-equal ["down"] (complete-line "svc2 d"))
+(test::assert-equal ["mytool" "mytool2" "mytool3" "svc" "svc2"] (complete))
+(complete "-r" "svc" "svc2")
+(complete "mytool" nil)
+(test::assert-equal nil (complete "mytool"))
+(test::assert-equal ["mytool2" "mytool3"] (complete))
+"#,
+    );
+    add_builtin(
+        env,
+        "complete-line",
+        complete_line,
+        r#"Usage: (complete-line string) -> vector or nil
+
+Return the completions from the spec registered with complete for the last word of the command
+line string (a trailing space completes a new word). Returns nil if there is no spec or the
+spec uses the default completions. Useful for testing completion specs.
+
+Section: shell
+
+Example:
+(complete "mytool4" ["alpha" "beta" "alps"])
+(test::assert-equal ["alpha" "alps"] (complete-line "mytool4 al"))
+(test::assert-equal nil (complete-line "not-a-mytool al"))
+(complete "mytool4" nil)
+"#,
+    );
+}
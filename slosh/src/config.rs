@@ -5,8 +5,28 @@ pub struct Config {
     pub command: Option<String>,
     pub script: Option<String>,
     pub args: Vec<String>,
+    pub profile: bool,
+    pub profile_folded: Option<String>,
+    pub coverage: Option<String>,
+    pub test: Option<String>,
+    pub test_filters: Vec<String>,
+    pub test_tags: Vec<String>,
+    pub test_format: TestFormat,
+    pub test_output: Option<String>,
+    pub fmt: Option<String>,
+    pub fmt_width: usize,
 }
 
+/// How slosh --test reports results.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TestFormat {
+    Tap,
+    Junit,
+}
+
+/// Default for --width, the line width slosh --fmt aims for.
+pub const FMT_WIDTH: usize = 100;
+
 pub const VERSION_STRING: &str = env!("VERSION_STRING");
 
 const HELP: &str = r#"slosh - Experimental Lisp REPL
@@ -19,7 +39,18 @@ FLAGS:
     -h, --help     Print help (this) and exit.
 
 OPTIONS:
-    -c             Command to run instead of entering the REPL.
+    -c                      Command to run instead of entering the REPL.
+    --profile               Profile the script or command, print the report to stderr when done.
+    --profile-folded FILE   Profile and write folded stacks (for flamegraph tools) to FILE.
+    --coverage FILE         Record which lines run and write them to FILE in lcov format.
+    --test PATH             Run the tests (deftest) in PATH, a file or a directory to search for
+                            files named *_test.slosh or test_*.slosh.
+    --filter NAME           With --test only run tests with NAME in their name (repeatable).
+    --tag TAG               With --test only run tests tagged TAG (repeatable).
+    --format FORMAT         With --test report results as tap (the default) or junit (XML).
+    --output FILE           With --test write the report to FILE instead of stdout.
+    --fmt FILE              Format the slosh source in FILE in place (- formats stdin to stdout).
+    --width N               With --fmt the line width to fit forms in (default 100).
 
 ARGS:
     <args>...      Script to run with arguments."#;
@@ -46,6 +77,16 @@ pub fn get_config() -> Option<Config> {
     let mut command: Option<String> = None;
     let mut script: Option<String> = None;
     let mut command_args: Vec<String> = Vec::new();
+    let mut profile = false;
+    let mut profile_folded: Option<String> = None;
+    let mut coverage: Option<String> = None;
+    let mut test: Option<String> = None;
+    let mut test_filters: Vec<String> = Vec::new();
+    let mut test_tags: Vec<String> = Vec::new();
+    let mut test_format = TestFormat::Tap;
+    let mut test_output: Option<String> = None;
+    let mut fmt: Option<String> = None;
+    let mut fmt_width = FMT_WIDTH;
 
     let mut args: Vec<OsString> = env::args_os().collect();
 
@@ -63,6 +104,47 @@ pub fn get_config() -> Option<Config> {
                         }
                         command = Some(get_arg(&exe_name, &mut args)?);
                     }
+                    "--profile" if script.is_none() => profile = true,
+                    "--profile-folded" if script.is_none() => {
+                        profile_folded = Some(get_arg(&exe_name, &mut args)?);
+                    }
+                    "--coverage" if script.is_none() => {
+                        coverage = Some(get_arg(&exe_name, &mut args)?);
+                    }
+                    "--test" if script.is_none() && command.is_none() => {
+                        test = Some(get_arg(&exe_name, &mut args)?);
+                    }
+                    "--filter" if script.is_none() => {
+                        test_filters.push(get_arg(&exe_name, &mut args)?);
+                    }
+                    "--tag" if script.is_none() => {
+                        test_tags.push(get_arg(&exe_name, &mut args)?);
+                    }
+                    "--format" if script.is_none() => {
+                        test_format = match &get_arg(&exe_name, &mut args)?[..] {
+                            "tap" => TestFormat::Tap,
+                            "junit" => TestFormat::Junit,
+                            _ => {
+                                help(&exe_name);
+                                return None;
+                            }
+                        };
+                    }
+                    "--output" if script.is_none() => {
+                        test_output = Some(get_arg(&exe_name, &mut args)?);
+                    }
+                    "--fmt" if script.is_none() && command.is_none() => {
+                        fmt = Some(get_arg(&exe_name, &mut args)?);
+                    }
+                    "--width" if script.is_none() => {
+                        fmt_width = match get_arg(&exe_name, &mut args)?.parse() {
+                            Ok(width) if width > 0 => width,
+                            _ => {
+                                help(&exe_name);
+                                return None;
+                            }
+                        };
+                    }
                     "-v" | "--version" => {
                         version();
                         return None;
@@ -89,5 +171,15 @@ pub fn get_config() -> Option<Config> {
         command,
         script,
         args: command_args,
+        profile,
+        profile_folded,
+        coverage,
+        test,
+        test_filters,
+        test_tags,
+        test_format,
+        test_output,
+        fmt,
+        fmt_width,
     })
 }
@@ -103,6 +103,30 @@ fn dump_stack(vm: &SloshVm) {
     }
 }
 
+/// Print the restarts (see restart-case) that were active when the error was raised.
+fn list_restarts(env: &SloshVm) {
+    let Some(slot) = env
+        .get_if_interned("*restarts*")
+        .and_then(|i| env.global_intern_slot(i))
+    else {
+        return;
+    };
+    let restarts: Vec<Value> = env.get_global(slot).iter(env).collect();
+    if restarts.is_empty() {
+        return;
+    }
+    println!("Restarts active at the error (innermost first):");
+    for (i, restart) in restarts.iter().enumerate() {
+        let mut parts = restart.iter(env);
+        let name = parts.next().unwrap_or(Value::Nil);
+        let params = match parts.next() {
+            Some(Value::Nil) | None => "()".to_string(),
+            Some(params) => params.display_value(env),
+        };
+        println!("{:#3}: {} {}", i, name.display_value(env), params);
+    }
+}
+
 pub fn debug(env: &mut SloshVm) {
     let abort = env.intern("abort");
     let globals = env.intern("globals");
@@ -110,7 +134,10 @@ pub fn debug(env: &mut SloshVm) {
     let regs = env.intern("regs");
     let regs_raw = env.intern("regs-raw");
     let stack = env.intern("stack");
+    let restarts = env.intern("restarts");
+    let restarts_global = env.intern("*restarts*");
     let mut con = Context::new();
+    list_restarts(env);
 
     if let Err(e) = con.history.set_file_name_and_load_history("history_debug") {
         println!("Error loading history: {e}");
@@ -144,7 +171,6 @@ pub fn debug(env: &mut SloshVm) {
         // This should be fine, we are abusing the reader to parse debug input so should be no chance
         // any string pointers are saved.  Could intern these as well for a legit 'static but should
         // not need that (although a lot of debug commands will be repetitive so may not be a big deal.
-        // TODO- once there is a "secure" mode for the reader use that here.
         //let text: &str = &res;
         //let text = unsafe { &*(text as *const str) };
         //let exps = read_all(vm, &mut reader_state, text);
@@ -155,8 +181,13 @@ pub fn debug(env: &mut SloshVm) {
         match exps.next() {
             Some(Ok(Value::Keyword(k))) if k == abort => {
                 env.reset();
+                // The restart-case forms were unwound without running their defers.
+                if let Some(slot) = env.global_intern_slot(restarts_global) {
+                    env.set_global(slot, Value::Nil);
+                }
                 return;
             }
+            Some(Ok(Value::Keyword(k))) if k == restarts => list_restarts(env),
             Some(Ok(Value::Keyword(k))) if k == globals => env.dump_globals(),
             Some(Ok(Value::Keyword(k))) if k == dasm => {
                 if let Some(Ok(parm)) = exps.next() {
@@ -1,8 +1,8 @@
 use bridge_adapters::add_builtin;
 use bridge_adapters::lisp_adapters::SlFrom;
+use builtins::doc_string::{self, DocString};
 use compile_state::state::{SloshVm, SloshVmTrait};
 use lazy_static::lazy_static;
-use regex::{Regex, RegexBuilder};
 use slvm::VMErrorObj::Message;
 use slvm::{Interned, VMError, VMResult, Value};
 use std::borrow::Cow;
@@ -18,15 +18,6 @@ const SECTION: &str = "section";
 const EXAMPLE: &str = "example";
 
 lazy_static! {
-    static ref DOC_REGEX: Regex =
-    //TODO PC optional Usage section OR must be auto generated?
-    // legacy/builtins.rs L#937
-        RegexBuilder::new(r#"(\s*?Usage:(.+?)$\n\n|\s*?)(\S{1}.*)\n\n\s*Section:(.+?)$(\n\n\s*Example:\n(.*)|\s*)"#)
-            .multi_line(true)
-            .dot_matches_new_line(true)
-            .crlf(true)
-            .build()
-            .unwrap();
     static ref EXEMPTIONS: HashSet<&'static str> = {
         let mut exemption_set = HashSet::new();
         exemption_set.insert("version");
@@ -61,6 +52,8 @@ lazy_static! {
         exemption_set.insert("*int-max*");
         exemption_set.insert("prn");
         exemption_set.insert("pr");
+        exemption_set.insert("eprn");
+        exemption_set.insert("epr");
         exemption_set.insert("sizeof-value");
         exemption_set.insert("dump-regs");
         exemption_set.insert("dasm");
@@ -80,7 +73,10 @@ lazy_static! {
         exemption_set.insert("tok-sys-command-color");
         exemption_set.insert("tok-sys-alias-color");
         exemption_set.insert("tok-string-color");
+        exemption_set.insert("tok-number-color");
+        exemption_set.insert("tok-redirect-color");
         exemption_set.insert("tok-invalid-color");
+        exemption_set.insert("*syntax-highlight*");
 
         exemption_set.insert("*fg-default*");
         exemption_set.insert("*fg-black*");
@@ -198,29 +194,26 @@ impl Display for DocStringSection {
 
 impl DocStringSection {
     pub fn from_symbol(slot: u32, sym: Value, vm: &mut SloshVm) -> DocResult<DocStringSection> {
-        let docstring_key = vm.intern_static("doc-string");
         let sym_str = sym.display_value(&vm);
-        let raw_doc_string = vm
-            .get_global_property(slot, docstring_key)
-            .map_or(None, |x| match x {
-                Value::String(h) => Some(vm.get_string(h).to_string()),
-                Value::StringConst(i) => Some(vm.get_interned(i).to_string()),
-                _ => None,
-            })
-            // return default empty string and have parse_doc_string handle error if no doc provided.
-            .unwrap_or_default();
+        // return default empty string and have parse_doc_string handle error if no doc provided.
+        let raw_doc_string = doc_string::global_doc_string(vm, slot).unwrap_or_default();
         let backup_usage = crate::usage(vm, slot, &sym);
         Self::parse_doc_string(Cow::Owned(sym_str), raw_doc_string, backup_usage)
     }
 
-    /// Given the rules for parsing slosh docstrings, parse one! See [`DOC_REGEX`]
-    /// for the specification.
+    /// Given the rules for parsing slosh docstrings, parse one! See
+    /// [`doc_string::DOC_REGEX`] for the specification.
     pub fn parse_doc_string(
         symbol: Cow<'_, String>,
         raw_doc_string: String,
         backup_usage: String,
     ) -> DocResult<DocStringSection> {
-        let cap = DOC_REGEX.captures(raw_doc_string.as_str()).ok_or_else(|| {
+        let DocString {
+            mut usage,
+            description,
+            section,
+            example,
+        } = DocString::parse(raw_doc_string.as_str()).ok_or_else(|| {
             if EXEMPTIONS.contains(symbol.as_str()) {
                 DocError::ExemptFromProperDocString {
                     symbol: symbol.to_owned().to_string(),
@@ -231,25 +224,9 @@ impl DocStringSection {
                 }
             }
         })?;
-        let mut usage = cap.get(2).map(|x| x.as_str().trim().to_string());
         if usage.is_none() && !backup_usage.trim().is_empty() {
             usage = Some(backup_usage);
         }
-        let description = cap
-            .get(3)
-            .ok_or_else(|| DocError::DocStringMissingSection {
-                symbol: symbol.to_owned().to_string(),
-                section: "Description".to_string(),
-            })
-            .map(|x| x.as_str().to_string())?;
-        let section = cap
-            .get(4)
-            .ok_or_else(|| DocError::DocStringMissingSection {
-                symbol: symbol.to_owned().to_string(),
-                section: "Section".to_string(),
-            })
-            .map(|x| x.as_str().trim().to_string())?;
-        let example = cap.get(6).map(|x| x.as_str().trim().to_string());
 
         Ok(DocStringSection {
             usage,
@@ -342,7 +319,6 @@ impl SloshDoc {
 enum DocError {
     NoSymbol { symbol: String },
     NoDocString { symbol: String },
-    DocStringMissingSection { symbol: String, section: String },
     ExemptFromProperDocString { symbol: String },
 }
 
@@ -370,9 +346,6 @@ impl Display for DocError {
                     "No documentation exists for provided symbol {symbol}, this should be rectified."
                 )
             }
-            DocError::DocStringMissingSection { symbol, section } => {
-                format!("Invalid documentation string for symbol {symbol}, missing required section {section:?}")
-            }
         }
             .to_string();
         write!(f, "{}", str)
@@ -437,8 +410,11 @@ impl SlFrom<SloshDoc> for HashMap<Value, Value> {
 
 impl SlFrom<SloshDoc> for Value {
     fn sl_from(value: SloshDoc, vm: &mut SloshVm) -> VMResult<Self> {
-        let map = HashMap::sl_from(value, vm)?.into();
-        Ok(vm.alloc_map(map))
+        // The section strings are not rooted until the map is allocated.
+        vm.pause_gc();
+        let res = HashMap::sl_from(value, vm).map(|map| vm.alloc_map(map));
+        vm.unpause_gc();
+        res
     }
 }
 
@@ -506,11 +482,12 @@ Section: core
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::{run_reader, set_builtins, set_initial_load_path, ENV};
+    use crate::{coverage_lcov, run_reader, set_builtins, set_initial_load_path, ENV};
     use compile_state::state::new_slosh_vm;
     use sl_compiler::Reader;
     use std::collections::BTreeMap;
     use std::ops::DerefMut;
+    use std::path::PathBuf;
     use tempdir::TempDir;
 
     #[test]
@@ -525,6 +502,11 @@ mod test {
                 let mut vm = env.borrow_mut();
                 set_builtins(vm.deref_mut());
                 set_initial_load_path(vm.deref_mut(), vec![&home_path]);
+                // Set SLOSH_COVERAGE_DIR to write the lines the examples run as lcov.
+                let coverage_dir = std::env::var("SLOSH_COVERAGE_DIR").ok();
+                if coverage_dir.is_some() {
+                    vm.set_coverage(Some(slvm::Coverage::new()));
+                }
                 let mut reader =
                     Reader::from_string(r#"(load "core.slosh")"#.to_string(), &mut vm, "", 1, 0);
                 _ = run_reader(&mut reader).unwrap();
@@ -548,6 +530,11 @@ mod test {
                         println!("{} ===============================", symbol);
                     }
                 }
+                if let (Some(dir), Some(coverage)) = (coverage_dir, vm.take_coverage()) {
+                    let lisp_dir = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/../lisp"));
+                    let lcov = coverage_lcov(&mut vm, &coverage, &[lisp_dir]);
+                    std::fs::write(PathBuf::from(dir).join("doc-examples.lcov"), lcov).unwrap();
+                }
             })
         });
     }
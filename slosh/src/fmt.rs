@@ -0,0 +1,652 @@
+//! slosh --fmt, re-indent slosh source keeping its comments, doc strings and blank lines.
+//!
+//! The Reader drops comments so the source is first split into the text of each atom, comment
+//! and list.  A list is printed on one line if it fits in the width, otherwise it is broken using
+//! the rule for its head (see [`body_args`]).  The result is read back with the Reader and has to
+//! give the same forms as the original, so formatting never changes what a file means.
+
+use std::fs;
+use std::io::{self, Read, Write};
+
+use compile_state::state::new_slosh_vm;
+use sl_compiler::Reader;
+
+/// A piece of source as it was written.
+#[derive(Debug)]
+enum Node {
+    /// Symbol, number, string, char, etc (a string can span lines).
+    Atom(String),
+    /// A ; or #! comment, runs to the end of its line.
+    Comment(String),
+    /// A #| |# comment or a #% %# doc string.
+    Block(String),
+    /// A form after ', `, ~, ~@, ~., #. or #;.
+    Prefixed(&'static str, Box<Node>),
+    /// A list, vector or map with its open char.
+    Seq(char, Vec<Item>),
+}
+
+impl Node {
+    fn is_comment(&self) -> bool {
+        matches!(self, Node::Comment(_) | Node::Block(_))
+    }
+}
+
+/// A node in a sequence (or the file) and the white space before it.
+#[derive(Debug)]
+struct Item {
+    node: Node,
+    /// Started a line in the source.
+    newline: bool,
+    /// Came after a blank line in the source (runs of them are kept as one).
+    blank: bool,
+}
+
+/// Same as the Reader, a comma is white space.
+fn is_whitespace(ch: char) -> bool {
+    matches!(ch, ' ' | '\t' | '\n' | ',')
+}
+
+/// Chars that end a symbol, same as the Reader.
+fn end_symbol(ch: char) -> bool {
+    is_whitespace(ch)
+        || matches!(
+            ch,
+            '(' | ')' | '#' | '"' | '~' | '\'' | '`' | '[' | ']' | '{' | '}' | '\\' | ';'
+        )
+}
+
+fn close_of(open: char) -> char {
+    match open {
+        '[' => ']',
+        '{' => '}',
+        _ => ')',
+    }
+}
+
+/// Splits source into nodes, keeping the text of each.
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Parser {
+    fn new(text: &str) -> Self {
+        Self {
+            chars: text.chars().collect(),
+            pos: 0,
+            line: 1,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let ch = self.peek()?;
+        self.pos += 1;
+        if ch == '\n' {
+            self.line += 1;
+        }
+        Some(ch)
+    }
+
+    fn next_or(&mut self, what: &str, line: usize) -> Result<char, String> {
+        self.next()
+            .ok_or_else(|| format!("line {line}: unclosed {what}"))
+    }
+
+    fn text(&self, start: usize) -> String {
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    /// Skip white space, returns the number of newlines skipped.
+    fn skip_whitespace(&mut self) -> usize {
+        let mut newlines = 0;
+        while let Some(ch) = self.peek() {
+            if !is_whitespace(ch) {
+                break;
+            }
+            if ch == '\n' {
+                newlines += 1;
+            }
+            self.next();
+        }
+        newlines
+    }
+
+    /// The items up to close (or the end of the text if None), consumes close.
+    fn items(&mut self, close: Option<char>) -> Result<Vec<Item>, String> {
+        let line = self.line;
+        let mut items = Vec::new();
+        loop {
+            let newlines = self.skip_whitespace();
+            match (self.peek(), close) {
+                (None, None) => return Ok(items),
+                (None, Some(close)) => return Err(format!("line {line}: missing {close}")),
+                (Some(ch @ (')' | ']' | '}')), _) => {
+                    if Some(ch) != close {
+                        return Err(format!("line {}: unexpected {ch}", self.line));
+                    }
+                    self.next();
+                    return Ok(items);
+                }
+                (Some(_), _) => items.push(Item {
+                    node: self.node()?,
+                    newline: newlines > 0,
+                    blank: newlines > 1,
+                }),
+            }
+        }
+    }
+
+    fn node(&mut self) -> Result<Node, String> {
+        let line = self.line;
+        let start = self.pos;
+        match self.next_or("form", line)? {
+            open @ ('(' | '[' | '{') => Ok(Node::Seq(open, self.items(Some(close_of(open)))?)),
+            ';' => Ok(self.line_comment(start)),
+            '\'' => self.prefixed("'"),
+            '`' => self.prefixed("`"),
+            '~' => match self.peek() {
+                Some('@') => {
+                    self.next();
+                    self.prefixed("~@")
+                }
+                Some('.') => {
+                    self.next();
+                    self.prefixed("~.")
+                }
+                _ => self.prefixed("~"),
+            },
+            '"' => {
+                self.string(line)?;
+                Ok(Node::Atom(self.text(start)))
+            }
+            '\\' => {
+                // The char after \ is part of it even if it would end a symbol.
+                self.next_or("char", line)?;
+                self.symbol();
+                Ok(Node::Atom(self.text(start)))
+            }
+            '#' => match self.next_or("#", line)? {
+                '!' => Ok(self.line_comment(start)),
+                '|' => {
+                    self.block_comment(line)?;
+                    Ok(Node::Block(self.text(start)))
+                }
+                '%' => {
+                    self.doc_string(line)?;
+                    Ok(Node::Block(self.text(start)))
+                }
+                '.' => self.prefixed("#."),
+                ';' => self.prefixed("#;"),
+                '"' => {
+                    self.raw_string(line)?;
+                    Ok(Node::Atom(self.text(start)))
+                }
+                _ => {
+                    self.symbol();
+                    Ok(Node::Atom(self.text(start)))
+                }
+            },
+            _ => {
+                self.symbol();
+                Ok(Node::Atom(self.text(start)))
+            }
+        }
+    }
+
+    fn prefixed(&mut self, prefix: &'static str) -> Result<Node, String> {
+        self.skip_whitespace();
+        let line = self.line;
+        match self.peek() {
+            None | Some(')' | ']' | '}') => Err(format!("line {line}: nothing after {prefix}")),
+            Some(_) => {
+                let node = self.node()?;
+                if node.is_comment() {
+                    Err(format!("line {line}: comment after {prefix}"))
+                } else {
+                    Ok(Node::Prefixed(prefix, Box::new(node)))
+                }
+            }
+        }
+    }
+
+    fn symbol(&mut self) {
+        while let Some(ch) = self.peek() {
+            // The Reader keeps ~ after . in a symbol for gets like v.~i.
+            if end_symbol(ch) && !(ch == '~' && self.chars[self.pos - 1] == '.') {
+                break;
+            }
+            self.next();
+        }
+    }
+
+    fn line_comment(&mut self, start: usize) -> Node {
+        while self.peek().is_some_and(|ch| ch != '\n') {
+            self.next();
+        }
+        Node::Comment(self.text(start).trim_end().to_string())
+    }
+
+    fn string(&mut self, line: usize) -> Result<(), String> {
+        loop {
+            match self.next_or("string", line)? {
+                '\\' => {
+                    self.next_or("string", line)?;
+                }
+                '"' => return Ok(()),
+                _ => {}
+            }
+        }
+    }
+
+    /// #"X...X", the char after #" ends the string when followed by ".
+    fn raw_string(&mut self, line: usize) -> Result<(), String> {
+        let end = self.next_or("string", line)?;
+        loop {
+            if self.next_or("string", line)? == end && self.peek() == Some('"') {
+                self.next();
+                return Ok(());
+            }
+        }
+    }
+
+    /// Block comments nest.
+    fn block_comment(&mut self, line: usize) -> Result<(), String> {
+        let mut depth = 1;
+        loop {
+            match (self.next_or("#| comment", line)?, self.peek()) {
+                ('|', Some('#')) => {
+                    self.next();
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+                ('#', Some('|')) => {
+                    self.next();
+                    depth += 1;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn doc_string(&mut self, line: usize) -> Result<(), String> {
+        loop {
+            match (self.next_or("doc string", line)?, self.peek()) {
+                ('\\', _) => {
+                    self.next_or("doc string", line)?;
+                }
+                ('%', Some('#')) => {
+                    self.next();
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// How many arguments of a form with a body stay on the first line with its head, the rest are
+/// indented two spaces on their own lines.  None for calls, which line their arguments up under
+/// the first.
+fn body_args(head: &str) -> Option<usize> {
+    match head {
+        "do" | "cond" | "defer" | "get-error" => Some(0),
+        "def" | "fn" | "macro" | "let" | "when" | "while" | "match" | "dotimes" | "block"
+        | "deftest" => Some(1),
+        "defn" | "defmacro" | "loop" | "dyn" | "dotimes-i" => Some(2),
+        "seq-for" | "let-while" => Some(3),
+        _ => None,
+    }
+}
+
+/// Does the head of (head ...) take name value pairs as its argument arg (from 1)?
+fn pairs_arg(head: Option<&str>, arg: usize) -> bool {
+    matches!((head, arg), (Some("let"), 1) | (Some("let-while"), 1 | 2))
+}
+
+/// Could text (an atom) be a symbol, the head of a call?
+fn is_symbol(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some('0'..='9' | '"' | ':' | '\\' | '#') | None => false,
+        Some('-' | '+') => !chars.next().is_some_and(|ch| ch.is_ascii_digit()),
+        Some(_) => true,
+    }
+}
+
+fn width(text: &str) -> usize {
+    text.chars().count()
+}
+
+/// Does a list stay broken?  Forms with a body do if they were in the source.
+fn keep_lines(open: char, items: &[Item]) -> bool {
+    head(open, items).and_then(body_args).is_some() && items.iter().skip(1).any(|item| item.newline)
+}
+
+/// node on one line, None if it can not be (it has a comment, a blank line, a multi-line string or
+/// a form that stays broken).
+fn flat(node: &Node) -> Option<String> {
+    match node {
+        Node::Atom(text) => (!text.contains('\n')).then(|| text.clone()),
+        Node::Comment(_) | Node::Block(_) => None,
+        Node::Prefixed(prefix, node) => flat(node).map(|text| format!("{prefix}{text}")),
+        Node::Seq(open, items) => {
+            if keep_lines(*open, items) {
+                return None;
+            }
+            let mut res = open.to_string();
+            for (i, item) in items.iter().enumerate() {
+                if item.blank {
+                    return None;
+                }
+                if i > 0 {
+                    res.push(' ');
+                }
+                res.push_str(&flat(&item.node)?);
+            }
+            res.push(close_of(*open));
+            Some(res)
+        }
+    }
+}
+
+/// Where the items of a broken sequence go.
+#[derive(Copy, Clone)]
+enum Layout {
+    /// The head and this many arguments on the first line, one item per line after.
+    Lines(usize),
+    /// As many items on a line as fit (vectors and data lists).
+    Fill,
+    /// Two items on a line (maps and let bindings).
+    Pairs,
+    /// The head, the first test and its form on the first line then a test and form per line (an
+    /// if with more than one test).
+    Clauses,
+}
+
+struct Printer {
+    out: String,
+    col: usize,
+    width: usize,
+}
+
+impl Printer {
+    fn push(&mut self, text: &str) {
+        self.out.push_str(text);
+        match text.rfind('\n') {
+            Some(i) => self.col = width(&text[i + 1..]),
+            None => self.col += width(text),
+        }
+    }
+
+    fn newline(&mut self, blank: bool, indent: usize) {
+        if blank {
+            self.out.push('\n');
+        }
+        self.out.push('\n');
+        self.out.push_str(&" ".repeat(indent));
+        self.col = indent;
+    }
+
+    /// The top level forms, each on its own line (a comment after a form stays on its line).
+    fn file(&mut self, items: &[Item]) {
+        let mut after_comment = false;
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                if item.node.is_comment() && !item.newline && !after_comment {
+                    self.push(" ");
+                } else {
+                    self.newline(item.blank, 0);
+                }
+            }
+            self.node(&item.node, false);
+            after_comment = matches!(item.node, Node::Comment(_));
+        }
+        if !items.is_empty() {
+            self.out.push('\n');
+        }
+    }
+
+    /// Print node at the current column, pairs if it is a list of name value pairs.
+    fn node(&mut self, node: &Node, pairs: bool) {
+        match node {
+            Node::Atom(text) | Node::Comment(text) | Node::Block(text) => self.push(text),
+            Node::Prefixed(prefix, node) => {
+                self.push(prefix);
+                self.node(node, false);
+            }
+            Node::Seq(open, items) => {
+                if let Some(text) = flat(node) {
+                    if self.col + width(&text) <= self.width {
+                        self.push(&text);
+                        return;
+                    }
+                }
+                self.seq(*open, items, head(*open, items), pairs);
+            }
+        }
+    }
+
+    fn seq(&mut self, open: char, items: &[Item], head: Option<&str>, pairs: bool) {
+        let start = self.col;
+        let (layout, indent) = if pairs || open == '{' {
+            (Layout::Pairs, start + 1)
+        } else if open == '[' {
+            (Layout::Fill, start + 1)
+        } else if let Some(head) = head {
+            let align = start + width(head) + 2;
+            let args = items.iter().filter(|item| !item.node.is_comment()).count() - 1;
+            match body_args(head) {
+                Some(args) => (Layout::Lines(args), start + 2),
+                None if head == "if" && args > 3 => (Layout::Clauses, align),
+                None if align <= self.width / 2 => (Layout::Lines(1), align),
+                None => (Layout::Lines(0), start + 2),
+            }
+        } else if matches!(
+            items.first(),
+            Some(Item {
+                node: Node::Atom(_),
+                ..
+            })
+        ) {
+            // Starts with a number, string, keyword, etc so data.
+            (Layout::Fill, start + 1)
+        } else {
+            (Layout::Lines(0), start + 1)
+        };
+        self.push(&open.to_string());
+        let mut after_comment = false;
+        // Forms (not comments) printed so far.
+        let mut forms = 0;
+        for (i, item) in items.iter().enumerate() {
+            let comment = item.node.is_comment();
+            let same_line = if i == 0 {
+                !item.blank && !(comment && item.newline)
+            } else if after_comment || item.blank {
+                false
+            } else if comment {
+                !item.newline
+            } else {
+                match layout {
+                    Layout::Lines(args) => forms <= args,
+                    Layout::Pairs => forms % 2 == 1,
+                    Layout::Clauses => forms == 1 || forms % 2 == 0,
+                    Layout::Fill => flat(&item.node)
+                        .is_some_and(|text| self.col + 1 + width(&text) <= self.width),
+                }
+            };
+            if !same_line {
+                self.newline(item.blank, indent);
+            } else if i > 0 {
+                self.push(" ");
+            }
+            self.node(&item.node, !comment && pairs_arg(head, forms));
+            after_comment = matches!(item.node, Node::Comment(_));
+            if !comment {
+                forms += 1;
+            }
+        }
+        if after_comment {
+            self.newline(false, indent);
+        }
+        self.push(&close_of(open).to_string());
+    }
+}
+
+/// The symbol a list starts with, if it does.
+fn head(open: char, items: &[Item]) -> Option<&str> {
+    match items.first() {
+        Some(Item {
+            node: Node::Atom(text),
+            ..
+        }) if open == '(' && is_symbol(text) => Some(text),
+        _ => None,
+    }
+}
+
+/// Read text with the Reader, each form as text.
+fn read_forms(text: &str) -> Result<Vec<String>, String> {
+    let mut vm = new_slosh_vm();
+    let mut reader = Reader::from_string(text.to_string(), &mut vm, "", 1, 0);
+    let mut forms = Vec::new();
+    while let Some(exp) = reader.next() {
+        match exp {
+            Ok(exp) => forms.push(exp.display_value(reader.vm())),
+            Err(err) => return Err(format!("line {}: {}", reader.line(), err.reason)),
+        }
+    }
+    Ok(forms)
+}
+
+/// Format slosh source to fit in width columns (where it can).
+pub fn format_source(text: &str, width: usize) -> Result<String, String> {
+    let forms = read_forms(text)?;
+    let items = Parser::new(text).items(None)?;
+    let mut printer = Printer {
+        out: String::new(),
+        col: 0,
+        width,
+    };
+    printer.file(&items);
+    match read_forms(&printer.out) {
+        Ok(new_forms) if new_forms == forms => Ok(printer.out),
+        _ => Err("formatting would change the forms read, the file was left as is".to_string()),
+    }
+}
+
+/// Format file in place (only written if it changes), - formats stdin to stdout.
+pub fn format_file(file: &str, width: usize) -> i32 {
+    let text = if file == "-" {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text).map(|_| text)
+    } else {
+        fs::read_to_string(file)
+    };
+    let text = match text {
+        Ok(text) => text,
+        Err(err) => {
+            eprintln!("ERROR reading {file}: {err}");
+            return 1;
+        }
+    };
+    let formatted = match format_source(&text, width) {
+        Ok(formatted) => formatted,
+        Err(err) => {
+            eprintln!("ERROR formatting {file}: {err}");
+            return 1;
+        }
+    };
+    let res = if file == "-" {
+        io::stdout().write_all(formatted.as_bytes())
+    } else if formatted != text {
+        fs::write(file, formatted)
+    } else {
+        Ok(())
+    };
+    if let Err(err) = res {
+        eprintln!("ERROR writing {file}: {err}");
+        return 1;
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(text: &str, width: usize) -> String {
+        let res = format_source(text, width).unwrap();
+        assert_eq!(format_source(&res, width).unwrap(), res, "idempotent");
+        res
+    }
+
+    #[test]
+    fn test_fits_on_a_line() {
+        assert_eq!(format("(+  1\n   2)", 80), "(+ 1 2)\n");
+        assert_eq!(format("'( a  b )  `(c ~@d)", 80), "'(a b)\n`(c ~@d)\n");
+    }
+
+    #[test]
+    fn test_form_rules() {
+        let src = "(defn f (x) (let (a 1 b 2) (if (> x a) (cond ((= x 1) \"one\") (#t b)) x)))";
+        assert_eq!(
+            format(src, 30),
+            r#"(defn f (x)
+  (let (a 1 b 2)
+    (if (> x a)
+        (cond
+          ((= x 1) "one")
+          (#t b))
+        x)))
+"#
+        );
+        assert_eq!(
+            format("(match v (1 :one) (2 :two))", 20),
+            "(match v\n  (1 :one)\n  (2 :two))\n"
+        );
+        assert_eq!(
+            format("(let (first-name 1 second-name 2) a)", 20),
+            "(let (first-name 1\n      second-name 2)\n  a)\n"
+        );
+        // Body forms broken in the source stay broken.
+        assert_eq!(format("(defn f ()\n  1)", 80), "(defn f ()\n  1)\n");
+    }
+
+    #[test]
+    fn test_if_clauses() {
+        assert_eq!(
+            format(
+                "(if (vec? o) (vec-first o) (list? o) (car o) (err \"bad\"))",
+                30
+            ),
+            "(if (vec? o) (vec-first o)\n    (list? o) (car o)\n    (err \"bad\"))\n"
+        );
+        assert_eq!(
+            format("(if (> x 1000) (str \"big \" x) (str \"small \" x))", 30),
+            "(if (> x 1000)\n    (str \"big \" x)\n    (str \"small \" x))\n"
+        );
+    }
+
+    #[test]
+    fn test_comments_and_blank_lines() {
+        let src = "#!/usr/bin/env slosh\n\n\n#%\nDoc.\n%#\n(defn f (x) ; trailing\n\n  ; own line\n  x)\n#| block |#\n(f 1) ; after\n";
+        assert_eq!(
+            format(src, 80),
+            "#!/usr/bin/env slosh\n\n#%\nDoc.\n%#\n(defn f (x) ; trailing\n\n  ; own line\n  x)\n#| block |#\n(f 1) ; after\n"
+        );
+        assert_eq!(format("(f a ; why\n b)", 80), "(f a ; why\n   b)\n");
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(format_source("(f a", 80).is_err());
+        assert!(format_source("#.(+ 1 2)", 80).is_err());
+    }
+}
@@ -0,0 +1,514 @@
+use crate::SHELL_ENV;
+use compile_state::state::{SloshVm, SloshVmTrait};
+use shell::builtins::{expand_tilde, is_builtin};
+use slvm::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Used to end a color if tok-default-color is not set.
+const FG_DEFAULT: &str = "\x1b[39m";
+
+/// Colors for each kind of token, read from the tok-*-color globals (see sh-color.slosh).
+/// A missing color leaves that kind of token uncolored.
+struct Colors {
+    form: String,
+    fcn: String,
+    default: String,
+    command: String,
+    alias: String,
+    string: String,
+    number: String,
+    redirect: String,
+    invalid: String,
+}
+
+impl Colors {
+    fn new(env: &SloshVm) -> Self {
+        let default = color(env, "tok-default-color");
+        Self {
+            form: color(env, "tok-slsh-form-color"),
+            fcn: color(env, "tok-slsh-fcn-color"),
+            default: if default.is_empty() {
+                FG_DEFAULT.to_string()
+            } else {
+                default
+            },
+            command: color(env, "tok-sys-command-color"),
+            alias: color(env, "tok-sys-alias-color"),
+            string: color(env, "tok-string-color"),
+            number: color(env, "tok-number-color"),
+            redirect: color(env, "tok-redirect-color"),
+            invalid: color(env, "tok-invalid-color"),
+        }
+    }
+
+    fn paint(&self, out: &mut String, color: &str, text: &str) {
+        if color.is_empty() {
+            out.push_str(text);
+        } else {
+            out.push_str(color);
+            out.push_str(text);
+            out.push_str(&self.default);
+        }
+    }
+}
+
+/// Value of the global name without interning anything, None if not defined.
+fn global(env: &SloshVm, name: &str) -> Option<Value> {
+    let slot = env.global_intern_slot(env.get_if_interned(name)?)?;
+    match env.get_global(slot) {
+        Value::Undefined => None,
+        val => Some(val),
+    }
+}
+
+fn color(env: &SloshVm, name: &str) -> String {
+    match global(env, name) {
+        Some(Value::String(h)) => env.get_string(h).to_string(),
+        Some(Value::StringConst(i)) => env.get_interned(i).to_string(),
+        _ => String::new(),
+    }
+}
+
+/// True if highlighting was turned on with syntax-on.
+pub fn syntax_on(env: &SloshVm) -> bool {
+    global(env, "*syntax-highlight*").is_some_and(|v| v.is_truethy())
+}
+
+/// Return line with terminal color codes added, the visible text is unchanged.
+/// Lines starting with '(' are highlighted as lisp, anything else as a shell command line.
+pub fn highlight(env: &SloshVm, line: &str) -> String {
+    let colors = Colors::new(env);
+    if line.starts_with('(') {
+        highlight_lisp(env, &colors, line)
+    } else {
+        highlight_shell(env, &colors, line)
+    }
+}
+
+/// Length of the quoted string at the start of text (including the quotes), all of text if the
+/// quote is not closed.  Backslash escapes the next char if escapes is true.
+fn quoted_len(text: &str, escapes: bool) -> usize {
+    let mut chars = text.char_indices();
+    let quote = chars.next().map(|(_, ch)| ch);
+    while let Some((i, ch)) = chars.next() {
+        if escapes && ch == '\\' {
+            chars.next();
+        } else if Some(ch) == quote {
+            return i + ch.len_utf8();
+        }
+    }
+    text.len()
+}
+
+fn is_number(token: &str) -> bool {
+    let token = token.strip_prefix(['-', '+']).unwrap_or(token);
+    let token = token.strip_prefix('.').unwrap_or(token);
+    token.starts_with(|ch: char| ch.is_ascii_digit())
+}
+
+fn is_lisp_delim(ch: char) -> bool {
+    ch.is_whitespace() || matches!(ch, '(' | ')' | '[' | ']' | '{' | '}' | '"' | ';')
+}
+
+fn lisp_token_len(text: &str) -> usize {
+    text.find(is_lisp_delim).unwrap_or(text.len())
+}
+
+fn lisp_token_color<'c>(env: &SloshVm, colors: &'c Colors, token: &str) -> &'c str {
+    if is_number(token) {
+        return &colors.number;
+    }
+    match global(env, token) {
+        Some(Value::Special(_)) => &colors.form,
+        Some(val @ (Value::Lambda(_) | Value::Closure(_)))
+            if matches!(env.get_heap_property(val, ":macro"), Some(Value::True)) =>
+        {
+            &colors.form
+        }
+        Some(Value::Builtin(_) | Value::Lambda(_) | Value::Closure(_) | Value::Continuation(_)) => {
+            &colors.fcn
+        }
+        _ => "",
+    }
+}
+
+fn highlight_lisp(env: &SloshVm, colors: &Colors, line: &str) -> String {
+    let mut out = String::with_capacity(line.len() * 2);
+    let mut open = Vec::new();
+    let mut i = 0;
+    while let Some(ch) = line[i..].chars().next() {
+        let rest = &line[i..];
+        let len = match ch {
+            '"' => {
+                let len = quoted_len(rest, true);
+                colors.paint(&mut out, &colors.string, &rest[..len]);
+                len
+            }
+            ';' => {
+                out.push_str(rest);
+                rest.len()
+            }
+            '(' | '[' | '{' => {
+                open.push(ch);
+                out.push(ch);
+                1
+            }
+            ')' | ']' | '}' => {
+                let opener = match ch {
+                    ')' => '(',
+                    ']' => '[',
+                    _ => '{',
+                };
+                if open.last() == Some(&opener) {
+                    open.pop();
+                    out.push(ch);
+                } else {
+                    colors.paint(&mut out, &colors.invalid, &rest[..1]);
+                }
+                1
+            }
+            '\\' => {
+                // Char literal, the next char is part of it even if a delimiter.
+                let len = rest[1..]
+                    .chars()
+                    .next()
+                    .map(|ch| 1 + ch.len_utf8())
+                    .unwrap_or(1);
+                let len = len + lisp_token_len(&rest[len..]);
+                colors.paint(&mut out, &colors.string, &rest[..len]);
+                len
+            }
+            ch if ch.is_whitespace() => {
+                out.push(ch);
+                ch.len_utf8()
+            }
+            _ => {
+                let len = lisp_token_len(rest);
+                let token = &rest[..len];
+                let color = lisp_token_color(env, colors, token);
+                colors.paint(&mut out, color, token);
+                len
+            }
+        };
+        i += len;
+    }
+    out
+}
+
+/// Length of a pipe/sequence operator at the start of text.
+fn operator_len(text: &str) -> Option<usize> {
+    ["&&", "||", "|", ";", "&"]
+        .iter()
+        .find(|op| text.starts_with(*op))
+        .map(|op| op.len())
+}
+
+/// Length of a redirect operator at the start of text, for instance 2>, >>, < or 2>&1.
+/// The bool is true if the redirect is followed by a target (file) word.
+fn redirect_len(text: &str) -> Option<(usize, bool)> {
+    let digits = text.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(0);
+    let rest = &text[digits..];
+    let op_len = ["<<", "<>", "<&", "<", ">>", ">&", ">"]
+        .iter()
+        .find(|op| rest.starts_with(*op))?
+        .len();
+    let len = digits + op_len;
+    if rest[..op_len].ends_with('&') {
+        let fd_len = text[len..]
+            .find(|ch: char| !(ch.is_ascii_digit() || ch == '-'))
+            .unwrap_or(text.len() - len);
+        Some((len + fd_len, false))
+    } else {
+        Some((len, true))
+    }
+}
+
+/// Length of a balanced (...) group at the start of text (quotes respected), all of text if
+/// not closed.
+fn group_len(text: &str) -> usize {
+    let mut depth = 0;
+    let mut i = 0;
+    while let Some(ch) = text[i..].chars().next() {
+        match ch {
+            '"' | '\'' => {
+                i += quoted_len(&text[i..], ch == '"');
+                continue;
+            }
+            '\\' => i += 1,
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            _ => {}
+        }
+        i += text[i..]
+            .chars()
+            .next()
+            .map(|ch| ch.len_utf8())
+            .unwrap_or(0);
+    }
+    text.len()
+}
+
+/// Length of the shell word at the start of text, stops at unquoted whitespace, operators and
+/// redirects.
+fn word_len(text: &str) -> usize {
+    let mut i = 0;
+    while let Some(ch) = text[i..].chars().next() {
+        match ch {
+            '"' | '\'' => i += quoted_len(&text[i..], ch == '"'),
+            '\\' => {
+                i += 1 + text[i + 1..]
+                    .chars()
+                    .next()
+                    .map(|ch| ch.len_utf8())
+                    .unwrap_or(0)
+            }
+            '$' if text[i + 1..].starts_with('(') => i += 1 + group_len(&text[i + 1..]),
+            ch if ch.is_whitespace() || matches!(ch, '|' | ';' | '&' | '(' | ')' | '<' | '>') => {
+                break
+            }
+            ch => i += ch.len_utf8(),
+        }
+    }
+    i
+}
+
+/// Length of a lisp function pipeline stage ("(fn ...)", "(lambda ...)" or "(lisp ...)") at the
+/// start of text.
+fn lisp_stage_len(text: &str) -> Option<usize> {
+    let first = text.strip_prefix('(')?.trim_start();
+    let word = &first[..first.find(is_lisp_delim).unwrap_or(first.len())];
+    if word == "fn" || word == "lambda" || word == "lisp" {
+        Some(group_len(text))
+    } else {
+        None
+    }
+}
+
+/// Remove quotes and escapes from a shell word.
+fn unquote(word: &str) -> String {
+    let mut res = String::with_capacity(word.len());
+    let mut chars = word.chars();
+    let mut quote = None;
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' if quote != Some('\'') => {
+                if let Some(ch) = chars.next() {
+                    res.push(ch);
+                }
+            }
+            '"' | '\'' if quote.is_none() => quote = Some(ch),
+            ch if Some(ch) == quote => quote = None,
+            ch => res.push(ch),
+        }
+    }
+    res
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path)
+        .map(|md| md.is_file() && md.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+thread_local! {
+    /// PATH value and the find_command results for it, the highlighter runs on every keystroke so
+    /// avoid searching every PATH dir each time.
+    static COMMAND_CACHE: RefCell<(OsString, HashMap<String, bool>)> =
+        RefCell::new((OsString::new(), HashMap::new()));
+}
+
+/// True if command is a path to an executable or an executable in one of the PATH dirs.
+/// Results for commands found in PATH are cached until PATH changes.
+fn find_command(command: &str) -> bool {
+    if command.contains('/') {
+        return is_executable(&expand_tilde(PathBuf::from(command)));
+    }
+    let Some(paths) = env::var_os("PATH") else {
+        return false;
+    };
+    COMMAND_CACHE.with(|cache| {
+        let (cache_path, found) = &mut *cache.borrow_mut();
+        if *cache_path != paths {
+            found.clear();
+            *cache_path = paths.clone();
+        }
+        *found.entry(command.to_string()).or_insert_with(|| {
+            env::split_paths(&paths).any(|dir| is_executable(&dir.join(command)))
+        })
+    })
+}
+
+fn is_alias(command: &str) -> bool {
+    SHELL_ENV.with(|jobs| {
+        jobs.try_borrow()
+            .map(|jobs| jobs.get_alias(command).is_some())
+            .unwrap_or(false)
+    })
+}
+
+/// Paint a shell word that is not a command, only the quoted parts get a color.
+fn paint_arg(colors: &Colors, out: &mut String, word: &str) {
+    let mut i = 0;
+    while let Some(ch) = word[i..].chars().next() {
+        let len = match ch {
+            '"' | '\'' => {
+                let len = quoted_len(&word[i..], ch == '"');
+                colors.paint(out, &colors.string, &word[i..i + len]);
+                len
+            }
+            '\\' => {
+                let len = 1 + word[i + 1..]
+                    .chars()
+                    .next()
+                    .map(|ch| ch.len_utf8())
+                    .unwrap_or(0);
+                out.push_str(&word[i..i + len]);
+                len
+            }
+            ch => {
+                out.push(ch);
+                ch.len_utf8()
+            }
+        };
+        i += len;
+    }
+}
+
+fn highlight_shell(env: &SloshVm, colors: &Colors, line: &str) -> String {
+    let mut out = String::with_capacity(line.len() * 2);
+    let mut command_pos = true;
+    let mut i = 0;
+    while let Some(ch) = line[i..].chars().next() {
+        let rest = &line[i..];
+        let len = if ch.is_whitespace() {
+            out.push(ch);
+            ch.len_utf8()
+        } else if let Some(len) = operator_len(rest) {
+            out.push_str(&rest[..len]);
+            command_pos = true;
+            len
+        } else if let Some((len, has_target)) = redirect_len(rest) {
+            colors.paint(&mut out, &colors.redirect, &rest[..len]);
+            if !has_target {
+                i += len;
+                continue;
+            }
+            // Skip the target so it is not taken as a command.
+            let ws = rest[len..]
+                .find(|ch: char| !ch.is_whitespace())
+                .unwrap_or(rest.len() - len);
+            out.push_str(&rest[len..len + ws]);
+            let target = word_len(&rest[len + ws..]);
+            paint_arg(colors, &mut out, &rest[len + ws..len + ws + target]);
+            len + ws + target
+        } else if ch == '(' || ch == ')' {
+            if let Some(len) = lisp_stage_len(rest).filter(|_| command_pos) {
+                out.push_str(&highlight_lisp(env, colors, &rest[..len]));
+                command_pos = false;
+                len
+            } else {
+                out.push(ch);
+                command_pos = ch == '(';
+                1
+            }
+        } else {
+            let len = word_len(rest).max(ch.len_utf8());
+            let word = &rest[..len];
+            let command = unquote(word);
+            if command_pos && command.contains('=') && !command.starts_with('=') {
+                // Leading VAR=val, the command is still to come.
+                paint_arg(colors, &mut out, word);
+            } else if command_pos {
+                let color = if is_alias(&command) {
+                    &colors.alias
+                } else if is_builtin(&command) || find_command(&command) {
+                    &colors.command
+                } else {
+                    &colors.invalid
+                };
+                colors.paint(&mut out, color, word);
+                command_pos = false;
+            } else {
+                paint_arg(colors, &mut out, word);
+            }
+            len
+        };
+        i += len;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::set_builtins;
+    use compile_state::state::new_slosh_vm;
+
+    fn test_vm() -> SloshVm {
+        let mut vm = new_slosh_vm();
+        set_builtins(&mut vm);
+        for (name, color) in [
+            ("tok-slsh-form-color", "<form>"),
+            ("tok-slsh-fcn-color", "<fcn>"),
+            ("tok-default-color", "</>"),
+            ("tok-sys-command-color", "<cmd>"),
+            ("tok-string-color", "<str>"),
+            ("tok-number-color", "<num>"),
+            ("tok-redirect-color", "<redir>"),
+            ("tok-invalid-color", "<bad>"),
+        ] {
+            let color = vm.alloc_string(color.to_string());
+            vm.set_named_global(name, color);
+        }
+        vm
+    }
+
+    #[test]
+    fn test_highlight_lisp() {
+        let vm = test_vm();
+        assert_eq!(
+            highlight(&vm, "(if (str-empty? x) \"a\" 10))"),
+            "(<form>if</> (<fcn>str-empty?</> x) <str>\"a\"</> <num>10</>)<bad>)</>"
+        );
+        assert_eq!(
+            highlight(&vm, "(not-a-fn-xyz '(a b) [1 :k] \\( \"open"),
+            "(not-a-fn-xyz '(a b) [<num>1</> :k] <str>\\(</> <str>\"open</>"
+        );
+    }
+
+    #[test]
+    fn test_highlight_shell() {
+        let vm = test_vm();
+        assert_eq!(
+            highlight(&vm, "cd /tmp && not-a-cmd-xyz 'a b'|sh -c x 2>&1 >out.txt"),
+            "<cmd>cd</> /tmp && <bad>not-a-cmd-xyz</> <str>'a b'</>|<cmd>sh</> -c x <redir>2>&1</> <redir>></>out.txt"
+        );
+        assert_eq!(
+            highlight(&vm, "X=1 sh | (fn (l) (if (str-empty? l) 1 l))"),
+            "X=1 <cmd>sh</> | (<form>fn</> (l) (<form>if</> (<fcn>str-empty?</> l) <num>1</> l))"
+        );
+    }
+
+    #[test]
+    fn test_find_command_cache() {
+        assert!(find_command("sh"));
+        assert!(!find_command("not-a-cmd-xyz"));
+        COMMAND_CACHE.with(|cache| {
+            let (path, found) = &*cache.borrow();
+            assert_eq!(Some(path.clone()), env::var_os("PATH"));
+            assert_eq!(found.get("sh"), Some(&true));
+            assert_eq!(found.get("not-a-cmd-xyz"), Some(&false));
+        });
+    }
+}
@@ -1,12 +1,13 @@
 extern crate sl_liner;
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::ffi::OsString;
 use std::fmt::Debug;
 use std::fs::{create_dir_all, File};
 use std::io::{BufRead, ErrorKind, Write};
 use std::ops::DerefMut;
 use std::path::{Path, PathBuf};
+use std::ptr::NonNull;
 use std::sync::Arc;
 use std::{env, fs};
 
@@ -17,7 +18,9 @@ use sl_compiler::compile::*;
 use sl_compiler::reader::*;
 
 use builtins::add_global_value;
+use builtins::doc_string;
 use builtins::print::display_value;
+use builtins::profile::{profile_folded, profile_report};
 use sl_liner::vi::AlphanumericAndVariableKeywordRule;
 use sl_liner::{keymap, ColorClosure, Context, Prompt};
 
@@ -26,25 +29,28 @@ mod config;
 pub mod debug;
 #[cfg(any(test, feature = "lisp-test"))]
 pub mod docs;
+mod fmt;
+mod highlight;
 mod liner_rules;
 
 pub use sl_compiler::load_eval::load_one_expression;
 pub use sl_compiler::load_eval::run_reader;
 mod shell_builtins;
+mod test_runner;
 
-use crate::completions::ShellCompleter;
+use crate::completions::{add_complete_builtins, ShellCompleter};
 use crate::liner_rules::make_editor_rules;
 use crate::shell_builtins::add_shell_builtins;
 use config::*;
 use debug::*;
-use shell::platform::{Platform, Sys, STDIN_FILENO};
+use shell::platform::{FromFileDesc, Platform, Sys, STDIN_FILENO};
 use sl_compiler::load_eval::{load_internal, SLSHRC};
 use sl_compiler::pass1::pass1;
-use slvm::{VMError, VMResult, Value};
+use slvm::{Coverage, Profile, VMError, VMResult, Value};
 
 thread_local! {
     /// Env (job control status, etc) for the shell.
-    pub static SHELL_ENV: RefCell<shell::jobs::Jobs> = RefCell::new(shell::jobs::Jobs::new(true));
+    pub static SHELL_ENV: RefCell<shell::jobs::Jobs> = RefCell::new(new_shell_env());
 }
 
 thread_local! {
@@ -54,6 +60,117 @@ thread_local! {
 
 const PROMPT_FN: &str = "prompt";
 
+fn new_shell_env() -> shell::jobs::Jobs {
+    let mut jobs = shell::jobs::Jobs::new(true);
+    jobs.set_lisp_runner(run_lisp_stage);
+    jobs
+}
+
+thread_local! {
+    /// VM of the lisp builtin currently running shell jobs, see with_stage_vm.
+    static STAGE_VM: Cell<Option<NonNull<SloshVm>>> = const { Cell::new(None) };
+}
+
+/// Run f (which parses or runs shell jobs) with vm available to any lisp pipeline stages.
+/// A builtin holds the borrow of ENV while it runs so stages (and forks of this process) can not
+/// borrow it again, they use the builtin's VM instead.
+pub fn with_stage_vm<R>(vm: &mut SloshVm, f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<NonNull<SloshVm>>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            STAGE_VM.with(|stage_vm| stage_vm.set(self.0));
+        }
+    }
+    let _restore = Restore(STAGE_VM.with(|stage_vm| stage_vm.replace(Some(NonNull::from(vm)))));
+    f()
+}
+
+/// Call f with the VM for lisp pipeline stages, None if it is not available.
+fn with_lisp_vm<R>(f: impl FnOnce(&mut SloshVm) -> R) -> Option<R> {
+    if let Some(mut vm) = STAGE_VM.with(|stage_vm| stage_vm.get()) {
+        // SAFETY: set by with_stage_vm from the exclusive borrow of a builtin that is blocked
+        // in f until this returns, nothing else uses that borrow meanwhile.
+        return Some(f(unsafe { vm.as_mut() }));
+    }
+    ENV.with(|env| env.try_borrow_mut().ok().map(|mut env| f(&mut env)))
+}
+
+/// Runs a lisp function as a stage in a shell pipeline, this is called in the forked process for
+/// the stage.  The stage source, (fn ...), (lambda ...) or (lisp expression), is evaluated to get
+/// the function (for (lisp expression) expression is evaluated).  The function is called with each line of stdin (without the newline) and each
+/// non-nil result is printed as a line to stdout.
+fn run_lisp_stage(source: &str) -> i32 {
+    let status = with_lisp_vm(|env| match exec_lisp_stage(env, source) {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("ERROR in lisp pipeline stage: {}", err.display(env));
+            1
+        }
+    });
+    status.unwrap_or_else(|| {
+        eprintln!("ERROR in lisp pipeline stage: the lisp environment is not available");
+        1
+    })
+}
+
+fn exec_lisp_stage(env: &mut SloshVm, source: &str) -> VMResult<()> {
+    let exp = Reader::from_string(source.to_string(), env, "", 1, 0)
+        .next()
+        .ok_or_else(|| VMError::new_compile("empty lisp pipeline stage"))?
+        .map_err(|e| VMError::new("read", e.to_string()))?;
+    // (lisp expression) uses the value of expression as the stage.
+    let exp = match exp.get_pair(env) {
+        Some((Value::Symbol(head), rest)) if env.get_interned(head) == "lisp" => {
+            match rest.get_pair(env) {
+                Some((expression, Value::Nil)) => expression,
+                _ => {
+                    return Err(VMError::new_compile(
+                        "lisp pipeline stage: expected (lisp expression)",
+                    ))
+                }
+            }
+        }
+        _ => exp,
+    };
+    env.heap_sticky(exp);
+    let res = load_one_expression(env, exp, "", None);
+    env.heap_unsticky(exp);
+    let (chunk, _) = res?;
+    let func = env.do_call(chunk, &[], None)?;
+    let (lambda, caps) = match func {
+        Value::Lambda(h) => (env.get_lambda(h), None),
+        Value::Closure(h) => {
+            let (l, tcaps) = env.get_closure(h);
+            (l, Some(Vec::from(tcaps)))
+        }
+        _ => {
+            return Err(VMError::new_vm(format!(
+                "lisp pipeline stage must be a function, got {}",
+                func.display_type(env)
+            )))
+        }
+    };
+    env.heap_sticky(func);
+    // Read fd 0 directly, std::io::stdin() may hold input buffered by the parent before the fork.
+    let stdin = std::io::BufReader::new(unsafe { File::from_file_desc(STDIN_FILENO) });
+    let mut stdout = std::io::stdout().lock();
+    for line in stdin.lines() {
+        let param = env.alloc_string(line?);
+        let res = env.do_call(lambda.clone(), &[param], caps.as_deref())?;
+        if !res.is_nil() {
+            if let Err(err) = writeln!(stdout, "{}", res.pretty_value(env)) {
+                if err.kind() == ErrorKind::BrokenPipe {
+                    // The next stage is not reading anymore so we are done.
+                    break;
+                }
+                return Err(err.into());
+            }
+        }
+    }
+    env.heap_unsticky(func);
+    Ok(())
+}
+
 fn get_prompt(env: &mut SloshVm) -> String {
     let i_val = env.intern("__prompt");
     if let Some(idx) = env.global_intern_slot(i_val) {
@@ -259,14 +376,28 @@ fn get_color_closure() -> Option<ColorClosure> {
                         res
                     })
                 })),
-                _ => None,
+                _ => get_highlight_closure(&env),
             }
         } else {
-            None
+            get_highlight_closure(&env)
         }
     })
 }
 
+/// Built in syntax highlighting, used if there is no __line_handler and syntax-on was called.
+fn get_highlight_closure(env: &SloshVm) -> Option<ColorClosure> {
+    if highlight::syntax_on(env) {
+        Some(Box::new(|input: &str| -> String {
+            ENV.with(|renv| match renv.try_borrow() {
+                Ok(env) => highlight::highlight(&env, input),
+                Err(_) => input.to_string(),
+            })
+        }))
+    } else {
+        None
+    }
+}
+
 fn get_usage(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
     if registers.len() > 1 {
         Err(VMError::new_compile(
@@ -285,16 +416,8 @@ fn get_usage(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
                     Some(slot) => {
                         let mut usage = usage(vm, slot, sym);
                         if usage.trim().is_empty() {
-                            let docstring_key = vm.intern_static("doc-string");
-                            let raw_doc_string = vm
-                                .get_global_property(slot, docstring_key)
-                                .and_then(|x| match x {
-                                    Value::String(h) => Some(vm.get_string(h).to_string()),
-                                    Value::StringConst(i) => Some(vm.get_interned(i).to_string()),
-                                    _ => None,
-                                })
-                                // return default empty string and have parse_doc_string handle error if no doc provided.
-                                .unwrap_or_default();
+                            let raw_doc_string =
+                                doc_string::global_doc_string(vm, slot).unwrap_or_default();
                             if let Some(test) = raw_doc_string.trim().lines().next() {
                                 if test.starts_with("Usage:") {
                                     usage = test.to_string();
@@ -316,37 +439,13 @@ fn get_usage(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
 
 fn usage(vm: &mut SloshVm, slot: u32, sym: &Value) -> String {
     let name = sym.display_value(vm);
-    let mut doc_str = String::new();
-    let sym = vm.get_global(slot);
-    let args = match sym {
-        Value::Lambda(h) => {
-            let l = vm.get_lambda(h);
-            l.dbg_args.clone()
-        }
-        Value::Closure(h) => {
-            let (l, _h) = vm.get_closure(h);
-            l.dbg_args.clone()
-        }
-        _ => {
-            return doc_str;
-        }
-    };
-    if let Some(args) = args {
-        doc_str.push('(');
-        doc_str.push_str(&name);
-        for a in args {
-            let arg = vm.get_interned(a);
-            doc_str.push(' ');
-            doc_str.push_str(arg);
-        }
-        doc_str.push(')');
-    }
-    doc_str
+    doc_string::usage(vm, slot, &name)
 }
 
 pub fn set_builtins(env: &mut SloshVm) {
     sl_compiler::set_builtins(env);
     add_shell_builtins(env);
+    add_complete_builtins(env);
     env.set_global_builtin("dump-regs", builtin_dump_regs);
 
     let uid = Sys::current_uid();
@@ -379,14 +478,25 @@ fn run_slosh() -> i32 {
             let mut env = renv.borrow_mut();
             set_builtins(&mut env);
         });
-        if config.command.is_none() && config.script.is_none() {
+        let profile = config.profile || config.profile_folded.is_some();
+        if profile {
+            ENV.with(|env| env.borrow_mut().set_profile(Some(Profile::default())));
+        }
+        if config.coverage.is_some() {
+            ENV.with(|env| env.borrow_mut().set_coverage(Some(Coverage::new())));
+        }
+        if let Some(path) = &config.test {
+            status = ENV.with(|env| test_runner::run_tests(&mut env.borrow_mut(), &config, path));
+        } else if let Some(file) = &config.fmt {
+            status = fmt::format_file(file, config.fmt_width);
+        } else if config.command.is_none() && config.script.is_none() {
             load_sloshrc();
             if Sys::is_tty(STDIN_FILENO) {
                 status = run_shell_tty();
             } else {
                 status = run_shell_with_stdin();
             }
-        } else if let Some(mut command) = config.command {
+        } else if let Some(mut command) = config.command.clone() {
             for a in &config.args {
                 command.push(' ');
                 command.push_str(a);
@@ -412,7 +522,7 @@ fn run_slosh() -> i32 {
             SHELL_ENV.with(|jobs| {
                 jobs.borrow_mut().reap_procs();
             });
-        } else if let Some(script) = config.script {
+        } else if let Some(script) = config.script.clone() {
             load_sloshrc();
             status = ENV.with(|renv| {
                 let mut env = renv.borrow_mut();
@@ -427,10 +537,71 @@ fn run_slosh() -> i32 {
                 }
             });
         }
+        if profile {
+            ENV.with(|env| report_profile(&mut env.borrow_mut(), &config));
+        }
+        if let Some(file) = &config.coverage {
+            ENV.with(|env| write_coverage(&mut env.borrow_mut(), file));
+        }
     }
     status
 }
 
+/// Stop recording coverage and write it to file in lcov format.
+fn write_coverage(env: &mut SloshVm, file: &str) {
+    let Some(coverage) = env.take_coverage() else {
+        return;
+    };
+    let lcov = coverage_lcov(env, &coverage, &[]);
+    if let Err(err) = fs::write(file, lcov) {
+        eprintln!("ERROR writing coverage to {file}: {err}");
+    }
+}
+
+/// Coverage in lcov format.  File names are resolved like load does (as given then in
+/// *load-path*, then extra_dirs) to absolute paths so they match other coverage data.
+pub fn coverage_lcov(env: &mut SloshVm, coverage: &Coverage, extra_dirs: &[PathBuf]) -> String {
+    let mut dirs = Vec::new();
+    let i_load_path = env.intern("*load-path*");
+    if let Some(slot) = env.global_intern_slot(i_load_path) {
+        for dir in env.get_global(slot).iter(env) {
+            match dir {
+                Value::StringConst(i) => dirs.push(PathBuf::from(env.get_interned(i))),
+                Value::String(h) => dirs.push(PathBuf::from(env.get_string(h))),
+                _ => {}
+            }
+        }
+    }
+    dirs.extend_from_slice(extra_dirs);
+    coverage.lcov(|name| {
+        let path = Path::new(name);
+        let found = if path.is_file() {
+            Some(path.to_path_buf())
+        } else {
+            dirs.iter().map(|dir| dir.join(name)).find(|p| p.is_file())
+        };
+        found
+            .and_then(|path| fs::canonicalize(path).ok())
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_else(|| name.to_string())
+    })
+}
+
+/// Stop profiling, print the report (to stderr) and/or write folded stacks to a file.
+fn report_profile(env: &mut SloshVm, config: &Config) {
+    let Some(profile) = env.take_profile() else {
+        return;
+    };
+    if config.profile {
+        eprint!("{}", profile_report(env, &profile));
+    }
+    if let Some(folded_file) = &config.profile_folded {
+        if let Err(err) = fs::write(folded_file, profile_folded(env, &profile)) {
+            eprintln!("ERROR writing profile to {folded_file}: {err}");
+        }
+    }
+}
+
 fn run_shell_tty() -> i32 {
     let mut con = Context::new();
     //con.set_completer(Box::new(FilenameCompleter::new(Some("."))));
@@ -455,6 +626,8 @@ fn run_shell_tty() -> i32 {
         println!("Error loading history: {e}");
     }
     shell::run::setup_shell_tty(STDIN_FILENO);
+    // Ctrl-C stops running lisp code with an :interrupted error.
+    ENV.with(|env| shell::signals::forward_sigint_to(env.borrow().interrupt_flag()));
     SHELL_ENV.with(|jobs| {
         jobs.borrow_mut().cap_term();
     });
@@ -555,6 +728,8 @@ fn read_expression_to_list(res: String, env: &mut SloshVm) -> Result<Vec<Value>,
 }
 
 fn exec_expression(res: String, env: &mut SloshVm) {
+    // Ignore a Ctrl-C from before this expression started.
+    env.clear_interrupt();
     let exps = read_expression_to_list(res, env);
     match exps {
         Ok(exps) => {
@@ -1,5 +1,5 @@
 use crate::config::VERSION_STRING;
-use crate::SHELL_ENV;
+use crate::{with_stage_vm, SHELL_ENV};
 use bridge_adapters::add_builtin;
 use compile_state::state::SloshVm;
 use shell::platform::{FromFileDesc, Platform, Sys};
@@ -9,6 +9,9 @@ use std::fs::File;
 use std::io::{BufRead, ErrorKind};
 use std::{env, io};
 
+mod pipeline;
+mod process;
+
 fn sh(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
     let mut command = String::new();
     for exp in registers {
@@ -26,14 +29,14 @@ fn sh(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
     let mut run_res = Err(io::Error::new(ErrorKind::Other, "broken parse"));
     SHELL_ENV.with(|jobs_ref| {
         let jobs = &mut jobs_ref.borrow_mut();
-        run_res = shell::parse::parse_line(jobs, &command)
+        run_res = with_stage_vm(vm, || shell::parse::parse_line(jobs, &command))
     });
     let run = run_res
         .map_err(|e| VMError::new_compile(format!("sh: {e}")))?
         .into_run();
     SHELL_ENV.with(|jobs_ref| {
         let jobs = &mut jobs_ref.borrow_mut();
-        fork_res = shell::run::run_job(&run, jobs, false);
+        fork_res = with_stage_vm(vm, || shell::run::run_job(&run, jobs, false));
     });
     fork_res
         .map(|i| i.into())
@@ -57,7 +60,7 @@ fn sh_str(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
     let mut run_res = Err(io::Error::new(ErrorKind::Other, "broken parse"));
     SHELL_ENV.with(|jobs_ref| {
         let jobs = &mut jobs_ref.borrow_mut();
-        run_res = shell::parse::parse_line(jobs, &command)
+        run_res = with_stage_vm(vm, || shell::parse::parse_line(jobs, &command))
     });
     let mut run = run_res
         .map_err(|e| VMError::new_compile(format!("$sh: {e}")))?
@@ -66,7 +69,7 @@ fn sh_str(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
     run.push_stdout_front(Some(output));
     SHELL_ENV.with(|jobs_ref| {
         let jobs = &mut jobs_ref.borrow_mut();
-        fork_res = shell::run::run_job(&run, jobs, true);
+        fork_res = with_stage_vm(vm, || shell::run::run_job(&run, jobs, true));
     });
     fork_res.map_err(|e| VMError::new_compile(format!("$sh: {e}")))?;
     let lines = io::BufReader::new(unsafe { File::from_file_desc(input) }).lines();
@@ -146,4 +149,6 @@ Section: core"#,
         version,
         "Return the software version string.",
     );
+    process::add_process_builtins(env);
+    pipeline::add_pipeline_builtins(env);
 }
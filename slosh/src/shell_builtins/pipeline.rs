@@ -0,0 +1,679 @@
+use super::process::value_to_os_string;
+use crate::{with_stage_vm, SHELL_ENV};
+use bridge_adapters::add_builtin;
+use compile_state::state::SloshVm;
+use shell::command_data::{Arg, CommandWithArgs, RedirArg, RedirType, Redirects, Run};
+use shell::platform::{FileDesc, STDERR_FILENO, STDIN_FILENO, STDOUT_FILENO};
+use slvm::{VMError, VMResult, Value};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::str::FromStr;
+
+/// Redirects and command settings parsed from the keyword options of cmd or run.
+struct RunOptions {
+    /// Redirects for the first command (stdin).
+    start_redirs: Redirects,
+    /// Redirects for the last command (stdout/stderr and raw redirects).
+    end_redirs: Redirects,
+    env: Vec<(OsString, OsString)>,
+    cwd: Option<OsString>,
+}
+
+fn keyword(vm: &mut SloshVm, name: &'static str) -> Value {
+    Value::Keyword(vm.intern_static(name))
+}
+
+fn map_get(vm: &mut SloshVm, map: Value, key: &'static str) -> Option<Value> {
+    let key = keyword(vm, key);
+    if let Value::Map(h) = map {
+        vm.get_map(h).get(&key).copied()
+    } else {
+        None
+    }
+}
+
+fn type_name(vm: &mut SloshVm, name: &str, map: Value) -> VMResult<&'static str> {
+    match map_get(vm, map, "type") {
+        Some(Value::Keyword(i)) => Ok(vm.get_interned(i)),
+        _ => Err(VMError::new_vm(format!(
+            "{name}: expected a map with a :type keyword, got {}",
+            map.display_value(vm)
+        ))),
+    }
+}
+
+fn string_val(vm: &mut SloshVm, name: &str, val: Option<Value>, what: &str) -> VMResult<OsString> {
+    match val {
+        Some(val @ (Value::String(_) | Value::StringConst(_))) => {
+            Ok(val.get_string(vm)?.to_string().into())
+        }
+        _ => Err(VMError::new_vm(format!("{name}: {what} requires a string"))),
+    }
+}
+
+fn arg_from_value(vm: &mut SloshVm, name: &str, val: Value) -> VMResult<Arg> {
+    if !matches!(val, Value::Map(_)) {
+        return Ok(Arg::Str(value_to_os_string(vm, val)));
+    }
+    match type_name(vm, name, val)? {
+        "var" => {
+            let var = map_get(vm, val, "name");
+            Ok(Arg::Var(string_val(vm, name, var, ":var :name")?))
+        }
+        "sub" => {
+            let run = map_get(vm, val, "run").unwrap_or(Value::Nil);
+            Ok(Arg::Command(run_from_value(vm, name, run)?))
+        }
+        "compound" => {
+            let args: Vec<Value> = map_get(vm, val, "args")
+                .map(|args| args.iter(vm).collect())
+                .unwrap_or_default();
+            let mut cargs = Vec::with_capacity(args.len());
+            for arg in args {
+                cargs.push(arg_from_value(vm, name, arg)?);
+            }
+            Ok(Arg::Compound(cargs))
+        }
+        t => Err(VMError::new_vm(format!(
+            "{name}: unknown arg type :{t}, expected :var, :sub or :compound"
+        ))),
+    }
+}
+
+fn push_redirect(vm: &mut SloshVm, name: &str, redirs: &mut Redirects, val: Value) -> VMResult<()> {
+    let parts: Vec<Value> = val.iter(vm).collect();
+    let (op, fd, target) = match parts[..] {
+        [Value::Keyword(op), fd, target] if fd.is_int() => {
+            (vm.get_interned(op), fd.get_int(vm)?, target)
+        }
+        _ => {
+            return Err(VMError::new_vm(format!(
+                "{name}: redirect must be [op fd target], got {}",
+                val.display_value(vm)
+            )))
+        }
+    };
+    let fd = FileDesc::from_str(&fd.to_string())
+        .map_err(|e| VMError::new_vm(format!("{name}: invalid file descriptor {fd}: {e}")))?;
+    let target = arg_from_value(vm, name, target)?;
+    match op {
+        "in" => redirs.set_in_path(fd, target),
+        "in-fd" => redirs.set_in_fd(fd, target, true),
+        "in-direct" => redirs.set_in_direct(fd, target),
+        "out" => redirs.set_out_path(fd, target, true),
+        "out-append" => redirs.set_out_path(fd, target, false),
+        "out-fd" => redirs.set_out_fd(fd, target, true),
+        "in-out" => redirs.set_in_out_path(fd, target),
+        "in-out-fd" => redirs.set_in_out_fd(fd, target),
+        _ => {
+            return Err(VMError::new_vm(format!(
+                "{name}: unknown redirect :{op}, expected :in, :in-fd, :in-direct, :out, :out-append, :out-fd, :in-out or :in-out-fd"
+            )))
+        }
+    }
+    Ok(())
+}
+
+fn redirects_from_value(vm: &mut SloshVm, name: &str, val: Option<Value>) -> VMResult<Redirects> {
+    let mut redirs = Redirects::new();
+    if let Some(val) = val {
+        let items: Vec<Value> = val.iter(vm).collect();
+        for item in items {
+            push_redirect(vm, name, &mut redirs, item)?;
+        }
+    }
+    Ok(redirs)
+}
+
+fn command_from_value(vm: &mut SloshVm, name: &str, map: Value) -> VMResult<CommandWithArgs> {
+    let mut command = CommandWithArgs::new();
+    let args: Vec<Value> = map_get(vm, map, "args")
+        .map(|args| args.iter(vm).collect())
+        .unwrap_or_default();
+    if args.is_empty() {
+        return Err(VMError::new_vm(format!("{name}: empty command")));
+    }
+    for arg in args {
+        command.push_arg(arg_from_value(vm, name, arg)?);
+    }
+    if let Some(Value::Map(h)) = map_get(vm, map, "env") {
+        let env: Vec<(Value, Value)> = vm.get_map(h).iter().map(|(k, v)| (*k, *v)).collect();
+        for (key, val) in env {
+            command.push_env_var(value_to_os_string(vm, key), value_to_os_string(vm, val));
+        }
+    }
+    if let Some(cwd) = map_get(vm, map, "cwd") {
+        command.set_cwd(string_val(vm, name, Some(cwd), ":cwd")?);
+    }
+    let redirs = map_get(vm, map, "redirects");
+    let redirs = redirects_from_value(vm, name, redirs)?;
+    command.set_stdios(redirs);
+    Ok(command)
+}
+
+fn runs_from_value(vm: &mut SloshVm, name: &str, map: Value) -> VMResult<Vec<Run>> {
+    let vals: Vec<Value> = map_get(vm, map, "runs")
+        .map(|runs| runs.iter(vm).collect())
+        .unwrap_or_default();
+    let mut runs = Vec::with_capacity(vals.len());
+    for val in vals {
+        runs.push(run_from_value(vm, name, val)?);
+    }
+    Ok(runs)
+}
+
+/// Convert lisp data (as produced by cmd, pipe, sh-parse etc) to a Run.
+/// A string is parsed as a shell command line and nil is an empty Run.
+fn run_from_value(vm: &mut SloshVm, name: &str, val: Value) -> VMResult<Run> {
+    match val {
+        Value::Nil => return Ok(Run::Empty),
+        Value::String(_) | Value::StringConst(_) => {
+            let line = val.get_string(vm)?.to_string();
+            return parse(vm, name, &line);
+        }
+        Value::Map(_) => {}
+        _ => {
+            return Err(VMError::new_vm(format!(
+                "{name}: expected a command map or string, got {}",
+                val.display_type(vm)
+            )))
+        }
+    }
+    Ok(match type_name(vm, name, val)? {
+        "command" => Run::Command(command_from_value(vm, name, val)?),
+        "background" => Run::BackgroundCommand(command_from_value(vm, name, val)?),
+        "pipe" => Run::Pipe(runs_from_value(vm, name, val)?),
+        "sequence" => Run::Sequence(runs_from_value(vm, name, val)?),
+        "and" => Run::And(runs_from_value(vm, name, val)?),
+        "or" => Run::Or(runs_from_value(vm, name, val)?),
+        "subshell" => {
+            let run = map_get(vm, val, "run").unwrap_or(Value::Nil);
+            Run::Subshell(Box::new(run_from_value(vm, name, run)?))
+        }
+        "lisp" => {
+            let source = map_get(vm, val, "source");
+            let mut command = CommandWithArgs::new();
+            command.push_arg(Arg::Str(string_val(vm, name, source, ":lisp :source")?));
+            Run::Lisp(command)
+        }
+        t => {
+            return Err(VMError::new_vm(format!(
+                "{name}: unknown run type :{t}, expected :command, :background, :pipe, :sequence, :and, :or, :subshell or :lisp"
+            )))
+        }
+    })
+}
+
+fn parse(vm: &mut SloshVm, name: &str, line: &str) -> VMResult<Run> {
+    SHELL_ENV
+        .with(|jobs| {
+            with_stage_vm(vm, || {
+                shell::parse::parse_line(&mut jobs.borrow_mut(), line)
+            })
+        })
+        .map(|job| job.into_run())
+        .map_err(|e| VMError::new_vm(format!("{name}: {e}")))
+}
+
+fn os_str_value(vm: &mut SloshVm, s: &OsString) -> Value {
+    vm.alloc_string(s.to_string_lossy().to_string())
+}
+
+fn type_map(vm: &mut SloshVm, type_name: &'static str) -> HashMap<Value, Value> {
+    let mut map = HashMap::new();
+    map.insert(keyword(vm, "type"), keyword(vm, type_name));
+    map
+}
+
+// All the *_to_value functions allocate without rooting, call with GC paused.
+
+fn arg_to_value(vm: &mut SloshVm, arg: &Arg) -> Value {
+    match arg {
+        Arg::Str(s) => os_str_value(vm, s),
+        Arg::Var(var) => {
+            let mut map = type_map(vm, "var");
+            let var = os_str_value(vm, var);
+            map.insert(keyword(vm, "name"), var);
+            vm.alloc_map(map)
+        }
+        Arg::Command(run) => {
+            let mut map = type_map(vm, "sub");
+            let run = run_to_value(vm, run);
+            map.insert(keyword(vm, "run"), run);
+            vm.alloc_map(map)
+        }
+        Arg::Compound(cargs) => {
+            let mut map = type_map(vm, "compound");
+            let args = cargs.iter().map(|a| arg_to_value(vm, a)).collect();
+            let args = vm.alloc_vector(args);
+            map.insert(keyword(vm, "args"), args);
+            vm.alloc_map(map)
+        }
+    }
+}
+
+fn redirect_to_value(vm: &mut SloshVm, redir: &RedirType) -> Option<Value> {
+    let (op, fd, arg) = match redir {
+        RedirType::In(fd, RedirArg::Path(arg)) => ("in", fd, arg),
+        RedirType::In(fd, RedirArg::Fd(arg)) => ("in-fd", fd, arg),
+        RedirType::InDirect(fd, arg) => ("in-direct", fd, arg),
+        RedirType::Out(fd, RedirArg::Path(arg)) => ("out-append", fd, arg),
+        RedirType::OutTrunc(fd, RedirArg::Path(arg)) => ("out", fd, arg),
+        RedirType::Out(fd, RedirArg::Fd(arg)) | RedirType::OutTrunc(fd, RedirArg::Fd(arg)) => {
+            ("out-fd", fd, arg)
+        }
+        RedirType::InOut(fd, RedirArg::Path(arg)) => ("in-out", fd, arg),
+        RedirType::InOut(fd, RedirArg::Fd(arg)) => ("in-out-fd", fd, arg),
+        // Internal fds are pipes created by the shell while running.
+        RedirType::In(_, RedirArg::InternalFd(_))
+        | RedirType::Out(_, RedirArg::InternalFd(_))
+        | RedirType::OutTrunc(_, RedirArg::InternalFd(_))
+        | RedirType::InOut(_, RedirArg::InternalFd(_)) => return None,
+    };
+    let op = keyword(vm, op);
+    let fd = fd.to_string().parse::<i64>().ok()?;
+    let arg = arg_to_value(vm, arg);
+    Some(vm.alloc_vector(vec![op, fd.into(), arg]))
+}
+
+fn redirects_to_value(vm: &mut SloshVm, redirs: &Option<Redirects>) -> Value {
+    let redirs = redirs
+        .iter()
+        .flat_map(|r| r.redirs())
+        .filter_map(|r| redirect_to_value(vm, r))
+        .collect();
+    vm.alloc_vector(redirs)
+}
+
+fn command_to_value(vm: &mut SloshVm, type_name: &'static str, command: &CommandWithArgs) -> Value {
+    let mut map = type_map(vm, type_name);
+    let args = command
+        .command_and_args()
+        .iter()
+        .map(|a| arg_to_value(vm, a))
+        .collect();
+    let args = vm.alloc_vector(args);
+    map.insert(keyword(vm, "args"), args);
+    let redirs = redirects_to_value(vm, command.stdios());
+    map.insert(keyword(vm, "redirects"), redirs);
+    if !command.env_vars().is_empty() {
+        let mut env = HashMap::new();
+        for (key, val) in command.env_vars() {
+            env.insert(os_str_value(vm, key), os_str_value(vm, val));
+        }
+        let env = vm.alloc_map(env);
+        map.insert(keyword(vm, "env"), env);
+    }
+    if let Some(cwd) = command.cwd() {
+        let cwd = os_str_value(vm, cwd);
+        map.insert(keyword(vm, "cwd"), cwd);
+    }
+    vm.alloc_map(map)
+}
+
+fn runs_to_value(vm: &mut SloshVm, type_name: &'static str, runs: &[Run]) -> Value {
+    let mut map = type_map(vm, type_name);
+    let runs = runs.iter().map(|r| run_to_value(vm, r)).collect();
+    let runs = vm.alloc_vector(runs);
+    map.insert(keyword(vm, "runs"), runs);
+    vm.alloc_map(map)
+}
+
+fn run_to_value(vm: &mut SloshVm, run: &Run) -> Value {
+    match run {
+        Run::Command(command) => command_to_value(vm, "command", command),
+        Run::BackgroundCommand(command) => command_to_value(vm, "background", command),
+        Run::Pipe(runs) => runs_to_value(vm, "pipe", runs),
+        Run::Sequence(runs) => runs_to_value(vm, "sequence", runs),
+        Run::And(runs) => runs_to_value(vm, "and", runs),
+        Run::Or(runs) => runs_to_value(vm, "or", runs),
+        Run::Subshell(sub_run) => {
+            let mut map = type_map(vm, "subshell");
+            let sub_run = run_to_value(vm, sub_run);
+            map.insert(keyword(vm, "run"), sub_run);
+            vm.alloc_map(map)
+        }
+        Run::Lisp(command) => {
+            let mut map = type_map(vm, "lisp");
+            let source = command
+                .command_and_args()
+                .first()
+                .map(|a| a.to_string())
+                .unwrap_or_default();
+            let source = vm.alloc_string(source);
+            map.insert(keyword(vm, "source"), source);
+            vm.alloc_map(map)
+        }
+        Run::Empty => Value::Nil,
+    }
+}
+
+fn to_value(vm: &mut SloshVm, run: &Run) -> Value {
+    vm.pause_gc();
+    let res = run_to_value(vm, run);
+    vm.unpause_gc();
+    res
+}
+
+fn parse_options(
+    vm: &mut SloshVm,
+    name: &str,
+    registers: &[Value],
+    command_opts: bool,
+) -> VMResult<RunOptions> {
+    let mut opts = RunOptions {
+        start_redirs: Redirects::new(),
+        end_redirs: Redirects::new(),
+        env: vec![],
+        cwd: None,
+    };
+    let mut regs = registers.iter();
+    while let Some(key) = regs.next() {
+        let val = *regs.next().ok_or_else(|| {
+            VMError::new_vm(format!(
+                "{name}: option {} missing value",
+                key.display_value(vm)
+            ))
+        })?;
+        let key = if let Value::Keyword(i) = key {
+            vm.get_interned(*i)
+        } else {
+            return Err(VMError::new_vm(format!(
+                "{name}: options must be keywords, got {}",
+                key.display_value(vm)
+            )));
+        };
+        match key {
+            "in" => {
+                let path = arg_from_value(vm, name, val)?;
+                opts.start_redirs.set_in_path(STDIN_FILENO, path);
+            }
+            "out" | "append" => {
+                let path = arg_from_value(vm, name, val)?;
+                opts.end_redirs
+                    .set_out_path(STDOUT_FILENO, path, key == "out");
+            }
+            "err" => {
+                let path = arg_from_value(vm, name, val)?;
+                opts.end_redirs.set_out_path(STDERR_FILENO, path, true);
+            }
+            "err-to-out" => {
+                if val.is_truethy() {
+                    opts.end_redirs
+                        .set_out_fd(STDERR_FILENO, Arg::Str("1".into()), true);
+                }
+            }
+            "redirects" => {
+                let redirs = redirects_from_value(vm, name, Some(val))?;
+                opts.end_redirs.extend(&redirs);
+            }
+            "env" if command_opts => {
+                if let Value::Map(h) = val {
+                    let env: Vec<(Value, Value)> =
+                        vm.get_map(h).iter().map(|(k, v)| (*k, *v)).collect();
+                    for (k, v) in env {
+                        opts.env
+                            .push((value_to_os_string(vm, k), value_to_os_string(vm, v)));
+                    }
+                } else {
+                    return Err(VMError::new_vm(format!("{name}: :env requires a map")));
+                }
+            }
+            "cwd" if command_opts => opts.cwd = Some(string_val(vm, name, Some(val), ":cwd")?),
+            _ if command_opts => {
+                return Err(VMError::new_vm(format!(
+                    "{name}: unknown option :{key}, expected :in, :out, :append, :err, :err-to-out, :redirects, :env or :cwd"
+                )))
+            }
+            _ => {
+                return Err(VMError::new_vm(format!(
+                    "{name}: unknown option :{key}, expected :in, :out, :append, :err, :err-to-out or :redirects"
+                )))
+            }
+        }
+    }
+    Ok(opts)
+}
+
+fn cmd(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    let split = registers
+        .iter()
+        .position(|v| matches!(v, Value::Keyword(_)))
+        .unwrap_or(registers.len());
+    let (args, options) = registers.split_at(split);
+    if args.is_empty() {
+        return Err(VMError::new_vm("cmd: requires a command"));
+    }
+    let opts = parse_options(vm, "cmd", options, true)?;
+    let mut command = CommandWithArgs::new();
+    for arg in args {
+        command.push_arg(arg_from_value(vm, "cmd", *arg)?);
+    }
+    for (key, val) in opts.env {
+        command.push_env_var(key, val);
+    }
+    if let Some(cwd) = opts.cwd {
+        command.set_cwd(cwd);
+    }
+    command.extend_stdios(&opts.start_redirs);
+    command.extend_stdios(&opts.end_redirs);
+    Ok(to_value(vm, &Run::Command(command)))
+}
+
+fn seq_builtin(
+    vm: &mut SloshVm,
+    name: &str,
+    registers: &[Value],
+    make_run: fn(Vec<Run>) -> Run,
+) -> VMResult<Value> {
+    if registers.is_empty() {
+        return Err(VMError::new_vm(format!(
+            "{name}: requires at least one command"
+        )));
+    }
+    let mut runs = Vec::with_capacity(registers.len());
+    for val in registers {
+        runs.push(run_from_value(vm, name, *val)?);
+    }
+    Ok(to_value(vm, &make_run(runs)))
+}
+
+fn pipe(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    seq_builtin(vm, "pipe", registers, Run::Pipe)
+}
+
+fn cmd_and(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    seq_builtin(vm, "cmd-and", registers, Run::And)
+}
+
+fn cmd_or(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    seq_builtin(vm, "cmd-or", registers, Run::Or)
+}
+
+fn cmd_seq(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    seq_builtin(vm, "cmd-seq", registers, Run::Sequence)
+}
+
+fn run(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    let (data, options) = registers
+        .split_first()
+        .ok_or_else(|| VMError::new_vm("run: requires a command"))?;
+    let mut run = run_from_value(vm, "run", *data)?;
+    let opts = parse_options(vm, "run", options, false)?;
+    run.extend_redirs_each(&opts.start_redirs, &opts.end_redirs);
+    SHELL_ENV
+        .with(|jobs| {
+            with_stage_vm(vm, || {
+                shell::run::run_job(&run, &mut jobs.borrow_mut(), false)
+            })
+        })
+        .map(|status| (status as i64).into())
+        .map_err(|e| VMError::new("io", format!("run: {e}")))
+}
+
+fn sh_parse(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    let line = match registers {
+        [line @ (Value::String(_) | Value::StringConst(_))] => line.get_string(vm)?.to_string(),
+        _ => return Err(VMError::new_vm("sh-parse: requires one string")),
+    };
+    let run = parse(vm, "sh-parse", &line)?;
+    Ok(to_value(vm, &run))
+}
+
+pub fn add_pipeline_builtins(env: &mut SloshVm) {
+    add_builtin(
+        env,
+        "cmd",
+        cmd,
+        r#"Usage: (cmd command arg* :in path :out path :append path :err path :err-to-out bool :redirects [redirect*] :env map :cwd dir) -> command-map
+
+Build a command for run or pipe from command and args without any shell parsing, quoting is
+never needed. Returns the command as data, a map of :type :command, :args, :redirects and
+optionally :env and :cwd.
+An arg can also be a map that is resolved when the command runs, {:type :var :name "NAME"}
+for an environment variable, {:type :sub :run command} for the output of a command (like $())
+or {:type :compound :args [arg*]} to concatenate args.
+Options start at the first keyword. :in redirects stdin from a file, :out and :append send
+stdout to a file (truncated or appended), :err sends stderr to a file and :err-to-out sends
+stderr to stdout. :redirects is a vector of raw redirects of the form [op fd target] where op
+is one of :in, :in-fd, :in-direct, :out, :out-append, :out-fd, :in-out or :in-out-fd.
+:env is a map of extra environment variables and :cwd the directory to run in.
+
+Section: shell
+
+Example:
+(def c (cmd "grep" "-c" "a b" :out "/tmp/slosh-cmd-example.txt"))
+(test::assert-equal :command (get c :type))
+(test::assert-equal ["grep" "-c" "a b"] (get c :args))
+(test::assert-equal [[:out 1 "/tmp/slosh-cmd-example.txt"]] (get c :redirects))
+(test::assert-equal "/" (get (cmd "ls" :cwd "/") :cwd))
+(test::assert-equal 0 (run (cmd "test" {:type :var :name "HOME"} "=" (env "HOME"))))
+(test::assert-equal 0 (run (cmd "test" {:type :compound :args ["a" {:type :sub :run (cmd "echo" "b")}]} "=" "ab")))
+"#,
+    );
+    add_builtin(
+        env,
+        "pipe",
+        pipe,
+        r#"Usage: (pipe command+) -> pipe-map
+
+Build a pipeline from commands (from cmd, other builders or strings parsed as shell command
+lines). The stdout of each command is sent to the stdin of the next. Returns a map of
+:type :pipe and :runs.
+
+Section: shell
+
+Example:
+(def p (pipe (cmd "echo" "hello") (cmd "wc" "-c")))
+(test::assert-equal :pipe (get p :type))
+(test::assert-equal 2 (len (get p :runs)))
+(test::assert-equal ["wc" "-c"] (get (get (get p :runs) 1) :args))
+"#,
+    );
+    add_builtin(
+        env,
+        "cmd-and",
+        cmd_and,
+        r#"Usage: (cmd-and command+) -> and-map
+
+Build a list of commands where each command only runs if the previous one succeeded (the
+shell's &&). Returns a map of :type :and and :runs.
+
+Section: shell
+
+Example:
+(test::assert-equal 1 (run (cmd-and (cmd "true") (cmd "false") (cmd "true"))))
+(test::assert-equal 0 (run (cmd-and (cmd "true") "true")))
+"#,
+    );
+    add_builtin(
+        env,
+        "cmd-or",
+        cmd_or,
+        r#"Usage: (cmd-or command+) -> or-map
+
+Build a list of commands where each command only runs if the previous one failed (the
+shell's ||). Returns a map of :type :or and :runs.
+
+Section: shell
+
+Example:
+(test::assert-equal 0 (run (cmd-or (cmd "false") (cmd "true"))))
+(test::assert-equal 1 (run (cmd-or (cmd "false") (cmd "false"))))
+"#,
+    );
+    add_builtin(
+        env,
+        "cmd-seq",
+        cmd_seq,
+        r#"Usage: (cmd-seq command+) -> sequence-map
+
+Build a sequence of commands that are run one after the other (the shell's ;). Returns a map
+of :type :sequence and :runs.
+
+Section: shell
+
+Example:
+(test::assert-equal 0 (run (cmd-seq (cmd "false") (cmd "true"))))
+(test::assert-equal :sequence (get (cmd-seq "ls" "pwd") :type))
+"#,
+    );
+    add_builtin(
+        env,
+        "run",
+        run,
+        r#"Usage: (run command :in path :out path :append path :err path :err-to-out bool :redirects [redirect*]) -> int
+
+Run command (data from cmd, pipe, cmd-and, cmd-or, cmd-seq or sh-parse, or a string to parse
+as a shell command line) as a foreground job and return its exit status.
+:in redirects the stdin of the first command from a file, the other options apply to the last
+command, see cmd for their meaning. For cmd-seq, cmd-and and cmd-or every command (or the
+first/last of every pipe) gets the options, each opens the :in file and output files are
+truncated by the first command only so all the output is kept.
+
+Section: shell
+
+Example:
+(def out-file "/tmp/slosh-run-example.txt")
+(test::assert-equal 0 (run (pipe (cmd "echo" "a b" "c") (cmd "tr" " " "\n") (cmd "wc" "-l")) :out out-file))
+(test::assert-equal "3" (str-trim (get (proc-run ["cat" out-file]) :stdout)))
+(run (cmd "echo" "d e") :append out-file)
+(test::assert-equal "3\nd e\n" (get (proc-run ["cat" out-file]) :stdout))
+(test::assert-equal 0 (run (cmd "grep" "-q" "d e" :in out-file)))
+(test::assert-equal 0 (run (pipe (cmd "cat") (cmd "grep" "-c" "d e")) :in out-file :out "/tmp/slosh-run-example2.txt"))
+(test::assert-equal "1\n" (get (proc-run ["cat" "/tmp/slosh-run-example2.txt"]) :stdout))
+(test::assert-equal 0 (run (cmd-seq (cmd "echo" "one") (cmd-and (cmd "true") (cmd "echo" "two"))) :out out-file))
+(test::assert-equal "one\ntwo\n" (get (proc-run ["cat" out-file]) :stdout))
+(test::assert-equal 0 (run "cat; grep -c e" :in out-file :out "/tmp/slosh-run-example2.txt"))
+(test::assert-equal "one\ntwo\n1\n" (get (proc-run ["cat" "/tmp/slosh-run-example2.txt"]) :stdout))
+(run (cmd "rm" out-file "/tmp/slosh-run-example2.txt"))
+"#,
+    );
+    add_builtin(
+        env,
+        "sh-parse",
+        sh_parse,
+        r#"Usage: (sh-parse string) -> command-data
+
+Parse string as a shell command line and return it as the same data built by cmd, pipe, cmd-and,
+cmd-or and cmd-seq so it can be inspected, changed and passed to run. Variables and $()
+substitutions are expanded while parsing. Backgrounded commands have :type :background,
+subshells :type :subshell (with :run) and lisp pipeline stages, (fn ...), (lambda ...) or
+(lisp expression) where expression evaluates to a function, :type :lisp (with :source).
+
+Section: shell
+
+Example:
+(def p (sh-parse "grep -v 'a b' file.txt | wc -l > out.txt"))
+(test::assert-equal :pipe (get p :type))
+(test::assert-equal ["grep" "-v" "a b" "file.txt"] (get (get (get p :runs) 0) :args))
+(test::assert-equal [[:out 1 "out.txt"]] (get (get (get p :runs) 1) :redirects))
+(test::assert-equal ["echo" (env "HOME")] (get (sh-parse "echo $HOME") :args))
+(test::assert-equal {:type :lisp :source "(fn (l) (str-upper l))"} (get (get (sh-parse "ls | (fn (l) (str-upper l))") :runs) 1))
+(test::assert-equal :lisp (get (get (get (sh-parse "ls | (lisp str-upper)") :runs) 1) :type))
+(test::assert-equal :subshell (get (get (get (sh-parse "ls | (str-upper x)") :runs) 1) :type))
+(test::assert-equal :and (get (sh-parse "true && false") :type))
+(test::assert-equal 1 (run (sh-parse "true && false")))
+"#,
+    );
+}
@@ -0,0 +1,532 @@
+use crate::SHELL_ENV;
+use bridge_adapters::add_builtin;
+use compile_state::state::SloshVm;
+use shell::command_data::{Arg, CommandWithArgs};
+use shell::jobs::{Job, PidStatus};
+use shell::platform::{FromFileDesc, Pid, Platform, Sys, SIGINT, SIGKILL, SIGTERM};
+use shell::signals::test_clear_sigint;
+use slvm::{VMError, VMResult, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Options parsed from the arguments to proc-run or proc-spawn.
+struct ProcOptions {
+    args: Vec<OsString>,
+    stdin: Option<String>,
+    env: Vec<(OsString, OsString)>,
+    cwd: Option<OsString>,
+    timeout: Option<Duration>,
+}
+
+/// A process started by proc-spawn that has not been waited on yet.
+struct Proc {
+    job: Job,
+    pid: Pid,
+    stdout: Option<BufReader<File>>,
+    stderr: Option<BufReader<File>>,
+    stdin_writer: Option<JoinHandle<()>>,
+}
+
+/// Final state of a process after waiting on it.
+struct ProcResult {
+    status: Option<i32>,
+    signal: Option<i32>,
+    stdout: String,
+    stderr: String,
+}
+
+thread_local! {
+    /// Processes from proc-spawn keyed by pid, removed once waited on.
+    static PROCS: RefCell<HashMap<i64, Proc>> = RefCell::new(HashMap::new());
+}
+
+/// Reap spawned processes that have exited so they do not linger as zombies, their status stays
+/// in the job for proc-wait.
+fn reap_procs() {
+    PROCS.with(|procs| {
+        for proc in procs.borrow_mut().values_mut() {
+            if is_running(proc) {
+                Sys::try_wait_pid(proc.pid, &mut proc.job);
+            }
+        }
+    });
+}
+
+/// Is the spawned process still running (not reaped)?
+fn is_running(proc: &Proc) -> bool {
+    matches!(proc.job.pids().first(), Some(PidStatus::Running(_)))
+}
+
+pub(super) fn value_to_os_string(vm: &SloshVm, val: Value) -> OsString {
+    match val {
+        Value::Symbol(i) | Value::Keyword(i) => vm.get_interned(i).into(),
+        _ => val.pretty_value(vm).into(),
+    }
+}
+
+fn parse_options(vm: &mut SloshVm, name: &str, registers: &[Value]) -> VMResult<ProcOptions> {
+    let mut regs = registers.iter();
+    let command = regs
+        .next()
+        .ok_or_else(|| VMError::new_vm(format!("{name}: requires a command vector or list")))?;
+    let args: Vec<OsString> = match command {
+        Value::Vector(_) | Value::List(_, _) | Value::Pair(_) => command
+            .iter(vm)
+            .map(|arg| value_to_os_string(vm, arg))
+            .collect(),
+        _ => {
+            return Err(VMError::new_vm(format!(
+                "{name}: requires a command vector or list, got {}",
+                command.display_type(vm)
+            )))
+        }
+    };
+    if args.is_empty() {
+        return Err(VMError::new_vm(format!("{name}: empty command")));
+    }
+    let mut opts = ProcOptions {
+        args,
+        stdin: None,
+        env: vec![],
+        cwd: None,
+        timeout: None,
+    };
+    while let Some(key) = regs.next() {
+        let val = regs.next().ok_or_else(|| {
+            VMError::new_vm(format!(
+                "{name}: option {} missing value",
+                key.display_value(vm)
+            ))
+        })?;
+        let key = if let Value::Keyword(i) = key {
+            vm.get_interned(*i)
+        } else {
+            return Err(VMError::new_vm(format!(
+                "{name}: options must be keywords, got {}",
+                key.display_value(vm)
+            )));
+        };
+        match key {
+            "stdin" => opts.stdin = Some(val.pretty_value(vm)),
+            "env" => {
+                if let Value::Map(h) = val {
+                    for (k, v) in vm.get_map(*h) {
+                        opts.env
+                            .push((value_to_os_string(vm, *k), value_to_os_string(vm, *v)));
+                    }
+                } else {
+                    return Err(VMError::new_vm(format!("{name}: :env requires a map")));
+                }
+            }
+            "cwd" => opts.cwd = Some(val.pretty_value(vm).into()),
+            "timeout" => {
+                let ms = val.get_int(vm)?;
+                if ms < 0 {
+                    return Err(VMError::new_vm(format!(
+                        "{name}: :timeout must be a positive number of milliseconds"
+                    )));
+                }
+                opts.timeout = Some(Duration::from_millis(ms as u64));
+            }
+            _ => {
+                return Err(VMError::new_vm(format!(
+                    "{name}: unknown option :{key}, expected :stdin, :env, :cwd or :timeout"
+                )))
+            }
+        }
+    }
+    Ok(opts)
+}
+
+/// Fork and exec the command in opts with stdout and stderr attached to pipes.
+/// If foreground is false the process is started in the background and never gets the terminal.
+fn spawn(name: &str, opts: &ProcOptions, foreground: bool) -> VMResult<Proc> {
+    let io_err = |e| VMError::new("io", format!("{name}: {e}"));
+    let mut command = CommandWithArgs::new();
+    for arg in &opts.args {
+        command.push_arg(Arg::Str(arg.clone()));
+    }
+    for (key, val) in &opts.env {
+        command.push_env_var(key.clone(), val.clone());
+    }
+    if let Some(cwd) = &opts.cwd {
+        command.set_cwd(cwd.clone());
+    }
+    // A background process reads :stdin or nothing (the write end is closed when not used), never
+    // the terminal.
+    let stdin = if opts.stdin.is_some() || !foreground {
+        let (input, output) = Sys::anon_pipe().map_err(io_err)?;
+        command.push_stdin_front(Some(input));
+        Some(unsafe { File::from_file_desc(output) })
+    } else {
+        None
+    };
+    let (input, output) = Sys::anon_pipe().map_err(io_err)?;
+    let stdout = unsafe { File::from_file_desc(input) };
+    command.push_stdout_front(Some(output));
+    let (input, output) = Sys::anon_pipe().map_err(io_err)?;
+    let stderr = unsafe { File::from_file_desc(input) };
+    command.push_stderr_front(Some(output));
+
+    let job = SHELL_ENV.with(|jobs_ref| {
+        let jobs = &mut jobs_ref.borrow_mut();
+        let mut job = jobs.new_job();
+        job.set_stealth(true);
+        job.set_background(!foreground);
+        Sys::fork_exec(&command, &mut job, jobs).map(|_| job)
+    });
+    let job = job.map_err(io_err)?;
+    let pid = job
+        .pids()
+        .first()
+        .map(|p| p.pid())
+        .ok_or_else(|| VMError::new("io", format!("{name}: failed to start process")))?;
+    // Feed stdin from a thread so a child that fills its output pipes can not deadlock us.
+    let stdin_writer = match (stdin, &opts.stdin) {
+        (Some(mut stdin), Some(input)) => {
+            let input = input.clone();
+            Some(thread::spawn(move || {
+                // The child may exit without reading all of its input, that is not an error.
+                let _ = stdin.write_all(input.as_bytes());
+            }))
+        }
+        _ => None,
+    };
+    Ok(Proc {
+        job,
+        pid,
+        stdout: Some(BufReader::new(stdout)),
+        stderr: Some(BufReader::new(stderr)),
+        stdin_writer,
+    })
+}
+
+fn read_all(reader: Option<BufReader<File>>) -> Option<JoinHandle<String>> {
+    reader.map(|mut reader| {
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            let _ = reader.read_to_end(&mut buf);
+            String::from_utf8_lossy(&buf).to_string()
+        })
+    })
+}
+
+/// Wait for proc to exit while draining its output.
+/// If timeout expires the process is killed with SIGKILL, a SIGINT to the shell is forwarded.
+fn wait_proc(mut proc: Proc, timeout: Option<Duration>) -> ProcResult {
+    let stdout = read_all(proc.stdout.take());
+    let stderr = read_all(proc.stderr.take());
+    let start = Instant::now();
+    let mut killed = false;
+    loop {
+        if test_clear_sigint() {
+            let _ = Sys::kill_pid(proc.pid, SIGINT);
+        }
+        let (stop, _status) = Sys::try_wait_pid(proc.pid, &mut proc.job);
+        if stop {
+            break;
+        }
+        if let Some(timeout) = timeout {
+            if !killed && start.elapsed() >= timeout {
+                let _ = Sys::kill_pid(proc.pid, SIGKILL);
+                killed = true;
+            }
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+    SHELL_ENV.with(|jobs_ref| jobs_ref.borrow().restore_terminal());
+    if let Some(writer) = proc.stdin_writer.take() {
+        let _ = writer.join();
+    }
+    let (status, signal) = match proc.job.pids().first() {
+        Some(PidStatus::Done(_, status)) => (Some(*status), None),
+        Some(PidStatus::Signaled(_, signal)) => (None, Some(*signal)),
+        _ => (None, None),
+    };
+    ProcResult {
+        status,
+        signal,
+        stdout: stdout.and_then(|h| h.join().ok()).unwrap_or_default(),
+        stderr: stderr.and_then(|h| h.join().ok()).unwrap_or_default(),
+    }
+}
+
+fn result_map(vm: &mut SloshVm, result: ProcResult) -> Value {
+    let mut map = HashMap::new();
+    vm.pause_gc();
+    let status = result
+        .status
+        .map(|s| (s as i64).into())
+        .unwrap_or(Value::Nil);
+    let signal = result
+        .signal
+        .map(|s| (s as i64).into())
+        .unwrap_or(Value::Nil);
+    let stdout = vm.alloc_string(result.stdout);
+    let stderr = vm.alloc_string(result.stderr);
+    map.insert(Value::Keyword(vm.intern_static("status")), status);
+    map.insert(Value::Keyword(vm.intern_static("signal")), signal);
+    map.insert(Value::Keyword(vm.intern_static("stdout")), stdout);
+    map.insert(Value::Keyword(vm.intern_static("stderr")), stderr);
+    let res = vm.alloc_map(map);
+    vm.unpause_gc();
+    res
+}
+
+fn get_pid(vm: &SloshVm, name: &str, val: Option<&Value>) -> VMResult<i64> {
+    match val {
+        Some(val) if val.is_int() => val.get_int(vm),
+        Some(val) => Err(VMError::new_vm(format!(
+            "{name}: requires a process handle from proc-spawn, got {}",
+            val.display_value(vm)
+        ))),
+        None => Err(VMError::new_vm(format!(
+            "{name}: requires a process handle from proc-spawn"
+        ))),
+    }
+}
+
+fn proc_run(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    reap_procs();
+    let opts = parse_options(vm, "proc-run", registers)?;
+    let proc = spawn("proc-run", &opts, true)?;
+    let result = wait_proc(proc, opts.timeout);
+    Ok(result_map(vm, result))
+}
+
+fn proc_spawn(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    reap_procs();
+    let opts = parse_options(vm, "proc-spawn", registers)?;
+    if opts.timeout.is_some() {
+        return Err(VMError::new_vm(
+            "proc-spawn: :timeout is not supported, pass it to proc-wait",
+        ));
+    }
+    let proc = spawn("proc-spawn", &opts, false)?;
+    let pid: i32 = proc.pid.into();
+    let pid = pid as i64;
+    PROCS.with(|procs| procs.borrow_mut().insert(pid, proc));
+    Ok(pid.into())
+}
+
+fn proc_wait(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    let pid = get_pid(vm, "proc-wait", registers.first())?;
+    let timeout = match registers.get(1) {
+        Some(ms) => {
+            let ms = ms.get_int(vm)?;
+            if ms < 0 {
+                return Err(VMError::new_vm(
+                    "proc-wait: timeout must be a positive number of milliseconds",
+                ));
+            }
+            Some(Duration::from_millis(ms as u64))
+        }
+        None => None,
+    };
+    if registers.len() > 2 {
+        return Err(VMError::new_vm(
+            "proc-wait: takes a process handle and optional timeout",
+        ));
+    }
+    let proc = PROCS
+        .with(|procs| procs.borrow_mut().remove(&pid))
+        .ok_or_else(|| VMError::new_vm(format!("proc-wait: no running process {pid}")))?;
+    let result = wait_proc(proc, timeout);
+    Ok(result_map(vm, result))
+}
+
+fn proc_kill(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    reap_procs();
+    let pid = get_pid(vm, "proc-kill", registers.first())?;
+    let signal = match registers.get(1) {
+        Some(sig) => {
+            let sig = sig.get_int(vm)?;
+            i32::try_from(sig)
+                .map_err(|_| VMError::new_vm(format!("proc-kill: invalid signal {sig}")))?
+        }
+        None => SIGTERM,
+    };
+    if registers.len() > 2 {
+        return Err(VMError::new_vm(
+            "proc-kill: takes a process handle and optional signal number",
+        ));
+    }
+    let sent = PROCS.with(|procs| {
+        if let Some(proc) = procs.borrow().get(&pid) {
+            // Once reaped the pid may belong to another process.
+            if !is_running(proc) {
+                return Ok(false);
+            }
+            Sys::kill_pid(proc.pid, signal)
+                .map(|_| true)
+                .map_err(|e| VMError::new("io", format!("proc-kill: {e}")))
+        } else {
+            Err(VMError::new_vm(format!(
+                "proc-kill: no running process {pid}"
+            )))
+        }
+    })?;
+    Ok(if sent { Value::True } else { Value::False })
+}
+
+fn proc_read_line(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    reap_procs();
+    let pid = get_pid(vm, "proc-read-line", registers.first())?;
+    let use_stderr = match registers.get(1) {
+        Some(Value::Keyword(i)) if vm.get_interned(*i) == "stdout" => false,
+        Some(Value::Keyword(i)) if vm.get_interned(*i) == "stderr" => true,
+        None => false,
+        Some(_) => {
+            return Err(VMError::new_vm(
+                "proc-read-line: stream must be :stdout or :stderr",
+            ))
+        }
+    };
+    let line = PROCS.with(|procs| {
+        let mut procs = procs.borrow_mut();
+        let proc = procs
+            .get_mut(&pid)
+            .ok_or_else(|| VMError::new_vm(format!("proc-read-line: no running process {pid}")))?;
+        let reader = if use_stderr {
+            proc.stderr.as_mut()
+        } else {
+            proc.stdout.as_mut()
+        };
+        let mut line = String::new();
+        match reader {
+            Some(reader) => match reader.read_line(&mut line) {
+                Ok(0) => Ok(None),
+                Ok(_) => Ok(Some(line)),
+                Err(e) => Err(VMError::new("io", format!("proc-read-line: {e}"))),
+            },
+            None => Ok(None),
+        }
+    })?;
+    Ok(match line {
+        Some(line) => vm.alloc_string(line),
+        None => Value::Nil,
+    })
+}
+
+pub fn add_process_builtins(env: &mut SloshVm) {
+    add_builtin(
+        env,
+        "proc-run",
+        proc_run,
+        r#"Usage: (proc-run [command arg*] :stdin string :env map :cwd dir :timeout ms) -> map
+
+Run command with args directly (no shell parsing, globbing or aliases) and wait for it to
+finish. Returns a map with keys :status (exit code or nil if killed by a signal), :signal
+(signal number or nil), :stdout and :stderr (the captured output as strings).
+All options are optional. :stdin is written to the command's standard input (without it the
+command reads the shell's standard input), :env is a map of
+extra environment variables for the command, :cwd is the directory to run in and :timeout
+is the number of milliseconds to wait before killing the command with SIGKILL.
+
+Section: shell
+
+Example:
+(def res (proc-run ["sh" "-c" "echo out; echo err >&2; exit 3"]))
+(test::assert-equal 3 (get res :status))
+(test::assert-equal "out\n" (get res :stdout))
+(test::assert-equal "err\n" (get res :stderr))
+(test::assert-equal nil (get res :signal))
+(test::assert-equal "hello" (get (proc-run ["cat"] :stdin "hello") :stdout))
+(test::assert-equal "bar\n" (get (proc-run ["sh" "-c" "echo $FOO"] :env {"FOO" "bar"}) :stdout))
+(test::assert-equal "/\n" (get (proc-run ["pwd"] :cwd "/") :stdout))
+(test::assert-equal 9 (get (proc-run ["sleep" "5"] :timeout 50) :signal))
+(test::assert-error (proc-run ["true"] :env {"FOO" "a\x00b"}))
+"#,
+    );
+    add_builtin(
+        env,
+        "proc-spawn",
+        proc_spawn,
+        r#"Usage: (proc-spawn [command arg*] :stdin string :env map :cwd dir) -> process
+
+Start command with args in the background and return a process handle (it's pid).
+Accepts the same options as proc-run except :timeout. The handle can be used with
+proc-read-line, proc-kill and must be passed to proc-wait to collect the process.
+The handle owns the process's output pipes and exit status until proc-wait, a process that
+exits is reaped by the next proc-* call (so it is not left a zombie) but the handle is only
+released by proc-wait. The process stays in the shell's process group and does not take the
+terminal, without :stdin its standard input is empty (not the shell's).
+
+Section: shell
+
+Example:
+(def proc (proc-spawn ["sh" "-c" "echo one; echo two"]))
+(test::assert-equal "one\n" (proc-read-line proc))
+(def res (proc-wait proc))
+(test::assert-equal 0 (get res :status))
+(test::assert-equal "two\n" (get res :stdout))
+(test::assert-equal 0 (get (proc-wait (proc-spawn ["cat"]) 5000) :status))
+"#,
+    );
+    add_builtin(
+        env,
+        "proc-wait",
+        proc_wait,
+        r#"Usage: (proc-wait process timeout?) -> map
+
+Wait for a process from proc-spawn to exit and return the same result map as proc-run.
+Output not yet consumed by proc-read-line is returned in :stdout and :stderr. If timeout
+(milliseconds) is provided then the process is killed with SIGKILL if it runs longer.
+
+Section: shell
+
+Example:
+(def proc (proc-spawn ["sh" "-c" "exit 2"]))
+(test::assert-equal 2 (get (proc-wait proc) :status))
+(test::assert-error (proc-wait proc))
+"#,
+    );
+    add_builtin(
+        env,
+        "proc-kill",
+        proc_kill,
+        r#"Usage: (proc-kill process signal?) -> #t/#f
+
+Send signal (a signal number, default SIGTERM) to a process from proc-spawn.
+Returns #f without sending it if the process already exited. The process still needs to be
+collected with proc-wait.
+
+Section: shell
+
+Example:
+(def proc (proc-spawn ["sleep" "10"]))
+; Not a signal number (it would truncate to SIGTERM).
+(test::assert-error (proc-kill proc 4294967311))
+(test::assert-true (proc-kill proc))
+(test::assert-equal 15 (get (proc-wait proc) :signal))
+"#,
+    );
+    add_builtin(
+        env,
+        "proc-read-line",
+        proc_read_line,
+        r#"Usage: (proc-read-line process :stdout|:stderr) -> string
+
+Read the next line (including the newline) from a process started with proc-spawn.
+Reads stdout unless :stderr is provided. Returns nil once the stream is closed.
+
+Section: shell
+
+Example:
+(def proc (proc-spawn ["sh" "-c" "echo err >&2"]))
+(test::assert-equal "err\n" (proc-read-line proc :stderr))
+(test::assert-equal nil (proc-read-line proc :stderr))
+(test::assert-equal nil (proc-read-line proc))
+(proc-wait proc)
+; Lines as they are produced without collecting all the output first.
+(def proc (proc-spawn ["seq" "1" "3"]))
+(test::assert-equal '("1\n" "2\n" "3\n") (gen->list (gen-from (fn () (proc-read-line proc)))))
+(proc-wait proc)
+"#,
+    );
+}
@@ -0,0 +1,347 @@
+//! slosh --test, find the files with tests (see the deftest macro), run them and report the results
+//! as TAP or JUnit XML.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use builtins::print::display_value;
+use compile_state::state::{SloshVm, SloshVmTrait};
+use sl_compiler::load_eval::load_internal;
+use slvm::{VMError, VMResult, Value};
+
+use crate::config::{Config, TestFormat};
+
+/// The outcome of one test (or of loading a file, which fails like a test).
+struct TestResult {
+    file: String,
+    name: String,
+    duration: Duration,
+    error: Option<String>,
+}
+
+/// A registered test, the values are kept alive by test::*tests*.
+struct Test {
+    name: String,
+    tags: Vec<String>,
+    fixtures: Value,
+    thunk: Value,
+}
+
+/// Is path a test file, *_test.slosh or test_*.slosh.
+fn is_test_file(path: &Path) -> bool {
+    match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => {
+            name.ends_with("_test.slosh") || (name.starts_with("test_") && name.ends_with(".slosh"))
+        }
+        None => false,
+    }
+}
+
+/// The test files under dir, sorted so runs are repeatable.
+fn find_test_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<_>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            find_test_files(&path, files)?;
+        } else if is_test_file(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn call(env: &mut SloshVm, f: Value, args: &[Value]) -> VMResult<Value> {
+    match f {
+        Value::Lambda(h) => {
+            let l = env.get_lambda(h);
+            env.do_call(l, args, None)
+        }
+        Value::Closure(h) => {
+            let (l, caps) = env.get_closure(h);
+            let caps = caps.to_vec();
+            env.do_call(l, args, Some(&caps[..]))
+        }
+        Value::Builtin(idx) => env.call_builtin(idx, args),
+        _ => Err(VMError::new_vm(format!(
+            "test: expected a function, got a {}",
+            f.display_type(env)
+        ))),
+    }
+}
+
+fn global_slot(env: &mut SloshVm, name: &str) -> VMResult<u32> {
+    let i_name = env.intern(name);
+    env.global_intern_slot(i_name)
+        .ok_or_else(|| VMError::new_vm(format!("test: {name} is not defined, is core loaded?")))
+}
+
+/// Name without the leading : of a keyword so tags can be keywords or symbols.
+fn tag_name(env: &SloshVm, tag: Value) -> String {
+    display_value(env, tag).trim_start_matches(':').to_string()
+}
+
+/// Load file with an empty test::*tests* and return the tests it registered.
+fn load_tests(env: &mut SloshVm, file: &str) -> VMResult<Vec<Test>> {
+    let tests_slot = global_slot(env, "test::*tests*")?;
+    let tests = env.alloc_vector(Vec::new());
+    env.set_global(tests_slot, tests);
+    let i_file = env.intern(file);
+    let file = env.get_interned(i_file);
+    load_internal(env, file)?;
+    let mut res = Vec::new();
+    for test in env.get_global(tests_slot).iter(env).collect::<Vec<Value>>() {
+        if let Value::Vector(h) = test {
+            if let [name, tags, fixtures, thunk] = env.get_vector(h) {
+                res.push(Test {
+                    name: display_value(env, *name),
+                    tags: tags.iter(env).map(|tag| tag_name(env, tag)).collect(),
+                    fixtures: *fixtures,
+                    thunk: *thunk,
+                });
+                continue;
+            }
+        }
+        return Err(VMError::new_vm(format!(
+            "test: invalid entry in test::*tests*: {}",
+            display_value(env, test)
+        )));
+    }
+    Ok(res)
+}
+
+/// Run a test (with its fixtures), changes to globals are undone after.  This is shallow, objects
+/// the globals refer to are not copied so changes made to them in place are kept.
+fn run_test(env: &mut SloshVm, test: &Test) -> VMResult<Value> {
+    let run_slot = global_slot(env, "test::run-with-fixtures")?;
+    let run = env.get_global(run_slot);
+    env.save_globals();
+    let res = call(env, run, &[test.fixtures, test.thunk]);
+    env.restore_globals();
+    res
+}
+
+fn selected(config: &Config, test: &Test) -> bool {
+    (config.test_filters.is_empty()
+        || config
+            .test_filters
+            .iter()
+            .any(|filter| test.name.contains(filter.as_str())))
+        && (config.test_tags.is_empty()
+            || config
+                .test_tags
+                .iter()
+                .any(|tag| test.tags.contains(&tag.trim_start_matches(':').to_string())))
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// TAP line(s) for the nth result.
+fn tap_result(n: usize, result: &TestResult) -> String {
+    let mut out = String::new();
+    match &result.error {
+        None => {
+            let _ = writeln!(
+                out,
+                "ok {n} - {} # time={:.3}ms",
+                result.name,
+                millis(result.duration)
+            );
+        }
+        Some(error) => {
+            let _ = writeln!(out, "not ok {n} - {}", result.name);
+            let _ = writeln!(out, "  ---");
+            let _ = writeln!(out, "  file: {}", result.file);
+            let _ = writeln!(out, "  duration_ms: {:.3}", millis(result.duration));
+            let _ = writeln!(out, "  message: |");
+            for line in error.lines() {
+                let _ = writeln!(out, "    {line}");
+            }
+            let _ = writeln!(out, "  ...");
+        }
+    }
+    out
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            '\'' => res.push_str("&apos;"),
+            _ => res.push(ch),
+        }
+    }
+    res
+}
+
+/// JUnit XML report, a testsuite for each file.
+fn junit_report(results: &[TestResult], total: Duration) -> String {
+    let mut out = String::new();
+    let failures = results.iter().filter(|r| r.error.is_some()).count();
+    let _ = writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(
+        out,
+        r#"<testsuites name="slosh" tests="{}" failures="{failures}" time="{:.6}">"#,
+        results.len(),
+        total.as_secs_f64()
+    );
+    let mut start = 0;
+    while start < results.len() {
+        let file = &results[start].file;
+        let end = start
+            + results[start..]
+                .iter()
+                .take_while(|r| &r.file == file)
+                .count();
+        let suite = &results[start..end];
+        let _ = writeln!(
+            out,
+            r#"  <testsuite name="{}" tests="{}" failures="{}" time="{:.6}">"#,
+            xml_escape(file),
+            suite.len(),
+            suite.iter().filter(|r| r.error.is_some()).count(),
+            suite
+                .iter()
+                .map(|r| r.duration)
+                .sum::<Duration>()
+                .as_secs_f64()
+        );
+        for result in suite {
+            let _ = write!(
+                out,
+                r#"    <testcase name="{}" classname="{}" time="{:.6}""#,
+                xml_escape(&result.name),
+                xml_escape(file),
+                result.duration.as_secs_f64()
+            );
+            match &result.error {
+                None => {
+                    let _ = writeln!(out, "/>");
+                }
+                Some(error) => {
+                    let message = error.lines().next().unwrap_or_default();
+                    let _ = writeln!(out, ">");
+                    let _ = writeln!(
+                        out,
+                        r#"      <failure message="{}">{}</failure>"#,
+                        xml_escape(message),
+                        xml_escape(error)
+                    );
+                    let _ = writeln!(out, "    </testcase>");
+                }
+            }
+        }
+        let _ = writeln!(out, "  </testsuite>");
+        start = end;
+    }
+    let _ = writeln!(out, "</testsuites>");
+    out
+}
+
+/// Run the tests in path (a test file or a directory searched for them) and write the report,
+/// returns the exit status (0 if every test passed).
+///
+/// Each file is loaded (after core.slosh) then its tests run, a test starts with the globals as
+/// they were after its file loaded and globals are restored after each file so files do not see
+/// each other's definitions.  Only the global bindings are restored (see run_test).
+pub fn run_tests(env: &mut SloshVm, config: &Config, path: &str) -> i32 {
+    let mut out: Box<dyn Write> = match &config.test_output {
+        Some(file) => match fs::File::create(file) {
+            Ok(f) => Box::new(f),
+            Err(err) => {
+                eprintln!("ERROR creating {file}: {err}");
+                return 1;
+            }
+        },
+        None => Box::new(io::stdout()),
+    };
+    let i_core = env.intern("core.slosh");
+    let core = env.get_interned(i_core);
+    if let Err(err) = load_internal(env, core) {
+        eprintln!("ERROR: {err}");
+        return 1;
+    }
+    let root = Path::new(path);
+    let files = if root.is_dir() {
+        let mut files = Vec::new();
+        if let Err(err) = find_test_files(root, &mut files) {
+            eprintln!("ERROR reading {path}: {err}");
+            return 1;
+        }
+        files
+    } else {
+        vec![root.to_path_buf()]
+    };
+
+    let tap = config.test_format == TestFormat::Tap;
+    if tap {
+        let _ = writeln!(out, "TAP version 13");
+    }
+    let start = Instant::now();
+    let mut results: Vec<TestResult> = Vec::new();
+    for file in files {
+        let file = file.to_string_lossy().into_owned();
+        if tap {
+            let _ = writeln!(out, "# {file}");
+        }
+        env.save_globals();
+        let load_start = Instant::now();
+        let mut file_results = Vec::new();
+        match load_tests(env, &file) {
+            Ok(tests) => {
+                for test in tests.iter().filter(|test| selected(config, test)) {
+                    let test_start = Instant::now();
+                    let res = run_test(env, test);
+                    file_results.push(TestResult {
+                        file: file.clone(),
+                        name: test.name.clone(),
+                        duration: test_start.elapsed(),
+                        error: res.err().map(|err| err.display(env)),
+                    });
+                }
+            }
+            Err(err) => file_results.push(TestResult {
+                file: file.clone(),
+                name: format!("load {file}"),
+                duration: load_start.elapsed(),
+                error: Some(err.display(env)),
+            }),
+        }
+        env.restore_globals();
+        for result in file_results {
+            if tap {
+                let _ = write!(out, "{}", tap_result(results.len() + 1, &result));
+            }
+            results.push(result);
+        }
+    }
+    let failed = results.iter().filter(|r| r.error.is_some()).count();
+    if tap {
+        let _ = writeln!(out, "1..{}", results.len());
+        let _ = writeln!(
+            out,
+            "# {} passed, {failed} failed, {:.3}ms",
+            results.len() - failed,
+            millis(start.elapsed())
+        );
+    } else {
+        let _ = write!(out, "{}", junit_report(&results, start.elapsed()));
+    }
+    let _ = out.flush();
+    if failed > 0 {
+        1
+    } else {
+        0
+    }
+}
@@ -0,0 +1,101 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use tempdir::TempDir;
+
+pub fn get_slosh_exe() -> PathBuf {
+    PathBuf::from(env!("CARGO_BIN_EXE_slosh"))
+}
+
+const SOURCE: &str = r#"#%
+Usage: (classify x)
+
+Say what x is.
+
+Section: test
+%#
+(defn classify (x) ; one of three
+  (cond ((< x 0) "negative")
+
+
+        ; zero is special
+        ((= x 0) "zero") (#t "positive")))
+(let (a (classify 1) b (classify -1)) (if (= a b) (prn "same") (prn "different: " a " and " b)))
+"#;
+
+const FORMATTED: &str = r#"#%
+Usage: (classify x)
+
+Say what x is.
+
+Section: test
+%#
+(defn classify (x) ; one of three
+  (cond
+    ((< x 0) "negative")
+
+    ; zero is special
+    ((= x 0) "zero")
+    (#t "positive")))
+(let (a (classify 1) b (classify -1))
+  (if (= a b)
+      (prn "same")
+      (prn "different: " a " and " b)))
+"#;
+
+fn format(path: &Path) -> Output {
+    Command::new(get_slosh_exe())
+        .arg("--fmt")
+        .arg(path)
+        .args(["--width", "40"])
+        .output()
+        .expect("Failed to execute command")
+}
+
+#[test]
+fn test_fmt_file() {
+    let tmp_dir = TempDir::new("test_fmt_file").unwrap();
+    let path = tmp_dir.path().join("classify.slosh");
+    std::fs::write(&path, SOURCE).unwrap();
+    let output = format(&path);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), FORMATTED);
+    // Formatting again changes nothing.
+    let output = format(&path);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), FORMATTED);
+}
+
+#[test]
+fn test_fmt_stdin() {
+    let mut child = Command::new(get_slosh_exe())
+        .args(["--fmt", "-", "--width", "40"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to execute command");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(SOURCE.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), FORMATTED);
+}
+
+#[test]
+fn test_fmt_read_error() {
+    let tmp_dir = TempDir::new("test_fmt_read_error").unwrap();
+    let path = tmp_dir.path().join("broken.slosh");
+    std::fs::write(&path, "(defn broken (x)\n    (+ x 1)\n").unwrap();
+    let output = format(&path);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("ERROR formatting"));
+    // Left as is.
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "(defn broken (x)\n    (+ x 1)\n"
+    );
+}
@@ -44,6 +44,9 @@ pub fn get_globs_from_test_directory(glob: &str) -> Vec<PathBuf> {
 /// All slosh scripts in the ./slosh/tests/ directory will be run by this integration test. If
 /// the suffix of the file is _fail.slosh, then the test script is expected to fail. All other
 /// slosh scripts should return a 0 exit code or the integration test will fail.
+///
+/// With SLOSH_COVERAGE_DIR set each script writes its line coverage to <script name>.lcov in that
+/// directory.
 fn lisp_scripts() {
     let scripts = get_globs_from_test_directory("*.slosh");
     let fails = get_globs_from_test_directory("*_fail.slosh")
@@ -65,7 +68,14 @@ fn lisp_scripts() {
         } else {
             println!("Verify run fails: {basename:?}");
         }
-        let output = Command::new(slosh_path)
+        let mut command = Command::new(slosh_path);
+        // Set SLOSH_COVERAGE_DIR to write the lines each script runs as lcov.
+        if let Some(dir) = std::env::var_os("SLOSH_COVERAGE_DIR") {
+            let mut lcov = PathBuf::from(dir).join(basename);
+            lcov.set_extension("lcov");
+            command.arg("--coverage").arg(lcov);
+        }
+        let output = command
             .arg(test_script)
             .output()
             .expect("Failed to execute command");
@@ -0,0 +1,88 @@
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use tempdir::TempDir;
+
+pub fn get_slosh_exe() -> PathBuf {
+    PathBuf::from(env!("CARGO_BIN_EXE_slosh"))
+}
+
+/// The deftest files used to test slosh --test.
+fn runner_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("runner")
+}
+
+fn run_tests(args: &[&str]) -> (Output, String) {
+    let output = Command::new(get_slosh_exe())
+        .arg("--test")
+        .arg(runner_dir())
+        .args(args)
+        .output()
+        .expect("Failed to execute command");
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    println!("stdout:\n{stdout}");
+    (output, stdout)
+}
+
+#[test]
+fn test_runner_tap() {
+    let (output, stdout) = run_tests(&[]);
+    assert!(!output.status.success());
+    assert!(stdout.starts_with("TAP version 13\n"));
+    assert!(stdout.contains("ok 1 - addition # time="));
+    assert!(stdout.contains("ok 2 - globals-are-restored # time="));
+    assert!(stdout.contains("ok 3 - globals-are-restored-again # time="));
+    assert!(stdout.contains("ok 4 - fixture-runs # time="));
+    assert!(stdout.contains("not ok 5 - failing\n"));
+    assert!(stdout.contains("Expected: 5 :Int, Got: 4 :Int."));
+    // The fixture's deferred teardown runs even though the test failed, its output goes to stderr
+    // and leaves the TAP stream alone.
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("teardown ran"));
+    assert!(!stdout.contains("teardown ran"));
+    assert!(stdout.contains("ok 6 - concat # time="));
+    assert!(stdout.contains("ok 7 - counter-is-not-shared # time="));
+    assert!(stdout.contains("\n1..7\n"));
+    assert!(stdout.contains("# 6 passed, 1 failed"));
+    // Only files named like tests are loaded.
+    assert!(!stdout.contains("helper.slosh"));
+}
+
+#[test]
+fn test_runner_filter_and_tags() {
+    let (output, stdout) = run_tests(&["--filter", "globals", "--tag", "strings"]);
+    assert!(output.status.success());
+    assert!(stdout.contains("\n1..0\n"));
+
+    let (output, stdout) = run_tests(&["--filter", "globals"]);
+    assert!(output.status.success());
+    assert!(stdout.contains("ok 1 - globals-are-restored # time="));
+    assert!(stdout.contains("ok 2 - globals-are-restored-again # time="));
+    assert!(stdout.contains("\n1..2\n"));
+
+    let (output, stdout) = run_tests(&["--tag", ":math", "--tag", "strings"]);
+    assert!(output.status.success());
+    assert!(stdout.contains("ok 1 - addition # time="));
+    assert!(stdout.contains("ok 2 - concat # time="));
+    assert!(stdout.contains("\n1..2\n"));
+}
+
+#[test]
+fn test_runner_junit() {
+    let tmp_dir = TempDir::new("test_runner_junit").unwrap();
+    let report = tmp_dir.path().join("report.xml");
+    let (output, _) = run_tests(&["--format", "junit", "--output", report.to_str().unwrap()]);
+    assert!(!output.status.success());
+    let xml = std::fs::read_to_string(report).unwrap();
+    println!("report:\n{xml}");
+    assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"));
+    assert!(xml.contains("<testsuites name=\"slosh\" tests=\"7\" failures=\"1\""));
+    assert!(xml.contains("math_test.slosh\" tests=\"5\" failures=\"1\""));
+    assert!(xml.contains("test_strings.slosh\" tests=\"2\" failures=\"0\""));
+    assert!(xml.contains("<testcase name=\"addition\""));
+    assert!(xml.contains(
+        "<failure message=\"[error]: error [assert]: &quot;Expected: 5 :Int, Got: 4 :Int.&quot;\">"
+    ));
+    assert!(xml.trim_end().ends_with("</testsuites>"));
+}
@@ -0,0 +1,53 @@
+//! Use slosh as a configuration and plugin language for a Rust program.
+//!
+//! Run with: cargo run -p slosh_embed --example plugin
+use slosh_embed::{SloshBuilder, SloshError};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const CONFIG: &str = r#"
+(def app-name "embed-demo")
+(def workers 4)
+
+(defn on-event (name count)
+    (log (str "event " name " seen " count " times"))
+    (* count workers))
+"#;
+
+fn main() -> Result<(), SloshError> {
+    // The config is not trusted: no file system, printing, load or eval and limited resources.
+    let mut slosh = SloshBuilder::sandbox().heap_object_cap(1_000_000).build()?;
+    slosh.set_fuel(Some(1_000_000));
+
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let plugin_log = log.clone();
+    slosh.add_builtin_closure(
+        "log",
+        Box::new(move |vm, args| {
+            for a in args {
+                plugin_log.borrow_mut().push(a.pretty_value(vm));
+            }
+            Ok(slvm::Value::Nil)
+        }),
+        "Usage: (log string)\n\nAdd a line to the application log.\n\nSection: app\n",
+    );
+
+    slosh.set_global("version", "1.0".to_string())?;
+    slosh.eval_str(CONFIG)?;
+
+    let name: &str = slosh.get_global("app-name")?;
+    let workers: i32 = slosh.get_global("workers")?;
+    println!("{name} running with {workers} workers");
+
+    let event = slosh.to_value("startup".to_string())?;
+    let res = slosh.call_fn("on-event", &[event, 3.into()])?;
+    println!("on-event returned {}", slosh.display(res));
+
+    if let Err(err) = slosh.eval_str("(fs-exists? \"/\")") {
+        println!("sandboxed: {err}");
+    }
+    for line in log.borrow().iter() {
+        println!("log: {line}");
+    }
+    Ok(())
+}
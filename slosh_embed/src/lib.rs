@@ -0,0 +1,637 @@
+//! High level API for embedding slosh in a Rust application.
+//!
+//! [`Slosh`] wraps a VM with a compile environment and the builtins that were selected with
+//! [`SloshBuilder`].  It can evaluate strings or files, call lisp functions by name and move
+//! values in and out of globals using the bridge conversions from bridge_adapters.
+//!
+//! ```
+//! use slosh_embed::Slosh;
+//!
+//! let mut slosh = Slosh::new().unwrap();
+//! slosh.eval_str("(def scale 3) (defn scaled (x) (* x scale))").unwrap();
+//! let res = slosh.call_fn("scaled", &[14.into()]).unwrap();
+//! assert_eq!(slosh.display(res), "42");
+//! slosh.set_global("greeting", "hello".to_string()).unwrap();
+//! let s: String = slosh.get_global("greeting").unwrap();
+//! assert_eq!(s, "hello");
+//! ```
+//!
+//! Note that a [`Value`] returned from the VM is only valid until the next garbage collection.
+//! GC only runs when the VM allocates, so convert values to Rust types (or store them in a
+//! global) before evaluating more code.
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+use bridge_adapters::lisp_adapters::{SlFrom, SlFromRef};
+use compile_state::state::{new_slosh_vm, CompileState, SloshVm, SloshVmTrait};
+use sl_compiler::load_eval::{add_load_builtins, CORE_LISP};
+use sl_compiler::pass1::pass1;
+use sl_compiler::reader::Reader;
+pub use sl_compiler::Capability;
+use sl_compiler::{add_int_globals, compile};
+use slvm::{CallClosure, CallFuncSig, Chunk, VMError, Value, RET};
+use std::sync::Arc;
+
+/// Groups of builtins that can be added to a [`Slosh`] instance.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BuiltinSet {
+    /// Vector, hash map and pair functions.
+    Collections,
+    /// pr, prn, eprn, dump-regs and friends.
+    Print,
+    /// load, eval, read and read-all.
+    Load,
+    /// String functions.
+    Strings,
+    /// Errors, properties, gensym, coroutines and other core functions.
+    Misc,
+    /// Macro expansion helpers.
+    Macros,
+    /// File system functions (fs-*).
+    Io,
+    /// Conversions between types (str->int, char->int, etc).
+    Conversions,
+    /// The core lisp library (defn, let*, etc) loaded from core.slosh, most of it needs the
+    /// other sets.
+    CoreLisp,
+}
+
+impl BuiltinSet {
+    /// All builtin sets, in the order they are added.
+    pub const ALL: [BuiltinSet; 9] = [
+        BuiltinSet::Collections,
+        BuiltinSet::Print,
+        BuiltinSet::Load,
+        BuiltinSet::Strings,
+        BuiltinSet::Misc,
+        BuiltinSet::Macros,
+        BuiltinSet::Io,
+        BuiltinSet::Conversions,
+        BuiltinSet::CoreLisp,
+    ];
+
+    /// The capability the builtins in this set need.
+    pub fn capability(self) -> Capability {
+        match self {
+            BuiltinSet::Print => Capability::Print,
+            BuiltinSet::Load => Capability::Eval,
+            BuiltinSet::Io => Capability::FileSystem,
+            BuiltinSet::Collections
+            | BuiltinSet::Strings
+            | BuiltinSet::Misc
+            | BuiltinSet::Macros
+            | BuiltinSet::Conversions
+            | BuiltinSet::CoreLisp => Capability::Core,
+        }
+    }
+
+    fn add(self, vm: &mut SloshVm) -> Result<(), SloshError> {
+        match self {
+            BuiltinSet::Collections => builtins::collections::setup_collection_builtins(vm),
+            BuiltinSet::Print => {
+                builtins::print::add_print_builtins(vm);
+                builtins::profile::add_profile_builtins(vm);
+            }
+            BuiltinSet::Load => add_load_builtins(vm),
+            BuiltinSet::Strings => builtins::string::add_str_builtins(vm),
+            BuiltinSet::Misc => builtins::add_misc_builtins(vm),
+            BuiltinSet::Macros => builtins::macros::add_macro_builtins(vm),
+            BuiltinSet::Io => builtins::io::add_io_builtins(vm),
+            BuiltinSet::Conversions => builtins::conversions::add_conv_builtins(vm),
+            BuiltinSet::CoreLisp => {
+                let reader = Reader::from_static_string(CORE_LISP, vm, "core.slosh", 1, 0);
+                eval_reader(reader, "core.slosh")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Errors produced by [`Slosh`].
+#[derive(Clone, Debug)]
+pub enum SloshError {
+    /// The source could not be read.
+    Read { file: &'static str, reason: String },
+    /// An expression failed to compile.
+    Compile {
+        file: &'static str,
+        line: u32,
+        reason: String,
+    },
+    /// Evaluation raised an error, key is the error key (rt, io, etc).
+    Runtime { key: String, message: String },
+    /// A file could not be opened.
+    Io(String),
+    /// A global was not defined.
+    Undefined(String),
+    /// A global was not a callable.
+    NotCallable(String),
+    /// A value could not be converted to or from a Rust type.
+    Conversion(String),
+}
+
+impl fmt::Display for SloshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SloshError::Read { file: "", reason } => write!(f, "Read error: {reason}"),
+            SloshError::Read { file, reason } => write!(f, "Read error, {file}: {reason}"),
+            SloshError::Compile {
+                file: "",
+                line,
+                reason,
+            } => write!(f, "Compile error, line {line}: {reason}"),
+            SloshError::Compile { file, line, reason } => {
+                write!(f, "Compile error, {file} line {line}: {reason}")
+            }
+            SloshError::Runtime { key, message } => write!(f, "[{key}]: {message}"),
+            SloshError::Io(msg) => write!(f, "IO error: {msg}"),
+            SloshError::Undefined(name) => write!(f, "{name} is not defined"),
+            SloshError::NotCallable(name) => write!(f, "{name} is not callable"),
+            SloshError::Conversion(msg) => write!(f, "Conversion error: {msg}"),
+        }
+    }
+}
+
+impl Error for SloshError {}
+
+impl SloshError {
+    fn runtime(vm: &SloshVm, err: VMError) -> Self {
+        let message = match &err.obj {
+            slvm::VMErrorObj::Message(msg) => msg.clone(),
+            slvm::VMErrorObj::Object(val) => val.pretty_value(vm),
+        };
+        SloshError::Runtime {
+            key: err.key.to_string(),
+            message,
+        }
+    }
+}
+
+/// Builds a [`Slosh`] instance, by default with all the builtin sets.
+#[derive(Clone, Debug)]
+pub struct SloshBuilder {
+    builtins: Vec<BuiltinSet>,
+    load_path: Vec<String>,
+    read_eval: bool,
+    heap_object_cap: Option<usize>,
+}
+
+impl Default for SloshBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SloshBuilder {
+    /// New builder with all the builtin sets.
+    pub fn new() -> Self {
+        Self {
+            builtins: BuiltinSet::ALL.to_vec(),
+            load_path: Vec::new(),
+            read_eval: false,
+            heap_object_cap: None,
+        }
+    }
+
+    /// New builder with no builtins (only the compiler special forms).
+    pub fn empty() -> Self {
+        Self {
+            builtins: Vec::new(),
+            ..Self::new()
+        }
+    }
+
+    /// New builder for running untrusted code.  Only builtins with the Core capability (no
+    /// printing, file system, load or eval), reader evaluation (#.) stays off.  Combine with a heap
+    /// object cap and set fuel or a time limit before evaluating.
+    pub fn sandbox() -> Self {
+        Self::new().capabilities(&[Capability::Core])
+    }
+
+    /// Only keep the builtin sets that need one of capabilities.
+    pub fn capabilities(mut self, capabilities: &[Capability]) -> Self {
+        self.builtins
+            .retain(|s| capabilities.contains(&s.capability()));
+        self
+    }
+
+    /// Allow reader evaluation (#.), the form after it is run while the code is read.  Off by
+    /// default, only enable it for trusted code.
+    pub fn read_eval(mut self, read_eval: bool) -> Self {
+        self.read_eval = read_eval;
+        self
+    }
+
+    /// Cap the number of live heap objects, evaluation that goes over raises a :memory error.
+    /// This counts objects not bytes (see Heap::set_object_cap) and is applied after the core lisp
+    /// is loaded.
+    pub fn heap_object_cap(mut self, object_cap: usize) -> Self {
+        self.heap_object_cap = Some(object_cap);
+        self
+    }
+
+    /// Add a builtin set.
+    pub fn with(mut self, set: BuiltinSet) -> Self {
+        if !self.builtins.contains(&set) {
+            self.builtins.push(set);
+        }
+        self
+    }
+
+    /// Remove a builtin set.
+    pub fn without(mut self, set: BuiltinSet) -> Self {
+        self.builtins.retain(|s| *s != set);
+        self
+    }
+
+    /// Set the directories searched by load (*load-path*).
+    pub fn load_path<S: Into<String>>(mut self, paths: impl IntoIterator<Item = S>) -> Self {
+        self.load_path = paths.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Create the VM and add the selected builtins.  CoreLisp is always loaded last.
+    pub fn build(self) -> Result<Slosh, SloshError> {
+        let mut vm = new_slosh_vm();
+        let mut core_lisp = false;
+        for set in self.builtins {
+            if set == BuiltinSet::CoreLisp {
+                core_lisp = true;
+            } else {
+                set.add(&mut vm)?;
+            }
+        }
+        add_int_globals(&mut vm);
+        let paths = self
+            .load_path
+            .iter()
+            .map(|p| Value::StringConst(vm.intern(p)))
+            .collect();
+        let paths = vm.alloc_vector(paths);
+        vm.set_named_global("*load-path*", paths);
+        if core_lisp {
+            BuiltinSet::CoreLisp.add(&mut vm)?;
+        }
+        vm.set_heap_object_cap(self.heap_object_cap);
+        Ok(Slosh {
+            vm,
+            read_eval: self.read_eval,
+        })
+    }
+}
+
+/// An embedded slosh VM.
+pub struct Slosh {
+    vm: SloshVm,
+    read_eval: bool,
+}
+
+impl Slosh {
+    /// New instance with all the builtin sets, use [`Slosh::builder`] to pick them.
+    pub fn new() -> Result<Self, SloshError> {
+        SloshBuilder::new().build()
+    }
+
+    pub fn builder() -> SloshBuilder {
+        SloshBuilder::new()
+    }
+
+    /// Evaluate all the forms in text and return the result of the last one.
+    pub fn eval_str(&mut self, text: &str) -> Result<Value, SloshError> {
+        let reader = Reader::from_string(text.to_string(), &mut self.vm, "", 1, 0);
+        let reader = if self.read_eval {
+            reader.with_read_eval()
+        } else {
+            reader
+        };
+        eval_reader(reader, "")
+    }
+
+    /// Evaluate all the forms in a file and return the result of the last one.
+    pub fn eval_file(&mut self, path: impl AsRef<Path>) -> Result<Value, SloshError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| SloshError::Io(format!("{}: {e}", path.display())))?;
+        let name = self.vm.intern(&path.to_string_lossy());
+        let name = self.vm.get_interned(name);
+        let reader = Reader::from_string(text, &mut self.vm, name, 1, 0);
+        let reader = if self.read_eval {
+            reader.with_read_eval()
+        } else {
+            reader
+        };
+        eval_reader(reader, name)
+    }
+
+    /// Call the global function name with args.
+    pub fn call_fn(&mut self, name: &str, args: &[Value]) -> Result<Value, SloshError> {
+        let vm = &mut self.vm;
+        let res = match global(vm, name)? {
+            Value::Lambda(h) => {
+                let l = vm.get_lambda(h);
+                vm.do_call(l, args, None)
+            }
+            Value::Closure(h) => {
+                let (l, caps) = vm.get_closure(h);
+                let caps = caps.to_vec();
+                vm.do_call(l, args, Some(&caps[..]))
+            }
+            Value::Builtin(idx) => vm.call_builtin(idx, args),
+            _ => return Err(SloshError::NotCallable(name.to_string())),
+        };
+        res.map_err(|e| SloshError::runtime(vm, e))
+    }
+
+    /// Convert a Rust value to a slosh value.  The result is not a GC root so use it (in a call
+    /// or global) before evaluating anything else.
+    pub fn to_value<T>(&mut self, val: T) -> Result<Value, SloshError>
+    where
+        Value: SlFrom<T>,
+    {
+        Value::sl_from(val, &mut self.vm).map_err(|e| SloshError::Conversion(e.to_string()))
+    }
+
+    /// Set (or define) the global name to val.
+    pub fn set_global<T>(&mut self, name: &str, val: T) -> Result<(), SloshError>
+    where
+        Value: SlFrom<T>,
+    {
+        let val = self.to_value(val)?;
+        self.vm.set_named_global(name, val);
+        Ok(())
+    }
+
+    /// Get the global name converted to T.
+    pub fn get_global<'a, T>(&'a self, name: &str) -> Result<T, SloshError>
+    where
+        T: SlFromRef<'a, Value>,
+    {
+        let val = global(&self.vm, name)?;
+        T::sl_from_ref(val, &self.vm).map_err(|e| SloshError::Conversion(e.to_string()))
+    }
+
+    /// Get the raw value of the global name.
+    pub fn global(&self, name: &str) -> Result<Value, SloshError> {
+        global(&self.vm, name)
+    }
+
+    /// Register a builtin function as the global name.
+    pub fn add_builtin(
+        &mut self,
+        name: &str,
+        func: CallFuncSig<compile_state::state::CompileEnvironment>,
+        doc: &str,
+    ) {
+        bridge_adapters::add_builtin(&mut self.vm, name, func, doc);
+    }
+
+    /// Register a builtin closure (it can capture Rust state) as the global name.
+    pub fn add_builtin_closure(
+        &mut self,
+        name: &str,
+        func: Box<CallClosure<compile_state::state::CompileEnvironment>>,
+        doc: &str,
+    ) {
+        bridge_adapters::add_builtin_closure(&mut self.vm, name, func, doc);
+    }
+
+    /// Limit the calls and loop iterations evaluation can make, see [`slvm::GVm::set_fuel`].
+    /// Running out raises a :fuel error.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.vm.set_fuel(fuel);
+    }
+
+    /// Limit the time evaluation can run (starting now), running out raises a :fuel error.
+    pub fn set_time_limit(&mut self, limit: Option<Duration>) {
+        self.vm.set_time_limit(limit);
+    }
+
+    /// Flag that stops evaluation with an :interrupted error when set (from any thread).
+    pub fn interrupt_flag(&self) -> Arc<AtomicBool> {
+        self.vm.interrupt_flag()
+    }
+
+    /// The string pr would print for val.
+    pub fn display(&self, val: Value) -> String {
+        val.pretty_value(&self.vm)
+    }
+
+    pub fn vm(&self) -> &SloshVm {
+        &self.vm
+    }
+
+    pub fn vm_mut(&mut self) -> &mut SloshVm {
+        &mut self.vm
+    }
+}
+
+fn global(vm: &SloshVm, name: &str) -> Result<Value, SloshError> {
+    let undefined = || SloshError::Undefined(name.to_string());
+    let sym = vm.get_if_interned(name).ok_or_else(undefined)?;
+    let slot = vm.global_intern_slot(sym).ok_or_else(undefined)?;
+    match vm.get_global(slot) {
+        Value::Undefined => Err(undefined()),
+        val => Ok(val),
+    }
+}
+
+fn compile_exp(
+    vm: &mut SloshVm,
+    exp: Value,
+    file: &'static str,
+    doc_string: Option<Value>,
+) -> Result<(Arc<Chunk>, Option<Value>), SloshError> {
+    let mut state = CompileState::new_state(file, vm.line_num(), None);
+    state.chunk.dbg_args = Some(Vec::new());
+    state.doc_string = doc_string;
+    pass1(vm, &mut state, exp)
+        .and_then(|_| compile(vm, &mut state, exp, 0))
+        .and_then(|_| state.chunk.encode0(RET, vm.own_line()))
+        .map_err(|e| SloshError::Compile {
+            file,
+            line: vm.line_num(),
+            reason: e.display(vm),
+        })?;
+    state.chunk.extra_regs = state.max_regs;
+    Ok((Arc::new(state.chunk), state.doc_string))
+}
+
+fn eval_reader(mut reader: Reader, file: &'static str) -> Result<Value, SloshError> {
+    reader.vm().set_line_num(1);
+    let mut last = Value::Nil;
+    let mut doc_string = None;
+    while let Some(exp) = reader.next() {
+        let vm = reader.vm();
+        let exp = exp.map_err(|e| SloshError::Read {
+            file,
+            reason: e.reason,
+        })?;
+        vm.heap_sticky(exp);
+        let result = compile_exp(vm, exp, file, doc_string);
+        vm.heap_unsticky(exp);
+        let (chunk, new_doc_string) = result?;
+        doc_string = new_doc_string;
+        last = vm.execute(chunk).map_err(|e| {
+            let err = SloshError::runtime(vm, e);
+            // Nothing can resume the failed code, release its stack.
+            vm.reset();
+            err
+        })?;
+    }
+    Ok(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eval_and_call() {
+        let mut slosh = Slosh::new().unwrap();
+        let res = slosh.eval_str("(+ 1 2) (* 2 3)").unwrap();
+        assert_eq!(res, 6.into());
+        slosh
+            .eval_str("(defn add3 (a b c) (+ a b c)) (def adder (let (x 10) (fn (y) (+ x y))))")
+            .unwrap();
+        let res = slosh.call_fn("add3", &[1.into(), 2.into(), 3.into()]);
+        assert_eq!(res.unwrap(), 6.into());
+        let res = slosh.call_fn("adder", &[5.into()]);
+        assert_eq!(res.unwrap(), 15.into());
+        let s = slosh.to_value("abc".to_string()).unwrap();
+        let res = slosh.call_fn("str-upper", &[s]).unwrap();
+        assert_eq!(slosh.display(res), "ABC");
+        assert!(matches!(
+            slosh.call_fn("not-a-fn", &[]),
+            Err(SloshError::Undefined(_))
+        ));
+        slosh.eval_str("(def not-fn 1)").unwrap();
+        assert!(matches!(
+            slosh.call_fn("not-fn", &[]),
+            Err(SloshError::NotCallable(_))
+        ));
+        let mut slosh = SloshBuilder::new().read_eval(true).build().unwrap();
+        assert_eq!(slosh.eval_str("'#.(+ 1 2)").unwrap(), 3.into());
+    }
+
+    #[test]
+    fn test_globals() {
+        let mut slosh = Slosh::new().unwrap();
+        slosh.set_global("name", "slosh".to_string()).unwrap();
+        slosh.set_global("count", 41).unwrap();
+        slosh.eval_str("(set! count (+ count 1))").unwrap();
+        let count: i32 = slosh.get_global("count").unwrap();
+        assert_eq!(count, 42);
+        let res = slosh.eval_str("(str name \"-\" count)").unwrap();
+        assert_eq!(slosh.display(res), "slosh-42");
+        let name: String = slosh.get_global("name").unwrap();
+        assert_eq!(name, "slosh");
+        let bad: Result<i32, _> = slosh.get_global("name");
+        assert!(matches!(bad, Err(SloshError::Conversion(_))));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut slosh = Slosh::new().unwrap();
+        let err = slosh.eval_str("(err \"boom\")").unwrap_err();
+        assert!(matches!(&err, SloshError::Runtime { key, .. } if key == "error"));
+        assert!(err.to_string().contains("boom"));
+        let err = slosh.eval_str("(car 1 2 3 4)").unwrap_err();
+        assert!(matches!(err, SloshError::Compile { .. }));
+        let err = slosh.eval_str("(+ 1 2").unwrap_err();
+        assert!(matches!(err, SloshError::Read { .. }));
+        // Reader evaluation is off unless enabled.
+        let err = slosh.eval_str("#.(+ 1 2)").unwrap_err();
+        assert!(matches!(err, SloshError::Read { .. }));
+        let err = slosh.eval_file("/not/a/file.slosh").unwrap_err();
+        assert!(matches!(err, SloshError::Io(_)));
+        // The VM is still usable after errors.
+        assert_eq!(slosh.eval_str("(+ 1 2)").unwrap(), 3.into());
+        let err: Box<dyn Error> = Box::new(err);
+        assert!(err.to_string().starts_with("IO error"));
+    }
+
+    #[test]
+    fn test_builtin_sets() {
+        let mut slosh = SloshBuilder::empty().build().unwrap();
+        assert_eq!(slosh.eval_str("(+ 1 2)").unwrap(), 3.into());
+        assert!(slosh.eval_str("(str-upper \"a\")").is_err());
+        assert!(slosh.global("defn").is_err());
+        let mut slosh = SloshBuilder::new()
+            .without(BuiltinSet::Io)
+            .load_path(["/tmp"])
+            .build()
+            .unwrap();
+        assert!(slosh.global("defn").is_ok());
+        assert!(slosh.global("fs-exists?").is_err());
+        let res = slosh.eval_str("*load-path*").unwrap();
+        assert_eq!(slosh.display(res), "[\"/tmp\"]");
+    }
+
+    #[test]
+    fn test_fuel() {
+        let mut slosh = Slosh::new().unwrap();
+        slosh
+            .eval_str("(defn spin () (loop (i) (0) (recur (+ i 1))))")
+            .unwrap();
+        slosh.set_fuel(Some(10_000));
+        let err = slosh.call_fn("spin", &[]).unwrap_err();
+        assert!(matches!(&err, SloshError::Runtime { key, .. } if key == "fuel"));
+        slosh.set_fuel(Some(10_000));
+        let res = slosh.eval_str("(car (get-error (spin)))").unwrap();
+        assert_eq!(slosh.display(res), ":fuel");
+        slosh.set_fuel(None);
+        slosh.set_time_limit(Some(Duration::from_millis(20)));
+        let err = slosh.eval_str("(spin)").unwrap_err();
+        assert!(matches!(&err, SloshError::Runtime { key, .. } if key == "fuel"));
+        slosh.set_time_limit(None);
+        slosh
+            .interrupt_flag()
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+        let err = slosh.eval_str("(spin)").unwrap_err();
+        assert!(matches!(&err, SloshError::Runtime { key, .. } if key == "interrupted"));
+        assert_eq!(slosh.eval_str("(+ 1 2)").unwrap(), 3.into());
+    }
+
+    #[test]
+    fn test_sandbox() {
+        let mut slosh = SloshBuilder::sandbox()
+            .heap_object_cap(100_000)
+            .build()
+            .unwrap();
+        for name in ["load", "eval", "read-all", "fs-exists?", "prn"] {
+            assert!(slosh.global(name).is_err(), "{name} is defined");
+        }
+        let res = slosh.eval_str("(defn sq (x) (* x x)) (str (sq 4) \"!\")");
+        assert_eq!(slosh.display(res.unwrap()), "16!");
+        let err = slosh.eval_str("#.(+ 1 2)").unwrap_err();
+        assert!(matches!(err, SloshError::Read { .. }));
+        slosh.set_fuel(Some(1_000_000));
+        let err = slosh
+            .eval_str("(def v (vec)) (loop (i) (0) (vec-push! v (str i)) (recur (+ i 1)))")
+            .unwrap_err();
+        assert!(matches!(&err, SloshError::Runtime { key, .. } if key == "memory"));
+        slosh.eval_str("(set! v nil)").unwrap();
+        assert_eq!(slosh.eval_str("(sq 5)").unwrap(), 25.into());
+    }
+
+    #[test]
+    fn test_builtin_closure() {
+        let mut slosh = Slosh::new().unwrap();
+        let mut total = 0;
+        slosh.add_builtin_closure(
+            "accumulate",
+            Box::new(move |vm, args| {
+                for a in args {
+                    total += a.get_int(vm)?;
+                }
+                Ok(total.into())
+            }),
+            "Usage: (accumulate int*)\n\nAdd ints to a running total.\n\nSection: test\n",
+        );
+        slosh.eval_str("(accumulate 1 2 3)").unwrap();
+        assert_eq!(slosh.eval_str("(accumulate 4)").unwrap(), 10.into());
+    }
+}
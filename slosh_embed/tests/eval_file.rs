@@ -0,0 +1,40 @@
+use slosh_embed::{Slosh, SloshError};
+use std::io::Write;
+
+#[test]
+fn test_eval_file() {
+    let dir = std::env::temp_dir().join(format!("slosh_embed_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.slosh");
+    let mut file = std::fs::File::create(&path).unwrap();
+    writeln!(
+        file,
+        "(def limit 10)\n(defn over-limit? (x) (> x limit))\n(over-limit? 11)"
+    )
+    .unwrap();
+    drop(file);
+
+    let mut slosh = Slosh::builder()
+        .load_path([dir.to_string_lossy().to_string()])
+        .build()
+        .unwrap();
+    let res = slosh.eval_file(&path).unwrap();
+    assert_eq!(slosh.display(res), "true");
+    let limit: i32 = slosh.get_global("limit").unwrap();
+    assert_eq!(limit, 10);
+    let res = slosh.call_fn("over-limit?", &[3.into()]).unwrap();
+    assert_eq!(slosh.display(res), "false");
+
+    // load uses the load path.
+    std::fs::write(dir.join("extra.slosh"), "(def extra (* limit 2))").unwrap();
+    slosh.eval_str("(load \"extra.slosh\")").unwrap();
+    let extra: i32 = slosh.get_global("extra").unwrap();
+    assert_eq!(extra, 20);
+
+    std::fs::write(dir.join("bad.slosh"), "(def x 1)\n(err \"bad config\")").unwrap();
+    let err = slosh.eval_file(dir.join("bad.slosh")).unwrap_err();
+    assert!(matches!(err, SloshError::Runtime { .. }));
+    assert!(err.to_string().contains("bad config"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
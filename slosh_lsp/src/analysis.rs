@@ -0,0 +1,304 @@
+//! Read and compile documents with a slosh VM to find their errors and definitions.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use builtins::doc_string::{global_arities, global_doc_string, DocString, Params};
+use compile_state::state::{new_slosh_vm, CompileState, SloshVm, SloshVmTrait};
+use lsp_types::{Diagnostic, DiagnosticSeverity};
+use sl_compiler::compile::compile;
+use sl_compiler::load_eval::load_internal;
+use sl_compiler::pass1::pass1;
+use sl_compiler::{Capability, Reader};
+use slvm::{from_i56, Chunk, Interned, VMResult, Value, RET};
+use std::sync::Arc;
+
+use crate::text;
+
+/// Longest time running a document's definitions (or its macros) can take, this keeps a looping
+/// macro from hanging the server.
+const RUN_LIMIT: Duration = Duration::from_secs(2);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function,
+    Macro,
+    Special,
+    Variable,
+}
+
+/// What is known about a global.
+#[derive(Clone, Debug)]
+pub struct SymbolInfo {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// The raw doc string.
+    pub doc: Option<String>,
+    /// The parameters of each arity, empty if not a lambda.
+    pub params: Vec<Params>,
+    /// File name the def was compiled with and its line (starting at 1).
+    pub location: Option<(String, u32)>,
+}
+
+impl SymbolInfo {
+    fn new(vm: &mut SloshVm, name: Interned, slot: u32) -> Self {
+        let value = vm.get_global(slot);
+        let kind = match value {
+            Value::Lambda(_) | Value::Closure(_)
+                if matches!(vm.get_heap_property(value, ":macro"), Some(Value::True)) =>
+            {
+                SymbolKind::Macro
+            }
+            Value::Lambda(_) | Value::Closure(_) | Value::Builtin(_) => SymbolKind::Function,
+            Value::Special(_) => SymbolKind::Special,
+            _ => SymbolKind::Variable,
+        };
+        let params = global_arities(vm, slot)
+            .iter()
+            .filter_map(|l| Params::from_chunk(vm, l))
+            .collect();
+        let file_key = vm.intern_static("def-file");
+        let line_key = vm.intern_static("def-line");
+        let location = match (
+            vm.get_global_property(slot, file_key),
+            vm.get_global_property(slot, line_key),
+        ) {
+            (Some(Value::StringConst(file)), Some(Value::Int(line))) => {
+                Some((vm.get_interned(file).to_string(), from_i56(&line) as u32))
+            }
+            _ => None,
+        };
+        Self {
+            name: vm.get_interned(name).to_string(),
+            kind,
+            doc: global_doc_string(vm, slot),
+            params,
+            location,
+        }
+    }
+
+    /// The doc string split into sections, None if it does not have one in the usual format.
+    pub fn doc_string(&self) -> Option<DocString> {
+        self.doc.as_deref().and_then(DocString::parse)
+    }
+
+    /// How to call it, from the parameters of a lambda or the Usage: section of the doc string.
+    pub fn usage(&self) -> Vec<String> {
+        if !self.params.is_empty() {
+            self.params.iter().map(|p| p.usage(&self.name)).collect()
+        } else {
+            self.doc_string()
+                .and_then(|doc| doc.usage)
+                .into_iter()
+                .collect()
+        }
+    }
+
+    /// Markdown for hovers, usage then the description and section (or the doc string as is if it
+    /// does not have the usual format).
+    pub fn markdown(&self) -> String {
+        let mut res = String::new();
+        let usage = self.usage();
+        if !usage.is_empty() {
+            res.push_str("```slosh\n");
+            res.push_str(&usage.join("\n"));
+            res.push_str("\n```\n\n");
+        }
+        match (self.doc_string(), &self.doc) {
+            (Some(doc), _) => {
+                res.push_str(doc.description.trim());
+                res.push_str("\n\nSection: ");
+                res.push_str(&doc.section);
+            }
+            (None, Some(doc)) => res.push_str(doc.trim()),
+            (None, None) => {}
+        }
+        if res.is_empty() {
+            res = self.name.clone();
+        }
+        res
+    }
+}
+
+/// Results of analyzing a document.
+#[derive(Clone, Debug, Default)]
+pub struct Analysis {
+    pub diagnostics: Vec<Diagnostic>,
+    /// Globals the document defines.
+    pub symbols: BTreeMap<String, SymbolInfo>,
+}
+
+/// A VM with the core builtins and core.slosh loaded for analyzing documents.  Documents'
+/// definitions are run in it so it can not print, touch the file system, eval or start processes or
+/// threads.
+pub struct Analyzer {
+    vm: SloshVm,
+    globals: BTreeMap<String, SymbolInfo>,
+}
+
+impl Default for Analyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Analyzer {
+    pub fn new() -> Self {
+        let mut vm = new_slosh_vm();
+        sl_compiler::set_builtins_with(&mut vm, &[Capability::Core]);
+        let i_core = vm.intern("core.slosh");
+        let core = vm.get_interned(i_core);
+        if let Err(err) = load_internal(&mut vm, core) {
+            eprintln!("slosh-lsp: error loading core.slosh: {}", err.display(&vm));
+        }
+        let globals = global_symbols(&mut vm, |_| true);
+        Self { vm, globals }
+    }
+
+    /// The builtins and globals from core.slosh.
+    pub fn globals(&self) -> &BTreeMap<String, SymbolInfo> {
+        &self.globals
+    }
+
+    /// Read and compile each form of text, which is the contents of file_name.
+    ///
+    /// Definitions of functions and macros (defn, defmacro and def of a fn, macro or constant) are
+    /// run so later forms can use them, nothing else is.  Globals are put back after so documents
+    /// do not see each other's definitions.
+    pub fn analyze(&mut self, file_name: &str, text: &str) -> Analysis {
+        let i_name = self.vm.intern(file_name);
+        let name = self.vm.get_interned(i_name);
+        let line_num = self.vm.line_num();
+        self.vm.save_globals();
+        self.vm.set_time_limit(Some(RUN_LIMIT));
+        self.vm.set_line_num(1);
+        let mut diagnostics = Vec::new();
+        let mut reader = Reader::from_string(text.to_string(), &mut self.vm, name, 1, 0);
+        let mut doc_string = None;
+        while let Some(exp) = reader.next() {
+            let exp = match exp {
+                Ok(exp) => exp,
+                Err(err) => {
+                    // The reader can not find the start of the next form after an error.
+                    let line = reader.line().saturating_sub(1) as u32;
+                    let start = text::utf16_column(text, line, reader.column());
+                    diagnostics.push(diagnostic(text, line, Some(start), err.reason));
+                    break;
+                }
+            };
+            let vm = reader.vm();
+            vm.heap_sticky(exp);
+            let res = compile_form(vm, exp, name, doc_string);
+            let definition = is_definition(vm, exp);
+            let line = form_line(vm, exp);
+            vm.heap_unsticky(exp);
+            match res {
+                Ok((chunk, new_doc_string)) => {
+                    doc_string = new_doc_string;
+                    if definition {
+                        if let Err(err) = vm.execute(chunk) {
+                            diagnostics.push(diagnostic(text, line, None, err.display(vm)));
+                            // execute leaves the VM as the error found it.
+                            vm.reset();
+                        }
+                    }
+                }
+                Err(err) => {
+                    doc_string = None;
+                    let line = vm.line_num().saturating_sub(1);
+                    diagnostics.push(diagnostic(text, line, None, err.display(vm)));
+                }
+            }
+        }
+        let symbols = global_symbols(
+            &mut self.vm,
+            |info| matches!(&info.location, Some((file, _)) if file == name),
+        );
+        self.vm.set_time_limit(None);
+        self.vm.set_line_num(line_num);
+        self.vm.restore_globals();
+        Analysis {
+            diagnostics,
+            symbols,
+        }
+    }
+}
+
+/// Info for each global with a value or doc string that keep returns true for.
+fn global_symbols(
+    vm: &mut SloshVm,
+    keep: impl Fn(&SymbolInfo) -> bool,
+) -> BTreeMap<String, SymbolInfo> {
+    let globals: Vec<(Interned, usize)> = vm.globals().iter().map(|(i, s)| (*i, *s)).collect();
+    let mut res = BTreeMap::new();
+    for (name, slot) in globals {
+        let info = SymbolInfo::new(vm, name, slot as u32);
+        if (!matches!(vm.get_global(slot as u32), Value::Undefined) || info.location.is_some())
+            && keep(&info)
+        {
+            res.insert(info.name.clone(), info);
+        }
+    }
+    res
+}
+
+/// Compile exp like load does but without printing errors.
+fn compile_form(
+    vm: &mut SloshVm,
+    exp: Value,
+    name: &'static str,
+    doc_string: Option<Value>,
+) -> VMResult<(Arc<Chunk>, Option<Value>)> {
+    let line_num = vm.line_num();
+    let mut state = CompileState::new_state(name, line_num, None);
+    state.chunk.dbg_args = Some(Vec::new());
+    state.doc_string = doc_string;
+    pass1(vm, &mut state, exp)?;
+    compile(vm, &mut state, exp, 0)?;
+    state.chunk.encode0(RET, vm.own_line())?;
+    state.chunk.extra_regs = state.max_regs;
+    Ok((Arc::new(state.chunk), state.doc_string))
+}
+
+/// Is exp a definition that is safe to run (of a function, macro or constant)?
+fn is_definition(vm: &SloshVm, exp: Value) -> bool {
+    if !matches!(exp, Value::Pair(_) | Value::List(_, _)) {
+        return false;
+    }
+    let items: Vec<Value> = exp.iter(vm).take(4).collect();
+    match items.as_slice() {
+        [Value::Symbol(head), ..] if matches!(vm.get_interned(*head), "defn" | "defmacro") => true,
+        [Value::Symbol(head), _, value] if vm.get_interned(*head) == "def" => match value {
+            Value::Pair(_) | Value::List(_, _) => matches!(
+                value.iter(vm).next(),
+                Some(Value::Symbol(i)) if matches!(vm.get_interned(i), "fn" | "macro" | "quote")
+            ),
+            Value::Symbol(_) => false,
+            _ => true,
+        },
+        _ => false,
+    }
+}
+
+/// Line (from 0) a form read from a document starts on.
+fn form_line(vm: &SloshVm, exp: Value) -> u32 {
+    match vm.get_heap_property(exp, "dbg-line") {
+        Some(Value::Int(line)) => (from_i56(&line) as u32).saturating_sub(1),
+        _ => 0,
+    }
+}
+
+/// An error on line (from 0), from start (or the first non blank character) to the end of line.
+fn diagnostic(text: &str, line: u32, start: Option<u32>, message: String) -> Diagnostic {
+    let mut range = text::line_range(text, line);
+    if let Some(start) = start {
+        range.start.character = start.min(range.end.character);
+    }
+    Diagnostic {
+        range,
+        severity: Some(DiagnosticSeverity::ERROR),
+        source: Some("slosh".to_string()),
+        message,
+        ..Default::default()
+    }
+}
@@ -0,0 +1,344 @@
+//! A language server for slosh (see the slosh-lsp binary).
+//!
+//! Documents are read and compiled with a slosh VM (with the builtins and core.slosh), that gives
+//! the diagnostics and the globals a document defines.  Completion, hover, go to definition and
+//! signature help use those and the VM's globals.  Shell builtins (from the slosh binary) and files
+//! a document loads are not known.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use builtins::doc_string::Params;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+    Notification as NotificationTrait, PublishDiagnostics,
+};
+use lsp_types::request::{
+    Completion, GotoDefinition, HoverRequest, Request as RequestTrait, SignatureHelpRequest,
+};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    Documentation, GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents, HoverParams,
+    HoverProviderCapability, Location, MarkupContent, MarkupKind, OneOf, ParameterInformation,
+    ParameterLabel, PublishDiagnosticsParams, ServerCapabilities, SignatureHelp,
+    SignatureHelpOptions, SignatureHelpParams, SignatureInformation, TextDocumentSyncCapability,
+    TextDocumentSyncKind, Url,
+};
+
+pub mod analysis;
+pub mod text;
+
+use analysis::{Analysis, Analyzer, SymbolInfo, SymbolKind};
+
+pub type ServerResult<T> = Result<T, Box<dyn Error + Sync + Send>>;
+
+/// An open document.
+struct Document {
+    text: String,
+    // The name forms are compiled with (the path for files).
+    file_name: String,
+    analysis: Analysis,
+}
+
+/// The state of the server, the open documents and the VM used to analyze them.
+pub struct Server {
+    analyzer: Analyzer,
+    documents: HashMap<Url, Document>,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self {
+            analyzer: Analyzer::new(),
+            documents: HashMap::new(),
+        }
+    }
+
+    pub fn capabilities() -> ServerCapabilities {
+        ServerCapabilities {
+            text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+            completion_provider: Some(CompletionOptions::default()),
+            hover_provider: Some(HoverProviderCapability::Simple(true)),
+            definition_provider: Some(OneOf::Left(true)),
+            signature_help_provider: Some(SignatureHelpOptions {
+                trigger_characters: Some(vec!["(".to_string(), " ".to_string()]),
+                retrigger_characters: None,
+                work_done_progress_options: Default::default(),
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// Analyze text as the contents of uri and return its diagnostics.
+    fn update(&mut self, uri: Url, text: String) -> PublishDiagnosticsParams {
+        let file_name = match uri.to_file_path() {
+            Ok(path) => path.to_string_lossy().into_owned(),
+            Err(_) => uri.to_string(),
+        };
+        let analysis = self.analyzer.analyze(&file_name, &text);
+        let diagnostics = analysis.diagnostics.clone();
+        self.documents.insert(
+            uri.clone(),
+            Document {
+                text,
+                file_name,
+                analysis,
+            },
+        );
+        PublishDiagnosticsParams::new(uri, diagnostics, None)
+    }
+
+    /// Handle a notification, returns diagnostics to publish if a document changed.
+    pub fn notification(&mut self, not: Notification) -> Option<PublishDiagnosticsParams> {
+        match not.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params = not
+                    .extract::<<DidOpenTextDocument as NotificationTrait>::Params>(
+                        DidOpenTextDocument::METHOD,
+                    )
+                    .ok()?;
+                Some(self.update(params.text_document.uri, params.text_document.text))
+            }
+            DidChangeTextDocument::METHOD => {
+                let mut params = not
+                    .extract::<<DidChangeTextDocument as NotificationTrait>::Params>(
+                        DidChangeTextDocument::METHOD,
+                    )
+                    .ok()?;
+                // Full sync, the last change has the whole text.
+                let text = params.content_changes.pop()?.text;
+                Some(self.update(params.text_document.uri, text))
+            }
+            DidCloseTextDocument::METHOD => {
+                let params = not
+                    .extract::<<DidCloseTextDocument as NotificationTrait>::Params>(
+                        DidCloseTextDocument::METHOD,
+                    )
+                    .ok()?;
+                self.documents.remove(&params.text_document.uri);
+                Some(PublishDiagnosticsParams::new(
+                    params.text_document.uri,
+                    Vec::new(),
+                    None,
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    /// Handle a request (other than shutdown).
+    pub fn request(&mut self, req: Request) -> Response {
+        let id = req.id.clone();
+        let res = match req.method.as_str() {
+            Completion::METHOD => req
+                .extract::<CompletionParams>(Completion::METHOD)
+                .map(|(id, params)| Response::new_ok(id, self.completion(params))),
+            HoverRequest::METHOD => req
+                .extract::<HoverParams>(HoverRequest::METHOD)
+                .map(|(id, params)| Response::new_ok(id, self.hover(params))),
+            GotoDefinition::METHOD => req
+                .extract::<GotoDefinitionParams>(GotoDefinition::METHOD)
+                .map(|(id, params)| Response::new_ok(id, self.definition(params))),
+            SignatureHelpRequest::METHOD => req
+                .extract::<SignatureHelpParams>(SignatureHelpRequest::METHOD)
+                .map(|(id, params)| Response::new_ok(id, self.signature_help(params))),
+            method => {
+                return Response::new_err(
+                    id,
+                    ErrorCode::MethodNotFound as i32,
+                    format!("slosh-lsp: {method} is not supported"),
+                )
+            }
+        };
+        res.unwrap_or_else(|err| {
+            Response::new_err(id, ErrorCode::InvalidParams as i32, format!("{err:?}"))
+        })
+    }
+
+    /// The symbol called name as seen from document, its own definitions come first.
+    fn symbol<'a>(&'a self, document: &'a Document, name: &str) -> Option<&'a SymbolInfo> {
+        document
+            .analysis
+            .symbols
+            .get(name)
+            .or_else(|| self.analyzer.globals().get(name))
+    }
+
+    fn completion(&self, params: CompletionParams) -> Option<CompletionResponse> {
+        let position = params.text_document_position;
+        let document = self.documents.get(&position.text_document.uri)?;
+        let prefix = text::prefix_at(&document.text, position.position);
+        let mut items: Vec<CompletionItem> = Vec::new();
+        let symbols = document.analysis.symbols.values().chain(
+            self.analyzer
+                .globals()
+                .values()
+                .filter(|info| !document.analysis.symbols.contains_key(&info.name)),
+        );
+        for info in symbols.filter(|info| info.name.starts_with(&prefix)) {
+            let kind = match info.kind {
+                SymbolKind::Function => CompletionItemKind::FUNCTION,
+                SymbolKind::Macro | SymbolKind::Special => CompletionItemKind::KEYWORD,
+                SymbolKind::Variable => CompletionItemKind::VARIABLE,
+            };
+            items.push(CompletionItem {
+                label: info.name.clone(),
+                kind: Some(kind),
+                detail: info.usage().first().cloned(),
+                documentation: info
+                    .doc_string()
+                    .map(|doc| Documentation::String(doc.description.trim().to_string())),
+                ..Default::default()
+            });
+        }
+        Some(CompletionResponse::Array(items))
+    }
+
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let position = params.text_document_position_params;
+        let document = self.documents.get(&position.text_document.uri)?;
+        let (name, range) = text::symbol_at(&document.text, position.position)?;
+        let info = self.symbol(document, &name)?;
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: info.markdown(),
+            }),
+            range: Some(range),
+        })
+    }
+
+    fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let position = params.text_document_position_params;
+        let uri = &position.text_document.uri;
+        let document = self.documents.get(uri)?;
+        let (name, _) = text::symbol_at(&document.text, position.position)?;
+        let (file, line) = self.symbol(document, &name)?.location.clone()?;
+        let line = line.saturating_sub(1);
+        let location = if file == document.file_name {
+            Location::new(uri.clone(), text::symbol_range(&document.text, line, &name))
+        } else {
+            // Definitions from core.slosh have a path only if it was loaded from a file.
+            let path = fs::canonicalize(Path::new(&file)).ok()?;
+            let text = fs::read_to_string(&path).unwrap_or_default();
+            Location::new(
+                Url::from_file_path(path).ok()?,
+                text::symbol_range(&text, line, &name),
+            )
+        };
+        Some(GotoDefinitionResponse::Scalar(location))
+    }
+
+    fn signature_help(&self, params: SignatureHelpParams) -> Option<SignatureHelp> {
+        let position = params.text_document_position_params;
+        let document = self.documents.get(&position.text_document.uri)?;
+        let (name, arg) = text::call_at(&document.text, position.position)?;
+        let info = self.symbol(document, &name)?;
+        let documentation = info
+            .doc_string()
+            .map(|doc| Documentation::String(doc.description.trim().to_string()));
+        if info.params.is_empty() {
+            // Builtins and specials only have the usage from their doc string.
+            let usage = info.usage().pop()?;
+            return Some(SignatureHelp {
+                signatures: vec![SignatureInformation {
+                    label: usage,
+                    documentation,
+                    parameters: None,
+                    active_parameter: None,
+                }],
+                active_signature: Some(0),
+                active_parameter: None,
+            });
+        }
+        let signatures = info
+            .params
+            .iter()
+            .map(|params| signature(&info.name, params, documentation.clone()))
+            .collect();
+        // The first arity that takes that many arguments.
+        let active = info
+            .params
+            .iter()
+            .position(|params| {
+                params.rest.is_some() || arg < params.required.len() + params.optional.len()
+            })
+            .unwrap_or(0);
+        let params = &info.params[active];
+        let count = params.labels().len();
+        let active_parameter = if arg < count {
+            Some(arg as u32)
+        } else if params.rest.is_some() {
+            Some(count as u32 - 1)
+        } else {
+            None
+        };
+        Some(SignatureHelp {
+            signatures,
+            active_signature: Some(active as u32),
+            active_parameter,
+        })
+    }
+}
+
+/// Signature of one arity, the parameters are given as offsets in the label.
+fn signature(
+    name: &str,
+    params: &Params,
+    documentation: Option<Documentation>,
+) -> SignatureInformation {
+    let mut label = format!("({name}");
+    let mut parameters = Vec::new();
+    for param in params.labels() {
+        label.push(' ');
+        let start = label.encode_utf16().count() as u32;
+        label.push_str(&param);
+        let end = label.encode_utf16().count() as u32;
+        parameters.push(ParameterInformation {
+            label: ParameterLabel::LabelOffsets([start, end]),
+            documentation: None,
+        });
+    }
+    label.push(')');
+    SignatureInformation {
+        label,
+        documentation,
+        parameters: Some(parameters),
+        active_parameter: None,
+    }
+}
+
+/// Initialize then serve requests on connection until it is shutdown.
+pub fn run(connection: Connection) -> ServerResult<()> {
+    let capabilities = serde_json::to_value(Server::capabilities())?;
+    connection.initialize(capabilities)?;
+    let mut server = Server::new();
+    for msg in &connection.receiver {
+        match msg {
+            Message::Request(req) => {
+                if connection.handle_shutdown(&req)? {
+                    return Ok(());
+                }
+                let response = server.request(req);
+                connection.sender.send(Message::Response(response))?;
+            }
+            Message::Notification(not) => {
+                if let Some(params) = server.notification(not) {
+                    let not = Notification::new(PublishDiagnostics::METHOD.to_string(), params);
+                    connection.sender.send(Message::Notification(not))?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
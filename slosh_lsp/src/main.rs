@@ -0,0 +1,10 @@
+//! slosh-lsp, the slosh language server, talks LSP on stdin and stdout.
+
+use lsp_server::Connection;
+
+fn main() -> slosh_lsp::ServerResult<()> {
+    let (connection, io_threads) = Connection::stdio();
+    slosh_lsp::run(connection)?;
+    io_threads.join()?;
+    Ok(())
+}
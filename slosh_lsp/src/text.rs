@@ -0,0 +1,246 @@
+//! Finding things in document text by LSP position (line from 0 and UTF-16 column).
+
+use lsp_types::{Position, Range};
+
+/// Is ch part of a symbol?
+fn is_symbol_char(ch: char) -> bool {
+    !(ch.is_whitespace()
+        || matches!(
+            ch,
+            '(' | ')' | '[' | ']' | '{' | '}' | '"' | '\'' | '`' | ','
+        ))
+}
+
+fn utf16_len(s: &str) -> u32 {
+    s.encode_utf16().count() as u32
+}
+
+/// Text of line (from 0), empty past the end.
+pub fn line_text(text: &str, line: u32) -> &str {
+    text.lines().nth(line as usize).unwrap_or("")
+}
+
+/// UTF-16 column of the column'th character on line.
+pub fn utf16_column(text: &str, line: u32, column: usize) -> u32 {
+    let line_text = line_text(text, line);
+    let end = line_text
+        .char_indices()
+        .nth(column)
+        .map_or(line_text.len(), |(i, _)| i);
+    utf16_len(&line_text[..end])
+}
+
+/// Range of line without its leading and trailing white space.
+pub fn line_range(text: &str, line: u32) -> Range {
+    let line_text = line_text(text, line);
+    let trimmed = line_text.trim_start();
+    let start = utf16_len(&line_text[..line_text.len() - trimmed.len()]);
+    let end = start + utf16_len(trimmed.trim_end());
+    Range::new(Position::new(line, start), Position::new(line, end))
+}
+
+/// Byte offset of position in text, the end of its line if past it.
+pub fn offset(text: &str, position: Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find('\n') {
+            Some(i) => line_start += i + 1,
+            None => return text.len(),
+        }
+    }
+    let line_end = text[line_start..]
+        .find('\n')
+        .map_or(text.len(), |i| line_start + i);
+    let mut units = 0;
+    for (i, ch) in text[line_start..line_end].char_indices() {
+        if units >= position.character {
+            return line_start + i;
+        }
+        units += ch.len_utf16() as u32;
+    }
+    line_end
+}
+
+/// The symbol at (or just before) position and its range.
+pub fn symbol_at(text: &str, position: Position) -> Option<(String, Range)> {
+    let line_text = line_text(text, position.line);
+    let line_offset = offset(text, Position::new(position.line, 0));
+    let cursor = offset(text, position) - line_offset;
+    let start = line_text[..cursor]
+        .char_indices()
+        .rev()
+        .take_while(|(_, ch)| is_symbol_char(*ch))
+        .last()
+        .map_or(cursor, |(i, _)| i);
+    let end = line_text[cursor..]
+        .char_indices()
+        .find(|(_, ch)| !is_symbol_char(*ch))
+        .map_or(line_text.len(), |(i, _)| cursor + i);
+    if start == end {
+        return None;
+    }
+    let range = Range::new(
+        Position::new(position.line, utf16_len(&line_text[..start])),
+        Position::new(position.line, utf16_len(&line_text[..end])),
+    );
+    Some((line_text[start..end].to_string(), range))
+}
+
+/// The part of a symbol before position, what to complete.
+pub fn prefix_at(text: &str, position: Position) -> String {
+    let end = offset(text, position);
+    let line_start = offset(text, Position::new(position.line, 0));
+    let start = text[line_start..end]
+        .char_indices()
+        .rev()
+        .take_while(|(_, ch)| is_symbol_char(*ch))
+        .last()
+        .map_or(end, |(i, _)| line_start + i);
+    text[start..end].to_string()
+}
+
+/// Where name is first found as a whole symbol on line, the start of the line if it is not.
+pub fn symbol_range(text: &str, line: u32, name: &str) -> Range {
+    let line_text = line_text(text, line);
+    let mut from = 0;
+    while let Some(i) = line_text[from..].find(name) {
+        let start = from + i;
+        let end = start + name.len();
+        let before = line_text[..start].chars().next_back();
+        let after = line_text[end..].chars().next();
+        if !before.is_some_and(is_symbol_char) && !after.is_some_and(is_symbol_char) {
+            return Range::new(
+                Position::new(line, utf16_len(&line_text[..start])),
+                Position::new(line, utf16_len(&line_text[..end])),
+            );
+        }
+        from = end;
+    }
+    Range::new(Position::new(line, 0), Position::new(line, 0))
+}
+
+struct Call {
+    head: Option<String>,
+    // Forms started in the list, including the head.
+    forms: usize,
+}
+
+/// The function called by the innermost list around position and the index of the argument
+/// position is in.
+pub fn call_at(text: &str, position: Position) -> Option<(String, usize)> {
+    let end = offset(text, position);
+    let mut calls: Vec<Option<Call>> = Vec::new();
+    let mut token: Option<String> = None;
+    let mut chars = text[..end].chars();
+    while let Some(ch) = chars.next() {
+        if token.is_some() && !is_symbol_char(ch) {
+            token = None;
+        }
+        match ch {
+            ';' if token.is_none() => {
+                for ch in chars.by_ref() {
+                    if ch == '\n' {
+                        break;
+                    }
+                }
+            }
+            '"' => {
+                start_form(&mut calls, None);
+                let mut escape = false;
+                for ch in chars.by_ref() {
+                    match ch {
+                        '"' if !escape => break,
+                        '\\' if !escape => escape = true,
+                        _ => escape = false,
+                    }
+                }
+            }
+            '(' | '[' | '{' => {
+                start_form(&mut calls, None);
+                calls.push((ch == '(').then_some(Call {
+                    head: None,
+                    forms: 0,
+                }));
+            }
+            ')' | ']' | '}' => {
+                calls.pop();
+            }
+            _ if is_symbol_char(ch) => match &mut token {
+                Some(token) => {
+                    token.push(ch);
+                    if let Some(Some(call)) = calls.last_mut() {
+                        if call.forms == 1 {
+                            call.head = Some(token.clone());
+                        }
+                    }
+                }
+                None => {
+                    token = Some(ch.to_string());
+                    start_form(&mut calls, Some(ch.to_string()));
+                }
+            },
+            _ => {}
+        }
+    }
+    let call = calls.pop()??;
+    let head = call.head?;
+    // Inside a symbol it is the argument being typed, after it the next one.
+    let args = call.forms - 1;
+    let arg = if token.is_some() {
+        args.saturating_sub(1)
+    } else {
+        args
+    };
+    Some((head, arg))
+}
+
+fn start_form(calls: &mut [Option<Call>], symbol: Option<String>) {
+    if let Some(Some(call)) = calls.last_mut() {
+        call.forms += 1;
+        if call.forms == 1 {
+            call.head = symbol;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbol_at() {
+        let text = "(defn foo (x)\n  (str-cat x \"y\"))";
+        let (name, range) = symbol_at(text, Position::new(1, 5)).unwrap();
+        assert_eq!(name, "str-cat");
+        assert_eq!(range, Range::new(Position::new(1, 3), Position::new(1, 10)));
+        assert_eq!(symbol_at(text, Position::new(1, 10)).unwrap().0, "str-cat");
+        assert_eq!(symbol_at(text, Position::new(1, 1)), None);
+        assert_eq!(prefix_at(text, Position::new(1, 6)), "str");
+    }
+
+    #[test]
+    fn test_call_at() {
+        let text = "(foo a (bar \"x (y\" b) ; (z\n c ";
+        assert_eq!(
+            call_at(text, Position::new(0, 4)),
+            Some(("foo".to_string(), 0))
+        );
+        assert_eq!(
+            call_at(text, Position::new(0, 5)),
+            Some(("foo".to_string(), 0))
+        );
+        assert_eq!(
+            call_at(text, Position::new(0, 19)),
+            Some(("bar".to_string(), 1))
+        );
+        assert_eq!(
+            call_at(text, Position::new(0, 21)),
+            Some(("foo".to_string(), 2))
+        );
+        assert_eq!(
+            call_at(text, Position::new(1, 3)),
+            Some(("foo".to_string(), 3))
+        );
+        assert_eq!(call_at("[a b", Position::new(0, 4)), None);
+    }
+}
@@ -0,0 +1,200 @@
+use std::io::{BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use lsp_server::{Message, Notification, Request, RequestId};
+use serde_json::{json, Value};
+
+const URI: &str = "file:///tmp/slosh-lsp-test/example.slosh";
+
+const TEXT: &str = r#"#%
+Usage: (add-two x)
+
+Add two to x.
+
+Section: test
+%#
+(defn add-two (x) (+ x 2))
+(defn greet (name % greeting & rest) (str greeting name))
+(defmacro twice (form) `(do ~form ~form))
+(add-two 1)
+(twice (greet "a" "b" ))
+(def)
+"#;
+
+/// A stand-in editor talking to slosh-lsp over its stdin and stdout.
+struct Client {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    next_id: i32,
+}
+
+impl Client {
+    fn start() -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_slosh-lsp"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to start slosh-lsp");
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut client = Self {
+            child,
+            stdin,
+            stdout,
+            next_id: 1,
+        };
+        let res = client.request("initialize", json!({ "capabilities": {} }));
+        assert_eq!(res["capabilities"]["hoverProvider"], json!(true));
+        client.notify("initialized", json!({}));
+        client
+    }
+
+    fn send(&mut self, msg: Message) {
+        msg.write(&mut self.stdin).unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    fn read(&mut self) -> Message {
+        Message::read(&mut self.stdout)
+            .unwrap()
+            .expect("slosh-lsp closed stdout")
+    }
+
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        let id = RequestId::from(self.next_id);
+        self.next_id += 1;
+        self.send(Message::Request(Request::new(
+            id.clone(),
+            method.to_string(),
+            params,
+        )));
+        loop {
+            if let Message::Response(res) = self.read() {
+                assert_eq!(res.id, id);
+                assert!(res.error.is_none(), "{method}: {:?}", res.error);
+                return res.result.unwrap_or(Value::Null);
+            }
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(Message::Notification(Notification::new(
+            method.to_string(),
+            params,
+        )));
+    }
+
+    /// The diagnostics published after a document changed.
+    fn diagnostics(&mut self) -> Vec<Value> {
+        loop {
+            if let Message::Notification(not) = self.read() {
+                if not.method == "textDocument/publishDiagnostics" {
+                    assert_eq!(not.params["uri"], json!(URI));
+                    return not.params["diagnostics"].as_array().unwrap().clone();
+                }
+            }
+        }
+    }
+
+    fn at(&mut self, method: &str, line: u32, character: u32) -> Value {
+        self.request(
+            method,
+            json!({
+                "textDocument": { "uri": URI },
+                "position": { "line": line, "character": character },
+            }),
+        )
+    }
+
+    fn stop(mut self) {
+        self.request("shutdown", Value::Null);
+        self.notify("exit", Value::Null);
+        assert!(self.child.wait().unwrap().success());
+    }
+}
+
+#[test]
+fn test_lsp() {
+    let mut client = Client::start();
+    client.notify(
+        "textDocument/didOpen",
+        json!({
+            "textDocument": { "uri": URI, "languageId": "slosh", "version": 1, "text": TEXT },
+        }),
+    );
+    let diagnostics = client.diagnostics();
+    assert_eq!(diagnostics.len(), 1, "{diagnostics:?}");
+    assert_eq!(diagnostics[0]["range"]["start"]["line"], json!(12));
+    assert!(diagnostics[0]["message"]
+        .as_str()
+        .unwrap()
+        .contains("def: expected symbol"));
+
+    // Completion of "add-" in (add-two 1).
+    let items = client.at("textDocument/completion", 10, 5);
+    let item = items
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|item| item["label"] == json!("add-two"))
+        .expect("add-two is completed");
+    assert_eq!(item["detail"], json!("(add-two x)"));
+    assert!(items
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|item| item["label"].as_str().unwrap().starts_with("add-")));
+
+    let hover = client.at("textDocument/hover", 10, 3);
+    let hover = hover["contents"]["value"].as_str().unwrap();
+    assert!(hover.contains("(add-two x)"), "{hover}");
+    assert!(hover.contains("Add two to x."), "{hover}");
+    assert!(hover.contains("Section: test"), "{hover}");
+    // Docs of a builtin.
+    let hover = client.at("textDocument/hover", 8, 39);
+    let hover = hover["contents"]["value"].as_str().unwrap();
+    assert!(hover.contains("Section: string"), "{hover}");
+
+    let definition = client.at("textDocument/definition", 10, 3);
+    assert_eq!(definition["uri"], json!(URI));
+    assert_eq!(
+        definition["range"],
+        json!({
+            "start": { "line": 7, "character": 6 },
+            "end": { "line": 7, "character": 13 },
+        })
+    );
+    let definition = client.at("textDocument/definition", 11, 2);
+    assert_eq!(definition["range"]["start"]["line"], json!(9));
+
+    // In (greet "a" "b" ) after "b".
+    let help = client.at("textDocument/signatureHelp", 11, 22);
+    let signature = &help["signatures"][0];
+    assert_eq!(signature["label"], json!("(greet name % greeting & rest)"));
+    assert_eq!(
+        signature["parameters"][1]["label"],
+        json!([12, 22]),
+        "% greeting"
+    );
+    assert_eq!(help["activeParameter"], json!(2));
+
+    client.notify(
+        "textDocument/didChange",
+        json!({
+            "textDocument": { "uri": URI, "version": 2 },
+            "contentChanges": [{ "text": "(defn oops (x)\n  (+ x 1)\n" }],
+        }),
+    );
+    let diagnostics = client.diagnostics();
+    assert_eq!(diagnostics.len(), 1, "{diagnostics:?}");
+    // Definitions from the old text are gone.
+    let items = client.at("textDocument/completion", 1, 3);
+    assert!(items
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|item| item["label"] != json!("add-two")));
+
+    client.stop();
+}
@@ -1,4 +1,6 @@
 use std::cmp::Ordering;
+use std::ops::Range;
+use std::sync::Arc;
 
 use crate::opcodes::*;
 use crate::{Interned, VMError, VMResult, Value};
@@ -23,6 +25,9 @@ pub struct Chunk {
     pub args: u16,
     pub opt_args: u16,
     pub rest: bool,
+    // Chunks for each arity of a multi-arity function, a call runs the first that accepts its
+    // number of args.  Captures are the concatenation of the arity captures.
+    pub arities: Option<Vec<Arc<Chunk>>>,
 
     pub dbg_args: Option<Vec<Interned>>,
 }
@@ -43,6 +48,7 @@ impl Chunk {
             args: 0,
             opt_args: 0,
             rest: false,
+            arities: None,
             dbg_args: None,
         }
     }
@@ -101,6 +107,11 @@ impl Chunk {
         }
     }
 
+    /// Line the chunk's code starts on.
+    pub fn start_line(&self) -> u32 {
+        self.start_line
+    }
+
     pub fn offset_to_line(&self, offset: usize) -> Option<u32> {
         let mut line = self.start_line;
         let mut current: usize = 0;
@@ -120,6 +131,28 @@ impl Chunk {
         None
     }
 
+    /// Each range of code offsets with the line it was compiled from, in order.
+    pub fn line_ranges(&self) -> Vec<(Range<usize>, u32)> {
+        let mut ranges = Vec::new();
+        let mut line = self.start_line;
+        let mut current: usize = 0;
+        for o in &self.line_numbers {
+            let start = current;
+            if (o & 0x40) > 0 {
+                line += (o & 0x3f) as u32;
+            } else {
+                current += (o & 0x3f) as usize;
+            }
+            if (o & 0x80) > 0 {
+                line += 1;
+            }
+            if current > start {
+                ranges.push((start..current, line));
+            }
+        }
+        ranges
+    }
+
     pub fn line_to_offset(&self, line: u32) -> Option<usize> {
         if line > self.last_line {
             return None;
@@ -565,6 +598,15 @@ mod tests {
         assert!(chunk.line_to_offset(0).is_none());
         assert!(chunk.line_to_offset(201).is_none());
         assert!(chunk.line_to_offset(101).is_none());
+        let ranges = chunk.line_ranges();
+        let lines: Vec<u32> = ranges.iter().map(|(_, line)| *line).collect();
+        assert_eq!(lines, vec![1, 2, 3, 4, 30, 200]);
+        assert_eq!(ranges[3].0, 9..15);
+        for (range, line) in ranges {
+            for offset in range {
+                assert_eq!(chunk.offset_to_line(offset), Some(line));
+            }
+        }
         assert!(chunk.encode0(RET, Some(1)).is_err());
     }
 }
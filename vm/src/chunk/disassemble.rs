@@ -336,6 +336,16 @@ impl Chunk {
                 println!();
                 Ok(false)
             }
+            KDSC => {
+                print!("KDSC({KDSC:#04x})   \t");
+                disassemble_operand!(code, true, wide);
+                print!("\t");
+                disassemble_immediate!(code, wide);
+                print!("\t");
+                disassemble_operand!(code, true, wide);
+                println!();
+                Ok(false)
+            }
             COPY => {
                 print!("COPY({COPY:#04x})   \t");
                 disassemble_operand!(code, true, wide);
@@ -474,6 +484,14 @@ impl Chunk {
                 println!();
                 Ok(false)
             }
+            YIELD => {
+                print!("YIELD  \t");
+                disassemble_operand!(code, true, wide);
+                print!("\t");
+                disassemble_operand!(code, true, wide);
+                println!();
+                Ok(false)
+            }
             DFR => {
                 print!("DFR    \t");
                 disassemble_operand!(code, true, wide);
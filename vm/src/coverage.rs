@@ -0,0 +1,105 @@
+//! Line coverage, see GVm::set_coverage.
+
+use crate::{Chunk, GVm, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::Arc;
+
+struct ChunkHits {
+    chunk: Arc<Chunk>,
+    // Times the instruction at each offset was executed.
+    hits: Vec<u64>,
+}
+
+/// Executed instructions of each chunk the VM has run.
+///
+/// When a chunk first runs the functions in its constants are added (with no hits) so code that
+/// was compiled but never called shows up as not covered.
+#[derive(Default)]
+pub struct Coverage {
+    chunks: Vec<ChunkHits>,
+    // Chunk address to index in chunks.
+    index: HashMap<usize, usize>,
+    // Address and index of the last chunk hit, most instructions run in the same chunk as the last.
+    last: Option<(usize, usize)>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that the instruction at offset in chunk was executed.
+    #[inline]
+    pub(crate) fn hit<ENV>(&mut self, vm: &GVm<ENV>, chunk: &Arc<Chunk>, offset: usize) {
+        let key = Arc::as_ptr(chunk) as usize;
+        let idx = match self.last {
+            Some((last_key, idx)) if last_key == key => idx,
+            _ => {
+                let idx = self.add_chunk(vm, chunk);
+                self.last = Some((key, idx));
+                idx
+            }
+        };
+        if let Some(hits) = self.chunks[idx].hits.get_mut(offset) {
+            *hits = hits.saturating_add(1);
+        }
+    }
+
+    fn add_chunk<ENV>(&mut self, vm: &GVm<ENV>, chunk: &Arc<Chunk>) -> usize {
+        let key = Arc::as_ptr(chunk) as usize;
+        if let Some(idx) = self.index.get(&key) {
+            return *idx;
+        }
+        let idx = self.chunks.len();
+        self.index.insert(key, idx);
+        self.chunks.push(ChunkHits {
+            chunk: chunk.clone(),
+            hits: vec![0; chunk.code.len()],
+        });
+        for arity in chunk.arities.iter().flatten() {
+            self.add_chunk(vm, arity);
+        }
+        for constant in &chunk.constants {
+            if let Value::Lambda(h) = constant {
+                self.add_chunk(vm, &vm.get_lambda(*h));
+            }
+        }
+        idx
+    }
+
+    /// Hit count of each line that has code, by file.  A line's count is the most times any of its
+    /// instructions ran.  Code without a file name (from eval or the REPL) is left out.
+    pub fn line_hits(&self) -> BTreeMap<&'static str, BTreeMap<u32, u64>> {
+        let mut files: BTreeMap<&'static str, BTreeMap<u32, u64>> = BTreeMap::new();
+        for ChunkHits { chunk, hits } in &self.chunks {
+            if chunk.file_name.is_empty() {
+                continue;
+            }
+            let lines = files.entry(chunk.file_name).or_default();
+            for (range, line) in chunk.line_ranges() {
+                let count = hits[range].iter().copied().max().unwrap_or(0);
+                let line_count = lines.entry(line).or_default();
+                *line_count = (*line_count).max(count);
+            }
+        }
+        files
+    }
+
+    /// Coverage in lcov tracefile format, source_path maps the file names chunks were compiled
+    /// with to the path to report (so they match the paths in other coverage data).
+    pub fn lcov(&self, source_path: impl Fn(&str) -> String) -> String {
+        let mut out = String::new();
+        for (file, lines) in self.line_hits() {
+            let _ = writeln!(out, "TN:");
+            let _ = writeln!(out, "SF:{}", source_path(file));
+            for (line, count) in &lines {
+                let _ = writeln!(out, "DA:{line},{count}");
+            }
+            let _ = writeln!(out, "LF:{}", lines.len());
+            let _ = writeln!(out, "LH:{}", lines.values().filter(|c| **c > 0).count());
+            let _ = writeln!(out, "end_of_record");
+        }
+        out
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::rc::Rc;
 use std::sync::Arc;
 
 use crate::bits::FLAG_MUT;
@@ -8,6 +9,8 @@ pub use crate::handle::Handle;
 use crate::heap::storage::Storage;
 
 pub mod bits;
+pub mod native;
+pub use crate::heap::native::NativeObject;
 mod storage;
 
 #[derive(Clone, Debug)]
@@ -43,6 +46,71 @@ pub struct Continuation {
     pub stack: Vec<Value>,
 }
 
+/// The execution state of a suspended coroutine, saved by YIELD and restored by resume.
+#[derive(Clone, Debug)]
+pub struct CoroutineFrame {
+    pub chunk: Arc<Chunk>,
+    pub ip: *const u8,
+    pub current_ip: *const u8,
+    pub stack_top: usize,
+    pub stack_max: usize,
+    pub this_fn: Option<Value>,
+    pub defers: Vec<Value>,
+    pub on_error: Option<Value>,
+    /// Stack index that receives the value passed to the next resume.
+    pub resume_reg: usize,
+}
+
+#[derive(Clone, Debug)]
+pub enum CoroutineState {
+    /// Created but never resumed, the next resume calls the lambda.
+    New,
+    Suspended(CoroutineFrame),
+    Running,
+    Done,
+}
+
+#[derive(Clone, Debug)]
+pub struct Coroutine {
+    pub lambda: Value,
+    pub state: CoroutineState,
+    /// The coroutine's own stack segment, allocated on the first resume and dropped when done.
+    pub stack: Vec<Value>,
+}
+
+impl Coroutine {
+    pub fn new(lambda: Value) -> Self {
+        Self {
+            lambda,
+            state: CoroutineState::New,
+            stack: Vec::new(),
+        }
+    }
+
+    /// Values on the coroutine stack that are in use (none unless suspended).
+    fn live_stack(&self) -> &[Value] {
+        match &self.state {
+            CoroutineState::Suspended(frame) => &self.stack[..=frame.stack_max],
+            _ => &[],
+        }
+    }
+
+    fn trace_values(&self, greys: &mut Vec<Value>) {
+        greys.push(self.lambda);
+        if let CoroutineState::Suspended(frame) = &self.state {
+            greys.extend_from_slice(&frame.chunk.constants);
+            if let Some(this_fn) = frame.this_fn {
+                greys.push(this_fn);
+            }
+            greys.extend_from_slice(&frame.defers);
+            if let Some(on_error) = frame.on_error {
+                greys.push(on_error);
+            }
+        }
+        greys.extend_from_slice(self.live_stack());
+    }
+}
+
 // This is anything that can live on the heap.  Values normally live on the
 // stack or as constants.
 #[derive(Clone, Debug)]
@@ -55,6 +123,8 @@ enum Object {
     // Everything below here is always read only.
     Lambda(Arc<Chunk>),
     Closure(Arc<(Arc<Chunk>, Vec<Handle>)>),
+    // An opaque Rust object, its contents are accessed through get_object_mut.
+    Native(Rc<NativeObject>),
     // Place holder for an empty object slot.
     Empty,
 }
@@ -84,12 +154,15 @@ pub struct Heap {
     objects: Storage<Object>,
     callframes: Storage<CallFrame>,
     continuations: Storage<Continuation>,
+    coroutines: Storage<Coroutine>,
     errors: Storage<Error>,
     pairs: Storage<(Value, Value)>,
     values: Storage<Value>,
     props: Option<FxHashMap<Value, Arc<FxHashMap<Interned, Value>>>>,
     greys: Vec<Value>,
     paused: u32,
+    object_cap: Option<usize>,
+    limit_exceeded: bool,
 }
 
 impl Default for Heap {
@@ -110,7 +183,9 @@ macro_rules! value_op {
             $crate::Value::List(handle, _) => $heap.objects.$op(handle.idx()),
             $crate::Value::Lambda(handle) => $heap.objects.$op(handle.idx()),
             $crate::Value::Closure(handle) => $heap.objects.$op(handle.idx()),
+            $crate::Value::Object(handle) => $heap.objects.$op(handle.idx()),
             $crate::Value::Continuation(handle) => $heap.continuations.$op(handle.idx()),
+            $crate::Value::Coroutine(handle) => $heap.coroutines.$op(handle.idx()),
             $crate::Value::CallFrame(handle) => $heap.callframes.$op(handle.idx()),
             $crate::Value::Value(handle) => $heap.values.$op(handle.idx()),
 
@@ -146,12 +221,15 @@ impl Heap {
             objects: Storage::default(),
             callframes: Storage::default(),
             continuations: Storage::default(),
+            coroutines: Storage::default(),
             errors: Storage::default(),
             pairs: Storage::default(),
             values: Storage::default(),
             props: Some(FxHashMap::default()),
             greys: vec![],
             paused: 0,
+            object_cap: None,
+            limit_exceeded: false,
         }
     }
 
@@ -183,13 +261,66 @@ impl Heap {
         self.objects.set_grow_factor(grow_factor);
     }
 
-    fn alloc<MarkFunc>(&mut self, obj: Object, flags: u8, mark_roots: MarkFunc) -> Handle
+    /// Cap the number of live heap objects (of all types), None for no cap.
+    /// This is an object cap not a memory limit, every object counts as one whatever its size so a
+    /// single large string or vector is not limited.  Allocation does not fail at the cap, it is
+    /// flagged (see take_limit_exceeded) so the VM can raise an error.
+    pub fn set_object_cap(&mut self, object_cap: Option<usize>) {
+        self.object_cap = object_cap;
+        self.limit_exceeded = false;
+    }
+
+    pub fn object_cap(&self) -> Option<usize> {
+        self.object_cap
+    }
+
+    /// True if the object cap was reached (even after a GC) since the last call, clears it.
+    pub fn take_limit_exceeded(&mut self) -> bool {
+        std::mem::take(&mut self.limit_exceeded)
+    }
+
+    /// Collect garbage and return true if the heap is still at or over the object cap.
+    pub fn collect_over_limit<MarkFunc>(&mut self, mark_roots: MarkFunc) -> bool
+    where
+        MarkFunc: FnMut(&mut Heap) -> VMResult<()>,
+    {
+        let Some(max) = self.object_cap else {
+            return false;
+        };
+        if self.paused == 0 {
+            self.collect(mark_roots);
+        }
+        self.live_objects() >= max
+    }
+
+    /// Collect garbage if full (a storage is at capacity) or the object cap is reached.
+    fn collect_if<MarkFunc>(&mut self, full: bool, mark_roots: MarkFunc)
     where
         MarkFunc: FnMut(&mut Heap) -> VMResult<()>,
     {
-        if self.objects.live_objects() >= self.objects.capacity() && self.paused == 0 {
+        if let Some(max) = self.object_cap {
+            // Once flagged don't collect on every allocation, the VM will raise an error soon.
+            if !self.limit_exceeded && self.live_objects() >= max {
+                if self.paused == 0 {
+                    self.collect(mark_roots);
+                }
+                self.limit_exceeded = self.live_objects() >= max;
+                return;
+            }
+        }
+        if full && self.paused == 0 {
             self.collect(mark_roots);
         }
+    }
+
+    fn alloc<MarkFunc>(&mut self, obj: Object, flags: u8, mark_roots: MarkFunc) -> Handle
+    where
+        MarkFunc: FnMut(&mut Heap) -> VMResult<()>,
+    {
+        self.collect_if(
+            self.objects.live_objects() >= self.objects.capacity(),
+            mark_roots,
+        );
         Handle::new32(self.objects.alloc(obj, flags))
     }
 
@@ -203,9 +334,10 @@ impl Heap {
     where
         MarkFunc: FnMut(&mut Heap) -> VMResult<()>,
     {
-        if self.pairs.live_objects() >= self.pairs.capacity() && self.paused == 0 {
-            self.collect(mark_roots);
-        }
+        self.collect_if(
+            self.pairs.live_objects() >= self.pairs.capacity(),
+            mark_roots,
+        );
         Value::Pair(self.pairs.alloc((car, cdr), mutable.flag()).into())
     }
 
@@ -277,23 +409,43 @@ impl Heap {
         Value::Closure(self.alloc(Object::Closure(Arc::new((l, v))), 0, mark_roots))
     }
 
+    pub fn alloc_object<MarkFunc>(&mut self, obj: NativeObject, mark_roots: MarkFunc) -> Value
+    where
+        MarkFunc: FnMut(&mut Heap) -> VMResult<()>,
+    {
+        Value::Object(self.alloc(Object::Native(Rc::new(obj)), FLAG_MUT, mark_roots))
+    }
+
     pub fn alloc_continuation<MarkFunc>(&mut self, k: Continuation, mark_roots: MarkFunc) -> Value
     where
         MarkFunc: FnMut(&mut Heap) -> VMResult<()>,
     {
-        if self.continuations.live_objects() >= self.continuations.capacity() && self.paused == 0 {
-            self.collect(mark_roots);
-        }
+        self.collect_if(
+            self.continuations.live_objects() >= self.continuations.capacity(),
+            mark_roots,
+        );
         Value::Continuation(Handle::new32(self.continuations.alloc(k, 0)))
     }
 
+    pub fn alloc_coroutine<MarkFunc>(&mut self, co: Coroutine, mark_roots: MarkFunc) -> Value
+    where
+        MarkFunc: FnMut(&mut Heap) -> VMResult<()>,
+    {
+        self.collect_if(
+            self.coroutines.live_objects() >= self.coroutines.capacity(),
+            mark_roots,
+        );
+        Value::Coroutine(Handle::new32(self.coroutines.alloc(co, 0)))
+    }
+
     pub fn alloc_callframe<MarkFunc>(&mut self, frame: CallFrame, mark_roots: MarkFunc) -> Value
     where
         MarkFunc: FnMut(&mut Heap) -> VMResult<()>,
     {
-        if self.callframes.live_objects() >= self.callframes.capacity() && self.paused == 0 {
-            self.collect(mark_roots);
-        }
+        self.collect_if(
+            self.callframes.live_objects() >= self.callframes.capacity(),
+            mark_roots,
+        );
         Value::CallFrame(Handle::new32(self.callframes.alloc(frame, 0)))
     }
 
@@ -306,9 +458,10 @@ impl Heap {
     where
         MarkFunc: FnMut(&mut Heap) -> VMResult<()>,
     {
-        if self.values.live_objects() >= self.values.capacity() && self.paused == 0 {
-            self.collect(mark_roots);
-        }
+        self.collect_if(
+            self.values.live_objects() >= self.values.capacity(),
+            mark_roots,
+        );
         Value::Value(self.values.alloc(val, mutable.flag()).into())
     }
 
@@ -321,9 +474,10 @@ impl Heap {
     where
         MarkFunc: FnMut(&mut Heap) -> VMResult<()>,
     {
-        if self.errors.live_objects() >= self.errors.capacity() && self.paused == 0 {
-            self.collect(mark_roots);
-        }
+        self.collect_if(
+            self.errors.live_objects() >= self.errors.capacity(),
+            mark_roots,
+        );
         Value::Error(self.errors.alloc(error, mutable.flag()).into())
     }
 
@@ -427,6 +581,22 @@ impl Heap {
         }
     }
 
+    pub fn get_object(&self, handle: Handle) -> &NativeObject {
+        if let Some(Object::Native(obj)) = self.objects.get(handle.idx()) {
+            obj
+        } else {
+            panic!("Handle {} is not an object!", handle.idx());
+        }
+    }
+
+    pub fn get_object_mut(&mut self, handle: Handle) -> VMResult<&mut NativeObject> {
+        if let Some(Object::Native(obj)) = self.objects.get_mut(handle.idx()) {
+            Rc::get_mut(obj).ok_or_else(|| VMError::new_heap("Object is in use!"))
+        } else {
+            panic!("Handle {} is not an object!", handle.idx());
+        }
+    }
+
     pub fn get_closure(&self, handle: Handle) -> (Arc<Chunk>, &[Handle]) {
         if let Some(Object::Closure(clos))/*lambda, captures))*/ = self.objects.get(handle.idx()) {
             (clos.0.clone(), &clos.1)
@@ -451,6 +621,22 @@ impl Heap {
         }
     }
 
+    pub fn get_coroutine(&self, handle: Handle) -> &Coroutine {
+        if let Some(co) = self.coroutines.get(handle.idx()) {
+            co
+        } else {
+            panic!("Handle {} is not a coroutine!", handle.idx());
+        }
+    }
+
+    pub fn get_coroutine_mut(&mut self, handle: Handle) -> &mut Coroutine {
+        if let Some(co) = self.coroutines.get_mut(handle.idx()) {
+            co
+        } else {
+            panic!("Handle {} is not a coroutine!", handle.idx());
+        }
+    }
+
     pub fn get_callframe(&self, handle: Handle) -> &CallFrame {
         if let Some(call_frame) = self.callframes.get(handle.idx()) {
             call_frame
@@ -518,6 +704,11 @@ impl Heap {
         for constant in &chunk.constants {
             self.mark_trace(*constant);
         }
+        if let Some(arities) = &chunk.arities {
+            for arity in arities {
+                self.mark_chunk(arity);
+            }
+        }
     }
 
     fn mark_call_frame(&mut self, call_frame: &CallFrame) {
@@ -556,6 +747,7 @@ impl Heap {
                     self.mark_trace(Value::Value(*close));
                 }
             }
+            Object::Native(_) => {}
             Object::Empty => panic!("An empty object can not be live!"),
         }
     }
@@ -578,7 +770,8 @@ impl Heap {
             | Value::Bytes(handle)
             | Value::List(handle, _)
             | Value::Lambda(handle)
-            | Value::Closure(handle) => {
+            | Value::Closure(handle)
+            | Value::Object(handle) => {
                 let obj = self
                     .objects
                     .get(handle.idx())
@@ -617,6 +810,16 @@ impl Heap {
                     self.mark_trace(*obj);
                 }
             }
+            Value::Coroutine(handle) => {
+                let mut greys = Vec::new();
+                self.coroutines
+                    .get(handle.idx())
+                    .expect("Invalid coroutine handle!")
+                    .trace_values(&mut greys);
+                for val in greys {
+                    self.mark_trace(val);
+                }
+            }
             Value::CallFrame(handle) => {
                 let call_frame = self
                     .callframes
@@ -666,6 +869,7 @@ impl Heap {
         self.objects.clear_marks();
         self.callframes.clear_marks();
         self.continuations.clear_marks();
+        self.coroutines.clear_marks();
         self.errors.clear_marks();
         self.pairs.clear_marks();
         self.values.clear_marks();
@@ -680,6 +884,9 @@ impl Heap {
                 greys.push(*obj);
             }
         });
+        self.coroutines.trace_all_live(|co| {
+            co.trace_values(&mut greys);
+        });
         self.errors.trace_all_live(|err| {
             greys.push(err.data);
         });
@@ -710,13 +917,17 @@ impl Heap {
         let mut props = self.props.take().expect("missing heap props");
         props.retain(|key, _val| self.is_live(*key));
         self.props = Some(props);
+        // Dropping a dead native object runs its finalizer.
         self.objects.set_all_dead(Object::Empty);
+        // Release the stacks of dead coroutines.
+        self.coroutines.set_all_dead(Coroutine::new(Value::Nil));
     }
 
     pub fn live_objects(&self) -> usize {
         self.objects.live_objects()
             + self.continuations.live_objects()
             + self.callframes.live_objects()
+            + self.coroutines.live_objects()
             + self.pairs.live_objects()
             + self.values.live_objects()
             + self.errors.live_objects()
@@ -847,6 +1058,82 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_native_object() -> VMResult<()> {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        struct Counter(i64);
+        let finalized = Rc::new(Cell::new(0));
+        let mut heap = Heap::default();
+        let mark_roots = |_heap: &mut Heap| -> VMResult<()> { Ok(()) };
+        let fin = finalized.clone();
+        let obj = NativeObject::new("Counter", Counter(1))
+            .with_finalizer(move |c: &mut Counter| fin.set(fin.get() + c.0));
+        let Value::Object(h1) = heap.alloc_object(obj, mark_roots) else {
+            panic!("not an object");
+        };
+        let fin = finalized.clone();
+        let obj = NativeObject::new("Counter", Counter(10))
+            .with_finalizer(move |c: &mut Counter| fin.set(fin.get() + c.0));
+        let o2 = heap.alloc_object(obj, mark_roots);
+        let Value::Object(h2) = o2 else {
+            panic!("not an object");
+        };
+        assert_eq!(heap.get_object(h1).type_name(), "Counter");
+        assert_eq!(heap.get_object(h1).display(), "#<Counter>");
+        assert!(heap.get_object(h1).downcast_ref::<String>().is_none());
+        heap.get_object_mut(h2)?
+            .downcast_mut::<Counter>()
+            .expect("a counter")
+            .0 += 5;
+        let mark_roots = |heap: &mut Heap| -> VMResult<()> {
+            heap.mark(o2);
+            Ok(())
+        };
+        heap.collect(mark_roots);
+        assert_eq!(finalized.get(), 1);
+        assert_eq!(
+            heap.get_object(h2)
+                .downcast_ref::<Counter>()
+                .expect("a counter")
+                .0,
+            15
+        );
+        let mark_roots = |_heap: &mut Heap| -> VMResult<()> { Ok(()) };
+        heap.collect(mark_roots);
+        assert_eq!(finalized.get(), 16);
+        Ok(())
+    }
+
+    #[test]
+    fn test_object_cap() {
+        let mut heap = Heap::default();
+        heap.set_object_cap(Some(10));
+        let live = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+        let live_mark = live.clone();
+        let mark_roots = |heap: &mut Heap| -> VMResult<()> {
+            for v in live_mark.borrow().iter() {
+                heap.mark(*v);
+            }
+            Ok(())
+        };
+        // Garbage is collected at the limit.
+        for _ in 0..100 {
+            heap.alloc_string("garbage".to_string(), MutState::Mutable, mark_roots);
+        }
+        assert!(!heap.take_limit_exceeded());
+        assert!(heap.live_objects() <= 10);
+        for _ in 0..20 {
+            let s = heap.alloc_string("live".to_string(), MutState::Mutable, mark_roots);
+            live.borrow_mut().push(s);
+        }
+        assert!(heap.take_limit_exceeded());
+        assert!(!heap.take_limit_exceeded());
+        assert_eq!(heap.live_objects(), 20);
+        heap.set_object_cap(None);
+    }
+
     #[test]
     fn test_trace_val() -> VMResult<()> {
         let mut heap = Heap::default();
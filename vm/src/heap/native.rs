@@ -0,0 +1,86 @@
+use std::any::Any;
+use std::fmt;
+use std::fmt::Display;
+
+type Finalizer = Box<dyn FnOnce(&mut dyn Any)>;
+
+/// An opaque Rust value owned by the heap (a file handle, socket, compiled regex, DB cursor, etc).
+/// It is dropped, after running its finalizer if it has one, when GC sweeps it.
+pub struct NativeObject {
+    type_name: &'static str,
+    obj: Box<dyn Any>,
+    display: Option<fn(&dyn Any) -> String>,
+    finalizer: Option<Finalizer>,
+}
+
+impl NativeObject {
+    pub fn new<T: Any>(type_name: &'static str, obj: T) -> Self {
+        Self {
+            type_name,
+            obj: Box::new(obj),
+            display: None,
+            finalizer: None,
+        }
+    }
+
+    /// New object that is printed with its Display impl.
+    pub fn new_display<T: Any + Display>(type_name: &'static str, obj: T) -> Self {
+        fn display<T: Display + 'static>(obj: &dyn Any) -> String {
+            obj.downcast_ref::<T>()
+                .map(|obj| obj.to_string())
+                .unwrap_or_default()
+        }
+        let mut res = Self::new(type_name, obj);
+        res.display = Some(display::<T>);
+        res
+    }
+
+    /// Set a function to run on the object before it is dropped.
+    pub fn with_finalizer<T: Any, F: FnOnce(&mut T) + 'static>(mut self, finalizer: F) -> Self {
+        self.finalizer = Some(Box::new(move |obj: &mut dyn Any| {
+            if let Some(obj) = obj.downcast_mut::<T>() {
+                finalizer(obj);
+            }
+        }));
+        self
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    pub fn is<T: Any>(&self) -> bool {
+        self.obj.is::<T>()
+    }
+
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.obj.downcast_ref::<T>()
+    }
+
+    pub fn downcast_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.obj.downcast_mut::<T>()
+    }
+
+    /// String to print for the object, uses Display if available otherwise #<type_name>.
+    pub fn display(&self) -> String {
+        if let Some(display) = self.display {
+            display(self.obj.as_ref())
+        } else {
+            format!("#<{}>", self.type_name)
+        }
+    }
+}
+
+impl fmt::Debug for NativeObject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#<{}>", self.type_name)
+    }
+}
+
+impl Drop for NativeObject {
+    fn drop(&mut self) {
+        if let Some(finalizer) = self.finalizer.take() {
+            finalizer(self.obj.as_mut());
+        }
+    }
+}
@@ -16,6 +16,12 @@ pub use crate::chunk::*;
 pub mod vm;
 pub use crate::vm::*;
 
+pub mod profile;
+pub use crate::profile::*;
+
+pub mod coverage;
+pub use crate::coverage::*;
+
 pub mod interner;
 pub use crate::interner::*;
 
@@ -20,6 +20,7 @@ pub const REGN: OpCode = STACK_BASE + 10; // REGN A - R(A) = NIL
 pub const REGC: OpCode = STACK_BASE + 11; // REGC A - R(A) = UNDEFINED
 pub const REGB: OpCode = STACK_BASE + 12; // REGB A B - R(A) = Byte(B)
 pub const REGI: OpCode = STACK_BASE + 13; // REGI A B - R(A) = Int(B)
+pub const KDSC: OpCode = STACK_BASE + 14; // KDSC A B C - R(A)..R(A+B) = key args from the key/value list in R(C) (error on unknown keys), R(A..) start with keys
 pub const CLOSE: OpCode = STACK_BASE + 15; // CLOSE A B - R(A) = closure derived from lambda in R(B)
 pub const BMOV: OpCode = STACK_BASE + 16; // BMOV A B C - R(A)..R(A+C) = R(B)..R(B+C) does not respect closed over values
 pub const LDSC: OpCode = STACK_BASE + 17; // LDSC A B C - R(A)..R(A+B) = destructured list or vec in R(C) (ignore leftover values)
@@ -98,9 +99,11 @@ pub const MKERR: OpCode = FLOW_BASE + 24;
 pub const ISERR: OpCode = FLOW_BASE + 25;
 // ISOK A B - R(A) is #f if R(B) is an error type, #t otherwise
 pub const ISOK: OpCode = FLOW_BASE + 26;
+// YIELD A B - suspend the running coroutine producing R(B), R(A) gets the value it is resumed with
+pub const YIELD: OpCode = FLOW_BASE + 27;
 
 // Basic math
-const MATH_BASE: OpCode = FLOW_BASE + 27;
+const MATH_BASE: OpCode = FLOW_BASE + 28;
 // ADD A B - set R(A) = R(A) + R(B)
 pub const ADD: OpCode = MATH_BASE;
 // SUB A B - set R(A) = R(A) - R(B)
@@ -0,0 +1,137 @@
+//! Sampling profiler, see GVm::set_profile.
+
+use crate::{get_code, Chunk, GVm};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Sample every this many instructions by default, prime so loops do not line up with samples.
+pub const DEFAULT_PROFILE_INTERVAL: u32 = 97;
+
+/// Number of samples and the time they account for.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProfileCount {
+    pub samples: u64,
+    pub nanos: u64,
+}
+
+impl ProfileCount {
+    pub fn add(&mut self, other: ProfileCount) {
+        self.samples += other.samples;
+        self.nanos += other.nanos;
+    }
+}
+
+/// One frame of a sampled call stack.
+#[derive(Copy, Clone, Debug)]
+pub struct ProfileFrame<'a> {
+    pub chunk: &'a Arc<Chunk>,
+    pub line: Option<u32>,
+}
+
+/// Call stacks sampled while code runs.
+///
+/// Every interval instructions the VM records the call stack (chunk and line of each frame) along
+/// with the time since the previous sample, so time spent in builtins is charged to the line that
+/// called them.  Stacks stop at a native call into the VM (a builtin calling a lambda).
+pub struct Profile {
+    interval: u32,
+    countdown: u32,
+    last: Instant,
+    // Keeps sampled chunks alive so their addresses stay unique.
+    chunks: HashMap<usize, Arc<Chunk>>,
+    // Chunk address and line of each frame, innermost first.
+    stacks: HashMap<Vec<(usize, Option<u32>)>, ProfileCount>,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self::new(DEFAULT_PROFILE_INTERVAL)
+    }
+}
+
+impl Profile {
+    pub fn new(interval: u32) -> Self {
+        let interval = interval.max(1);
+        Self {
+            interval,
+            countdown: interval,
+            last: Instant::now(),
+            chunks: HashMap::new(),
+            stacks: HashMap::new(),
+        }
+    }
+
+    /// Start timing from now, time before this is not part of the next sample.
+    pub(crate) fn restart(&mut self) {
+        self.countdown = self.interval;
+        self.last = Instant::now();
+    }
+
+    /// Count an instruction, true when it is time to take a sample.
+    #[inline]
+    pub(crate) fn tick(&mut self) -> bool {
+        self.countdown -= 1;
+        if self.countdown == 0 {
+            self.countdown = self.interval;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Record the call stack of vm, chunk is running at ip.
+    pub(crate) fn sample<ENV>(&mut self, vm: &GVm<ENV>, chunk: &Arc<Chunk>, ip: *const u8) {
+        let now = Instant::now();
+        let nanos = now.duration_since(self.last).as_nanos() as u64;
+        self.last = now;
+        let offset = unsafe { ip.offset_from(get_code!(chunk)) as usize };
+        let mut stack = vec![self.frame_key(chunk, chunk.offset_to_line(offset))];
+        for frame in vm.get_call_stack() {
+            stack.push(self.frame_key(&frame.chunk, frame.current_line()));
+        }
+        self.stacks
+            .entry(stack)
+            .or_default()
+            .add(ProfileCount { samples: 1, nanos });
+    }
+
+    fn frame_key(&mut self, chunk: &Arc<Chunk>, line: Option<u32>) -> (usize, Option<u32>) {
+        let key = Arc::as_ptr(chunk) as usize;
+        self.chunks.entry(key).or_insert_with(|| chunk.clone());
+        (key, line)
+    }
+
+    /// Add the samples from other to this profile.
+    pub fn merge(&mut self, other: &Profile) {
+        for (key, chunk) in &other.chunks {
+            self.chunks.entry(*key).or_insert_with(|| chunk.clone());
+        }
+        for (stack, count) in &other.stacks {
+            self.stacks.entry(stack.clone()).or_default().add(*count);
+        }
+    }
+
+    /// Totals for all samples.
+    pub fn total(&self) -> ProfileCount {
+        let mut total = ProfileCount::default();
+        for count in self.stacks.values() {
+            total.add(*count);
+        }
+        total
+    }
+
+    /// Each distinct sampled call stack (innermost frame first) and its count.
+    pub fn stacks(&self) -> impl Iterator<Item = (Vec<ProfileFrame<'_>>, ProfileCount)> + '_ {
+        self.stacks.iter().map(|(stack, count)| {
+            let frames = stack
+                .iter()
+                .map(|(key, line)| ProfileFrame {
+                    chunk: &self.chunks[key],
+                    line: *line,
+                })
+                .collect();
+            (frames, *count)
+        })
+    }
+}
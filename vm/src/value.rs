@@ -1,27 +1,49 @@
 use crate::{float, Handle, Heap, Interned, VMError, VMResult};
 use bridge_types::BridgedType;
+use std::cell::RefCell;
 use std::collections::{BTreeSet, HashMap};
 use std::fmt;
 use std::fmt::{Display, Formatter};
 use std::hash::{Hash, Hasher};
 use std::iter;
 use std::ops::Deref;
+use std::rc::Rc;
 use std::sync::Arc;
 
 use crate::vm::GVm;
 
 pub type CallFuncSig<ENV> = fn(vm: &mut GVm<ENV>, registers: &[Value]) -> VMResult<Value>;
-#[derive(Copy, Clone)]
-pub struct CallFunc<ENV> {
-    pub func: CallFuncSig<ENV>,
+/// A native function that can own state (a builtin closure).
+pub type CallClosure<ENV> = dyn FnMut(&mut GVm<ENV>, &[Value]) -> VMResult<Value>;
+
+/// A builtin, either a plain function or a closure.  Closures are shared so the VM can call one
+/// while it is borrowed mutably.
+pub enum CallFunc<ENV> {
+    Func(CallFuncSig<ENV>),
+    Closure(Rc<RefCell<Box<CallClosure<ENV>>>>),
+}
+
+impl<ENV> Clone for CallFunc<ENV> {
+    fn clone(&self) -> Self {
+        match self {
+            CallFunc::Func(f) => CallFunc::Func(*f),
+            CallFunc::Closure(c) => CallFunc::Closure(c.clone()),
+        }
+    }
+}
+
+impl<ENV> CallFunc<ENV> {
+    fn addr(&self) -> usize {
+        match self {
+            CallFunc::Func(f) => *f as usize,
+            CallFunc::Closure(c) => Rc::as_ptr(c) as *const u8 as usize,
+        }
+    }
 }
 
 impl<ENV> PartialEq for CallFunc<ENV> {
     fn eq(&self, other: &CallFunc<ENV>) -> bool {
-        std::ptr::eq(
-            self.func as *const CallFuncSig<ENV>,
-            other.func as *const CallFuncSig<ENV>,
-        )
+        self.addr() == other.addr()
     }
 }
 
@@ -29,7 +51,7 @@ impl<ENV> Eq for CallFunc<ENV> {}
 
 impl<ENV> Hash for CallFunc<ENV> {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        state.write_usize(self.func as usize);
+        state.write_usize(self.addr());
     }
 }
 
@@ -134,7 +156,9 @@ pub enum Value {
     Lambda(Handle),
     Closure(Handle),
     Continuation(Handle),
+    Coroutine(Handle),
     CallFrame(Handle),
+    Object(Handle),
     Value(Handle),
     Error(Handle),
 }
@@ -282,6 +306,8 @@ impl Value {
             Value::Lambda(handle) => Some(*handle),
             Value::Closure(handle) => Some(*handle),
             Value::Continuation(handle) => Some(*handle),
+            Value::Coroutine(handle) => Some(*handle),
+            Value::Object(handle) => Some(*handle),
             Value::CallFrame(handle) => Some(*handle),
             Value::Value(handle) => Some(*handle),
             Value::Error(handle) => Some(*handle),
@@ -424,6 +450,8 @@ impl Value {
             Value::Lambda(_) => "#<Lambda>".to_string(),
             Value::Closure(_) => "#<Lambda>".to_string(),
             Value::Continuation(_) => "#<Continuation>".to_string(),
+            Value::Coroutine(_) => "#<Coroutine>".to_string(),
+            Value::Object(handle) => vm.get_object(*handle).display(),
             Value::CallFrame(_) => "#<CallFrame>".to_string(),
             Value::Vector(handle) => {
                 let v = vm.get_vector(*handle);
@@ -513,14 +541,19 @@ impl Value {
             Value::Lambda(_) => ValueType::Lambda,
             Value::Closure(_) => ValueType::Closure,
             Value::Continuation(_) => ValueType::Continuation,
+            Value::Coroutine(_) => ValueType::Coroutine,
             Value::CallFrame(_) => ValueType::CallFrame,
+            Value::Object(_) => ValueType::Object,
             Value::Error(_) => ValueType::Error,
             Value::Value(handle) => vm.get_value(*handle).value_type(vm),
         }
     }
 
     pub fn display_type<ENV>(&self, vm: &GVm<ENV>) -> &'static str {
-        self.value_type(vm).into()
+        match self {
+            Value::Object(handle) => vm.get_object(*handle).type_name(),
+            _ => self.value_type(vm).into(),
+        }
     }
 
     pub fn is_proper_list<ENV>(&self, vm: &GVm<ENV>) -> bool {
@@ -538,10 +571,15 @@ impl Value {
     }
 }
 
+/// Properties of each global by slot.
+type GlobalProps = HashMap<u32, Arc<HashMap<Interned, Value>>>;
+
 #[derive(Clone, Debug)]
 pub struct Globals {
     objects: Vec<Value>,
-    props: HashMap<u32, Arc<HashMap<Interned, Value>>>,
+    props: GlobalProps,
+    // Values and properties saved by save(), most recent last.
+    saved: Vec<(Vec<Value>, GlobalProps)>,
 }
 
 impl Default for Globals {
@@ -555,6 +593,7 @@ impl Globals {
         Globals {
             objects: Vec::new(),
             props: HashMap::new(),
+            saved: Vec::new(),
         }
     }
 
@@ -576,11 +615,32 @@ impl Globals {
             .map_or_else(|| Value::Undefined, |v| *v)
     }
 
+    /// Save the value and properties of every global, restore() puts them back.  Saves nest.
+    pub fn save(&mut self) {
+        self.saved.push((self.objects.clone(), self.props.clone()));
+    }
+
+    /// Restore the globals saved by the last save(), globals reserved since then are set to
+    /// Undefined (their slots stay reserved).  Returns false if nothing was saved.
+    pub fn restore(&mut self) -> bool {
+        if let Some((objects, props)) = self.saved.pop() {
+            let len = objects.len();
+            self.objects[..len].copy_from_slice(&objects);
+            self.objects[len..].fill(Value::Undefined);
+            self.props = props;
+            true
+        } else {
+            false
+        }
+    }
+
     pub fn mark(&self, heap: &mut Heap) {
-        self.objects.iter().for_each(|obj| {
+        let saved_objects = self.saved.iter().flat_map(|(objects, _)| objects.iter());
+        self.objects.iter().chain(saved_objects).for_each(|obj| {
             heap.mark(*obj);
         });
-        self.props.iter().for_each(|(_, map)| {
+        let saved_props = self.saved.iter().flat_map(|(_, props)| props.values());
+        self.props.values().chain(saved_props).for_each(|map| {
             for val in map.values() {
                 heap.mark(*val);
             }
@@ -625,7 +685,9 @@ pub const SLOSH_UNDEFINED: &str = "Undefined";
 pub const SLOSH_LAMBDA: &str = "Lambda";
 pub const SLOSH_CLOSURE: &str = "Lambda";
 pub const SLOSH_CONTINUATION: &str = "Continuation";
+pub const SLOSH_COROUTINE: &str = "Coroutine";
 pub const SLOSH_CALLFRAME: &str = "CallFrame";
+pub const SLOSH_OBJECT: &str = "Object";
 pub const SLOSH_VECTOR: &str = "Vector";
 pub const SLOSH_MAP: &str = "Map";
 pub const SLOSH_PAIR: &str = "Pair";
@@ -658,7 +720,9 @@ pub enum ValueType {
     Lambda,
     Closure,
     Continuation,
+    Coroutine,
     CallFrame,
+    Object,
     Error,
 }
 
@@ -690,7 +754,9 @@ impl From<ValueType> for &'static str {
             ValueType::Lambda => SLOSH_LAMBDA,
             ValueType::Closure => SLOSH_LAMBDA,
             ValueType::Continuation => SLOSH_CONTINUATION,
+            ValueType::Coroutine => SLOSH_COROUTINE,
             ValueType::CallFrame => SLOSH_CALLFRAME,
+            ValueType::Object => SLOSH_OBJECT,
             ValueType::Vector => SLOSH_VECTOR,
             ValueType::Map => SLOSH_MAP,
             ValueType::Pair => SLOSH_PAIR,
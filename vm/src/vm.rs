@@ -1,10 +1,14 @@
 use std::alloc;
 use std::alloc::Layout;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use crate::{
-    from_i56, CallFrame, CallFunc, CallFuncSig, Chunk, Globals, Handle, Heap, Interner, VMError,
-    VMErrorObj, VMResult, Value, HALT,
+    from_i56, CallClosure, CallFrame, CallFunc, CallFuncSig, Chunk, CoroutineFrame, Coverage,
+    Globals, Handle, Heap, Interner, Profile, VMError, VMErrorObj, VMResult, Value, HALT,
 };
 
 mod cons;
@@ -13,11 +17,18 @@ mod storage;
 pub mod macros;
 mod call;
 mod call_collection;
+mod coroutine;
 mod exec_loop;
 
 /// Size (in elements/Values) of the stack.
 pub const STACK_CAP: usize = 1024;
 
+/// Fuel given to error handlers after the budget runs out, once it is used up code can not run
+/// until a new budget is set.
+const FUEL_RESERVE: u64 = 1024;
+/// Extra time given to error handlers after the time limit passes.
+const TIME_RESERVE: Duration = Duration::from_millis(10);
+
 const DEAD_CODE: [u8; 3] = [HALT, HALT, HALT];
 
 pub struct GVm<ENV> {
@@ -25,6 +36,8 @@ pub struct GVm<ENV> {
     heap: Option<Heap>,
     //stack: [Value; STACK_CAP],
     stack: *mut Value,
+    // Number of Values in stack, STACK_CAP except for a running coroutine's (it grows as needed).
+    stack_cap: usize,
     registers: *mut Value,
     globals: Globals,
     buitins: Vec<CallFunc<ENV>>,
@@ -39,6 +52,27 @@ pub struct GVm<ENV> {
     current_ip_ptr: *const u8,
     callframe_id: usize,
     defers: Vec<Value>,
+    co_callers: Vec<coroutine::CoroutineCaller>,
+    // Chunks currently running under execute(), their constants are GC roots.
+    exec_chunks: Vec<Arc<Chunk>>,
+    // Set by YIELD for resume, the yielded value and the suspended coroutine state.
+    yielded: Option<(Value, CoroutineFrame)>,
+    // Number of nested execute2 calls, used to keep a yield from crossing a native call.
+    exec_depth: usize,
+    // Set (from any thread) to stop running code with an :interrupted error.
+    interrupt: Arc<AtomicBool>,
+    // Remaining fuel, each call and backward jump uses one.
+    fuel: Option<u64>,
+    // Running code is stopped with a :fuel error after this.
+    deadline: Option<Instant>,
+    // Fuel or time ran out and the handler reserve is in use.
+    budget_exceeded: bool,
+    // Counts budget checks so the clock is only read occasionally.
+    budget_ticks: u32,
+    // Samples the call stack while set.
+    profile: Option<Box<Profile>>,
+    // Records executed instructions while set.
+    coverage: Option<Box<Coverage>>,
     env: ENV,
 }
 
@@ -75,6 +109,7 @@ impl<ENV> GVm<ENV> {
             interner: Interner::with_capacity(8192),
             heap: Some(Heap::new()),
             stack, //: [Value::Undefined; STACK_CAP],
+            stack_cap: STACK_CAP,
             registers: stack,
             globals,
             buitins: Vec::new(),
@@ -88,10 +123,143 @@ impl<ENV> GVm<ENV> {
             current_ip_ptr: DEAD_CODE.as_ptr(),
             callframe_id: 0,
             defers: Vec::new(),
+            co_callers: Vec::new(),
+            exec_chunks: Vec::new(),
+            yielded: None,
+            exec_depth: 0,
+            interrupt: Arc::new(AtomicBool::new(false)),
+            fuel: None,
+            deadline: None,
+            budget_exceeded: false,
+            budget_ticks: 0,
+            profile: None,
+            coverage: None,
             env,
         }
     }
 
+    /// Flag that stops running code with an :interrupted error when set, it can be set from
+    /// another thread or a signal handler.  The flag is cleared when the error is raised.
+    pub fn interrupt_flag(&self) -> Arc<AtomicBool> {
+        self.interrupt.clone()
+    }
+
+    /// Use flag as the interrupt flag (see interrupt_flag), for instance one made before this VM so
+    /// whoever made it can interrupt code this VM runs.
+    pub fn set_interrupt_flag(&mut self, flag: Arc<AtomicBool>) {
+        self.interrupt = flag;
+    }
+
+    /// Clear a pending interrupt (for instance one from before the code was started).
+    pub fn clear_interrupt(&mut self) {
+        self.interrupt.store(false, Ordering::Relaxed);
+    }
+
+    /// Limit the number of calls and backward jumps (loop iterations) code can make before it is
+    /// stopped with a :fuel error, None removes the limit.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+        self.budget_exceeded = false;
+    }
+
+    /// Remaining fuel, if limited.
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Stop running code with a :fuel error once limit has passed (starting now), None removes
+    /// the limit.
+    pub fn set_time_limit(&mut self, limit: Option<Duration>) {
+        self.deadline = limit.map(|limit| Instant::now() + limit);
+        self.budget_exceeded = false;
+    }
+
+    /// Time left before the time limit passes, if limited.
+    pub fn time_left(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Cap the number of live heap objects, exceeding it raises a :memory error.  This is a count
+    /// of objects not a memory limit, see Heap::set_object_cap.
+    pub fn set_heap_object_cap(&mut self, object_cap: Option<usize>) {
+        self.heap_mut().set_object_cap(object_cap);
+    }
+
+    /// The heap object cap, if any.
+    pub fn heap_object_cap(&self) -> Option<usize> {
+        self.heap().object_cap()
+    }
+
+    /// Start sampling running code into profile (see Profile), None stops profiling.
+    pub fn set_profile(&mut self, profile: Option<Profile>) {
+        self.profile = profile.map(|mut profile| {
+            profile.restart();
+            Box::new(profile)
+        });
+    }
+
+    /// Stop profiling and return the samples collected.
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take().map(|profile| *profile)
+    }
+
+    pub fn is_profiling(&self) -> bool {
+        self.profile.is_some()
+    }
+
+    /// Record line coverage of running code into coverage, None stops recording.
+    pub fn set_coverage(&mut self, coverage: Option<Coverage>) {
+        self.coverage = coverage.map(Box::new);
+    }
+
+    /// Stop recording coverage and return what was recorded.
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take().map(|coverage| *coverage)
+    }
+
+    /// Check for an interrupt, the heap limit and use a unit of fuel, called on calls and backward
+    /// jumps.
+    /// When fuel or time first runs out a small reserve is given so error handlers can run, after
+    /// that every check fails until a new budget is set.
+    #[inline]
+    pub(crate) fn check_budget(&mut self) -> VMResult<()> {
+        if self.interrupt.load(Ordering::Relaxed) && self.interrupt.swap(false, Ordering::Relaxed) {
+            return Err(VMError::new("interrupted", "Interrupted."));
+        }
+        // The limit may have been hit while allocating garbage, so collect before failing.
+        if self.heap_mut().take_limit_exceeded() && self.heap_over_limit() {
+            return Err(VMError::new("memory", "Heap object cap exceeded."));
+        }
+        if let Some(fuel) = self.fuel {
+            if fuel == 0 {
+                if self.budget_exceeded {
+                    return Err(VMError::new("fuel", "Out of fuel (handler reserve used)."));
+                }
+                self.budget_exceeded = true;
+                self.fuel = Some(FUEL_RESERVE);
+                return Err(VMError::new("fuel", "Out of fuel."));
+            }
+            self.fuel = Some(fuel - 1);
+        }
+        if let Some(deadline) = self.deadline {
+            self.budget_ticks = self.budget_ticks.wrapping_add(1);
+            if (self.budget_exceeded || self.budget_ticks & 0xff == 0) && Instant::now() >= deadline
+            {
+                if self.budget_exceeded {
+                    return Err(VMError::new(
+                        "fuel",
+                        "Time limit exceeded (handler reserve used).",
+                    ));
+                }
+                self.budget_exceeded = true;
+                self.deadline = Some(Instant::now() + TIME_RESERVE);
+                return Err(VMError::new("fuel", "Time limit exceeded."));
+            }
+        }
+        Ok(())
+    }
+
     pub fn this_fn(&self) -> Option<Value> {
         self.this_fn
     }
@@ -105,11 +273,11 @@ impl<ENV> GVm<ENV> {
     }
 
     pub fn stack_slice(&self) -> &[Value] {
-        unsafe { std::slice::from_raw_parts(self.stack, STACK_CAP) }
+        unsafe { std::slice::from_raw_parts(self.stack, self.stack_cap) }
     }
 
     pub fn stack_slice_mut(&mut self) -> &mut [Value] {
-        unsafe { std::slice::from_raw_parts_mut(self.stack, STACK_CAP) }
+        unsafe { std::slice::from_raw_parts_mut(self.stack, self.stack_cap) }
     }
 
     /// Return the register for idx.
@@ -119,7 +287,10 @@ impl<ENV> GVm<ENV> {
 
     pub fn register_slice<'b>(&self) -> &'b [Value] {
         unsafe {
-            std::slice::from_raw_parts(self.stack.add(self.stack_top), STACK_CAP - self.stack_top)
+            std::slice::from_raw_parts(
+                self.stack.add(self.stack_top),
+                self.stack_cap - self.stack_top,
+            )
         }
     }
 
@@ -201,14 +372,37 @@ impl<ENV> GVm<ENV> {
 
     pub fn add_builtin(&mut self, func: CallFuncSig<ENV>) -> Value {
         let result = self.buitins.len();
-        self.buitins.push(CallFunc { func });
+        self.buitins.push(CallFunc::Func(func));
+        Value::Builtin(result as u32)
+    }
+
+    /// Add a builtin that can carry its own state (a DB pool, config, counter, etc).
+    /// Note, any Values it holds are not GC roots, keep them sticky or in a global.
+    pub fn add_builtin_closure(&mut self, func: Box<CallClosure<ENV>>) -> Value {
+        let result = self.buitins.len();
+        self.buitins
+            .push(CallFunc::Closure(Rc::new(RefCell::new(func))));
         Value::Builtin(result as u32)
     }
 
     /// Return the builtin function at idx.
     /// Note, will panic if idx is not a valid builtin index.
-    pub fn get_builtin(&self, idx: u32) -> &CallFuncSig<ENV> {
-        &self.buitins[idx as usize].func
+    pub fn get_builtin(&self, idx: u32) -> &CallFunc<ENV> {
+        &self.buitins[idx as usize]
+    }
+
+    /// Call the builtin at idx with args.
+    /// Note, will panic if idx is not a valid builtin index.
+    pub fn call_builtin(&mut self, idx: u32, args: &[Value]) -> VMResult<Value> {
+        match self.buitins[idx as usize].clone() {
+            CallFunc::Func(f) => f(self, args),
+            CallFunc::Closure(c) => {
+                let mut f = c.try_borrow_mut().map_err(|_| {
+                    VMError::new_vm("builtin closure can not be called recursively")
+                })?;
+                f(self, args)
+            }
+        }
     }
 
     pub fn is_equal_pair(&self, val1: Value, val2: Value) -> VMResult<Value> {
@@ -409,6 +603,8 @@ impl<ENV> GVm<ENV> {
         params: &[Value],
         caps: Option<&[Handle]>,
     ) -> VMResult<Value> {
+        let (chunk, cap_offset) = call::arity_chunk(&chunk, params.len() as u16)?;
+        let caps = caps.map(|caps| &caps[cap_offset..cap_offset + call::num_captures(&chunk)]);
         let stack_top = self.stack_top;
         let stack_max = self.stack_max;
         let ip = self.ip_ptr;
@@ -419,6 +615,7 @@ impl<ENV> GVm<ENV> {
         self.stack_top = self.stack_max + 1;
 
         self.stack_max = self.stack_top + chunk.input_regs + chunk.extra_regs;
+        self.grow_stack();
 
         // We don't have a call frame, this will cause RET/SRET to return control back when called.
         *self.stack_mut(self.stack_top) = Value::Undefined;
@@ -442,7 +639,16 @@ impl<ENV> GVm<ENV> {
                 mov_register!(self, cap_first + i, Value::Value(*c));
             }
         }
-        let res = self.execute2(chunk).map(|_| self.stack(self.stack_top));
+        // The defers belong to the frame that made this call, chunk starts with none.
+        let defers = std::mem::take(&mut self.defers);
+        let had_err_frame = self.err_frame.is_some();
+        let res = self
+            .execute2(chunk, false)
+            .map(|_| self.stack(self.stack_top));
+        if res.is_err() {
+            self.run_unwound_defers(!had_err_frame);
+        }
+        self.defers = defers;
         self.stack_top = stack_top;
         self.stack_max = stack_max;
         self.ip_ptr = ip;
@@ -451,6 +657,40 @@ impl<ENV> GVm<ENV> {
         res
     }
 
+    /// Run the defers of the frames an error left (innermost first) so cleanup code runs when an
+    /// error escapes a do_call.  If new_err_frame then the failing frame's defers were moved to
+    /// err_frame and are run from there.  Errors from the defers are ignored, the original error is
+    /// reported.
+    fn run_unwound_defers(&mut self, new_err_frame: bool) {
+        let mut defers: Vec<Value> = Vec::new();
+        if new_err_frame {
+            if let Some(frame) = self.err_frame.as_mut() {
+                defers.extend(std::mem::take(&mut frame.defers).into_iter().rev());
+            }
+        }
+        defers.extend(std::mem::take(&mut self.defers).into_iter().rev());
+        for frame in self.get_call_stack() {
+            defers.extend(frame.defers.iter().rev());
+        }
+        // The defers are only held here now.
+        self.pause_gc();
+        for defer in defers {
+            let _ = match defer {
+                Value::Lambda(h) => {
+                    let l = self.get_lambda(h);
+                    self.do_call(l, &[], None)
+                }
+                Value::Closure(h) => {
+                    let (l, caps) = self.get_closure(h);
+                    let caps = caps.to_vec();
+                    self.do_call(l, &[], Some(&caps[..]))
+                }
+                _ => Ok(Value::Nil),
+            };
+        }
+        self.unpause_gc();
+    }
+
     /// Executes chunk.  Will save the current VM state and restore on success or leave it on error.
     /// This allows a debugger to work with the "broken" image.
     pub fn execute(&mut self, chunk: Arc<Chunk>) -> VMResult<Value> {
@@ -462,10 +702,16 @@ impl<ENV> GVm<ENV> {
         self.this_fn = None;
         self.stack_top = self.stack_max;
         self.stack_max = self.stack_top + chunk.input_regs + chunk.extra_regs;
+        self.grow_stack();
 
+        // Nothing else references chunk so root it to keep its constants (lambdas for instance)
+        // alive while it runs.
+        self.exec_chunks.push(chunk.clone());
         // Return on error without resetting the VM.
         // This is to allow debugging a live image/vm.
-        self.execute2(chunk)?;
+        let res = self.execute2(chunk, false);
+        self.exec_chunks.pop();
+        res?;
         let res = self.stack(self.stack_top);
 
         self.stack_top = stack_top;
@@ -491,13 +737,17 @@ impl<ENV> GVm<ENV> {
         self.defers = Vec::new();
     }
 
-    fn execute2(&mut self, chunk: Arc<Chunk>) -> VMResult<()> {
+    /// Run chunk until it returns, if resume is set continue from the current ip (a coroutine).
+    fn execute2(&mut self, chunk: Arc<Chunk>, resume: bool) -> VMResult<()> {
         let mut chunk = chunk;
+        let mut resume = resume;
 
         let mut done = false;
         let mut result = Ok(());
+        self.exec_depth += 1;
         while !done {
-            result = if let Err((e, echunk)) = self.exec_loop(chunk.clone()) {
+            result = if let Err((e, echunk)) = self.exec_loop(chunk.clone(), resume) {
+                resume = false;
                 if self.err_frame.is_none() {
                     self.err_frame = Some(CallFrame {
                         id: 0,
@@ -539,6 +789,7 @@ impl<ENV> GVm<ENV> {
                 Ok(())
             };
         }
+        self.exec_depth -= 1;
 
         result
     }
@@ -788,6 +1039,35 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_save_globals() -> VMResult<()> {
+        let mut vm = Vm::new();
+        let slot = vm.reserve_global();
+        let prop = vm.intern("doc");
+        vm.set_global(slot, 1.into());
+        vm.set_global_property(slot, prop, 10.into());
+        assert!(!vm.restore_globals());
+
+        vm.save_globals();
+        vm.set_global(slot, 2.into());
+        vm.set_global_property(slot, prop, 20.into());
+        let slot2 = vm.reserve_global();
+        vm.set_global(slot2, 3.into());
+        vm.save_globals();
+        vm.set_global(slot2, 4.into());
+        assert!(vm.restore_globals());
+        assert_eq!(vm.get_global(slot2).get_int(&vm)?, 3);
+        assert!(vm.restore_globals());
+        assert_eq!(vm.get_global(slot).get_int(&vm)?, 1);
+        assert_eq!(
+            vm.get_global_property(slot, prop).unwrap().get_int(&vm)?,
+            10
+        );
+        assert!(vm.get_global(slot2).is_undef());
+        assert!(!vm.restore_globals());
+        Ok(())
+    }
+
     #[test]
     fn test_global() -> VMResult<()> {
         let mut chunk = Chunk::new("no_file", 1);
@@ -952,6 +1232,40 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_rest_args() -> VMResult<()> {
+        let mut vm = Vm::new();
+        // (fn (a & rest) rest)
+        let mut chunk = Chunk::new("no_file", 1);
+        let line = 1;
+        chunk.encode1(SRET, 2, Some(line))?;
+        chunk.args = 2;
+        chunk.rest = true;
+        chunk.input_regs = 3;
+        let rest = vm.alloc_lambda(Arc::new(chunk));
+
+        // Called from a non-zero first register, as an argument to another call for instance.
+        let mut chunk = Chunk::new("no_file", 1);
+        let const1 = chunk.add_constant(rest) as u16;
+        let consts: Vec<u16> = (1..=3)
+            .map(|i| chunk.add_constant(i.into()) as u16)
+            .collect();
+        chunk.encode2(CONST, 1, const1, Some(line))?;
+        for (i, c) in consts.iter().enumerate() {
+            chunk.encode2(CONST, 4 + i as u16, *c, Some(line))?;
+        }
+        chunk.encode3(CALL, 1, 3, 3, Some(line))?;
+        chunk.encode1(SRET, 3, Some(line))?;
+        chunk.extra_regs = 6;
+        let result = vm.execute(Arc::new(chunk))?;
+        let rest: Vec<i64> = result
+            .iter(&vm)
+            .map(|v| v.get_int(&vm))
+            .collect::<VMResult<_>>()?;
+        assert_eq!(rest, vec![2, 3]);
+        Ok(())
+    }
+
     #[test]
     fn test_tcall() -> VMResult<()> {
         let mut vm = Vm::new();
@@ -1103,6 +1417,490 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_do_call_tail_builtin() -> VMResult<()> {
+        fn add_b(vm: &mut Vm, registers: &[Value]) -> VMResult<Value> {
+            if registers.len() != 2 {
+                return Err(VMError::new_vm("test add: wrong number of args."));
+            }
+            Ok((registers[0].get_int(vm)? + registers[1].get_int(vm)?).into())
+        }
+        let mut vm = Vm::new();
+        let mut chunk = Chunk::new("no_file", 1);
+        let line = 1;
+        let const1 = chunk.add_constant(vm.add_builtin(add_b)) as u16;
+        chunk.encode2(CONST, 3, const1, Some(line)).unwrap();
+        chunk.encode2(TCALL, 3, 2, Some(line)).unwrap();
+        // Without a call frame the TCALL must return, this SRET would return Undefined.
+        chunk.encode1(SRET, 4, Some(line))?;
+        chunk.args = 2;
+        chunk.input_regs = 3;
+        chunk.extra_regs = 2;
+        let chunk = Arc::new(chunk);
+        let result = vm.do_call(chunk, &[5.into(), 7.into()], None)?;
+        assert_eq!(result.get_int(&vm)?, 12);
+
+        // The same through a global.
+        let slot = vm.reserve_global();
+        let add = vm.add_builtin(add_b);
+        vm.set_global(slot, add);
+        let mut chunk = Chunk::new("no_file", 1);
+        chunk.encode_tcallg(slot, 2, Some(line))?;
+        chunk.encode1(SRET, 4, Some(line))?;
+        chunk.args = 2;
+        chunk.input_regs = 3;
+        chunk.extra_regs = 2;
+        let chunk = Arc::new(chunk);
+        let result = vm.do_call(chunk, &[1.into(), 2.into()], None)?;
+        assert_eq!(result.get_int(&vm)?, 3);
+
+        // With a pending defer the tail call returns to the chunk so the defer runs (SRET) and
+        // the builtin's result is still returned.
+        let mut defer = Chunk::new("no_file", 1);
+        defer.encode1(SRET, 1, Some(line))?;
+        defer.input_regs = 1;
+        let defer = vm.alloc_lambda(Arc::new(defer));
+        let mut chunk = Chunk::new("no_file", 1);
+        let const1 = chunk.add_constant(defer) as u16;
+        chunk.encode2(CONST, 3, const1, Some(line))?;
+        chunk.encode1(DFR, 3, Some(line))?;
+        chunk.encode_tcallg(slot, 2, Some(line))?;
+        chunk.encode1(SRET, 0, Some(line))?;
+        chunk.args = 2;
+        chunk.input_regs = 3;
+        chunk.extra_regs = 2;
+        let chunk = Arc::new(chunk);
+        let result = vm.do_call(chunk, &[3.into(), 4.into()], None)?;
+        assert_eq!(result.get_int(&vm)?, 7);
+        assert!(vm.defers.is_empty());
+        Ok(())
+    }
+
+    /// A lambda that calls record with id, to defer.
+    fn record_lambda(vm: &mut Vm, record: Value, id: i64) -> Value {
+        let line = 1;
+        let mut chunk = Chunk::new("no_file", 1);
+        let const1 = chunk.add_constant(record) as u16;
+        let const2 = chunk.add_constant(id.into()) as u16;
+        chunk.encode2(CONST, 1, const1, Some(line)).unwrap();
+        chunk.encode2(CONST, 3, const2, Some(line)).unwrap();
+        chunk.encode3(CALL, 1, 1, 2, Some(line)).unwrap();
+        chunk.encode1(SRET, 2, Some(line)).unwrap();
+        chunk.input_regs = 1;
+        chunk.extra_regs = 2;
+        vm.alloc_lambda(Arc::new(chunk))
+    }
+
+    #[test]
+    fn test_do_call_error_runs_defers() -> VMResult<()> {
+        let mut vm = Vm::new();
+        let ran = Rc::new(RefCell::new(Vec::new()));
+        let ran_clone = ran.clone();
+        let record = vm.add_builtin_closure(Box::new(move |vm: &mut Vm, registers: &[Value]| {
+            ran_clone.borrow_mut().push(registers[0].get_int(vm)?);
+            Ok(Value::Nil)
+        }));
+        let line = 1;
+        let defer_outer = record_lambda(&mut vm, record, 1);
+        let defer_inner = record_lambda(&mut vm, record, 2);
+
+        // Defers then fails (car of an int).
+        let mut chunk = Chunk::new("no_file", 1);
+        let const1 = chunk.add_constant(defer_inner) as u16;
+        let const2 = chunk.add_constant(1.into()) as u16;
+        chunk.encode2(CONST, 1, const1, Some(line))?;
+        chunk.encode1(DFR, 1, Some(line))?;
+        chunk.encode2(CONST, 2, const2, Some(line))?;
+        chunk.encode2(CAR, 1, 2, Some(line))?;
+        chunk.encode1(SRET, 1, Some(line))?;
+        chunk.input_regs = 1;
+        chunk.extra_regs = 1;
+        let inner = vm.alloc_lambda(Arc::new(chunk));
+
+        // Defers then calls inner.
+        let mut chunk = Chunk::new("no_file", 1);
+        let const1 = chunk.add_constant(defer_outer) as u16;
+        let const2 = chunk.add_constant(inner) as u16;
+        chunk.encode2(CONST, 1, const1, Some(line))?;
+        chunk.encode1(DFR, 1, Some(line))?;
+        chunk.encode2(CONST, 1, const2, Some(line))?;
+        chunk.encode3(CALL, 1, 0, 2, Some(line))?;
+        chunk.encode1(SRET, 2, Some(line))?;
+        chunk.input_regs = 1;
+        chunk.extra_regs = 2;
+        let chunk = Arc::new(chunk);
+
+        let stack_top = vm.stack_top;
+        assert!(vm.do_call(chunk, &[], None).is_err());
+        // Innermost defer first and the VM is back where it was.
+        assert_eq!(*ran.borrow(), vec![2, 1]);
+        assert!(vm.defers.is_empty());
+        assert_eq!(vm.stack_top, stack_top);
+        Ok(())
+    }
+
+    #[test]
+    fn test_do_call_keeps_caller_defers() -> VMResult<()> {
+        let mut vm = Vm::new();
+        let ran = Rc::new(RefCell::new(Vec::new()));
+        let ran_clone = ran.clone();
+        let record = vm.add_builtin_closure(Box::new(move |vm: &mut Vm, registers: &[Value]| {
+            ran_clone.borrow_mut().push(registers[0].get_int(vm)?);
+            Ok(Value::Nil)
+        }));
+        let line = 1;
+        let defer_outer = record_lambda(&mut vm, record, 1);
+        let defer_inner = record_lambda(&mut vm, record, 2);
+
+        // Defers then returns or fails (car of an int).
+        let defer_then = |op| -> VMResult<Arc<Chunk>> {
+            let mut chunk = Chunk::new("no_file", 1);
+            let const1 = chunk.add_constant(defer_inner) as u16;
+            let const2 = chunk.add_constant(1.into()) as u16;
+            chunk.encode2(CONST, 1, const1, Some(line))?;
+            chunk.encode1(DFR, 1, Some(line))?;
+            chunk.encode2(CONST, 2, const2, Some(line))?;
+            chunk.encode2(op, 1, 2, Some(line))?;
+            chunk.encode1(SRET, 1, Some(line))?;
+            chunk.extra_regs = 2;
+            Ok(Arc::new(chunk))
+        };
+        let fails = defer_then(CAR)?;
+        let returns = defer_then(MOV)?;
+
+        // A builtin that runs both with do_call, recording 3 and 4 after them.
+        let ran_clone = ran.clone();
+        let builtin = vm.add_builtin_closure(Box::new(move |vm: &mut Vm, _registers: &[Value]| {
+            assert!(vm.do_call(fails.clone(), &[], None).is_err());
+            ran_clone.borrow_mut().push(3);
+            vm.do_call(returns.clone(), &[], None)?;
+            ran_clone.borrow_mut().push(4);
+            Ok(Value::Nil)
+        }));
+
+        // Defers then calls the builtin, its defer only runs when it returns.
+        let mut chunk = Chunk::new("no_file", 1);
+        let const1 = chunk.add_constant(defer_outer) as u16;
+        let const2 = chunk.add_constant(builtin) as u16;
+        chunk.encode2(CONST, 1, const1, Some(line))?;
+        chunk.encode1(DFR, 1, Some(line))?;
+        chunk.encode2(CONST, 1, const2, Some(line))?;
+        chunk.encode3(CALL, 1, 0, 2, Some(line))?;
+        chunk.encode1(SRET, 2, Some(line))?;
+        chunk.extra_regs = 2;
+        vm.execute(Arc::new(chunk))?;
+        assert_eq!(*ran.borrow(), vec![2, 3, 2, 4, 1]);
+        assert!(vm.defers.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_call_clears_result_reg() -> VMResult<()> {
+        let mut vm = Vm::new();
+        let cell = vm.alloc_value(5.into());
+        let Value::Value(handle) = cell else {
+            panic!("not a value");
+        };
+        // No extra regs, the result goes in the register after the inputs.
+        let mut chunk = Chunk::new("no_file", 1);
+        let line = 1;
+        let const1 = chunk.add_constant(42.into()) as u16;
+        chunk.encode2(CONST, 1, const1, Some(line))?;
+        chunk.encode1(SRET, 1, Some(line))?;
+        chunk.input_regs = 1;
+        let lambda = vm.alloc_lambda(Arc::new(chunk));
+
+        let mut chunk = Chunk::new("no_file", 1);
+        let const1 = chunk.add_constant(lambda) as u16;
+        let const2 = chunk.add_constant(cell) as u16;
+        chunk.encode2(CONST, 1, const1, Some(line))?;
+        // A captured variable left in the callee's result register by an earlier call.
+        chunk.encode2(CONST, 3, const2, Some(line))?;
+        chunk.encode3(CALL, 1, 0, 2, Some(line))?;
+        chunk.encode1(SRET, 2, Some(line))?;
+        chunk.extra_regs = 4;
+        let result = vm.execute(Arc::new(chunk))?;
+        assert_eq!(result.get_int(&vm)?, 42);
+        assert_eq!(vm.get_value(handle).get_int(&vm)?, 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_builtin_closure() -> VMResult<()> {
+        let mut vm = Vm::new();
+        let mut total = 0;
+        let counter = vm.add_builtin_closure(Box::new(move |vm: &mut Vm, registers: &[Value]| {
+            for r in registers {
+                total += r.get_int(vm)?;
+            }
+            Ok(total.into())
+        }));
+        let mut chunk = Chunk::new("no_file", 1);
+        let line = 1;
+        let const1 = chunk.add_constant(counter) as u16;
+        chunk.encode2(CONST, 10, const1, Some(line)).unwrap();
+        chunk.encode3(CALL, 10, 2, 1, Some(line)).unwrap();
+        chunk.encode3(CALL, 10, 1, 5, Some(line)).unwrap();
+        chunk.encode0(RET, Some(line))?;
+        let chunk = Arc::new(chunk);
+        *vm.stack_mut(2) = 2.into();
+        *vm.stack_mut(3) = 3.into();
+        *vm.stack_mut(6) = 10.into();
+        vm.execute(chunk)?;
+        assert_eq!(vm.stack(1).get_int(&vm)?, 5);
+        assert_eq!(vm.stack(5).get_int(&vm)?, 15);
+        let Value::Builtin(idx) = counter else {
+            panic!("not a builtin");
+        };
+        assert_eq!(vm.call_builtin(idx, &[1.into()])?.get_int(&vm)?, 16);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fuel_and_interrupt() -> VMResult<()> {
+        // An infinite loop.
+        let mut chunk = Chunk::new("no_file", 1);
+        let line = 1;
+        chunk.encode0(NOP, Some(line))?;
+        let jmp_back = chunk.add_jump(0);
+        chunk.encode1(JMP, jmp_back as u16, Some(line))?;
+        chunk.encode0(RET, Some(line))?;
+        let chunk = Arc::new(chunk);
+        let mut vm = Vm::new();
+
+        vm.set_fuel(Some(100));
+        let err = vm.execute(chunk.clone()).unwrap_err();
+        assert_eq!(err.key, "fuel");
+        assert_eq!(vm.fuel(), Some(1024));
+        // The reserve for error handlers is used up by the loop.
+        let err = vm.execute(chunk.clone()).unwrap_err();
+        assert_eq!(err.key, "fuel");
+        assert_eq!(vm.fuel(), Some(0));
+        vm.set_fuel(None);
+
+        vm.set_time_limit(Some(Duration::from_millis(20)));
+        assert!(vm
+            .time_left()
+            .is_some_and(|left| left <= Duration::from_millis(20)));
+        let err = vm.execute(chunk.clone()).unwrap_err();
+        assert_eq!(err.key, "fuel");
+        vm.set_time_limit(None);
+        assert_eq!(vm.time_left(), None);
+
+        // The flag can come from outside the VM.
+        let flag = Arc::new(AtomicBool::new(false));
+        vm.set_interrupt_flag(flag.clone());
+        let t = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            flag.store(true, Ordering::Relaxed);
+        });
+        let err = vm.execute(chunk.clone()).unwrap_err();
+        t.join().unwrap();
+        assert_eq!(err.key, "interrupted");
+        assert!(!vm.interrupt_flag().load(Ordering::Relaxed));
+        Ok(())
+    }
+
+    #[test]
+    fn test_profile() -> VMResult<()> {
+        // Loop on line 2 then return from line 3.
+        let mut chunk = Chunk::new("no_file", 1);
+        chunk.encode0(NOP, Some(1))?;
+        let jmp_back = chunk.add_jump(chunk.code.len() as u32);
+        chunk.encode0(NOP, Some(2))?;
+        chunk.encode1(JMP, jmp_back as u16, Some(2))?;
+        chunk.encode0(RET, Some(3))?;
+        let chunk = Arc::new(chunk);
+        let mut vm = Vm::new();
+
+        vm.set_fuel(Some(1000));
+        vm.set_profile(Some(Profile::new(10)));
+        assert!(vm.is_profiling());
+        assert_eq!(vm.execute(chunk.clone()).unwrap_err().key, "fuel");
+        let profile = vm.take_profile().unwrap();
+        assert!(!vm.is_profiling());
+        assert_eq!(profile.total().samples, 200);
+        let stacks: Vec<_> = profile.stacks().collect();
+        assert_eq!(stacks.len(), 1);
+        assert!(Arc::ptr_eq(stacks[0].0[0].chunk, &chunk));
+        assert_eq!(stacks[0].0[0].line, Some(2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_coverage() -> VMResult<()> {
+        let mut vm = Vm::new();
+        // A function on line 5 that is never called.
+        let mut uncalled = Chunk::new("no_file", 5);
+        uncalled.encode0(RET, Some(5))?;
+        let uncalled = vm.alloc_lambda(Arc::new(uncalled));
+        // Line 1 jumps over line 2 to line 3.
+        let mut chunk = Chunk::new("no_file", 1);
+        chunk.add_constant(uncalled);
+        chunk.encode0(NOP, Some(1))?;
+        let jmp = chunk.add_jump(0);
+        chunk.encode1(JMP, jmp as u16, Some(1))?;
+        chunk.encode0(NOP, Some(2))?;
+        chunk.update_jump(jmp, chunk.code.len() as u32);
+        chunk.encode0(RET, Some(3))?;
+        let chunk = Arc::new(chunk);
+
+        vm.set_coverage(Some(Coverage::new()));
+        vm.execute(chunk.clone())?;
+        vm.execute(chunk)?;
+        let coverage = vm.take_coverage().unwrap();
+        let hits = coverage.line_hits();
+        let lines: Vec<(u32, u64)> = hits["no_file"].iter().map(|(l, c)| (*l, *c)).collect();
+        assert_eq!(lines, vec![(1, 2), (2, 0), (3, 2), (5, 0)]);
+        let lcov = coverage.lcov(|file| format!("src/{file}"));
+        assert!(lcov.starts_with("TN:\nSF:src/no_file\nDA:1,2\n"));
+        assert!(lcov.ends_with("LF:4\nLH:2\nend_of_record\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_coroutine() -> VMResult<()> {
+        let mut vm = Vm::new();
+        let mut chunk = Chunk::new("no_file", 1);
+        let line = 1;
+        let const1 = chunk.add_constant(1.into()) as u16;
+        chunk.encode2(CONST, 1, const1, Some(line)).unwrap();
+        chunk.encode2(YIELD, 2, 1, Some(line)).unwrap();
+        chunk.encode2(ADD, 2, 1, Some(line)).unwrap();
+        chunk.encode2(YIELD, 3, 2, Some(line)).unwrap();
+        chunk.encode1(SRET, 3, Some(line))?;
+        chunk.extra_regs = 3;
+        let chunk = Arc::new(chunk);
+        let lambda = vm.alloc_lambda(chunk.clone());
+        let co = vm.new_coroutine(lambda)?;
+        let Value::Coroutine(h) = co else {
+            panic!("not a coroutine");
+        };
+        assert_eq!(vm.resume(h, Value::Nil)?.get_int(&vm)?, 1);
+        let Value::Coroutine(copy) = vm.copy_coroutine(h)? else {
+            panic!("not a coroutine");
+        };
+        assert_eq!(vm.resume(h, 10.into())?.get_int(&vm)?, 11);
+        assert_eq!(vm.resume(copy, 20.into())?.get_int(&vm)?, 21);
+        assert!(!vm.coroutine_done(h));
+        assert_eq!(vm.resume(h, 5.into())?.get_int(&vm)?, 5);
+        assert!(vm.coroutine_done(h));
+        assert!(vm.resume(h, Value::Nil).is_err());
+        // Not in a coroutine.
+        assert!(vm.execute(chunk).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_coroutine_stack_grows() -> VMResult<()> {
+        let mut vm = Vm::new();
+        // Needs more registers than a new coroutine has, called from a builtin in the coroutine.
+        let mut chunk = Chunk::new("no_file", 1);
+        let line = 1;
+        let const1 = chunk.add_constant(7.into()) as u16;
+        chunk.encode2(CONST, 300, const1, Some(line)).unwrap();
+        chunk.encode1(SRET, 300, Some(line))?;
+        chunk.extra_regs = 300;
+        let big = Arc::new(chunk);
+        let call_big = vm.add_builtin_closure(Box::new(move |vm: &mut Vm, registers: &[Value]| {
+            let res = vm.do_call(big.clone(), &[], None)?;
+            // The coroutine stack moved when it grew, the args must still be good.
+            Ok((res.get_int(vm)? + registers[0].get_int(vm)?).into())
+        }));
+        let mut chunk = Chunk::new("no_file", 1);
+        let const1 = chunk.add_constant(call_big) as u16;
+        let const2 = chunk.add_constant(1.into()) as u16;
+        chunk.encode2(CONST, 2, const1, Some(line)).unwrap();
+        chunk.encode2(CONST, 3, const2, Some(line)).unwrap();
+        chunk.encode2(YIELD, 4, 3, Some(line)).unwrap();
+        chunk.encode2(MOV, 5, 3, Some(line)).unwrap();
+        chunk.encode3(CALL, 2, 1, 4, Some(line)).unwrap();
+        chunk.encode1(SRET, 4, Some(line))?;
+        chunk.extra_regs = 5;
+        let lambda = vm.alloc_lambda(Arc::new(chunk));
+        let Value::Coroutine(h) = vm.new_coroutine(lambda)? else {
+            panic!("not a coroutine");
+        };
+        assert!(vm.get_coroutine(h).stack.is_empty());
+        assert_eq!(vm.resume(h, Value::Nil)?.get_int(&vm)?, 1);
+        let len = vm.get_coroutine(h).stack.len();
+        assert!(len > 4 && len < 32);
+        assert_eq!(vm.resume(h, Value::Nil)?.get_int(&vm)?, 8);
+        assert!(vm.coroutine_done(h));
+        Ok(())
+    }
+
+    /// A builtin that runs a full collection.
+    fn collect_garbage(vm: &mut Vm, _registers: &[Value]) -> VMResult<Value> {
+        vm.set_heap_object_cap(Some(usize::MAX));
+        vm.heap_over_limit();
+        vm.set_heap_object_cap(None);
+        Ok(Value::Nil)
+    }
+
+    #[test]
+    fn test_gc_marks_last_register() -> VMResult<()> {
+        fn make_str(vm: &mut Vm, _registers: &[Value]) -> VMResult<Value> {
+            Ok(vm.alloc_string("last reg".into()))
+        }
+        let mut vm = Vm::new();
+        let mut chunk = Chunk::new("no_file", 1);
+        let line = 1;
+        let const0 = chunk.add_constant(vm.add_builtin(make_str)) as u16;
+        let const1 = chunk.add_constant(vm.add_builtin(collect_garbage)) as u16;
+        // Register 3 is the frame's last (stack_max), the string must survive the collection.
+        chunk.encode2(CONST, 1, const0, Some(line)).unwrap();
+        chunk.encode3(CALL, 1, 0, 3, Some(line)).unwrap();
+        chunk.encode2(CONST, 1, const1, Some(line)).unwrap();
+        chunk.encode3(CALL, 1, 0, 2, Some(line)).unwrap();
+        chunk.encode1(SRET, 3, Some(line))?;
+        chunk.extra_regs = 3;
+        let s = vm.execute(Arc::new(chunk))?;
+        assert!(vm.heap().is_live(s));
+        assert_eq!(vm.get_string(s.get_handle().unwrap()), "last reg");
+        Ok(())
+    }
+
+    #[test]
+    fn test_execute_roots_constants() -> VMResult<()> {
+        let mut vm = Vm::new();
+        // Only the chunk references s.
+        let s = vm.alloc_string("constant".to_string());
+        let mut chunk = Chunk::new("no_file", 1);
+        let line = 1;
+        let const0 = chunk.add_constant(vm.add_builtin(collect_garbage)) as u16;
+        let const1 = chunk.add_constant(s) as u16;
+        chunk.encode2(CONST, 1, const0, Some(line)).unwrap();
+        chunk.encode3(CALL, 1, 0, 2, Some(line)).unwrap();
+        chunk.encode2(CONST, 2, const1, Some(line)).unwrap();
+        chunk.encode1(SRET, 2, Some(line))?;
+        chunk.extra_regs = 3;
+        let res = vm.execute(Arc::new(chunk))?;
+        assert!(vm.heap().is_live(res));
+        assert_eq!(vm.get_string(res.get_handle().unwrap()), "constant");
+        Ok(())
+    }
+
+    #[test]
+    fn test_execute_keeps_sticky() -> VMResult<()> {
+        let mut vm = Vm::new();
+        let s = vm.alloc_string("sticky".to_string());
+        vm.heap_sticky(s);
+        let mut chunk = Chunk::new("no_file", 1);
+        let line = 1;
+        let const1 = chunk.add_constant(s) as u16;
+        chunk.encode2(CONST, 1, const1, Some(line)).unwrap();
+        chunk.encode0(RET, Some(line))?;
+        chunk.extra_regs = 1;
+        vm.execute(Arc::new(chunk))?;
+        // Collect, s was sticky before execute so must still be.
+        vm.set_heap_object_cap(Some(usize::MAX));
+        assert!(!vm.heap_over_limit());
+        assert!(vm.heap().is_live(s));
+        assert_eq!(vm.get_string(s.get_handle().unwrap()), "sticky");
+        Ok(())
+    }
+
     #[test]
     fn test_jumps() -> VMResult<()> {
         let mut vm = Vm::new();
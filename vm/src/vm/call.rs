@@ -17,10 +17,10 @@ impl<ENV> GVm<ENV> {
             Value::Nil
         } else {
             let rest_len = (num_args - (chunk.args + chunk.opt_args)) as usize + 1;
+            // The callee's registers start at stack_top (already moved past first_reg).
+            let rest_start = (chunk.args + chunk.opt_args) as usize;
             let mut r = vec![Value::Undefined; rest_len];
-            r.copy_from_slice(
-                &self.register_slice()[rest_reg as usize..(rest_reg as usize + rest_len)],
-            );
+            r.copy_from_slice(&self.register_slice()[rest_start..(rest_start + rest_len)]);
             self.alloc_list_ro(r)
         };
         (rest_reg.into(), v)
@@ -137,6 +137,23 @@ impl<ENV> GVm<ENV> {
             chunk
         }
     }
+    /// True if a tail call to lambda will complete the current execution, i.e. a builtin or other
+    /// special call made with no call frame to return to (a lambda run with do_call for instance).
+    /// The result will be in the stack_top register and the exec loop should return.
+    pub(super) fn tail_call_ends_exec(&self, lambda: Value) -> bool {
+        let lambda = if let Value::Value(handle) = lambda {
+            self.get_value(handle)
+        } else {
+            lambda
+        };
+        self.defers.is_empty()
+            && self.call_frame().is_none()
+            && !matches!(
+                lambda,
+                Value::Lambda(_) | Value::Closure(_) | Value::Continuation(_)
+            )
+    }
+
     /// Main function to match and execute anything that is callable.
     pub fn make_call(
         &mut self,
@@ -152,23 +169,31 @@ impl<ENV> GVm<ENV> {
                 let last_reg = (first_reg + num_args + 1) as usize;
                 // Useful if the builtin runs bytecode that errors otherwise a waste...
                 let frame = self.make_call_frame(chunk.clone(), lambda, false);
-                let f = &self.buitins[f_idx as usize];
                 let regs = self.register_slice();
+                let args = &regs[(first_reg + 1) as usize..last_reg];
+                // A coroutine's stack can grow (and move) while the builtin runs so it gets a copy.
+                let co_args;
+                let args = if self.in_coroutine() {
+                    co_args = args.to_vec();
+                    &co_args[..]
+                } else {
+                    args
+                };
 
-                let res =
-                    (f.func)(self, &regs[(first_reg + 1) as usize..last_reg]).map_err(|e| {
-                        if self.err_frame().is_some() {
-                            let call_frame = self.alloc_callframe(frame);
-                            mov_register!(self, first_reg as usize, call_frame);
-                            self.stack_top += first_reg as usize;
-                        }
-                        (e, chunk.clone())
-                    })?;
+                let res = self.call_builtin(f_idx, args).map_err(|e| {
+                    if self.err_frame().is_some() {
+                        let call_frame = self.alloc_callframe(frame);
+                        mov_register!(self, first_reg as usize, call_frame);
+                        self.stack_top += first_reg as usize;
+                    }
+                    (e, chunk.clone())
+                })?;
                 Ok(self.finish_special_call(chunk, tail_call, first_reg, res))
             }
             Value::Lambda(handle) => {
                 let stack_top = self.stack_top;
                 let l = self.heap().get_lambda(handle);
+                let (l, _) = arity_chunk(&l, num_args).map_err(|e| (e, chunk.clone()))?;
                 check_num_args(&l, num_args).map_err(|e| (e, chunk.clone()))?;
                 if !tail_call {
                     let frame = self.make_call_frame(chunk, lambda, true);
@@ -177,6 +202,7 @@ impl<ENV> GVm<ENV> {
                     self.stack_top += first_reg as usize;
                 }
                 self.stack_max = self.stack_top + l.input_regs + l.extra_regs;
+                self.grow_stack();
                 self.this_fn = Some(lambda);
                 self.ip_ptr = get_code!(l);
                 if l.rest {
@@ -190,6 +216,7 @@ impl<ENV> GVm<ENV> {
             Value::Closure(handle) => {
                 let stack_top = self.stack_top;
                 let (l, _) = self.heap().get_closure(handle);
+                let (l, cap_offset) = arity_chunk(&l, num_args).map_err(|e| (e, chunk.clone()))?;
                 check_num_args(&l, num_args).map_err(|e| (e, chunk.clone()))?;
                 let frame = if !tail_call {
                     let frame = self.make_call_frame(chunk, lambda, true);
@@ -202,7 +229,9 @@ impl<ENV> GVm<ENV> {
                 // Take the heap so we can mutate self.  Put it back when done or will panic on next access.
                 let heap = self.heap.take().expect("VM must have a Heap!");
                 let caps = heap.get_closure_captures(handle);
+                let caps = &caps[cap_offset..cap_offset + num_captures(&l)];
                 self.stack_max = self.stack_top + l.input_regs + l.extra_regs;
+                self.grow_stack();
                 self.this_fn = Some(lambda);
                 self.ip_ptr = get_code!(l);
                 if l.rest {
@@ -299,11 +328,12 @@ impl<ENV> GVm<ENV> {
                     self.defers.resize(k.frame.defers.len(), Value::Undefined);
                     self.defers.copy_from_slice(&k.frame.defers[..]);
 
-                    self.stack_slice_mut()[..k.stack.len()].copy_from_slice(&k.stack[..]);
-                    *self.stack_mut(k.arg_reg) = arg;
                     self.stack_top = k.frame.stack_top;
                     self.stack_max =
                         self.stack_top + k.frame.chunk.input_regs + k.frame.chunk.extra_regs;
+                    self.grow_stack();
+                    self.stack_slice_mut()[..k.stack.len()].copy_from_slice(&k.stack[..]);
+                    *self.stack_mut(k.arg_reg) = arg;
                     self.ip_ptr = k.frame.ip;
                     self.current_ip_ptr = k.frame.current_ip;
                     self.this_fn = k.frame.this_fn;
@@ -345,8 +375,9 @@ impl<ENV> GVm<ENV> {
                 );
             }
         }
-        // Clear extra regs so things like closures or globals don't get changed by mistake.
-        if l.extra_regs > 0 {
+        // Clear extra regs so things like closures or globals don't get changed by mistake.  The
+        // register after the inputs is in use even with no extra regs (stack_max includes it).
+        if l.input_regs + l.extra_regs > 0 {
             for r in l.input_regs..=l.input_regs + l.extra_regs {
                 mov_register!(self, first_reg as usize + r, Value::Undefined);
             }
@@ -354,6 +385,30 @@ impl<ENV> GVm<ENV> {
     }
 }
 
+/// Number of values a chunk captures.
+pub(crate) fn num_captures(l: &Chunk) -> usize {
+    l.captures.as_ref().map(|c| c.len()).unwrap_or(0)
+}
+
+/// Select the chunk to run for num_args, the first arity that accepts them for a multi-arity
+/// chunk otherwise chunk.  Also returns the offset of its captures in the closure captures.
+pub(crate) fn arity_chunk(chunk: &Arc<Chunk>, num_args: u16) -> VMResult<(Arc<Chunk>, usize)> {
+    if let Some(arities) = &chunk.arities {
+        let mut cap_offset = 0;
+        for arity in arities {
+            if check_num_args(arity, num_args).is_ok() {
+                return Ok((arity.clone(), cap_offset));
+            }
+            cap_offset += num_captures(arity);
+        }
+        Err(VMError::new_vm(format!(
+            "No arity accepts {num_args} arguments."
+        )))
+    } else {
+        Ok((chunk.clone(), 0))
+    }
+}
+
 /// Verify the number of args provided will work with a chunk.
 fn check_num_args(l: &Chunk, num_args: u16) -> VMResult<()> {
     if l.rest {
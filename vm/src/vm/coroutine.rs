@@ -0,0 +1,226 @@
+use std::sync::Arc;
+
+use crate::{
+    Chunk, Coroutine, CoroutineFrame, CoroutineState, GVm, Handle, Heap, VMError, VMResult, Value,
+};
+
+/// State of the code that resumed a running coroutine, restored when it yields or finishes.
+pub(crate) struct CoroutineCaller {
+    stack: *mut Value,
+    stack_cap: usize,
+    stack_top: usize,
+    stack_max: usize,
+    ip: *const u8,
+    current_ip: *const u8,
+    this_fn: Option<Value>,
+    on_error: Option<Value>,
+    defers: Vec<Value>,
+    coroutine: Handle,
+    /// The coroutine's stack segment, it is the VM stack while the coroutine runs.  It starts empty
+    /// and grows as calls need more registers.
+    co_stack: Vec<Value>,
+    /// Execution depth of the coroutine body, a yield from any other depth would cross a native call.
+    depth: usize,
+}
+
+impl CoroutineCaller {
+    pub(crate) fn mark(&self, heap: &mut Heap) {
+        for i in 0..=self.stack_max {
+            heap.mark(unsafe { *self.stack.add(i) });
+        }
+        if let Some(this_fn) = self.this_fn {
+            heap.mark(this_fn);
+        }
+        if let Some(on_error) = self.on_error {
+            heap.mark(on_error);
+        }
+        for defer in &self.defers {
+            heap.mark(*defer);
+        }
+        heap.mark(Value::Coroutine(self.coroutine));
+    }
+}
+
+impl<ENV> GVm<ENV> {
+    /// Is a coroutine running (the stack is its segment)?
+    pub(super) fn in_coroutine(&self) -> bool {
+        !self.co_callers.is_empty()
+    }
+
+    /// Make sure the stack holds the registers up to stack_max.  Only a running coroutine's stack
+    /// grows (and so moves), the VM stack is always STACK_CAP.
+    pub(super) fn grow_stack(&mut self) {
+        if self.stack_max < self.stack_cap {
+            return;
+        }
+        if let Some(caller) = self.co_callers.last_mut() {
+            let len = (self.stack_max + 1).next_power_of_two();
+            caller.co_stack.resize(len, Value::Undefined);
+            self.stack = caller.co_stack.as_mut_ptr();
+            self.stack_cap = len;
+            self.make_registers();
+        }
+    }
+
+    /// Create a new coroutine that will call lambda (with no arguments) when first resumed.
+    pub fn new_coroutine(&mut self, lambda: Value) -> VMResult<Value> {
+        match lambda {
+            Value::Lambda(_) | Value::Closure(_) => {
+                Ok(self.alloc_coroutine(Coroutine::new(lambda)))
+            }
+            _ => Err(VMError::new_vm(format!(
+                "coroutine: requires a lambda, got a {}",
+                lambda.display_type(self)
+            ))),
+        }
+    }
+
+    /// Is the coroutine finished (returned or errored out)?
+    pub fn coroutine_done(&self, handle: Handle) -> bool {
+        matches!(self.get_coroutine(handle).state, CoroutineState::Done)
+    }
+
+    /// Return a new coroutine that will continue from where coroutine is suspended (its stack is
+    /// copied), resuming one does not affect the other.
+    pub fn copy_coroutine(&mut self, handle: Handle) -> VMResult<Value> {
+        let co = self.get_coroutine(handle);
+        if let CoroutineState::Running = co.state {
+            return Err(VMError::new_vm("coroutine-copy: coroutine is running"));
+        }
+        let co = co.clone();
+        Ok(self.alloc_coroutine(co))
+    }
+
+    /// Called by YIELD, stash the running coroutine's state so resume can suspend it.
+    pub(super) fn yield_coroutine(
+        &mut self,
+        chunk: Arc<Chunk>,
+        val: Value,
+        dest: usize,
+    ) -> VMResult<()> {
+        match self.co_callers.last() {
+            None => Err(VMError::new_vm("yield: not in a coroutine")),
+            Some(caller) if caller.depth != self.exec_depth => {
+                Err(VMError::new_vm("yield: can not yield across a native call"))
+            }
+            Some(_) => {
+                let frame = CoroutineFrame {
+                    chunk,
+                    ip: self.ip_ptr,
+                    current_ip: self.current_ip_ptr,
+                    stack_top: self.stack_top,
+                    stack_max: self.stack_max,
+                    this_fn: self.this_fn,
+                    defers: std::mem::take(&mut self.defers),
+                    on_error: self.on_error,
+                    resume_reg: self.stack_top + dest,
+                };
+                self.yielded = Some((val, frame));
+                Ok(())
+            }
+        }
+    }
+
+    /// Run the coroutine until it yields or finishes.  Returns the yielded value or the result of
+    /// the coroutine's lambda, arg is the value of the yield expression it was suspended on.
+    pub fn resume(&mut self, handle: Handle, arg: Value) -> VMResult<Value> {
+        let co = self.heap_mut().get_coroutine_mut(handle);
+        let state = std::mem::replace(&mut co.state, CoroutineState::Running);
+        let lambda = co.lambda;
+        let mut co_stack = std::mem::take(&mut co.stack);
+        match state {
+            CoroutineState::Done => {
+                self.heap_mut().get_coroutine_mut(handle).state = CoroutineState::Done;
+                return Err(VMError::new_vm("resume: coroutine is done"));
+            }
+            CoroutineState::Running => {
+                return Err(VMError::new_vm("resume: coroutine is already running"));
+            }
+            CoroutineState::New => co_stack = Vec::new(),
+            CoroutineState::Suspended(_) => {}
+        }
+        let depth = self.exec_depth + 1;
+        self.co_callers.push(CoroutineCaller {
+            stack: self.stack,
+            stack_cap: self.stack_cap,
+            stack_top: self.stack_top,
+            stack_max: self.stack_max,
+            ip: self.ip_ptr,
+            current_ip: self.current_ip_ptr,
+            this_fn: self.this_fn,
+            on_error: self.on_error,
+            defers: std::mem::take(&mut self.defers),
+            coroutine: handle,
+            co_stack,
+            depth,
+        });
+        let co_stack = &mut self
+            .co_callers
+            .last_mut()
+            .expect("just pushed a caller")
+            .co_stack;
+        self.stack = co_stack.as_mut_ptr();
+        self.stack_cap = co_stack.len();
+        let res = match state {
+            CoroutineState::Suspended(frame) => {
+                self.stack_top = frame.stack_top;
+                self.stack_max = frame.stack_max;
+                self.ip_ptr = frame.ip;
+                self.current_ip_ptr = frame.current_ip;
+                self.this_fn = frame.this_fn;
+                self.on_error = frame.on_error;
+                self.defers = frame.defers;
+                *self.stack_mut(frame.resume_reg) = arg;
+                self.execute2(frame.chunk, true)
+                    .map(|_| self.stack(self.stack_top))
+            }
+            _ => {
+                self.stack_top = 0;
+                self.stack_max = 0;
+                self.grow_stack();
+                match lambda {
+                    Value::Lambda(h) => {
+                        let l = self.get_lambda(h);
+                        self.do_call(l, &[], None)
+                    }
+                    Value::Closure(h) => {
+                        let (l, caps) = self.get_closure(h);
+                        let caps = caps.to_vec();
+                        self.do_call(l, &[], Some(&caps[..]))
+                    }
+                    _ => Err(VMError::new_vm("coroutine: requires a lambda")),
+                }
+            }
+        };
+
+        let caller = self.co_callers.pop().expect("missing coroutine caller");
+        self.stack = caller.stack;
+        self.stack_cap = caller.stack_cap;
+        self.stack_top = caller.stack_top;
+        self.stack_max = caller.stack_max;
+        self.ip_ptr = caller.ip;
+        self.current_ip_ptr = caller.current_ip;
+        self.this_fn = caller.this_fn;
+        self.on_error = caller.on_error;
+        self.defers = caller.defers;
+        self.make_registers();
+        let yielded = self.yielded.take();
+        let co = self.heap_mut().get_coroutine_mut(handle);
+        match (res, yielded) {
+            (Ok(_), Some((val, frame))) => {
+                co.state = CoroutineState::Suspended(frame);
+                co.stack = caller.co_stack;
+                Ok(val)
+            }
+            (res, _) => {
+                // Finished or errored out, either way it can not be resumed and the stack can go.
+                co.state = CoroutineState::Done;
+                if res.is_err() {
+                    // The error frame is on the coroutine's stack, let the caller record its own.
+                    self.err_frame = None;
+                }
+                res
+            }
+        }
+    }
+}
@@ -1,7 +1,6 @@
 use crate::opcodes::*;
 use crate::{
     from_i56, CallFrame, Chunk, Continuation, Error, GVm, VMError, VMErrorObj, VMResult, Value,
-    STACK_CAP,
 };
 use std::collections::HashMap;
 use std::marker::PhantomData;
@@ -112,6 +111,22 @@ impl<ENV> GVm<ENV> {
                         }
                     }
                 }
+                Value::Error(handle) => {
+                    // Errors destructure like a map of :key and :data.
+                    let err = self.get_error(handle);
+                    let key = Value::Keyword(self.intern_static("key"));
+                    let data = Value::Keyword(self.intern_static("data"));
+                    for i in 0..len {
+                        let reg_key = self.register(dest + i);
+                        *self.register_mut(dest + i) = if reg_key == key {
+                            Value::Keyword(err.keyword)
+                        } else if reg_key == data {
+                            err.data
+                        } else {
+                            Value::Undefined
+                        };
+                    }
+                }
                 Value::Nil => {
                     for i in 0..len {
                         *self.register_mut(dest + i) = Value::Undefined;
@@ -123,6 +138,42 @@ impl<ENV> GVm<ENV> {
         Ok(())
     }
 
+    /// Destructure key args, src is a list of key/value pairs and dest starts with the allowed
+    /// keys.  Keys not passed are left undefined (for their defaults), unknown keys are an error.
+    fn key_destructure(&mut self, decodes: (u16, u16, u16)) -> VMResult<()> {
+        let (dest, len, src) = decodes;
+        let dest = dest as usize;
+        let keys: Vec<Value> = (0..len as usize).map(|i| self.register(dest + i)).collect();
+        for i in 0..keys.len() {
+            *self.register_mut(dest + i) = Value::Undefined;
+        }
+        let val = self.register(src as usize);
+        let args: Vec<Value> = match val {
+            Value::Nil => Vec::new(),
+            Value::List(_, _) | Value::Pair(_) => val.iter(self).collect(),
+            _ => return Err(VMError::new_vm("key args must be a list")),
+        };
+        if !args.len().is_multiple_of(2) {
+            return Err(VMError::new_vm(format!(
+                "key args must be key value pairs, got {} values",
+                args.len()
+            )));
+        }
+        for pair in args.chunks(2) {
+            if let Some(i) = keys.iter().position(|k| *k == pair[0]) {
+                *self.register_mut(dest + i) = pair[1];
+            } else {
+                let expected: Vec<String> = keys.iter().map(|k| k.display_value(self)).collect();
+                return Err(VMError::new_vm(format!(
+                    "unknown key arg {}, expected one of: {}",
+                    pair[0].display_value(self),
+                    expected.join(" ")
+                )));
+            }
+        }
+        Ok(())
+    }
+
     fn get_line(&self, wide: bool, chunk: &Chunk) -> Option<u32> {
         if wide {
             unsafe { chunk.offset_to_line(self.ip_ptr.offset_from(get_code!(chunk)) as usize - 4) }
@@ -330,18 +381,24 @@ impl<ENV> GVm<ENV> {
 
     // Some macro expansions trips this.
     #[allow(clippy::redundant_closure_call)]
-    pub(super) fn exec_loop(&mut self, chunk: Arc<Chunk>) -> Result<(), (VMError, Arc<Chunk>)> {
+    pub(super) fn exec_loop(
+        &mut self,
+        chunk: Arc<Chunk>,
+        resume: bool,
+    ) -> Result<(), (VMError, Arc<Chunk>)> {
         let _env: PhantomData<ENV>;
         self.make_registers();
         let mut chunk = chunk;
-        self.ip_ptr = get_code!(chunk);
         let mut wide = false;
-        // Clean up the working regs we are about to use.
-        if chunk.extra_regs > 0 {
+        if !resume {
+            self.ip_ptr = get_code!(chunk);
+        }
+        // Clean up the working regs we are about to use (a resumed coroutine is using them).
+        if chunk.extra_regs > 0 && !resume {
             let regs = unsafe {
                 std::slice::from_raw_parts_mut(
                     self.stack.add(self.stack_top),
-                    STACK_CAP - self.stack_top,
+                    self.stack_cap - self.stack_top,
                 )
             };
             for reg in regs
@@ -358,6 +415,17 @@ impl<ENV> GVm<ENV> {
                 wide = false;
             }
             self.current_ip_ptr = self.ip_ptr;
+            if let Some(mut profile) = self.profile.take() {
+                if profile.tick() {
+                    profile.sample(self, &chunk, self.current_ip_ptr);
+                }
+                self.profile = Some(profile);
+            }
+            if let Some(mut coverage) = self.coverage.take() {
+                let offset = unsafe { self.current_ip_ptr.offset_from(get_code!(chunk)) as usize };
+                coverage.hit(self, &chunk, offset);
+                self.coverage = Some(coverage);
+            }
             opcode = decode_u8!(self.ip_ptr);
             match opcode {
                 NOP => {}
@@ -516,6 +584,11 @@ impl<ENV> GVm<ENV> {
                     self.map_destructure(decodes)
                         .map_err(|e| (e, chunk.clone()))?;
                 }
+                KDSC => {
+                    let decodes = decode3!(self.ip_ptr, wide);
+                    self.key_destructure(decodes)
+                        .map_err(|e| (e, chunk.clone()))?;
+                }
                 COPY => {
                     let (_dest, _src) = decode2!(self.ip_ptr, wide);
                     // XXX Deep copy src to dest
@@ -627,6 +700,7 @@ impl<ENV> GVm<ENV> {
                 CALL => {
                     let (lambda, num_args, first_reg) = decode3!(self.ip_ptr, wide);
                     let lambda = self.register(lambda as usize);
+                    self.check_budget().map_err(|e| (e, chunk.clone()))?;
                     chunk = self.make_call(lambda, chunk, first_reg, num_args, false)?;
                     self.make_registers();
                 }
@@ -638,13 +712,19 @@ impl<ENV> GVm<ENV> {
                     };
                     let (num_args, first_reg) = decode2!(self.ip_ptr, wide);
                     let lambda = self.get_global(idx);
+                    self.check_budget().map_err(|e| (e, chunk.clone()))?;
                     chunk = self.make_call(lambda, chunk, first_reg, num_args, false)?;
                     self.make_registers();
                 }
                 TCALL => {
                     let (lambda, num_args) = decode2!(self.ip_ptr, wide);
                     let lambda = self.register(lambda as usize);
+                    let ends_exec = self.tail_call_ends_exec(lambda);
+                    self.check_budget().map_err(|e| (e, chunk.clone()))?;
                     chunk = self.make_call(lambda, chunk, 0, num_args, true)?;
+                    if ends_exec {
+                        return Ok(());
+                    }
                     self.make_registers(); // In case of a builtin call
                 }
                 TCALLG => {
@@ -655,12 +735,18 @@ impl<ENV> GVm<ENV> {
                     };
                     let num_args = decode1!(self.ip_ptr, wide);
                     let lambda = self.get_global(idx);
+                    let ends_exec = self.tail_call_ends_exec(lambda);
+                    self.check_budget().map_err(|e| (e, chunk.clone()))?;
                     chunk = self.make_call(lambda, chunk, 0, num_args, true)?;
+                    if ends_exec {
+                        return Ok(());
+                    }
                     self.make_registers(); // In case of a builtin call
                 }
                 CALLM => {
                     let (num_args, first_reg) = decode2!(self.ip_ptr, wide);
                     if let Some(this_fn) = self.this_fn {
+                        self.check_budget().map_err(|e| (e, chunk.clone()))?;
                         chunk = self.make_call(this_fn, chunk, first_reg, num_args, false)?;
                         self.make_registers();
                     } else {
@@ -677,7 +763,12 @@ impl<ENV> GVm<ENV> {
                 TCALLM => {
                     let num_args = decode1!(self.ip_ptr, wide);
                     if let Some(this_fn) = self.this_fn {
+                        let ends_exec = self.tail_call_ends_exec(this_fn);
+                        self.check_budget().map_err(|e| (e, chunk.clone()))?;
                         chunk = self.make_call(this_fn, chunk, 0, num_args, true)?;
+                        if ends_exec {
+                            return Ok(());
+                        }
                         self.make_registers(); // In case of a builtin call
                     } else {
                         let line = self.get_line(wide, &chunk);
@@ -692,18 +783,18 @@ impl<ENV> GVm<ENV> {
                 }
                 JMP => {
                     let jmp = decode1!(self.ip_ptr, wide);
-                    self.ip_ptr = get_code_at!(chunk, chunk.jump_table[jmp as usize] as isize);
+                    jump!(self, chunk, jmp);
                 }
                 JMPT => {
                     let (test, jmp) = decode2!(self.ip_ptr, wide);
                     if self.register(test as usize).is_truethy() {
-                        self.ip_ptr = get_code_at!(chunk, chunk.jump_table[jmp as usize] as isize);
+                        jump!(self, chunk, jmp);
                     }
                 }
                 JMPF => {
                     let (test, jmp) = decode2!(self.ip_ptr, wide);
                     if self.register(test as usize).is_falsey() {
-                        self.ip_ptr = get_code_at!(chunk, chunk.jump_table[jmp as usize] as isize);
+                        jump!(self, chunk, jmp);
                     }
                 }
                 JMPEQ => {
@@ -717,7 +808,7 @@ impl<ENV> GVm<ENV> {
                         .get_int(self)
                         .map_err(|e| (e, chunk.clone()))?;
                     if op1 == op2 {
-                        self.ip_ptr = get_code_at!(chunk, chunk.jump_table[jmp as usize] as isize);
+                        jump!(self, chunk, jmp);
                     }
                 }
                 JMPLT => {
@@ -729,7 +820,7 @@ impl<ENV> GVm<ENV> {
                         .register_int(op2 as usize)
                         .map_err(|e| (e, chunk.clone()))?;
                     if op1 < op2 {
-                        self.ip_ptr = get_code_at!(chunk, chunk.jump_table[jmp as usize] as isize);
+                        jump!(self, chunk, jmp);
                     }
                 }
                 JMPGT => {
@@ -741,19 +832,19 @@ impl<ENV> GVm<ENV> {
                         .register_int(op2 as usize)
                         .map_err(|e| (e, chunk.clone()))?;
                     if op1 > op2 {
-                        self.ip_ptr = get_code_at!(chunk, chunk.jump_table[jmp as usize] as isize);
+                        jump!(self, chunk, jmp);
                     }
                 }
                 JMPU => {
                     let (test, jmp) = decode2!(self.ip_ptr, wide);
                     if self.register(test as usize).is_undef() {
-                        self.ip_ptr = get_code_at!(chunk, chunk.jump_table[jmp as usize] as isize);
+                        jump!(self, chunk, jmp);
                     }
                 }
                 JMPNU => {
                     let (test, jmp) = decode2!(self.ip_ptr, wide);
                     if !self.register(test as usize).is_undef() {
-                        self.ip_ptr = get_code_at!(chunk, chunk.jump_table[jmp as usize] as isize);
+                        jump!(self, chunk, jmp);
                     }
                 }
                 JMPRU => {
@@ -763,8 +854,7 @@ impl<ENV> GVm<ENV> {
                         let len = len as usize;
                         for i in 0..len {
                             if self.register(test + i).is_undef() {
-                                self.ip_ptr =
-                                    get_code_at!(chunk, chunk.jump_table[jmp as usize] as isize);
+                                jump!(self, chunk, jmp);
                                 break;
                             }
                         }
@@ -783,8 +873,7 @@ impl<ENV> GVm<ENV> {
                             }
                         }
                         if jump {
-                            self.ip_ptr =
-                                get_code_at!(chunk, chunk.jump_table[jmp as usize] as isize);
+                            jump!(self, chunk, jmp);
                         }
                     }
                 }
@@ -903,6 +992,13 @@ impl<ENV> GVm<ENV> {
                     chunk = self.make_call(lambda, chunk, first_reg, 1, false)?;
                     self.make_registers();
                 }
+                YIELD => {
+                    let (dest, src) = decode2!(self.ip_ptr, wide);
+                    let val = self.register(src as usize);
+                    self.yield_coroutine(chunk.clone(), val, dest as usize)
+                        .map_err(|e| (e, chunk.clone()))?;
+                    return Ok(());
+                }
                 DFR => {
                     let lambda = decode1!(self.ip_ptr, wide);
                     let lambda = self.register(lambda as usize);
@@ -23,6 +23,18 @@ macro_rules! get_code_at {
     }};
 }
 
+/// Jump to entry jmp of the chunk's jump table (for use in exec_loop).
+/// Backward jumps (loops) use fuel and check for interrupts.
+#[macro_export]
+macro_rules! jump {
+    ($vm:expr, $chunk:expr, $jmp:expr) => {{
+        $vm.ip_ptr = get_code_at!($chunk, $chunk.jump_table[$jmp as usize] as isize);
+        if $vm.ip_ptr <= $vm.current_ip_ptr {
+            $vm.check_budget().map_err(|e| (e, $chunk.clone()))?;
+        }
+    }};
+}
+
 #[macro_export]
 macro_rules! decode_u8 {
     ($code:expr) => {{
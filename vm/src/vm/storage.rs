@@ -1,5 +1,8 @@
 use crate::heap::Error;
-use crate::{CallFrame, Chunk, Continuation, Handle, Heap, Interned, MutState, VMResult, Value};
+use crate::{
+    CallFrame, Chunk, Continuation, Coroutine, Handle, Heap, Interned, MutState, NativeObject,
+    VMResult, Value,
+};
 use std::collections::HashMap;
 use std::sync::Arc;
 
@@ -92,6 +95,18 @@ impl<ENV> GVm<ENV> {
         self.globals.reserve()
     }
 
+    /// Save the value and properties of every global so restore_globals can undo any changes.
+    /// Saves nest and saved values stay alive (are GC roots) until restored.
+    pub fn save_globals(&mut self) {
+        self.globals.save();
+    }
+
+    /// Undo changes to globals since the last save_globals, globals defined since then become
+    /// undefined.  Returns false if there was no save to restore.
+    pub fn restore_globals(&mut self) -> bool {
+        self.globals.restore()
+    }
+
     pub fn get_call_stack(&self) -> CallStackIter<ENV> {
         CallStackIter::new(self)
     }
@@ -114,6 +129,14 @@ impl<ENV> GVm<ENV> {
         res
     }
 
+    /// Collect garbage and return true if the heap is still over its object cap.
+    pub(crate) fn heap_over_limit(&mut self) -> bool {
+        let mut heap = self.heap.take().expect("VM must have a Heap!");
+        let res = heap.collect_over_limit(|heap| self.mark_roots(heap));
+        self.heap = Some(heap);
+        res
+    }
+
     pub fn alloc_string(&mut self, s: String) -> Value {
         let mut heap = self.heap.take().expect("VM must have a Heap!");
         let res = heap.alloc_string(s, MutState::Mutable, |heap| self.mark_roots(heap));
@@ -214,6 +237,23 @@ impl<ENV> GVm<ENV> {
         res
     }
 
+    pub fn alloc_coroutine(&mut self, co: Coroutine) -> Value {
+        let mut heap = self.heap.take().expect("VM must have a Heap!");
+        // alloc must not save mark_roots (it does not) since we broke heap away from self.
+        let res = heap.alloc_coroutine(co, |heap| self.mark_roots(heap));
+        self.heap = Some(heap);
+        res
+    }
+
+    /// Put a Rust object on the heap, it is finalized and dropped when collected.
+    pub fn alloc_object(&mut self, obj: NativeObject) -> Value {
+        let mut heap = self.heap.take().expect("VM must have a Heap!");
+        // alloc must not save mark_roots (it does not) since we broke heap away from self.
+        let res = heap.alloc_object(obj, |heap| self.mark_roots(heap));
+        self.heap = Some(heap);
+        res
+    }
+
     pub fn alloc_callframe(&mut self, frame: CallFrame) -> Value {
         let mut heap = self.heap.take().expect("VM must have a Heap!");
         // alloc must not save mark_roots (it does not) since we broke heap away from self.
@@ -348,6 +388,18 @@ impl<ENV> GVm<ENV> {
         self.heap().get_continuation(handle)
     }
 
+    pub fn get_coroutine(&self, handle: Handle) -> &Coroutine {
+        self.heap().get_coroutine(handle)
+    }
+
+    pub fn get_object(&self, handle: Handle) -> &NativeObject {
+        self.heap().get_object(handle)
+    }
+
+    pub fn get_object_mut(&mut self, handle: Handle) -> VMResult<&mut NativeObject> {
+        self.heap_mut().get_object_mut(handle)
+    }
+
     pub fn get_callframe(&self, handle: Handle) -> &CallFrame {
         self.heap().get_callframe(handle)
     }
@@ -401,7 +453,7 @@ impl<ENV> GVm<ENV> {
         self.globals.mark(heap);
         // TODO- add a bound to ENV so we can call a mark_roots?  I think we need this for the
         // temporarily held doc_string for instance but also generally useful?
-        for i in 0..self.stack_max {
+        for i in 0..=self.stack_max {
             heap.mark(self.stack(i));
         }
         if let Some(this_fn) = self.this_fn {
@@ -416,6 +468,15 @@ impl<ENV> GVm<ENV> {
         for defer in &self.defers {
             heap.mark(*defer);
         }
+        // The suspended callers of any running coroutines.
+        for caller in &self.co_callers {
+            caller.mark(heap);
+        }
+        for chunk in &self.exec_chunks {
+            for constant in &chunk.constants {
+                heap.mark(*constant);
+            }
+        }
         Ok(())
     }
 }